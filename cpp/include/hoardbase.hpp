@@ -0,0 +1,507 @@
+// A header-only C++17 wrapper of the C API of hoardbase.
+//
+// Documents are nlohmann::json values, exchanged with the database as extended JSON: object ids, dates, decimals and the other bson types are
+// written and read in their relaxed extended JSON form, e.g. {"$oid": "..."}. Errors are thrown as hoardbase::Error.
+//
+//     hoardbase::Database db("test.db");
+//     auto people = db.create_collection("people");
+//     people.insert_one({{"name", "Ada"}, {"age", 36}});
+//     for (const auto &record : people.find({{"age", {{"$gte", 18}}}})) {
+//         std::cout << record.data["name"] << std::endl;
+//     }
+
+#pragma once
+
+#include <hoardbase.h>
+
+#include <chrono>
+#include <cstdint>
+#include <exception>
+#include <iterator>
+#include <memory>
+#include <nlohmann/json.hpp>
+#include <optional>
+#include <stdexcept>
+#include <string>
+#include <type_traits>
+#include <utility>
+#include <vector>
+
+namespace hoardbase
+{
+
+    /// An error returned by the C API, with its status, e.g. HOARDBASE_ERROR_OPERATION, and its message.
+    class Error : public std::runtime_error
+    {
+    public:
+        Error(hoardbase_status status, const std::string &message) : std::runtime_error(message), m_status(status) {}
+
+        hoardbase_status status() const noexcept { return m_status; }
+
+    private:
+        hoardbase_status m_status;
+    };
+
+    struct Record
+    {
+        int64_t id = 0;
+        /// When the document was last written, the epoch if the collection doesn't log it.
+        std::chrono::system_clock::time_point last_modified;
+        /// The hex blake3 hash of the document, empty if the collection doesn't hash documents.
+        std::string hash;
+        nlohmann::json data;
+    };
+
+    struct IndexInfo
+    {
+        std::string name;
+        bool unique = false;
+        bool partial = false;
+    };
+
+    struct OpenOptions
+    {
+        bool read_only = false;
+        /// Logs every sql statement.
+        bool trace = false;
+        /// Logs the time taken by every sql statement.
+        bool profile = false;
+    };
+
+    struct CollectionOptions
+    {
+        bool hash_document = true;
+        bool log_last_modified = true;
+        /// Rejects documents identical to an existing one. Requires hash_document.
+        bool hash_unique = false;
+        /// The limits of a capped collection, 0 for no limit.
+        uint64_t capped_max_documents = 0;
+        uint64_t capped_max_bytes = 0;
+    };
+
+    struct FindOptions
+    {
+        /// The maximum number of documents to return, negative for no limit.
+        int64_t limit = -1;
+        int64_t skip = 0;
+    };
+
+    struct UpdateOptions
+    {
+        int64_t skip = 0;
+        bool upsert = false;
+        /// An array of filter documents, or null.
+        nlohmann::json array_filters;
+    };
+
+    struct UpdateManyOptions
+    {
+        /// The maximum number of documents to update, 0 for no limit.
+        int64_t limit = 0;
+        int64_t skip = 0;
+        bool upsert = false;
+        nlohmann::json array_filters;
+    };
+
+    namespace detail
+    {
+
+        struct Deleter
+        {
+            void operator()(hoardbase_database *db) const { hoardbase_close(db); }
+            void operator()(hoardbase_collection *collection) const { hoardbase_collection_free(collection); }
+            void operator()(hoardbase_cursor *cursor) const { hoardbase_cursor_free(cursor); }
+        };
+
+        template <typename T>
+        using Handle = std::unique_ptr<T, Deleter>;
+
+        /// Throws the last error unless `status` is HOARDBASE_OK or HOARDBASE_NOT_FOUND.
+        inline hoardbase_status check(hoardbase_status status)
+        {
+            if (status != HOARDBASE_OK && status != HOARDBASE_NOT_FOUND)
+            {
+                throw Error(status, hoardbase_last_error());
+            }
+            return status;
+        }
+
+        /// A document encoded for the C API. A null json is passed as a null pointer, which the C API reads as the query matching everything.
+        class Input
+        {
+        public:
+            explicit Input(const nlohmann::json &json) : m_null(json.is_null()), m_text(m_null ? std::string() : json.dump()) {}
+
+            const uint8_t *data() const { return m_null ? nullptr : reinterpret_cast<const uint8_t *>(m_text.data()); }
+            size_t size() const { return m_text.size(); }
+
+        private:
+            bool m_null;
+            std::string m_text;
+        };
+
+        /// Owns the buffer written by a call.
+        struct Buffer
+        {
+            hoardbase_buffer buffer = {};
+            ~Buffer() { hoardbase_buffer_free(&buffer); }
+
+            nlohmann::json parse() const
+            {
+                auto text = reinterpret_cast<const char *>(buffer.data);
+                return nlohmann::json::parse(text, text + buffer.len);
+            }
+        };
+
+        /// Owns the record written by a call.
+        struct RecordOut
+        {
+            hoardbase_record record = {};
+            ~RecordOut() { hoardbase_record_free(&record); }
+
+            Record get() const
+            {
+                auto text = reinterpret_cast<const char *>(record.data.data);
+                return Record{record.id, std::chrono::system_clock::time_point(std::chrono::milliseconds(record.last_modified)), std::string(record.hash), nlohmann::json::parse(text, text + record.data.len)};
+            }
+
+            std::optional<Record> found(hoardbase_status status) const
+            {
+                if (check(status) == HOARDBASE_NOT_FOUND)
+                {
+                    return std::nullopt;
+                }
+                return get();
+            }
+        };
+
+    } // namespace detail
+
+    /// The results of Collection::find(), read in batches as the cursor advances. It is an input range:
+    ///
+    ///     for (const auto &record : collection.find(query)) { ... }
+    class Cursor
+    {
+    public:
+        class iterator
+        {
+        public:
+            using iterator_category = std::input_iterator_tag;
+            using value_type = Record;
+            using difference_type = std::ptrdiff_t;
+            using pointer = const Record *;
+            using reference = const Record &;
+
+            iterator() = default;
+
+            reference operator*() const { return *m_cursor->m_current; }
+            pointer operator->() const { return &*m_cursor->m_current; }
+
+            iterator &operator++()
+            {
+                m_cursor->m_current = m_cursor->next();
+                if (!m_cursor->m_current)
+                {
+                    m_cursor = nullptr;
+                }
+                return *this;
+            }
+
+            void operator++(int) { ++*this; }
+
+            friend bool operator==(const iterator &a, const iterator &b) { return a.m_cursor == b.m_cursor; }
+            friend bool operator!=(const iterator &a, const iterator &b) { return !(a == b); }
+
+        private:
+            friend class Cursor;
+
+            explicit iterator(Cursor *cursor) : m_cursor(cursor) { ++*this; }
+
+            Cursor *m_cursor = nullptr;
+        };
+
+        /// Reads the next record, or returns std::nullopt once all the records have been read.
+        std::optional<Record> next()
+        {
+            detail::RecordOut out;
+            return out.found(hoardbase_cursor_next(m_cursor.get(), &out.record));
+        }
+
+        /// Starts iterating from the next record. A cursor can only be iterated once.
+        iterator begin() { return iterator(this); }
+        iterator end() { return iterator(); }
+
+        /// Reads the remaining records.
+        std::vector<Record> to_vector()
+        {
+            std::vector<Record> records;
+            while (auto record = next())
+            {
+                records.push_back(std::move(*record));
+            }
+            return records;
+        }
+
+    private:
+        friend class Collection;
+
+        explicit Cursor(hoardbase_cursor *cursor) : m_cursor(cursor) {}
+
+        detail::Handle<hoardbase_cursor> m_cursor;
+        std::optional<Record> m_current;
+    };
+
+    /// A collection, obtained from a Database or from a Transaction. It can outlive the Database it was obtained from, but not the
+    /// transaction.
+    class Collection
+    {
+    public:
+        std::string name() const { return hoardbase_collection_name(m_collection.get()); }
+
+        /// Returns the inserted record.
+        Record insert_one(const nlohmann::json &document)
+        {
+            detail::Input input(document);
+            detail::RecordOut out;
+            detail::check(hoardbase_collection_insert_one(get(), HOARDBASE_FORMAT_JSON, input.data(), input.size(), &out.record));
+            return out.get();
+        }
+
+        /// Inserts an array of documents.
+        void insert_many(const nlohmann::json &documents)
+        {
+            detail::Input input(documents);
+            detail::check(hoardbase_collection_insert_many(get(), HOARDBASE_FORMAT_JSON, input.data(), input.size()));
+        }
+
+        /// Returns a cursor over the documents matching `query`. A null query matches every document.
+        Cursor find(const nlohmann::json &query = nullptr, const FindOptions &options = {})
+        {
+            detail::Input input(query);
+            hoardbase_cursor *cursor = nullptr;
+            detail::check(hoardbase_collection_find(get(), HOARDBASE_FORMAT_JSON, input.data(), input.size(), options.limit, options.skip, &cursor));
+            return Cursor(cursor);
+        }
+
+        std::optional<Record> find_one(const nlohmann::json &query = nullptr, int64_t skip = 0)
+        {
+            detail::Input input(query);
+            detail::RecordOut out;
+            return out.found(hoardbase_collection_find_one(get(), HOARDBASE_FORMAT_JSON, input.data(), input.size(), skip, &out.record));
+        }
+
+        int64_t count_documents(const nlohmann::json &query = nullptr, const FindOptions &options = {})
+        {
+            detail::Input input(query);
+            int64_t count = 0;
+            detail::check(hoardbase_collection_count_documents(get(), HOARDBASE_FORMAT_JSON, input.data(), input.size(), options.limit, options.skip, &count));
+            return count;
+        }
+
+        /// Returns the number of distinct values of `field` among the documents matching `query`.
+        int64_t distinct(const std::string &field, const nlohmann::json &query = nullptr)
+        {
+            detail::Input input(query);
+            int64_t count = 0;
+            detail::check(hoardbase_collection_distinct(get(), HOARDBASE_FORMAT_JSON, field.c_str(), input.data(), input.size(), &count));
+            return count;
+        }
+
+        /// Updates the first document matching `query`, with an update document or an array of pipeline stages. Returns the updated record, the
+        /// inserted one with `upsert`, or std::nullopt if no document matched.
+        std::optional<Record> update_one(const nlohmann::json &query, const nlohmann::json &update, const UpdateOptions &options = {})
+        {
+            detail::Input input(query);
+            detail::Input update_input(update);
+            detail::RecordOut out;
+            if (update.is_array())
+            {
+                return out.found(hoardbase_collection_update_one_pipeline(get(), HOARDBASE_FORMAT_JSON, input.data(), input.size(), update_input.data(), update_input.size(), options.skip, options.upsert, &out.record));
+            }
+            detail::Input filters(options.array_filters);
+            return out.found(hoardbase_collection_update_one(get(), HOARDBASE_FORMAT_JSON, input.data(), input.size(), update_input.data(), update_input.size(), options.skip, options.upsert, filters.data(), filters.size(), &out.record));
+        }
+
+        /// Updates the documents matching `query` and returns how many were updated.
+        int64_t update_many(const nlohmann::json &query, const nlohmann::json &update, const UpdateManyOptions &options = {})
+        {
+            detail::Input input(query);
+            detail::Input update_input(update);
+            int64_t count = 0;
+            if (update.is_array())
+            {
+                detail::check(hoardbase_collection_update_many_pipeline(get(), HOARDBASE_FORMAT_JSON, input.data(), input.size(), update_input.data(), update_input.size(), options.limit, options.skip, options.upsert, &count));
+                return count;
+            }
+            detail::Input filters(options.array_filters);
+            detail::check(hoardbase_collection_update_many(get(), HOARDBASE_FORMAT_JSON, input.data(), input.size(), update_input.data(), update_input.size(), options.limit, options.skip, options.upsert, filters.data(), filters.size(), &count));
+            return count;
+        }
+
+        /// Returns the new record, or std::nullopt if no document matched.
+        std::optional<Record> replace_one(const nlohmann::json &query, const nlohmann::json &replacement, int64_t skip = 0)
+        {
+            detail::Input input(query);
+            detail::Input replacement_input(replacement);
+            detail::RecordOut out;
+            return out.found(hoardbase_collection_replace_one(get(), HOARDBASE_FORMAT_JSON, input.data(), input.size(), replacement_input.data(), replacement_input.size(), skip, &out.record));
+        }
+
+        /// Returns the number of deleted documents, 0 or 1.
+        int64_t delete_one(const nlohmann::json &query)
+        {
+            detail::Input input(query);
+            int64_t count = 0;
+            detail::check(hoardbase_collection_delete_one(get(), HOARDBASE_FORMAT_JSON, input.data(), input.size(), &count));
+            return count;
+        }
+
+        int64_t delete_many(const nlohmann::json &query = nullptr)
+        {
+            detail::Input input(query);
+            int64_t count = 0;
+            detail::check(hoardbase_collection_delete_many(get(), HOARDBASE_FORMAT_JSON, input.data(), input.size(), &count));
+            return count;
+        }
+
+        std::optional<Record> find_one_and_delete(const nlohmann::json &query)
+        {
+            detail::Input input(query);
+            detail::RecordOut out;
+            return out.found(hoardbase_collection_find_one_and_delete(get(), HOARDBASE_FORMAT_JSON, input.data(), input.size(), &out.record));
+        }
+
+        /// Creates an index, e.g. create_index({{"age", 1}, {"name", -1}}).
+        void create_index(const nlohmann::json &keys, bool unique = false)
+        {
+            detail::Input input(keys);
+            detail::check(hoardbase_collection_create_index(get(), HOARDBASE_FORMAT_JSON, input.data(), input.size(), unique));
+        }
+
+        void drop_index(const std::string &name) { detail::check(hoardbase_collection_drop_index(get(), name.c_str())); }
+
+        std::vector<IndexInfo> get_indexes()
+        {
+            detail::Buffer out;
+            detail::check(hoardbase_collection_get_indexes(get(), HOARDBASE_FORMAT_JSON, &out.buffer));
+            std::vector<IndexInfo> indexes;
+            for (const auto &index : out.parse())
+            {
+                indexes.push_back(IndexInfo{index["name"].get<std::string>(), index["unique"].get<bool>(), index["partial"].get<bool>()});
+            }
+            return indexes;
+        }
+
+        void reindex() { detail::check(hoardbase_collection_reindex(get())); }
+
+    private:
+        friend class Database;
+        friend class Transaction;
+
+        explicit Collection(hoardbase_collection *collection) : m_collection(collection) {}
+
+        hoardbase_collection *get() const { return m_collection.get(); }
+
+        detail::Handle<hoardbase_collection> m_collection;
+    };
+
+    /// The transaction passed to the callback of Database::transaction().
+    class Transaction
+    {
+    public:
+        /// Returns a collection whose operations run in this transaction. It can't be used once the callback has returned.
+        Collection collection(const std::string &name)
+        {
+            hoardbase_collection *collection = nullptr;
+            detail::check(hoardbase_transaction_collection(m_tx, name.c_str(), &collection));
+            return Collection(collection);
+        }
+
+    private:
+        friend class Database;
+
+        explicit Transaction(hoardbase_transaction *tx) : m_tx(tx) {}
+
+        hoardbase_transaction *m_tx;
+    };
+
+    /// A database file. The Database and the collections obtained from it can be used from several threads, the calls are serialized.
+    class Database
+    {
+    public:
+        /// Opens the database at `path`, creating it if needed. The path ":memory:" opens a private in-memory database.
+        explicit Database(const std::string &path, const OpenOptions &options = {})
+        {
+            uint32_t flags = (options.read_only ? HOARDBASE_OPEN_READ_ONLY : 0) | (options.trace ? HOARDBASE_OPEN_TRACE : 0) | (options.profile ? HOARDBASE_OPEN_PROFILE : 0);
+            hoardbase_database *db = nullptr;
+            detail::check(hoardbase_open(path.c_str(), flags, &db));
+            m_db.reset(db);
+        }
+
+        /// Creates a collection, or returns it if it exists.
+        Collection create_collection(const std::string &name, const CollectionOptions &options = {})
+        {
+            uint32_t flags = (options.hash_document ? HOARDBASE_COLLECTION_HASH_DOCUMENT : 0) | (options.log_last_modified ? HOARDBASE_COLLECTION_LOG_LAST_MODIFIED : 0) | (options.hash_unique ? HOARDBASE_COLLECTION_HASH_UNIQUE : 0);
+            hoardbase_collection *collection = nullptr;
+            detail::check(hoardbase_create_collection(m_db.get(), name.c_str(), flags, options.capped_max_documents, options.capped_max_bytes, &collection));
+            return Collection(collection);
+        }
+
+        /// Returns an existing collection.
+        Collection collection(const std::string &name)
+        {
+            hoardbase_collection *collection = nullptr;
+            detail::check(hoardbase_get_collection(m_db.get(), name.c_str(), &collection));
+            return Collection(collection);
+        }
+
+        /// Returns the sorted names of the collections.
+        std::vector<std::string> list_collections()
+        {
+            detail::Buffer out;
+            detail::check(hoardbase_list_collections(m_db.get(), HOARDBASE_FORMAT_JSON, &out.buffer));
+            return out.parse().get<std::vector<std::string>>();
+        }
+
+        void drop_collection(const std::string &name) { detail::check(hoardbase_drop_collection(m_db.get(), name.c_str())); }
+
+        void rename_collection(const std::string &old_name, const std::string &new_name) { detail::check(hoardbase_rename_collection(m_db.get(), old_name.c_str(), new_name.c_str())); }
+
+        /// Calls `f` with a Transaction, and commits it when `f` returns. If `f` throws, the transaction is rolled back and the exception is
+        /// rethrown. The database and its collections can't be used from this thread inside `f`, only the collections of the transaction.
+        template <typename F>
+        void transaction(F &&f)
+        {
+            struct Context
+            {
+                std::remove_reference_t<F> *f;
+                std::exception_ptr exception;
+            };
+            Context context{&f, nullptr};
+            auto callback = [](hoardbase_transaction *tx, void *user_data) -> hoardbase_status {
+                auto context = static_cast<Context *>(user_data);
+                try
+                {
+                    Transaction transaction(tx);
+                    (*context->f)(transaction);
+                    return HOARDBASE_OK;
+                }
+                catch (...)
+                {
+                    context->exception = std::current_exception();
+                    return HOARDBASE_ERROR_OPERATION;
+                }
+            };
+            hoardbase_status status = hoardbase_run_transaction(m_db.get(), callback, &context);
+            if (context.exception)
+            {
+                std::rethrow_exception(context.exception);
+            }
+            detail::check(status);
+        }
+
+    private:
+        detail::Handle<hoardbase_database> m_db;
+    };
+
+} // namespace hoardbase
@@ -1,457 +1,1135 @@
-use std::ptr::null_mut;
-use libc::c_char;
-use std::ffi::CString;
-use std::ffi::CStr;
-use std::os::raw::{c_int, c_void};
-use serde_json::Value;
-use serde_json::Map;
-use std::borrow::Borrow;
-use serde_json::json;
-use hoardbase::base::CollectionTrait;
-//https://sixtyfps.io/blog/expose-rust-library-to-other-languages.html
-
-
-
-extern "C" {
-    fn test_print();
-    fn create_json() -> *mut c_void;
-    fn free_json(ptr: *mut c_void);
+//! The C API of hoardbase. CMake generates its header, `hoardbase.h`, from this file with cbindgen.
+//!
+//! Conventions:
+//! * every function that can fail returns a `hoardbase_status`. [`HOARDBASE_OK`] is success and [`HOARDBASE_NOT_FOUND`] means that no document
+//!   matched, the other values are errors. The message of the last error of the calling thread is returned by [`hoardbase_last_error()`].
+//! * results are written to out-parameters, which are left untouched on error.
+//! * documents are passed as a pointer and a length, in the format given by a `hoardbase_format`: bson, or UTF-8 extended JSON, relaxed when
+//!   written by hoardbase. A null query matches every document. A list of documents, like the input of [`hoardbase_collection_insert_many()`], is a
+//!   JSON array, or a bson array, i.e. a bson document whose keys are "0", "1", and so on.
+//! * the buffers and records returned by hoardbase are freed with [`hoardbase_buffer_free()`] and [`hoardbase_record_free()`], the handles with
+//!   their own `_free` or `_close` function. Strings are NUL-terminated UTF-8.
+//! * a database and the collections obtained from it can be used from any thread; calls on one database are serialized. Cursors and the
+//!   collections of a transaction must stay on the thread that created them.
+//!
+//! [`HOARDBASE_ABI_VERSION`] is incremented when a change breaks existing callers. Additions, like new functions or new flags, don't change it.
+
+#![allow(non_camel_case_types)]
+// the exported functions check their pointers themselves, as documented in the header.
+#![allow(clippy::missing_safety_doc)]
+
+use hoardbase::base::{CollectionTrait, Record, SearchOption, UpdateOption};
+use hoardbase::database::{Database, DatabaseConfig, Transaction};
+use std::cell::{Cell, RefCell};
+use std::collections::VecDeque;
+use std::ffi::{CStr, CString};
+use std::os::raw::{c_char, c_void};
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+
+/// The version of the C ABI.
+pub const HOARDBASE_ABI_VERSION: u32 = 1;
+
+/// The result of a call.
+pub type hoardbase_status = i32;
+pub const HOARDBASE_OK: hoardbase_status = 0;
+/// No document matched, or a cursor has no more documents. This isn't an error.
+pub const HOARDBASE_NOT_FOUND: hoardbase_status = 1;
+/// A null pointer, a string that isn't UTF-8, an unknown format or flag, or a handle used where it can't be.
+pub const HOARDBASE_ERROR_INVALID_ARGUMENT: hoardbase_status = 2;
+/// A document couldn't be decoded from the given format.
+pub const HOARDBASE_ERROR_INVALID_DOCUMENT: hoardbase_status = 3;
+/// The database rejected the operation, e.g. an invalid query, a unique index violation, a missing collection or an sqlite error.
+pub const HOARDBASE_ERROR_OPERATION: hoardbase_status = 4;
+/// An internal error. The database can still be used, but the operation may have been partially applied unless it ran in a transaction.
+pub const HOARDBASE_ERROR_PANIC: hoardbase_status = 5;
+
+/// The encoding of the documents exchanged with hoardbase.
+pub type hoardbase_format = u32;
+pub const HOARDBASE_FORMAT_BSON: hoardbase_format = 0;
+/// Extended JSON. hoardbase accepts both the canonical and the relaxed forms, and writes the relaxed form.
+pub const HOARDBASE_FORMAT_JSON: hoardbase_format = 1;
+
+/// Flags of [`hoardbase_open()`].
+pub const HOARDBASE_OPEN_READ_ONLY: u32 = 1;
+/// Logs every sql statement.
+pub const HOARDBASE_OPEN_TRACE: u32 = 2;
+/// Logs the time taken by every sql statement.
+pub const HOARDBASE_OPEN_PROFILE: u32 = 4;
+
+/// Flags of [`hoardbase_create_collection()`].
+pub const HOARDBASE_COLLECTION_HASH_DOCUMENT: u32 = 1;
+pub const HOARDBASE_COLLECTION_LOG_LAST_MODIFIED: u32 = 2;
+/// Rejects documents identical to an existing one. Requires [`HOARDBASE_COLLECTION_HASH_DOCUMENT`].
+pub const HOARDBASE_COLLECTION_HASH_UNIQUE: u32 = 4;
+/// The flags of a collection created with the default config: hashed documents and logged modification times.
+pub const HOARDBASE_COLLECTION_DEFAULT: u32 = 3;
+
+/// A buffer allocated by hoardbase. `data` is followed by a NUL byte that isn't counted in `len`, so a JSON buffer can be read as a C string.
+#[repr(C)]
+pub struct hoardbase_buffer {
+    pub data: *mut u8,
+    pub len: usize,
+}
 
+/// A document and its metadata.
+#[repr(C)]
+pub struct hoardbase_record {
+    pub id: i64,
+    /// When the document was last written, in milliseconds since the epoch. 0 if the collection doesn't log it.
+    pub last_modified: i64,
+    /// The hex blake3 hash of the document, empty if the collection doesn't hash documents.
+    pub hash: [c_char; 65],
+    pub data: hoardbase_buffer,
+}
 
-    fn print_json(ptr: *mut c_void);
+/// An open database.
+pub struct hoardbase_database {
+    db: Arc<Mutex<Database>>,
+}
 
-    fn insert_i64(ptr: *mut c_void, key: *const c_char, value: i64);
+#[derive(Clone)]
+enum Target {
+    Database(Arc<Mutex<Database>>),
+    /// A collection of a transaction points to the transaction, which only lives during [`hoardbase_run_transaction()`]. `active` is cleared when
+    /// it returns.
+    Transaction { tx: *const c_void, active: Arc<AtomicBool> },
+}
 
+/// A collection, obtained from a database or from a transaction.
+#[derive(Clone)]
+pub struct hoardbase_collection {
+    target: Target,
+    name: CString,
+}
 
-    fn insert_f64(ptr: *mut c_void, key: *const c_char, value: f64);
+/// The results of [`hoardbase_collection_find()`], read in batches as the cursor advances.
+pub struct hoardbase_cursor {
+    collection: hoardbase_collection,
+    format: hoardbase_format,
+    query: bson::Document,
+    /// How many documents are left to return, `None` for no limit.
+    remaining: Option<i64>,
+    skip: i64,
+    batch: VecDeque<Record>,
+    exhausted: bool,
+}
 
-    fn insert_null(ptr: *mut c_void, key: *const c_char);
+/// The transaction passed to the callback of [`hoardbase_run_transaction()`].
+pub struct hoardbase_transaction {
+    tx: *const c_void,
+    active: Arc<AtomicBool>,
+}
 
-    fn insert_bool(ptr: *mut c_void, key: *const c_char, value: bool);
+/// The callback of [`hoardbase_run_transaction()`]. The transaction is committed if it returns [`HOARDBASE_OK`], and rolled back otherwise.
+pub type hoardbase_transaction_callback = Option<unsafe extern "C" fn(tx: *mut hoardbase_transaction, user_data: *mut c_void) -> hoardbase_status>;
 
-    fn insert_str(ptr: *mut c_void, key: *const c_char, value: *const c_char);
+/// The number of documents a cursor reads at a time.
+const CURSOR_BATCH_SIZE: i64 = 100;
 
-    fn create_json_array() -> *mut c_void;
+struct Failure {
+    status: hoardbase_status,
+    message: String,
+}
 
-    fn array_push_i64(ptr: *mut c_void, value: i64);
+impl Failure {
+    fn argument(message: impl ToString) -> Self {
+        Failure { status: HOARDBASE_ERROR_INVALID_ARGUMENT, message: message.to_string() }
+    }
 
-    fn array_push_f64(ptr: *mut c_void, value: f64);
+    fn document(message: impl ToString) -> Self {
+        Failure { status: HOARDBASE_ERROR_INVALID_DOCUMENT, message: message.to_string() }
+    }
+}
 
-    fn array_push_null(ptr: *mut c_void);
+impl From<String> for Failure {
+    fn from(message: String) -> Self {
+        Failure { status: HOARDBASE_ERROR_OPERATION, message }
+    }
+}
 
-    fn array_push_bool(ptr: *mut c_void, value: bool);
+impl From<&str> for Failure {
+    fn from(message: &str) -> Self {
+        Failure::from(message.to_string())
+    }
+}
 
-    fn array_push_str(ptr: *mut c_void, value: *const c_char);
+thread_local! {
+    static LAST_ERROR: RefCell<CString> = RefCell::new(CString::default());
+    /// The database whose transaction callback is running on this thread. Locking it again would deadlock.
+    static TRANSACTION_DATABASE: Cell<*const Mutex<Database>> = const { Cell::new(std::ptr::null()) };
+}
 
-    fn array_push_obj(ptr: *mut c_void, obj: *mut c_void);
+fn set_last_error(message: &str) {
+    let message = CString::new(message.replace('\0', " ")).unwrap_or_default();
+    LAST_ERROR.with(|last_error| *last_error.borrow_mut() = message);
+}
 
-    fn insert_obj(ptr: *mut c_void, key: *const c_char, obj: *mut c_void);
+/// Runs the body of an exported function, recording its error and turning panics into [`HOARDBASE_ERROR_PANIC`], as unwinding into C is
+/// undefined behavior.
+fn call(f: impl FnOnce() -> Result<hoardbase_status, Failure>) -> hoardbase_status {
+    let (status, message) = match catch_unwind(AssertUnwindSafe(f)) {
+        Ok(Ok(status)) => (status, String::new()),
+        Ok(Err(failure)) => (failure.status, failure.message),
+        Err(panic) => {
+            let message = panic.downcast_ref::<&str>().map(|s| s.to_string()).or_else(|| panic.downcast_ref::<String>().cloned()).unwrap_or_default();
+            (HOARDBASE_ERROR_PANIC, format!("Internal error: {}", message))
+        }
+    };
+    set_last_error(&message);
+    status
+}
 
-    fn create_cpp_record(id: i64, json_ptr: *mut c_void, hash: *const c_char, last_modified: u64) -> *mut c_void;
+fn lock(db: &Arc<Mutex<Database>>) -> Result<MutexGuard<'_, Database>, Failure> {
+    if TRANSACTION_DATABASE.with(|current| current.get() == Arc::as_ptr(db)) {
+        return Err(Failure::argument("The database is in a transaction on this thread, use the collections of the transaction"));
+    }
+    // a panic while the lock was held leaves the database usable, see HOARDBASE_ERROR_PANIC.
+    Ok(db.lock().unwrap_or_else(|poisoned| poisoned.into_inner()))
 }
 
+unsafe fn reference<'a, T>(ptr: *const T, name: &str) -> Result<&'a T, Failure> {
+    ptr.as_ref().ok_or_else(|| Failure::argument(format!("{} is null", name)))
+}
 
-#[repr(C)]
-pub enum JsonFieldType {
-    String,
-    Int,
-    Float,
-    Object,
-    Array,
-    Boolean,
-    Null,
+unsafe fn string<'a>(ptr: *const c_char, name: &str) -> Result<&'a str, Failure> {
+    if ptr.is_null() {
+        return Err(Failure::argument(format!("{} is null", name)));
+    }
+    CStr::from_ptr(ptr).to_str().map_err(|_| Failure::argument(format!("{} isn't UTF-8", name)))
 }
 
-#[no_mangle]
-pub unsafe extern "C" fn sixtyfps_shared_vector_free(ptr: *mut u8, size: usize, align: usize) {
-    /*...*/
+unsafe fn bytes<'a>(data: *const u8, len: usize) -> &'a [u8] {
+    if data.is_null() || len == 0 {
+        &[]
+    } else {
+        std::slice::from_raw_parts(data, len)
+    }
 }
 
-#[no_mangle]
-pub unsafe extern "C" fn sixtyfps_shared_vector_allocate(size: usize, align: usize) -> *mut u8 {
-    /*...*/
-    return null_mut();
+fn check_format(format: hoardbase_format) -> Result<(), Failure> {
+    match format {
+        HOARDBASE_FORMAT_BSON | HOARDBASE_FORMAT_JSON => Ok(()),
+        _ => Err(Failure::argument(format!("Unknown format {}", format))),
+    }
 }
 
-#[no_mangle]
-pub unsafe extern "C" fn hoardbase_open(path: *const c_char) -> *mut c_void {
-    let c_str: &CStr = CStr::from_ptr(path);
-    let str_slice: &str = c_str.to_str().unwrap();
-    let str_buf: String = str_slice.to_owned(); 
-    println!("{}", str_buf);
+fn decode(format: hoardbase_format, bytes: &[u8]) -> Result<bson::Bson, Failure> {
+    check_format(format)?;
+    if format == HOARDBASE_FORMAT_BSON {
+        return bson::Document::from_reader(bytes).map(bson::Bson::Document).map_err(|e| Failure::document(format!("Invalid bson: {}", e)));
+    }
+    let json: serde_json::Value = serde_json::from_slice(bytes).map_err(|e| Failure::document(format!("Invalid JSON: {}", e)))?;
+    bson::Bson::try_from(json).map_err(|e| Failure::document(format!("Invalid extended JSON: {}", e)))
+}
 
-    let mut config = hoardbase::database::DatabaseConfig::new(str_slice);
-    config.trace(true);
-    config.profile(true);
-    let mut db = hoardbase::database::Database::open(&config).unwrap();
-    let handle = Box::new(db);
-    let handle_ptr = Box::<hoardbase::database::Database>::into_raw(handle);            
-    println!("created handle");
+unsafe fn document(format: hoardbase_format, data: *const u8, len: usize, name: &str) -> Result<bson::Document, Failure> {
+    if data.is_null() {
+        return Err(Failure::argument(format!("{} is null", name)));
+    }
+    match decode(format, bytes(data, len))? {
+        bson::Bson::Document(document) => Ok(document),
+        _ => Err(Failure::document(format!("{} isn't a document", name))),
+    }
+}
 
-    return handle_ptr as *mut c_void;
+/// Decodes a query, null being the query that matches everything.
+unsafe fn query(format: hoardbase_format, data: *const u8, len: usize) -> Result<bson::Document, Failure> {
+    if data.is_null() {
+        check_format(format)?;
+        return Ok(bson::Document::new());
+    }
+    document(format, data, len, "query")
 }
 
+unsafe fn documents(format: hoardbase_format, data: *const u8, len: usize, name: &str) -> Result<Vec<bson::Document>, Failure> {
+    if data.is_null() {
+        return Err(Failure::argument(format!("{} is null", name)));
+    }
+    let items = match decode(format, bytes(data, len))? {
+        bson::Bson::Array(items) => items,
+        // the bson encoding of an array.
+        bson::Bson::Document(document) if format == HOARDBASE_FORMAT_BSON => document.into_iter().map(|(_, value)| value).collect(),
+        _ => return Err(Failure::document(format!("{} isn't an array", name))),
+    };
+    items
+        .into_iter()
+        .map(|item| match item {
+            bson::Bson::Document(document) => Ok(document),
+            _ => Err(Failure::document(format!("{} has an item that isn't a document", name))),
+        })
+        .collect()
+}
 
-// I ran into a segmentation fault bug when naming this function close()
-// this naming poluted the std lib's close function.println
-// had to prefix it with hoardbase_ to avoid the collision.
-#[no_mangle]
-pub unsafe extern "C" fn hoardbase_close(handle: *mut c_void) {
-    let handle = Box::from_raw(handle as *mut hoardbase::database::Database);
-    println!("closed handle");
+unsafe fn update_option(format: hoardbase_format, array_filters: *const u8, len: usize) -> Result<Option<UpdateOption>, Failure> {
+    if array_filters.is_null() {
+        return Ok(None);
+    }
+    let mut options = UpdateOption::default();
+    options.array_filters(documents(format, array_filters, len, "array_filters")?);
+    Ok(Some(options))
 }
 
-#[no_mangle]
-pub unsafe extern "C" fn hoardbase_create_collection(handle: *mut c_void, name: *const c_char) -> *mut c_void {
-    let handle = handle as *mut hoardbase::database::Database;
-    let c_str: &CStr = CStr::from_ptr(name);
-    let str_slice: &str = c_str.to_str().unwrap();
-    let collection = (*handle).create_collection("test", &hoardbase::base::CollectionConfig::default("test")).unwrap();
-    let collection_ptr = Box::new(collection);
-    let collection_ptr = Box::<hoardbase::collection::Collection>::into_raw(collection_ptr);
-    return collection_ptr as *mut c_void;
+fn buffer(mut bytes: Vec<u8>) -> hoardbase_buffer {
+    let len = bytes.len();
+    bytes.push(0);
+    let data = Box::into_raw(bytes.into_boxed_slice()) as *mut u8;
+    hoardbase_buffer { data, len }
 }
 
-#[no_mangle]
-pub unsafe extern "C" fn hoardbase_collection_insert_one(handle: *mut c_void, val: *const c_void) -> *mut c_void {
-    let handle = handle as *mut hoardbase::collection::Collection;
-    let val = val as *mut serde_json::Value;
-    println!("debug val : {:?}", (*val));
-    if let Ok(inserted) = (*handle).insert_one(&*val) {
-        println!("inserted {:?}", inserted);
+fn encode(format: hoardbase_format, value: bson::Bson) -> Result<hoardbase_buffer, Failure> {
+    if format == HOARDBASE_FORMAT_JSON {
+        return Ok(buffer(value.into_relaxed_extjson().to_string().into_bytes()));
+    }
+    let document = match value {
+        bson::Bson::Document(document) => document,
+        bson::Bson::Array(items) => items.into_iter().enumerate().map(|(i, item)| (i.to_string(), item)).collect(),
+        other => bson::doc! { "value": other },
+    };
+    let mut bytes = Vec::new();
+    document.to_writer(&mut bytes).map_err(|e| e.to_string())?;
+    Ok(buffer(bytes))
+}
 
-        let inserted = inserted.unwrap();
+unsafe fn write_record(out: *mut hoardbase_record, format: hoardbase_format, record: Record) -> Result<(), Failure> {
+    if out.is_null() {
+        return Ok(());
+    }
+    let mut hash = [0 as c_char; 65];
+    for (target, byte) in hash.iter_mut().zip(record.hash.bytes().take(64)) {
+        *target = byte as c_char;
+    }
+    let data = encode(format, bson::Bson::Document(record.data))?;
+    *out = hoardbase_record { id: record.id, last_modified: record.last_modified.timestamp_millis(), hash, data };
+    Ok(())
+}
 
-        let json_ptr = serde_json2cpp_json(&inserted.data);
-        let hash_c_str = CString::new(inserted.hash.as_str()).unwrap();
-        let hash_c_str_ptr: *const c_char = hash_c_str.as_ptr() as *const c_char;
-        let inserted_ptr = create_cpp_record(inserted.id, json_ptr, hash_c_str_ptr, inserted.last_modified.timestamp() as u64);
-        free_json(json_ptr);
+/// Writes an optional record and returns the matching status.
+unsafe fn write_found(out: *mut hoardbase_record, format: hoardbase_format, record: Option<Record>) -> Result<hoardbase_status, Failure> {
+    match record {
+        Some(record) => {
+            write_record(out, format, record)?;
+            Ok(HOARDBASE_OK)
+        }
+        None => Ok(HOARDBASE_NOT_FOUND),
+    }
+}
 
-        return inserted_ptr as *mut c_void;
+/// `update_one()`, `replace_one()` and `find_one_and_delete()` fail with this rusqlite error when no document matches.
+fn not_found_as_none(result: Result<Option<Record>, String>) -> Result<Option<Record>, String> {
+    match result {
+        Err(e) if e == "Query returned no rows" => Ok(None),
+        other => other,
     }
-    else {
-        return null_mut();
+}
+
+unsafe fn write<T>(out: *mut T, value: T) {
+    if !out.is_null() {
+        *out = value;
     }
-    
 }
 
+impl hoardbase_collection {
+    fn with<T>(&self, f: impl FnOnce(&mut dyn CollectionTrait) -> Result<T, Failure>) -> Result<T, Failure> {
+        let name = self.name.to_str().unwrap();
+        match &self.target {
+            Target::Database(db) => {
+                let mut db = lock(db)?;
+                let mut collection = db.collection(name)?;
+                f(&mut collection)
+            }
+            Target::Transaction { tx, active } => {
+                if !active.load(Ordering::SeqCst) {
+                    return Err(Failure::argument("The transaction of this collection has ended"));
+                }
+                let tx = unsafe { &*(*tx as *const Transaction) };
+                let mut collection = tx.collection(name)?;
+                f(&mut collection)
+            }
+        }
+    }
+}
 
+/// Returns [`HOARDBASE_ABI_VERSION`] as compiled into the library, to be compared with the header's.
+#[no_mangle]
+pub extern "C" fn hoardbase_abi_version() -> u32 {
+    HOARDBASE_ABI_VERSION
+}
 
+/// Returns the version of the library, e.g. "0.1.0-alpha".
 #[no_mangle]
-pub unsafe extern "C" fn serde_json_map_new() -> *mut c_void {
-    let handle = Box::new(serde_json::Map::<String, serde_json::Value>::new());
-    let handle_ptr = Box::<serde_json::Map<String, serde_json::Value>>::into_raw(handle);
+pub extern "C" fn hoardbase_version() -> *const c_char {
+    concat!(env!("CARGO_PKG_VERSION"), "\0").as_ptr() as *const c_char
+}
 
-    return handle_ptr as *mut c_void;
+/// Returns the message of the last error of the calling thread, or an empty string if its last call succeeded. The string is valid until the
+/// next call on this thread.
+#[no_mangle]
+pub extern "C" fn hoardbase_last_error() -> *const c_char {
+    LAST_ERROR.with(|last_error| last_error.borrow().as_ptr())
 }
 
+/// Frees a buffer returned by hoardbase, and resets it. Freeing an empty buffer does nothing.
 #[no_mangle]
-pub unsafe extern "C" fn serde_json_map_drop(ptr: *mut c_void) {
-    let handle_ptr = Box::<serde_json::Map<String, serde_json::Value>>::from_raw(ptr as *mut serde_json::Map<String, serde_json::Value>);
+pub unsafe extern "C" fn hoardbase_buffer_free(buffer: *mut hoardbase_buffer) {
+    if let Some(buffer) = buffer.as_mut() {
+        if !buffer.data.is_null() {
+            drop(Box::from_raw(std::ptr::slice_from_raw_parts_mut(buffer.data, buffer.len + 1)));
+        }
+        buffer.data = std::ptr::null_mut();
+        buffer.len = 0;
+    }
 }
 
+/// Frees the document of a record.
 #[no_mangle]
-pub unsafe extern "C" fn serde_json_map_field_count(ptr: *mut c_void) -> usize {
-    let handle_ptr = Box::<serde_json::Map<String, serde_json::Value>>::from_raw(ptr as *mut serde_json::Map<String, serde_json::Value>);
+pub unsafe extern "C" fn hoardbase_record_free(record: *mut hoardbase_record) {
+    if let Some(record) = record.as_mut() {
+        hoardbase_buffer_free(&mut record.data);
+    }
+}
 
-    let count = handle_ptr.len() as usize;
+/// Opens the database file at `path`, creating it if needed, with a combination of the `HOARDBASE_OPEN_` flags. The path ":memory:" opens a
+/// private in-memory database.
+#[no_mangle]
+pub unsafe extern "C" fn hoardbase_open(path: *const c_char, flags: u32, out: *mut *mut hoardbase_database) -> hoardbase_status {
+    call(|| {
+        let path = string(path, "path")?;
+        reference(out, "out")?;
+        if flags & !(HOARDBASE_OPEN_READ_ONLY | HOARDBASE_OPEN_TRACE | HOARDBASE_OPEN_PROFILE) != 0 {
+            return Err(Failure::argument(format!("Unknown open flags {:#x}", flags)));
+        }
+        let mut config = if path == ":memory:" { DatabaseConfig::in_memory() } else { DatabaseConfig::new(path) };
+        config.read_only(flags & HOARDBASE_OPEN_READ_ONLY != 0).trace(flags & HOARDBASE_OPEN_TRACE != 0).profile(flags & HOARDBASE_OPEN_PROFILE != 0);
+        let db = Database::open(&config)?;
+        *out = Box::into_raw(Box::new(hoardbase_database { db: Arc::new(Mutex::new(db)) }));
+        Ok(HOARDBASE_OK)
+    })
+}
 
-    let handle_ptr = Box::<serde_json::Map<String, serde_json::Value>>::into_raw(handle_ptr);
+/// Frees a database handle. The database is closed once the collections obtained from it are freed too.
+#[no_mangle]
+pub unsafe extern "C" fn hoardbase_close(db: *mut hoardbase_database) {
+    if !db.is_null() {
+        drop(Box::from_raw(db));
+    }
+}
 
-    return count;
+/// Creates a collection, or returns it if it exists. `flags` is a combination of the `HOARDBASE_COLLECTION_` flags, and a capped collection is
+/// created if either limit isn't 0.
+#[no_mangle]
+pub unsafe extern "C" fn hoardbase_create_collection(db: *mut hoardbase_database, name: *const c_char, flags: u32, capped_max_documents: u64, capped_max_bytes: u64, out: *mut *mut hoardbase_collection) -> hoardbase_status {
+    call(|| {
+        let handle = reference(db, "db")?;
+        let name = string(name, "name")?;
+        reference(out, "out")?;
+        if flags & !(HOARDBASE_COLLECTION_HASH_DOCUMENT | HOARDBASE_COLLECTION_LOG_LAST_MODIFIED | HOARDBASE_COLLECTION_HASH_UNIQUE) != 0 {
+            return Err(Failure::argument(format!("Unknown collection flags {:#x}", flags)));
+        }
+        let mut config = hoardbase::base::CollectionConfig::default(name);
+        config
+            .hash_document(flags & HOARDBASE_COLLECTION_HASH_DOCUMENT != 0)
+            .log_last_modified(flags & HOARDBASE_COLLECTION_LOG_LAST_MODIFIED != 0)
+            .hash_unique(flags & HOARDBASE_COLLECTION_HASH_UNIQUE != 0)
+            .capped(capped_max_documents, capped_max_bytes);
+        lock(&handle.db)?.create_collection(name, &config)?;
+        *out = Box::into_raw(Box::new(hoardbase_collection { target: Target::Database(handle.db.clone()), name: CString::new(name).unwrap() }));
+        Ok(HOARDBASE_OK)
+    })
 }
-/*
+
+/// Returns an existing collection.
 #[no_mangle]
-pub unsafe extern "C" fn serde_json_map_key(ptr: *mut c_void, index: usize) -> CString {
-    let handle_ptr = Box::<serde_json::Map<String, serde_json::Value>>::from_raw(ptr as *mut serde_json::Map<String, serde_json::Value>);
+pub unsafe extern "C" fn hoardbase_get_collection(db: *mut hoardbase_database, name: *const c_char, out: *mut *mut hoardbase_collection) -> hoardbase_status {
+    call(|| {
+        let handle = reference(db, "db")?;
+        let name = string(name, "name")?;
+        reference(out, "out")?;
+        lock(&handle.db)?.collection(name)?;
+        *out = Box::into_raw(Box::new(hoardbase_collection { target: Target::Database(handle.db.clone()), name: CString::new(name).unwrap() }));
+        Ok(HOARDBASE_OK)
+    })
+}
 
-    let mut keys = handle_ptr.keys();
+/// Writes the sorted names of the collections to `out`, as a list of strings.
+#[no_mangle]
+pub unsafe extern "C" fn hoardbase_list_collections(db: *mut hoardbase_database, format: hoardbase_format, out: *mut hoardbase_buffer) -> hoardbase_status {
+    call(|| {
+        let handle = reference(db, "db")?;
+        check_format(format)?;
+        reference(out, "out")?;
+        let mut names: Vec<String> = lock(&handle.db)?.list_collections().into_iter().map(|(name, _)| name).collect();
+        names.sort();
+        *out = encode(format, bson::Bson::Array(names.into_iter().map(bson::Bson::String).collect()))?;
+        Ok(HOARDBASE_OK)
+    })
+}
 
-    let c_str = keys.nth(index).unwrap().as_str();
+#[no_mangle]
+pub unsafe extern "C" fn hoardbase_drop_collection(db: *mut hoardbase_database, name: *const c_char) -> hoardbase_status {
+    call(|| {
+        let handle = reference(db, "db")?;
+        let name = string(name, "name")?;
+        lock(&handle.db)?.drop_collection(name)?;
+        Ok(HOARDBASE_OK)
+    })
+}
 
-    let c_string = CString::new(&c_str[..]).unwrap() ;
-    
-    return c_string;
-}*/
+#[no_mangle]
+pub unsafe extern "C" fn hoardbase_rename_collection(db: *mut hoardbase_database, old_name: *const c_char, new_name: *const c_char) -> hoardbase_status {
+    call(|| {
+        let handle = reference(db, "db")?;
+        let old_name = string(old_name, "old_name")?;
+        let new_name = string(new_name, "new_name")?;
+        lock(&handle.db)?.rename_collection(old_name, new_name)?;
+        Ok(HOARDBASE_OK)
+    })
+}
 
+/// Runs `callback` in a transaction. Inside the callback, the collections obtained with [`hoardbase_transaction_collection()`] write to the
+/// transaction, and the database can't be used directly. The transaction is committed if the callback returns [`HOARDBASE_OK`]; otherwise it is
+/// rolled back, and its status is returned.
 #[no_mangle]
-pub unsafe extern "C" fn serde_json_map_iter_next(ptr: *mut c_void) -> *mut c_void {
-    let handle_ptr = ptr as *mut serde_json::map::Iter<'_>;
+pub unsafe extern "C" fn hoardbase_run_transaction(db: *mut hoardbase_database, callback: hoardbase_transaction_callback, user_data: *mut c_void) -> hoardbase_status {
+    call(|| {
+        let handle = reference(db, "db")?;
+        let callback = callback.ok_or_else(|| Failure::argument("callback is null"))?;
+        let mut guard = lock(&handle.db)?;
+        let mut failure = None;
+        TRANSACTION_DATABASE.with(|current| current.set(Arc::as_ptr(&handle.db)));
+        let result = guard.transaction(|tx| {
+            let mut transaction = hoardbase_transaction { tx: tx as *const Transaction as *const c_void, active: Arc::new(AtomicBool::new(true)) };
+            let status = callback(&mut transaction, user_data);
+            transaction.active.store(false, Ordering::SeqCst);
+            if status == HOARDBASE_OK {
+                return Ok(());
+            }
+            let message = LAST_ERROR.with(|last_error| last_error.borrow().to_string_lossy().into_owned());
+            failure = Some(Failure { status, message: if message.is_empty() { "The transaction callback failed".to_string() } else { message } });
+            Err("The transaction was rolled back")
+        });
+        TRANSACTION_DATABASE.with(|current| current.set(std::ptr::null()));
+        match (result, failure) {
+            (_, Some(failure)) => Err(failure),
+            (Err(e), None) => Err(Failure::from(e)),
+            (Ok(()), None) => Ok(HOARDBASE_OK),
+        }
+    })
+}
 
-    if let Some(item) = (*handle_ptr).next() {
-        let handle = Box::new(item);
-        let handle_ptr = Box::into_raw(handle);
+/// Returns a collection whose operations run in the transaction. It can only be used until the callback returns, and must still be freed.
+#[no_mangle]
+pub unsafe extern "C" fn hoardbase_transaction_collection(tx: *mut hoardbase_transaction, name: *const c_char, out: *mut *mut hoardbase_collection) -> hoardbase_status {
+    call(|| {
+        let transaction = reference(tx, "tx")?;
+        let name = string(name, "name")?;
+        reference(out, "out")?;
+        let collection = hoardbase_collection { target: Target::Transaction { tx: transaction.tx, active: transaction.active.clone() }, name: CString::new(name).unwrap() };
+        collection.with(|_| Ok(()))?;
+        *out = Box::into_raw(Box::new(collection));
+        Ok(HOARDBASE_OK)
+    })
+}
 
-        return handle_ptr as *mut c_void;
-    }
-    else {
-        return null_mut();
+#[no_mangle]
+pub unsafe extern "C" fn hoardbase_collection_free(collection: *mut hoardbase_collection) {
+    if !collection.is_null() {
+        drop(Box::from_raw(collection));
     }
 }
 
+/// Returns the name of the collection, valid until the collection is freed.
 #[no_mangle]
-pub unsafe extern "C" fn serde_json_map_item_print(ptr: *mut c_void) {
-    let handle_ptr = ptr as *mut <serde_json::map::Iter<'_> as Iterator>::Item;
-
-    println!("{:?}", *handle_ptr);
-
+pub unsafe extern "C" fn hoardbase_collection_name(collection: *const hoardbase_collection) -> *const c_char {
+    match collection.as_ref() {
+        Some(collection) => collection.name.as_ptr(),
+        None => std::ptr::null(),
+    }
 }
 
+/// Inserts a document, and writes the inserted record to `out` unless it is null.
 #[no_mangle]
-pub unsafe extern "C" fn serde_json_value_type(ptr: *mut c_void) -> JsonFieldType {
-    let handle_ptr = Box::<serde_json::Value>::from_raw(ptr as *mut serde_json::Value);
-
-    let value_type = match *handle_ptr {
-        serde_json::Value::Null => JsonFieldType::Null,
-        serde_json::Value::Bool(_) => JsonFieldType::Boolean,
-        serde_json::Value::Number(_) => JsonFieldType::Float,
-        serde_json::Value::String(_) => JsonFieldType::String,
-        serde_json::Value::Array(_) => JsonFieldType::Array,
-        serde_json::Value::Object(_) => JsonFieldType::Object,
-    };
-
-    let handle_ptr = Box::<serde_json::Value>::into_raw(handle_ptr);
+pub unsafe extern "C" fn hoardbase_collection_insert_one(collection: *mut hoardbase_collection, format: hoardbase_format, document: *const u8, document_len: usize, out: *mut hoardbase_record) -> hoardbase_status {
+    call(|| {
+        let collection = reference(collection, "collection")?;
+        let document = self::document(format, document, document_len, "document")?;
+        let record = collection.with(|c| Ok(c.insert_one(&document)?))?;
+        write_found(out, format, record)
+    })
+}
 
-    return value_type;
+/// Inserts a list of documents.
+#[no_mangle]
+pub unsafe extern "C" fn hoardbase_collection_insert_many(collection: *mut hoardbase_collection, format: hoardbase_format, documents: *const u8, documents_len: usize) -> hoardbase_status {
+    call(|| {
+        let collection = reference(collection, "collection")?;
+        let documents = self::documents(format, documents, documents_len, "documents")?;
+        collection.with(|c| Ok(c.insert_many(&documents)?))?;
+        Ok(HOARDBASE_OK)
+    })
 }
 
+/// Returns a cursor over the documents matching `query`, after skipping `skip` of them. A negative `limit` is no limit. The cursor writes its
+/// records in `format`, and is freed with [`hoardbase_cursor_free()`].
 #[no_mangle]
-pub unsafe extern "C" fn serde_json_vec_new() -> *mut c_void {
-    let handle = Box::new(Vec::<serde_json::Value>::new());
-    let handle_ptr = Box::<Vec<serde_json::Value>>::into_raw(handle);
+pub unsafe extern "C" fn hoardbase_collection_find(collection: *mut hoardbase_collection, format: hoardbase_format, query: *const u8, query_len: usize, limit: i64, skip: i64, out: *mut *mut hoardbase_cursor) -> hoardbase_status {
+    call(|| {
+        let collection = reference(collection, "collection")?;
+        let query = self::query(format, query, query_len)?;
+        reference(out, "out")?;
+        let cursor = hoardbase_cursor { collection: collection.clone(), format, query, remaining: if limit < 0 { None } else { Some(limit) }, skip: skip.max(0), batch: VecDeque::new(), exhausted: false };
+        *out = Box::into_raw(Box::new(cursor));
+        Ok(HOARDBASE_OK)
+    })
+}
 
-    return handle_ptr as *mut c_void;
+impl hoardbase_cursor {
+    fn fetch(&mut self) -> Result<(), Failure> {
+        let limit = self.remaining.map_or(CURSOR_BATCH_SIZE, |remaining| remaining.min(CURSOR_BATCH_SIZE));
+        if limit == 0 {
+            self.exhausted = true;
+            return Ok(());
+        }
+        let mut options = SearchOption::default();
+        options.limit(limit).skip(self.skip);
+        let (query, batch) = (&self.query, &mut self.batch);
+        self.collection.with(|c| {
+            Ok(c.find(query, &Some(options), &mut |r| {
+                batch.push_back(r.clone());
+                Ok(())
+            })?)
+        })?;
+        let fetched = batch.len() as i64;
+        self.skip += fetched;
+        self.remaining = self.remaining.map(|remaining| remaining - fetched);
+        self.exhausted = fetched < limit;
+        Ok(())
+    }
 }
 
+/// Writes the next record to `out`, or returns [`HOARDBASE_NOT_FOUND`] once all the records have been read. The documents are read in batches,
+/// so writes made while iterating may be seen by the later batches.
 #[no_mangle]
-pub unsafe extern "C" fn serde_json_vec_drop(ptr: *mut c_void) {
-    let handle_ptr = Box::<Vec<serde_json::Value>>::from_raw(ptr as *mut Vec<serde_json::Value>);
+pub unsafe extern "C" fn hoardbase_cursor_next(cursor: *mut hoardbase_cursor, out: *mut hoardbase_record) -> hoardbase_status {
+    call(|| {
+        let cursor = cursor.as_mut().ok_or_else(|| Failure::argument("cursor is null"))?;
+        reference(out, "out")?;
+        if cursor.batch.is_empty() && !cursor.exhausted {
+            cursor.fetch()?;
+        }
+        write_found(out, cursor.format, cursor.batch.pop_front())
+    })
 }
 
 #[no_mangle]
-pub unsafe extern "C" fn serde_json_value_drop(ptr: *mut c_void) {
-    let handle_ptr = ptr as *mut serde_json::Value;
-    let handle = Box::<serde_json::Value>::from_raw(handle_ptr);
-    println!("{:?}", handle);
+pub unsafe extern "C" fn hoardbase_cursor_free(cursor: *mut hoardbase_cursor) {
+    if !cursor.is_null() {
+        drop(Box::from_raw(cursor));
+    }
 }
 
+/// Writes the first document matching `query` after skipping `skip` of them to `out`, or returns [`HOARDBASE_NOT_FOUND`].
 #[no_mangle]
-pub unsafe extern "C" fn serde_json_value_debug_print(ptr: *mut c_void) {
-    let handle_ptr = ptr as *mut serde_json::Value;
-    let handle = Box::<serde_json::Value>::from_raw(handle_ptr);
-    println!("{:?}", handle);
-
-    let handle_ptr = Box::<serde_json::Value>::into_raw(handle);
+pub unsafe extern "C" fn hoardbase_collection_find_one(collection: *mut hoardbase_collection, format: hoardbase_format, query: *const u8, query_len: usize, skip: i64, out: *mut hoardbase_record) -> hoardbase_status {
+    call(|| {
+        let collection = reference(collection, "collection")?;
+        let query = self::query(format, query, query_len)?;
+        let mut options = SearchOption::default();
+        options.limit(1).skip(skip);
+        let mut found = None;
+        collection.with(|c| {
+            Ok(c.find(&query, &Some(options), &mut |r| {
+                found = Some(r.clone());
+                Ok(())
+            })?)
+        })?;
+        write_found(out, format, found)
+    })
 }
 
+/// Counts the documents matching `query`. A negative `limit` is no limit.
 #[no_mangle]
-pub unsafe extern "C" fn serde_json_map_debug_print(ptr: *mut c_void) {
-    let handle_ptr = ptr as *mut serde_json::Map<String, serde_json::Value>;
-    let handle = Box::<serde_json::Map<String, serde_json::Value>>::from_raw(handle_ptr);
-    println!("{:?}", handle);
+pub unsafe extern "C" fn hoardbase_collection_count_documents(collection: *mut hoardbase_collection, format: hoardbase_format, query: *const u8, query_len: usize, limit: i64, skip: i64, out: *mut i64) -> hoardbase_status {
+    call(|| {
+        let collection = reference(collection, "collection")?;
+        let query = self::query(format, query, query_len)?;
+        reference(out, "out")?;
+        let mut options = SearchOption::default();
+        options.limit(limit).skip(skip);
+        *out = collection.with(|c| Ok(c.count_documents(&query, &Some(options))?))?;
+        Ok(HOARDBASE_OK)
+    })
+}
 
-    let handle_ptr = Box::<serde_json::Map<String, serde_json::Value>>::into_raw(handle);
+/// Counts the distinct values of `field` among the documents matching `query`.
+#[no_mangle]
+pub unsafe extern "C" fn hoardbase_collection_distinct(collection: *mut hoardbase_collection, format: hoardbase_format, field: *const c_char, query: *const u8, query_len: usize, out: *mut i64) -> hoardbase_status {
+    call(|| {
+        let collection = reference(collection, "collection")?;
+        let field = string(field, "field")?;
+        let query = if query.is_null() { None } else { Some(self::query(format, query, query_len)?) };
+        reference(out, "out")?;
+        *out = collection.with(|c| Ok(c.distinct(field, &query, &None)?))?;
+        Ok(HOARDBASE_OK)
+    })
 }
 
+/// Updates the first document matching `query` after skipping `skip` of them, and writes the updated record to `out` unless it is null. With
+/// `upsert`, a document is inserted if none matches. `array_filters`, a list of documents, may be null. Returns [`HOARDBASE_NOT_FOUND`] if no
+/// document was updated.
 #[no_mangle]
-pub unsafe extern "C" fn serde_json_vec_debug_print(ptr: *mut c_void) {
-    let handle_ptr = ptr as *mut Vec<serde_json::Value>;
-    let handle = Box::<Vec<serde_json::Value>>::from_raw(handle_ptr);
-    println!("{:?}", handle);
+pub unsafe extern "C" fn hoardbase_collection_update_one(
+    collection: *mut hoardbase_collection,
+    format: hoardbase_format,
+    query: *const u8,
+    query_len: usize,
+    update: *const u8,
+    update_len: usize,
+    skip: i64,
+    upsert: bool,
+    array_filters: *const u8,
+    array_filters_len: usize,
+    out: *mut hoardbase_record,
+) -> hoardbase_status {
+    call(|| {
+        let collection = reference(collection, "collection")?;
+        let query = self::query(format, query, query_len)?;
+        let update = document(format, update, update_len, "update")?;
+        let options = update_option(format, array_filters, array_filters_len)?;
+        let record = collection.with(|c| Ok(not_found_as_none(c.update_one(&query, &update, skip, upsert, &options))?))?;
+        write_found(out, format, record)
+    })
+}
 
-    let handle_ptr = Box::<Vec<serde_json::Value>>::into_raw(handle);
+/// Like [`hoardbase_collection_update_one()`], with an aggregation pipeline, a list of stages, instead of an update document.
+#[no_mangle]
+pub unsafe extern "C" fn hoardbase_collection_update_one_pipeline(collection: *mut hoardbase_collection, format: hoardbase_format, query: *const u8, query_len: usize, pipeline: *const u8, pipeline_len: usize, skip: i64, upsert: bool, out: *mut hoardbase_record) -> hoardbase_status {
+    call(|| {
+        let collection = reference(collection, "collection")?;
+        let query = self::query(format, query, query_len)?;
+        let pipeline = documents(format, pipeline, pipeline_len, "pipeline")?;
+        let record = collection.with(|c| Ok(not_found_as_none(c.update_one_pipeline(&query, &pipeline, skip, upsert))?))?;
+        write_found(out, format, record)
+    })
 }
 
+/// Updates the documents matching `query`, and writes how many were updated to `out` unless it is null. A `limit` of 0 is no limit.
 #[no_mangle]
-pub unsafe extern "C" fn serde_json_map2value(ptr: *mut c_void) -> *mut c_void {
-    let handle_ptr = ptr as *mut serde_json::Map<String, serde_json::Value>;
-    let handle = Box::<serde_json::Map<String, serde_json::Value>>::from_raw(handle_ptr);
-    let value = serde_json::to_value(handle).unwrap();
-    let value_ptr = Box::<serde_json::Value>::into_raw(Box::new(value));
-    return value_ptr as *mut c_void;
+pub unsafe extern "C" fn hoardbase_collection_update_many(
+    collection: *mut hoardbase_collection,
+    format: hoardbase_format,
+    query: *const u8,
+    query_len: usize,
+    update: *const u8,
+    update_len: usize,
+    limit: i64,
+    skip: i64,
+    upsert: bool,
+    array_filters: *const u8,
+    array_filters_len: usize,
+    out: *mut i64,
+) -> hoardbase_status {
+    call(|| {
+        let collection = reference(collection, "collection")?;
+        let query = self::query(format, query, query_len)?;
+        let update = document(format, update, update_len, "update")?;
+        let options = update_option(format, array_filters, array_filters_len)?;
+        let count = collection.with(|c| Ok(c.update_many(&query, &update, limit, skip, upsert, &options)?))?;
+        write(out, count);
+        Ok(HOARDBASE_OK)
+    })
 }
 
+/// Like [`hoardbase_collection_update_many()`], with an aggregation pipeline instead of an update document.
 #[no_mangle]
-pub unsafe extern "C" fn serde_json_vec2value(ptr: *mut c_void) -> *mut c_void {
-    let handle_ptr = ptr as *mut Vec<serde_json::Value>;
-    let handle = Box::<Vec<serde_json::Value>>::from_raw(handle_ptr);
-    let value = serde_json::to_value(handle).unwrap();
-    let value_ptr = Box::<serde_json::Value>::into_raw(Box::new(value));
-    return value_ptr as *mut c_void;
+pub unsafe extern "C" fn hoardbase_collection_update_many_pipeline(collection: *mut hoardbase_collection, format: hoardbase_format, query: *const u8, query_len: usize, pipeline: *const u8, pipeline_len: usize, limit: i64, skip: i64, upsert: bool, out: *mut i64) -> hoardbase_status {
+    call(|| {
+        let collection = reference(collection, "collection")?;
+        let query = self::query(format, query, query_len)?;
+        let pipeline = documents(format, pipeline, pipeline_len, "pipeline")?;
+        let count = collection.with(|c| Ok(c.update_many_pipeline(&query, &pipeline, limit, skip, upsert)?))?;
+        write(out, count);
+        Ok(HOARDBASE_OK)
+    })
 }
 
+/// Replaces the first document matching `query` after skipping `skip` of them, and writes the new record to `out` unless it is null. Returns
+/// [`HOARDBASE_NOT_FOUND`] if no document matched.
 #[no_mangle]
-pub unsafe extern "C" fn serde_json_i642value(val: i64) -> *mut c_void {
-    let value = serde_json::to_value(val).unwrap();
-    let value_ptr = Box::<serde_json::Value>::into_raw(Box::new(value));
-    return value_ptr as *mut c_void;
+pub unsafe extern "C" fn hoardbase_collection_replace_one(collection: *mut hoardbase_collection, format: hoardbase_format, query: *const u8, query_len: usize, replacement: *const u8, replacement_len: usize, skip: i64, out: *mut hoardbase_record) -> hoardbase_status {
+    call(|| {
+        let collection = reference(collection, "collection")?;
+        let query = self::query(format, query, query_len)?;
+        let replacement = document(format, replacement, replacement_len, "replacement")?;
+        let record = collection.with(|c| Ok(not_found_as_none(c.replace_one(&query, &replacement, skip))?))?;
+        write_found(out, format, record)
+    })
 }
 
+/// Deletes the first document matching `query`, and writes how many were deleted, 0 or 1, to `out` unless it is null.
 #[no_mangle]
-pub unsafe extern "C" fn serde_json_str2value(val: *const c_char) -> *mut c_void {
-    let c_str: &CStr =  CStr::from_ptr(val) ;
-    let str_slice: &str = c_str.to_str().unwrap();
-    let value = serde_json::to_value(str_slice).unwrap();
-    let value_ptr = Box::<serde_json::Value>::into_raw(Box::new(value));
-    return value_ptr as *mut c_void;
+pub unsafe extern "C" fn hoardbase_collection_delete_one(collection: *mut hoardbase_collection, format: hoardbase_format, query: *const u8, query_len: usize, out: *mut i64) -> hoardbase_status {
+    call(|| {
+        let collection = reference(collection, "collection")?;
+        let query = self::query(format, query, query_len)?;
+        let count = collection.with(|c| Ok(c.delete_one(&query)?))?;
+        write(out, count as i64);
+        Ok(HOARDBASE_OK)
+    })
 }
 
+/// Deletes the documents matching `query`, and writes how many were deleted to `out` unless it is null.
 #[no_mangle]
-pub unsafe extern "C" fn serde_json_bool2value(val: bool) -> *mut c_void {
-    let value = serde_json::to_value(val).unwrap();
-    let value_ptr = Box::<serde_json::Value>::into_raw(Box::new(value));
-    return value_ptr as *mut c_void;
+pub unsafe extern "C" fn hoardbase_collection_delete_many(collection: *mut hoardbase_collection, format: hoardbase_format, query: *const u8, query_len: usize, out: *mut i64) -> hoardbase_status {
+    call(|| {
+        let collection = reference(collection, "collection")?;
+        let query = self::query(format, query, query_len)?;
+        let count = collection.with(|c| Ok(c.delete_many(&query)?))?;
+        write(out, count as i64);
+        Ok(HOARDBASE_OK)
+    })
 }
 
+/// Deletes the first document matching `query` and writes it to `out` unless it is null, or returns [`HOARDBASE_NOT_FOUND`].
 #[no_mangle]
-pub unsafe extern "C" fn serde_json_f642value(val: f64) -> *mut c_void {
-    let value = serde_json::to_value(val).unwrap();
-    let value_ptr = Box::<serde_json::Value>::into_raw(Box::new(value));
-    return value_ptr as *mut c_void;
+pub unsafe extern "C" fn hoardbase_collection_find_one_and_delete(collection: *mut hoardbase_collection, format: hoardbase_format, query: *const u8, query_len: usize, out: *mut hoardbase_record) -> hoardbase_status {
+    call(|| {
+        let collection = reference(collection, "collection")?;
+        let query = self::query(format, query, query_len)?;
+        let record = collection.with(|c| Ok(not_found_as_none(c.find_one_and_delete(&query))?))?;
+        write_found(out, format, record)
+    })
 }
 
+/// Creates an index. `keys` is a document of the indexed fields and their order, 1 or -1, e.g. `{"age": 1, "name": -1}`.
 #[no_mangle]
-pub unsafe extern "C" fn serde_json_null2value() -> *mut c_void {
-    let value = serde_json::to_value(serde_json::Value::Null).unwrap();
-    let value_ptr = Box::<serde_json::Value>::into_raw(Box::new(value));
-    return value_ptr as *mut c_void;
+pub unsafe extern "C" fn hoardbase_collection_create_index(collection: *mut hoardbase_collection, format: hoardbase_format, keys: *const u8, keys_len: usize, unique: bool) -> hoardbase_status {
+    call(|| {
+        let collection = reference(collection, "collection")?;
+        let keys = document(format, keys, keys_len, "keys")?;
+        collection.with(|c| Ok(c.create_index(&keys, unique)?))?;
+        Ok(HOARDBASE_OK)
+    })
 }
 
 #[no_mangle]
-pub unsafe extern "C" fn serde_json_map_insert(map: *mut c_void, key: *const c_char, val: *mut c_void) -> *mut c_void {
-    let value_ptr = val as *mut serde_json::Value;
-    let value = Box::<serde_json::Value>::from_raw(value_ptr);
-    let map_ptr = map as *mut serde_json::Map<String, serde_json::Value>;
-    let c_str: &CStr =  CStr::from_ptr(key) ;
-    let str_slice: &str = c_str.to_str().unwrap();
-    
-    (*map_ptr).insert(str_slice.to_string(), *value.clone());
-    let map = Box::<serde_json::Map<String, serde_json::Value>>::from_raw(map_ptr);
+pub unsafe extern "C" fn hoardbase_collection_drop_index(collection: *mut hoardbase_collection, name: *const c_char) -> hoardbase_status {
+    call(|| {
+        let collection = reference(collection, "collection")?;
+        let name = string(name, "name")?;
+        collection.with(|c| Ok(c.drop_index(name)?))?;
+        Ok(HOARDBASE_OK)
+    })
+}
 
-    return Box::<serde_json::Map<String, serde_json::Value>>::into_raw(map) as *mut c_void;
+/// Writes the indexes to `out`, as a list of documents with the fields `name`, `unique` and `partial`.
+#[no_mangle]
+pub unsafe extern "C" fn hoardbase_collection_get_indexes(collection: *mut hoardbase_collection, format: hoardbase_format, out: *mut hoardbase_buffer) -> hoardbase_status {
+    call(|| {
+        let collection = reference(collection, "collection")?;
+        check_format(format)?;
+        reference(out, "out")?;
+        let indexes = collection.with(|c| Ok(c.get_indexes()?))?;
+        let indexes = indexes.into_iter().map(|index| bson::Bson::Document(bson::doc! { "name": index.name, "unique": index.is_unique, "partial": index.is_partial })).collect();
+        *out = encode(format, bson::Bson::Array(indexes))?;
+        Ok(HOARDBASE_OK)
+    })
 }
 
 #[no_mangle]
-pub unsafe extern "C" fn serde_json_vec_push(vec: *mut c_void, val: *mut c_void) -> *mut c_void {
-    let value_ptr = val as *mut serde_json::Value;
-    let value = Box::<serde_json::Value>::from_raw(value_ptr);
-    let vec_ptr = vec as *mut Vec<serde_json::Value>;
-    (*vec_ptr).push(*value.clone());
-    let vec = Box::<Vec<serde_json::Value>>::from_raw(vec_ptr);
-    return Box::<Vec<serde_json::Value>>::into_raw(vec) as *mut c_void;
+pub unsafe extern "C" fn hoardbase_collection_reindex(collection: *mut hoardbase_collection) -> hoardbase_status {
+    call(|| {
+        let collection = reference(collection, "collection")?;
+        collection.with(|c| Ok(c.reindex()?))?;
+        Ok(HOARDBASE_OK)
+    })
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::ptr::{null, null_mut};
 
-unsafe fn serde_json2cpp_json(json: &serde_json::Value) -> *mut c_void {
-    let result = create_json();
+    fn c(s: &str) -> CString {
+        CString::new(s).unwrap()
+    }
 
-    for (key, value) in json.as_object().unwrap().iter() {
-        let key_c_str = CString::new(key.as_str()).unwrap();
-        let key_c_str_ptr: *const c_char = key_c_str.as_ptr() as *const c_char;
+    fn last_error() -> String {
+        unsafe { CStr::from_ptr(hoardbase_last_error()).to_string_lossy().into_owned() }
+    }
 
-        match value {
-            serde_json::Value::Null => {
-                insert_null(result, key_c_str_ptr);
-            },
-            serde_json::Value::Bool(val) => {
-                insert_bool(result, key_c_str_ptr, *val);
-            },
-            serde_json::Value::Number(val) => {
-                if let Some(i64val) = val.as_i64() {
-                    insert_i64(result, key_c_str_ptr, i64val);
-                } else if let Some(f64val) = val.as_f64() {
-                    insert_f64(result, key_c_str_ptr, f64val);
-                } else {
-                    panic!("unsupported number type");
-                }
-            },
-            serde_json::Value::String(val) => {
-                let val_c_str = CString::new(val.as_str()).unwrap();
-                let val_c_str_ptr: *const c_char = val_c_str.as_ptr() as *const c_char;
-                insert_str(result, key_c_str_ptr, val_c_str_ptr);
-            },
-            serde_json::Value::Array(val) => {
-                let vec_ptr = serde_json2cpp_array(val);
-
-                insert_obj(result, key_c_str_ptr, vec_ptr);
-
-                free_json(vec_ptr);
-            },
-            serde_json::Value::Object(val) => {
-                let map_ptr = serde_json2cpp_json(&value);
-                insert_obj(result, key_c_str_ptr, map_ptr);
-
-                free_json(map_ptr);
-            },
-        }
+    fn empty_record() -> hoardbase_record {
+        hoardbase_record { id: 0, last_modified: 0, hash: [0; 65], data: hoardbase_buffer { data: null_mut(), len: 0 } }
     }
 
-    return result;
-}
+    fn json(buffer: &hoardbase_buffer) -> serde_json::Value {
+        let bytes = unsafe { std::slice::from_raw_parts(buffer.data, buffer.len) };
+        serde_json::from_slice(bytes).unwrap()
+    }
 
-unsafe fn serde_json2cpp_array(arr: &Vec<serde_json::Value>) -> *mut c_void {
-    let result = create_json_array();
+    unsafe fn open() -> (*mut hoardbase_database, *mut hoardbase_collection) {
+        let mut db = null_mut();
+        assert_eq!(hoardbase_open(c(":memory:").as_ptr(), 0, &mut db), HOARDBASE_OK);
+        let mut collection = null_mut();
+        assert_eq!(hoardbase_create_collection(db, c("fruits").as_ptr(), HOARDBASE_COLLECTION_DEFAULT, 0, 0, &mut collection), HOARDBASE_OK);
+        (db, collection)
+    }
 
-    for value in arr.iter() {
-        match value {
-            serde_json::Value::Null => {
-                array_push_null(result);
-            },
-            serde_json::Value::Bool(val) => {
-                array_push_bool(result, *val);
-            },
-            serde_json::Value::Number(val) => {
-                if let Some(i64val) = val.as_i64() {
-                    array_push_i64(result, i64val);
-                } else if let Some(f64val) = val.as_f64() {
-                    array_push_f64(result, f64val);
-                } else {
-                    panic!("unsupported number type");
-                }
-            },
-            serde_json::Value::String(val) => {
-                let val_c_str = CString::new(val.as_str()).unwrap();
-                let val_c_str_ptr: *const c_char = val_c_str.as_ptr() as *const c_char;
-                array_push_str(result, val_c_str_ptr);
-            },
-            serde_json::Value::Array(val) => {
-                
-                let vec_ptr = serde_json2cpp_array(val);
-
-                array_push_obj(result, vec_ptr);
-
-                free_json(vec_ptr);
-            },
-            serde_json::Value::Object(val) => {
-                let map_ptr = serde_json2cpp_json(&value);
-                array_push_obj(result, map_ptr);
-                free_json(map_ptr);
-            },
-        }
+    macro_rules! j {
+        ($($json:tt)+) => {{
+            let text = serde_json::json!($($json)+).to_string();
+            text.into_bytes()
+        }};
     }
 
-    return result;
-}
+    #[test]
+    fn test_documents_and_queries() {
+        unsafe {
+            let (db, collection) = open();
+            let document = j!({"kind": "apple", "qty": 5, "when": {"$date": "2021-12-24T10:30:00Z"}});
+            let mut record = empty_record();
+            assert_eq!(hoardbase_collection_insert_one(collection, HOARDBASE_FORMAT_JSON, document.as_ptr(), document.len(), &mut record), HOARDBASE_OK);
+            assert_eq!(record.id, 1);
+            assert_eq!(CStr::from_ptr(record.hash.as_ptr()).to_bytes().len(), 64);
+            assert!(record.last_modified > 0);
+            assert_eq!(json(&record.data), serde_json::json!({"kind": "apple", "qty": 5, "when": {"$date": "2021-12-24T10:30:00Z"}}));
+            assert_eq!(*record.data.data.add(record.data.len), 0);
+            hoardbase_record_free(&mut record);
+            assert!(record.data.data.is_null());
+
+            let many = j!([{"kind": "pear", "qty": 2}, {"kind": "plum", "qty": 7}]);
+            assert_eq!(hoardbase_collection_insert_many(collection, HOARDBASE_FORMAT_JSON, many.as_ptr(), many.len()), HOARDBASE_OK);
+
+            // bson in, bson out.
+            let mut bytes = Vec::new();
+            bson::doc! { "kind": "pear" }.to_writer(&mut bytes).unwrap();
+            let mut found = empty_record();
+            assert_eq!(hoardbase_collection_find_one(collection, HOARDBASE_FORMAT_BSON, bytes.as_ptr(), bytes.len(), 0, &mut found), HOARDBASE_OK);
+            let data = bson::Document::from_reader(std::slice::from_raw_parts(found.data.data, found.data.len)).unwrap();
+            assert_eq!(data, bson::doc! { "kind": "pear", "qty": 2 });
+            hoardbase_record_free(&mut found);
+
+            let missing = j!({"kind": "fig"});
+            assert_eq!(hoardbase_collection_find_one(collection, HOARDBASE_FORMAT_JSON, missing.as_ptr(), missing.len(), 0, &mut found), HOARDBASE_NOT_FOUND);
+            assert_eq!(last_error(), "");
+
+            let query = j!({"qty": {"$gt": 3}});
+            let mut count = 0;
+            assert_eq!(hoardbase_collection_count_documents(collection, HOARDBASE_FORMAT_JSON, query.as_ptr(), query.len(), -1, 0, &mut count), HOARDBASE_OK);
+            assert_eq!(count, 2);
+            assert_eq!(hoardbase_collection_count_documents(collection, HOARDBASE_FORMAT_JSON, null(), 0, -1, 0, &mut count), HOARDBASE_OK);
+            assert_eq!(count, 3);
+            assert_eq!(hoardbase_collection_distinct(collection, HOARDBASE_FORMAT_JSON, c("kind").as_ptr(), null(), 0, &mut count), HOARDBASE_OK);
+            assert_eq!(count, 3);
+
+            hoardbase_collection_free(collection);
+            hoardbase_close(db);
+        }
+    }
 
-#[no_mangle]
-pub unsafe extern "C" fn call_cpp_test() {
-    println!("call_cpp_test");
-    test_print();
+    #[test]
+    fn test_cursors() {
+        unsafe {
+            let (db, collection) = open();
+            let documents: Vec<serde_json::Value> = (0..250).map(|i| serde_json::json!({ "n": i })).collect();
+            let documents = serde_json::to_vec(&documents).unwrap();
+            assert_eq!(hoardbase_collection_insert_many(collection, HOARDBASE_FORMAT_JSON, documents.as_ptr(), documents.len()), HOARDBASE_OK);
+
+            let read = |limit: i64, skip: i64| -> Vec<i64> {
+                let mut cursor = null_mut();
+                assert_eq!(hoardbase_collection_find(collection, HOARDBASE_FORMAT_JSON, null(), 0, limit, skip, &mut cursor), HOARDBASE_OK);
+                let mut values = Vec::new();
+                let mut record = empty_record();
+                while hoardbase_cursor_next(cursor, &mut record) == HOARDBASE_OK {
+                    values.push(json(&record.data)["n"].as_i64().unwrap());
+                    hoardbase_record_free(&mut record);
+                }
+                assert_eq!(hoardbase_cursor_next(cursor, &mut record), HOARDBASE_NOT_FOUND);
+                hoardbase_cursor_free(cursor);
+                values
+            };
+            assert_eq!(read(-1, 0), (0..250).collect::<Vec<_>>());
+            assert_eq!(read(120, 90), (90..210).collect::<Vec<_>>());
+            assert_eq!(read(0, 0), Vec::<i64>::new());
+
+            // the collection outlives the database handle.
+            hoardbase_close(db);
+            assert_eq!(read(2, 0), vec![0, 1]);
+            hoardbase_collection_free(collection);
+        }
+    }
 
+    #[test]
+    fn test_updates_and_deletes() {
+        unsafe {
+            let (db, collection) = open();
+            let documents = j!([{"kind": "apple", "qty": 5, "sizes": [1, 4]}, {"kind": "pear", "qty": 2}]);
+            hoardbase_collection_insert_many(collection, HOARDBASE_FORMAT_JSON, documents.as_ptr(), documents.len());
+
+            let query = j!({"kind": "apple"});
+            let update = j!({"$set": {"sizes.$[s]": 0}});
+            let filters = j!([{"s": {"$gt": 3}}]);
+            let mut record = empty_record();
+            assert_eq!(hoardbase_collection_update_one(collection, HOARDBASE_FORMAT_JSON, query.as_ptr(), query.len(), update.as_ptr(), update.len(), 0, false, filters.as_ptr(), filters.len(), &mut record), HOARDBASE_OK);
+            assert_eq!(json(&record.data)["sizes"], serde_json::json!([1, 0]));
+            hoardbase_record_free(&mut record);
+
+            let missing = j!({"kind": "fig"});
+            let set = j!({"$set": {"qty": 1}});
+            assert_eq!(hoardbase_collection_update_one(collection, HOARDBASE_FORMAT_JSON, missing.as_ptr(), missing.len(), set.as_ptr(), set.len(), 0, false, null(), 0, null_mut()), HOARDBASE_NOT_FOUND);
+            assert_eq!(hoardbase_collection_update_one(collection, HOARDBASE_FORMAT_JSON, missing.as_ptr(), missing.len(), set.as_ptr(), set.len(), 0, true, null(), 0, &mut record), HOARDBASE_OK);
+            assert_eq!(json(&record.data), serde_json::json!({"kind": "fig", "qty": 1}));
+            hoardbase_record_free(&mut record);
+
+            let pipeline = j!([{"$set": {"total": {"$multiply": ["$qty", 2]}}}]);
+            let mut count = 0;
+            assert_eq!(hoardbase_collection_update_many_pipeline(collection, HOARDBASE_FORMAT_JSON, null(), 0, pipeline.as_ptr(), pipeline.len(), 0, 0, false, &mut count), HOARDBASE_OK);
+            assert_eq!(count, 3);
+            let inc = j!({"$inc": {"qty": 1}});
+            assert_eq!(hoardbase_collection_update_many(collection, HOARDBASE_FORMAT_JSON, null(), 0, inc.as_ptr(), inc.len(), 0, 0, false, null(), 0, &mut count), HOARDBASE_OK);
+            assert_eq!(count, 3);
+
+            let replacement = j!({"kind": "fig", "qty": 9});
+            assert_eq!(hoardbase_collection_replace_one(collection, HOARDBASE_FORMAT_JSON, missing.as_ptr(), missing.len(), replacement.as_ptr(), replacement.len(), 0, null_mut()), HOARDBASE_OK);
+            let plum = j!({"kind": "plum"});
+            assert_eq!(hoardbase_collection_replace_one(collection, HOARDBASE_FORMAT_JSON, plum.as_ptr(), plum.len(), replacement.as_ptr(), replacement.len(), 0, null_mut()), HOARDBASE_NOT_FOUND);
+
+            assert_eq!(hoardbase_collection_find_one_and_delete(collection, HOARDBASE_FORMAT_JSON, missing.as_ptr(), missing.len(), &mut record), HOARDBASE_OK);
+            assert_eq!(json(&record.data)["qty"], 9);
+            hoardbase_record_free(&mut record);
+            assert_eq!(hoardbase_collection_find_one_and_delete(collection, HOARDBASE_FORMAT_JSON, missing.as_ptr(), missing.len(), &mut record), HOARDBASE_NOT_FOUND);
+            assert_eq!(hoardbase_collection_delete_one(collection, HOARDBASE_FORMAT_JSON, query.as_ptr(), query.len(), &mut count), HOARDBASE_OK);
+            assert_eq!(count, 1);
+            assert_eq!(hoardbase_collection_delete_many(collection, HOARDBASE_FORMAT_JSON, null(), 0, &mut count), HOARDBASE_OK);
+            assert_eq!(count, 1);
+
+            hoardbase_collection_free(collection);
+            hoardbase_close(db);
+        }
+    }
 
-    println!("call_cpp_test craft a json from rs");
-    let c_str = CString::new("test_field").unwrap();
-    let c_world: *const c_char = c_str.as_ptr() as *const c_char;
+    #[test]
+    fn test_errors() {
+        unsafe {
+            let (db, collection) = open();
+            let mut missing = null_mut();
+            assert_eq!(hoardbase_get_collection(db, c("missing").as_ptr(), &mut missing), HOARDBASE_ERROR_OPERATION);
+            assert!(!last_error().is_empty());
+            assert!(missing.is_null());
+
+            assert_eq!(hoardbase_collection_insert_one(collection, HOARDBASE_FORMAT_JSON, null(), 0, null_mut()), HOARDBASE_ERROR_INVALID_ARGUMENT);
+            assert_eq!(last_error(), "document is null");
+            let broken = b"{\"kind\": ";
+            assert_eq!(hoardbase_collection_insert_one(collection, HOARDBASE_FORMAT_JSON, broken.as_ptr(), broken.len(), null_mut()), HOARDBASE_ERROR_INVALID_DOCUMENT);
+            let array = j!([1]);
+            assert_eq!(hoardbase_collection_insert_one(collection, HOARDBASE_FORMAT_JSON, array.as_ptr(), array.len(), null_mut()), HOARDBASE_ERROR_INVALID_DOCUMENT);
+            assert_eq!(hoardbase_collection_insert_one(collection, 7, array.as_ptr(), array.len(), null_mut()), HOARDBASE_ERROR_INVALID_ARGUMENT);
+
+            let bad_query = j!({"qty": {"$gtee": 1}});
+            let mut count = 0;
+            assert_eq!(hoardbase_collection_count_documents(collection, HOARDBASE_FORMAT_JSON, bad_query.as_ptr(), bad_query.len(), -1, 0, &mut count), HOARDBASE_ERROR_OPERATION);
+
+            // a successful call clears the error.
+            assert_eq!(hoardbase_collection_count_documents(collection, HOARDBASE_FORMAT_JSON, null(), 0, -1, 0, &mut count), HOARDBASE_OK);
+            assert_eq!(last_error(), "");
+
+            let mut db2 = null_mut();
+            assert_eq!(hoardbase_open(c(":memory:").as_ptr(), 64, &mut db2), HOARDBASE_ERROR_INVALID_ARGUMENT);
+            assert_eq!(hoardbase_abi_version(), HOARDBASE_ABI_VERSION);
+            assert_eq!(CStr::from_ptr(hoardbase_version()).to_str().unwrap(), env!("CARGO_PKG_VERSION"));
+
+            hoardbase_collection_free(collection);
+            hoardbase_close(db);
+        }
+    }
 
-    let json = json!({
-        "test_field": "test_value",
-        "test_field2": 2,
-    });
+    #[test]
+    fn test_collections_and_indexes() {
+        unsafe {
+            let (db, collection) = open();
+            let keys = j!({"kind": 1});
+            assert_eq!(hoardbase_collection_create_index(collection, HOARDBASE_FORMAT_JSON, keys.as_ptr(), keys.len(), true), HOARDBASE_OK);
+            let mut buffer = hoardbase_buffer { data: null_mut(), len: 0 };
+            assert_eq!(hoardbase_collection_get_indexes(collection, HOARDBASE_FORMAT_JSON, &mut buffer), HOARDBASE_OK);
+            let indexes = json(&buffer);
+            hoardbase_buffer_free(&mut buffer);
+            assert!(indexes.as_array().unwrap().contains(&serde_json::json!({"name": "fruits_kind", "unique": true, "partial": false})), "{}", indexes);
+
+            let apple = j!({"kind": "apple"});
+            assert_eq!(hoardbase_collection_insert_one(collection, HOARDBASE_FORMAT_JSON, apple.as_ptr(), apple.len(), null_mut()), HOARDBASE_OK);
+            assert_eq!(hoardbase_collection_insert_one(collection, HOARDBASE_FORMAT_JSON, apple.as_ptr(), apple.len(), null_mut()), HOARDBASE_ERROR_OPERATION);
+            assert_eq!(hoardbase_collection_reindex(collection), HOARDBASE_OK);
+            assert_eq!(hoardbase_collection_drop_index(collection, c("fruits_kind").as_ptr()), HOARDBASE_OK);
+            assert_eq!(hoardbase_collection_insert_one(collection, HOARDBASE_FORMAT_JSON, apple.as_ptr(), apple.len(), null_mut()), HOARDBASE_OK);
+            assert_eq!(CStr::from_ptr(hoardbase_collection_name(collection)).to_str().unwrap(), "fruits");
+
+            let mut other = null_mut();
+            assert_eq!(hoardbase_create_collection(db, c("vegetables").as_ptr(), 0, 0, 0, &mut other), HOARDBASE_OK);
+            hoardbase_collection_free(other);
+            assert_eq!(hoardbase_rename_collection(db, c("vegetables").as_ptr(), c("greens").as_ptr()), HOARDBASE_OK);
+            assert_eq!(hoardbase_list_collections(db, HOARDBASE_FORMAT_BSON, &mut buffer), HOARDBASE_OK);
+            let names = bson::Document::from_reader(std::slice::from_raw_parts(buffer.data, buffer.len)).unwrap();
+            hoardbase_buffer_free(&mut buffer);
+            assert_eq!(names, bson::doc! { "0": "fruits", "1": "greens" });
+            assert_eq!(hoardbase_drop_collection(db, c("greens").as_ptr()), HOARDBASE_OK);
+            assert_eq!(hoardbase_get_collection(db, c("greens").as_ptr(), &mut other), HOARDBASE_ERROR_OPERATION);
+
+            hoardbase_collection_free(collection);
+            hoardbase_close(db);
+        }
+    }
 
-    let json_ptr = serde_json2cpp_json(&json);
+    struct Order {
+        item: &'static [u8],
+        fail: bool,
+        leaked: *mut hoardbase_collection,
+        direct: *mut hoardbase_collection,
+    }
 
-    print_json(json_ptr);
+    unsafe extern "C" fn place_order(tx: *mut hoardbase_transaction, user_data: *mut c_void) -> hoardbase_status {
+        let order = &mut *(user_data as *mut Order);
+        let mut orders = null_mut();
+        let status = hoardbase_transaction_collection(tx, c("fruits").as_ptr(), &mut orders);
+        if status != HOARDBASE_OK {
+            return status;
+        }
+        hoardbase_collection_insert_one(orders, HOARDBASE_FORMAT_JSON, order.item.as_ptr(), order.item.len(), null_mut());
+        // the database itself can't be used during its transaction.
+        let mut count = 0;
+        assert_eq!(hoardbase_collection_count_documents(order.direct, HOARDBASE_FORMAT_JSON, null(), 0, -1, 0, &mut count), HOARDBASE_ERROR_INVALID_ARGUMENT);
+        order.leaked = orders;
+        if order.fail {
+            let bad = b"{\"$increment\": {\"qty\": 1}}";
+            return hoardbase_collection_update_many(orders, HOARDBASE_FORMAT_JSON, null(), 0, bad.as_ptr(), bad.len(), 0, 0, false, null(), 0, null_mut());
+        }
+        HOARDBASE_OK
+    }
 
-    free_json(json_ptr);
-}
\ No newline at end of file
+    #[test]
+    fn test_transactions() {
+        unsafe {
+            let (db, collection) = open();
+            let mut order = Order { item: b"{\"kind\": \"apple\"}", fail: false, leaked: null_mut(), direct: collection };
+            assert_eq!(hoardbase_run_transaction(db, Some(place_order), &mut order as *mut Order as *mut c_void), HOARDBASE_OK);
+            // the collection of the transaction can't be used once it has ended.
+            let mut count = 0;
+            assert_eq!(hoardbase_collection_count_documents(order.leaked, HOARDBASE_FORMAT_JSON, null(), 0, -1, 0, &mut count), HOARDBASE_ERROR_INVALID_ARGUMENT);
+            hoardbase_collection_free(order.leaked);
+            assert_eq!(hoardbase_collection_count_documents(collection, HOARDBASE_FORMAT_JSON, null(), 0, -1, 0, &mut count), HOARDBASE_OK);
+            assert_eq!(count, 1);
+
+            order.fail = true;
+            order.item = b"{\"kind\": \"pear\"}";
+            assert_eq!(hoardbase_run_transaction(db, Some(place_order), &mut order as *mut Order as *mut c_void), HOARDBASE_ERROR_OPERATION);
+            assert!(!last_error().is_empty());
+            hoardbase_collection_free(order.leaked);
+            assert_eq!(hoardbase_collection_count_documents(collection, HOARDBASE_FORMAT_JSON, null(), 0, -1, 0, &mut count), HOARDBASE_OK);
+            assert_eq!(count, 1);
+            assert_eq!(hoardbase_run_transaction(db, None, null_mut()), HOARDBASE_ERROR_INVALID_ARGUMENT);
+
+            hoardbase_collection_free(collection);
+            hoardbase_close(db);
+        }
+    }
+}
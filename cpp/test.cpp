@@ -1,25 +1,36 @@
 #include <hoardbase.h>
-#include <iostream>
-#include "serde_json.h"
 #include <nlohmann/json.hpp>
-#include "record.h"
 #define CATCH_CONFIG_MAIN // This tells Catch to provide a main() - only do this in one cpp file
 #include <catch2/catch.hpp>
-#include <filesystem>
+#include <string>
+#include <vector>
+
+static std::string dump(const nlohmann::json &j)
+{
+    return j.dump();
+}
+
+static nlohmann::json parse(const hoardbase_buffer &buffer)
+{
+    return nlohmann::json::parse(reinterpret_cast<const char *>(buffer.data), reinterpret_cast<const char *>(buffer.data) + buffer.len);
+}
+
+static const uint8_t *bytes(const std::string &s)
+{
+    return reinterpret_cast<const uint8_t *>(s.data());
+}
 
 TEST_CASE("test hoardbase", "[basic]")
 {
+    REQUIRE(hoardbase_abi_version() == HOARDBASE_ABI_VERSION);
+
+    hoardbase_database *db = nullptr;
+    REQUIRE(hoardbase_open(":memory:", 0, &db) == HOARDBASE_OK);
+    hoardbase_collection *col = nullptr;
+    REQUIRE(hoardbase_create_collection(db, "test", HOARDBASE_COLLECTION_DEFAULT, 0, 0, &col) == HOARDBASE_OK);
 
-    if (std::filesystem::exists("test.db"))
-    {
-        std::filesystem::remove("test.db");
-    }
-    void *db = hoardbase_open("test.db");
     SECTION("test insert_one")
     {
-        SerdeJsonMap map;
-        map.init();
-
         nlohmann::json j2 = {
             {"pi", 3.141},
             {"happy", true},
@@ -28,37 +39,100 @@ TEST_CASE("test hoardbase", "[basic]")
             {"answer", {{"everything", 42}}},
             {"list", {1, 0, 2}},
             {"object", {{"currency", "USD"}, {"value", 42.99}}}};
+        std::string document = dump(j2);
+
+        hoardbase_record record = {};
+        REQUIRE(hoardbase_collection_insert_one(col, HOARDBASE_FORMAT_JSON, bytes(document), document.size(), &record) == HOARDBASE_OK);
+        REQUIRE(record.id == 1);
+        REQUIRE(std::string(record.hash).size() == 64);
+        REQUIRE(parse(record.data) == j2);
+        hoardbase_record_free(&record);
+        REQUIRE(record.data.data == nullptr);
+    }
 
-        map.from_json(j2);
-
-        // map.debug_print_cpp();
-
-        // map.debug_print();
-
-        // nlohmann::json j3;
-
-        // map.to_json(j3);
-
-        // std::cout << j3.dump(4) << std::endl;
-
-        auto col = hoardbase_create_collection(db, "test");
-
-        auto val = serde_json_map2value(map.m_internal);
-        map.m_internal = nullptr;
-
-        auto record = reinterpret_cast<Record *>(hoardbase_collection_insert_one(col, val));
-
-        std::cout << "record: " << record << std::endl;
+    SECTION("test find")
+    {
+        nlohmann::json documents = nlohmann::json::array();
+        for (int i = 0; i < 150; ++i)
+        {
+            documents.push_back(nlohmann::json{{"n", i}});
+        }
+        std::string many = dump(documents);
+        REQUIRE(hoardbase_collection_insert_many(col, HOARDBASE_FORMAT_JSON, bytes(many), many.size()) == HOARDBASE_OK);
+
+        std::string query = dump({{"n", {{"$gte", 10}}}});
+        hoardbase_cursor *cursor = nullptr;
+        REQUIRE(hoardbase_collection_find(col, HOARDBASE_FORMAT_JSON, bytes(query), query.size(), -1, 0, &cursor) == HOARDBASE_OK);
+        std::vector<int> seen;
+        hoardbase_record record = {};
+        while (hoardbase_cursor_next(cursor, &record) == HOARDBASE_OK)
+        {
+            seen.push_back(parse(record.data)["n"].get<int>());
+            hoardbase_record_free(&record);
+        }
+        hoardbase_cursor_free(cursor);
+        REQUIRE(seen.size() == 140);
+        REQUIRE(seen.front() == 10);
+        REQUIRE(seen.back() == 149);
+
+        int64_t count = 0;
+        REQUIRE(hoardbase_collection_count_documents(col, HOARDBASE_FORMAT_JSON, nullptr, 0, -1, 0, &count) == HOARDBASE_OK);
+        REQUIRE(count == 150);
+
+        std::string missing = dump({{"n", -1}});
+        REQUIRE(hoardbase_collection_find_one(col, HOARDBASE_FORMAT_JSON, bytes(missing), missing.size(), 0, &record) == HOARDBASE_NOT_FOUND);
+    }
 
-        record->debug_print();
+    SECTION("test update and delete")
+    {
+        std::string document = dump({{"item", "apple"}, {"qty", 5}});
+        REQUIRE(hoardbase_collection_insert_one(col, HOARDBASE_FORMAT_JSON, bytes(document), document.size(), nullptr) == HOARDBASE_OK);
+
+        std::string query = dump({{"item", "apple"}});
+        std::string update = dump({{"$inc", {{"qty", 1}}}});
+        hoardbase_record record = {};
+        REQUIRE(hoardbase_collection_update_one(col, HOARDBASE_FORMAT_JSON, bytes(query), query.size(), bytes(update), update.size(), 0, false, nullptr, 0, &record) == HOARDBASE_OK);
+        REQUIRE(parse(record.data)["qty"] == 6);
+        hoardbase_record_free(&record);
+
+        int64_t deleted = 0;
+        REQUIRE(hoardbase_collection_delete_many(col, HOARDBASE_FORMAT_JSON, bytes(query), query.size(), &deleted) == HOARDBASE_OK);
+        REQUIRE(deleted == 1);
+    }
 
-        REQUIRE(record->id == 1);
+    SECTION("test errors")
+    {
+        std::string broken = "{\"item\": ";
+        REQUIRE(hoardbase_collection_insert_one(col, HOARDBASE_FORMAT_JSON, bytes(broken), broken.size(), nullptr) == HOARDBASE_ERROR_INVALID_DOCUMENT);
+        REQUIRE(std::string(hoardbase_last_error()).size() > 0);
 
-        delete record;
+        hoardbase_collection *missing = nullptr;
+        REQUIRE(hoardbase_get_collection(db, "missing", &missing) == HOARDBASE_ERROR_OPERATION);
+        REQUIRE(missing == nullptr);
     }
 
-    if (std::filesystem::exists("test.db"))
+    SECTION("test transaction")
     {
-        std::filesystem::remove("test.db");
+        auto callback = [](hoardbase_transaction *tx, void *) -> hoardbase_status {
+            hoardbase_collection *tx_col = nullptr;
+            hoardbase_status status = hoardbase_transaction_collection(tx, "test", &tx_col);
+            if (status != HOARDBASE_OK)
+            {
+                return status;
+            }
+            std::string document = "{\"item\": \"pear\"}";
+            status = hoardbase_collection_insert_one(tx_col, HOARDBASE_FORMAT_JSON, bytes(document), document.size(), nullptr);
+            hoardbase_collection_free(tx_col);
+            // rolls the insert back.
+            return status == HOARDBASE_OK ? HOARDBASE_ERROR_OPERATION : status;
+        };
+        REQUIRE(hoardbase_run_transaction(db, callback, nullptr) == HOARDBASE_ERROR_OPERATION);
+
+        int64_t count = -1;
+        REQUIRE(hoardbase_collection_count_documents(col, HOARDBASE_FORMAT_JSON, nullptr, 0, -1, 0, &count) == HOARDBASE_OK);
+        REQUIRE(count == 0);
     }
+
+    hoardbase_collection_free(col);
+    hoardbase_close(db);
 }
@@ -0,0 +1,145 @@
+#include <hoardbase.hpp>
+#include <catch2/catch.hpp>
+#include <string>
+#include <vector>
+
+TEST_CASE("test the c++ wrapper", "[wrapper]")
+{
+    hoardbase::Database db(":memory:");
+    auto col = db.create_collection("people");
+
+    SECTION("test documents")
+    {
+        auto record = col.insert_one({{"name", "Ada"}, {"age", 36}, {"id", {{"$oid", "61c5b7a8e1d2c3b4a5f60718"}}}});
+        REQUIRE(record.id == 1);
+        REQUIRE(record.hash.size() == 64);
+        REQUIRE(record.last_modified.time_since_epoch().count() > 0);
+        REQUIRE(record.data["id"]["$oid"] == "61c5b7a8e1d2c3b4a5f60718");
+
+        auto found = col.find_one({{"name", "Ada"}});
+        REQUIRE(found);
+        REQUIRE(found->data["age"] == 36);
+        REQUIRE_FALSE(col.find_one({{"name", "Bob"}}));
+        REQUIRE(col.name() == "people");
+    }
+
+    SECTION("test range-for over a cursor")
+    {
+        nlohmann::json documents = nlohmann::json::array();
+        for (int i = 0; i < 250; ++i)
+        {
+            documents.push_back(nlohmann::json{{"n", i}});
+        }
+        col.insert_many(documents);
+
+        std::vector<int> seen;
+        for (const auto &record : col.find({{"n", {{"$gte", 20}}}}))
+        {
+            seen.push_back(record.data["n"].get<int>());
+        }
+        REQUIRE(seen.size() == 230);
+        REQUIRE(seen.front() == 20);
+        REQUIRE(seen.back() == 249);
+
+        auto page = col.find(nullptr, {5, 10}).to_vector();
+        REQUIRE(page.size() == 5);
+        REQUIRE(page.front().data["n"] == 10);
+
+        auto cursor = col.find({{"n", 3}});
+        REQUIRE(cursor.next()->data["n"] == 3);
+        REQUIRE_FALSE(cursor.next());
+
+        REQUIRE(col.count_documents() == 250);
+        REQUIRE(col.count_documents({{"n", {{"$lt", 10}}}}) == 10);
+    }
+
+    SECTION("test updates and deletes")
+    {
+        col.insert_many(nlohmann::json::array({{{"item", "apple"}, {"qty", 5}, {"sizes", {1, 4}}}, {{"item", "pear"}, {"qty", 2}}}));
+
+        REQUIRE(col.update_one({{"item", "apple"}}, {{"$inc", {{"qty", 1}}}})->data["qty"] == 6);
+        REQUIRE_FALSE(col.update_one({{"item", "fig"}}, {{"$set", {{"qty", 1}}}}));
+        hoardbase::UpdateOptions upsert;
+        upsert.upsert = true;
+        REQUIRE(col.update_one({{"item", "fig"}}, {{"$set", {{"qty", 1}}}}, upsert)->data["item"] == "fig");
+
+        hoardbase::UpdateOptions filters;
+        filters.array_filters = nlohmann::json::array({{{"s", {{"$gt", 3}}}}});
+        REQUIRE(col.update_one({{"item", "apple"}}, {{"$set", {{"sizes.$[s]", 0}}}}, filters)->data["sizes"] == nlohmann::json({1, 0}));
+
+        auto pipeline = nlohmann::json::array({{{"$set", {{"total", {{"$multiply", {"$qty", 2}}}}}}}});
+        REQUIRE(col.update_one({{"item", "apple"}}, pipeline)->data["total"] == 12);
+        REQUIRE(col.update_many(nullptr, {{"$set", {{"checked", true}}}}) == 3);
+
+        REQUIRE(col.replace_one({{"item", "fig"}}, {{"item", "fig"}, {"qty", 9}})->data == nlohmann::json({{"item", "fig"}, {"qty", 9}}));
+        REQUIRE_FALSE(col.replace_one({{"item", "plum"}}, {{"item", "plum"}}));
+        REQUIRE(col.find_one_and_delete({{"item", "fig"}})->data["qty"] == 9);
+        REQUIRE_FALSE(col.find_one_and_delete({{"item", "fig"}}));
+        REQUIRE(col.delete_one({{"item", "pear"}}) == 1);
+        REQUIRE(col.delete_many() == 1);
+    }
+
+    SECTION("test errors")
+    {
+        try
+        {
+            col.find_one({{"qty", {{"$gtee", 1}}}});
+            FAIL("expected an exception");
+        }
+        catch (const hoardbase::Error &e)
+        {
+            REQUIRE(e.status() == HOARDBASE_ERROR_OPERATION);
+            REQUIRE(std::string(e.what()).size() > 0);
+        }
+        REQUIRE_THROWS_AS(db.collection("missing"), hoardbase::Error);
+        REQUIRE_THROWS_AS(col.insert_many({{"not", "an array"}}), hoardbase::Error);
+    }
+
+    SECTION("test indexes and collections")
+    {
+        col.create_index({{"email", 1}}, true);
+        bool found = false;
+        for (const auto &index : col.get_indexes())
+        {
+            found = found || (index.name == "people_email" && index.unique);
+        }
+        REQUIRE(found);
+        col.insert_one({{"email", "ada@example.com"}});
+        REQUIRE_THROWS_AS(col.insert_one({{"email", "ada@example.com"}}), hoardbase::Error);
+        col.reindex();
+        col.drop_index("people_email");
+        col.insert_one({{"email", "ada@example.com"}});
+
+        db.create_collection("old_name");
+        db.rename_collection("old_name", "new_name");
+        REQUIRE(db.list_collections() == std::vector<std::string>{"new_name", "people"});
+        db.drop_collection("new_name");
+        REQUIRE(db.list_collections() == std::vector<std::string>{"people"});
+    }
+
+    SECTION("test transactions")
+    {
+        db.transaction([](hoardbase::Transaction &tx) {
+            auto people = tx.collection("people");
+            people.insert_one({{"name", "Ada"}});
+            people.insert_one({{"name", "Grace"}});
+        });
+        REQUIRE(col.count_documents() == 2);
+
+        REQUIRE_THROWS_WITH(db.transaction([](hoardbase::Transaction &tx) {
+            tx.collection("people").insert_one({{"name", "Edsger"}});
+            throw std::runtime_error("cancelled");
+        }),
+                            "cancelled");
+        REQUIRE(col.count_documents() == 2);
+
+        // a failed operation rolls back the ones before it.
+        auto failing = [](hoardbase::Transaction &tx) {
+            auto people = tx.collection("people");
+            people.insert_one({{"name", "Barbara"}});
+            people.update_many(nullptr, {{"$increment", {{"age", 1}}}});
+        };
+        REQUIRE_THROWS_AS(db.transaction(failing), hoardbase::Error);
+        REQUIRE(col.count_documents() == 2);
+    }
+}
@@ -0,0 +1,227 @@
+//! `#[derive(HoardDocument)]`, which implements `hoardbase::schema::HoardDocument` from attributes. It is re-exported by hoardbase as
+//! `hoardbase::schema::HoardDocument`, see the documentation of that module.
+//!
+//! The attributes are:
+//! * `#[hoard(collection = "users")]` on the struct names the collection. It defaults to the name of the struct in snake case.
+//! * `#[hoard(validator = r#"{"age": {"$gte": 0}}"#)]` on the struct sets the validator, a query document written in JSON.
+//! * `#[index]` on a field indexes it, `#[index(unique)]` makes the index unique and `#[index(desc)]` sorts it in descending order.
+//! * `#[index("last_name", "-first_name", unique)]` on the struct declares a compound index. A `-` sorts a field in descending order.
+//! * `#[ttl(expire_after = 3600)]` on a date field makes documents expire that many seconds after that date.
+//!
+//! Field names follow `#[serde(rename = "...")]` and `#[serde(rename_all = "...")]`, so that the indexes are on the names actually stored.
+
+use proc_macro::TokenStream;
+use quote::quote;
+use syn::{parse_macro_input, Data, DeriveInput, Fields, LitInt, LitStr};
+
+#[proc_macro_derive(HoardDocument, attributes(hoard, index, ttl))]
+pub fn derive_hoard_document(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    match expand(&input) {
+        Ok(tokens) => tokens.into(),
+        Err(error) => error.to_compile_error().into(),
+    }
+}
+
+struct Index {
+    fields: Vec<(String, i32)>,
+    unique: bool,
+}
+
+fn expand(input: &DeriveInput) -> syn::Result<proc_macro2::TokenStream> {
+    let fields = match &input.data {
+        Data::Struct(data) => match &data.fields {
+            Fields::Named(fields) => &fields.named,
+            _ => return Err(syn::Error::new_spanned(input, "HoardDocument needs a struct with named fields")),
+        },
+        _ => return Err(syn::Error::new_spanned(input, "HoardDocument can only be derived for structs")),
+    };
+
+    let mut collection = snake_case(&input.ident.to_string());
+    let mut validator = None;
+    let mut rename_all = None;
+    let mut indexes = Vec::new();
+    for attr in &input.attrs {
+        if attr.path().is_ident("hoard") {
+            attr.parse_nested_meta(|meta| {
+                if meta.path.is_ident("collection") {
+                    collection = meta.value()?.parse::<LitStr>()?.value();
+                } else if meta.path.is_ident("validator") {
+                    let json: LitStr = meta.value()?.parse()?;
+                    match serde_json::from_str::<serde_json::Value>(&json.value()) {
+                        Ok(serde_json::Value::Object(_)) => validator = Some(json),
+                        Ok(_) => return Err(syn::Error::new_spanned(&json, "The validator must be a JSON object")),
+                        Err(e) => return Err(syn::Error::new_spanned(&json, format!("The validator isn't valid JSON: {}", e))),
+                    }
+                } else {
+                    return Err(meta.error("Unknown hoard attribute, expected `collection` or `validator`"));
+                }
+                Ok(())
+            })?;
+        } else if attr.path().is_ident("index") {
+            let mut index = Index { fields: Vec::new(), unique: false };
+            attr.parse_args_with(|input: syn::parse::ParseStream| {
+                while !input.is_empty() {
+                    if input.peek(LitStr) {
+                        let field: LitStr = input.parse()?;
+                        let name = field.value();
+                        match name.strip_prefix('-') {
+                            Some(name) => index.fields.push((name.to_string(), -1)),
+                            None => index.fields.push((name, 1)),
+                        }
+                    } else {
+                        let option: syn::Ident = input.parse()?;
+                        if option != "unique" {
+                            return Err(syn::Error::new_spanned(option, "Unknown index option, expected `unique`"));
+                        }
+                        index.unique = true;
+                    }
+                    if !input.is_empty() {
+                        input.parse::<syn::Token![,]>()?;
+                    }
+                }
+                Ok(())
+            })?;
+            if index.fields.is_empty() {
+                return Err(syn::Error::new_spanned(attr, "A compound index needs at least one field"));
+            }
+            indexes.push(index);
+        } else if attr.path().is_ident("serde") {
+            parse_serde(attr, |key, value| {
+                if key == "rename_all" {
+                    rename_all = Some(value);
+                }
+            })?;
+        }
+    }
+
+    let mut ttl = None;
+    for field in fields {
+        let ident = field.ident.as_ref().unwrap();
+        let mut name = ident.to_string();
+        name = name.strip_prefix("r#").map(str::to_string).unwrap_or(name);
+        if let Some(rule) = &rename_all {
+            name = rename(&name, rule).ok_or_else(|| syn::Error::new_spanned(rule, "Unknown rename_all rule"))?;
+        }
+        for attr in &field.attrs {
+            if attr.path().is_ident("serde") {
+                parse_serde(attr, |key, value| {
+                    if key == "rename" {
+                        name = value.value();
+                    }
+                })?;
+            }
+        }
+
+        for attr in &field.attrs {
+            if attr.path().is_ident("index") {
+                let mut index = Index { fields: Vec::new(), unique: false };
+                let mut order = 1;
+                if !matches!(attr.meta, syn::Meta::Path(_)) {
+                    attr.parse_nested_meta(|meta| {
+                        if meta.path.is_ident("unique") {
+                            index.unique = true;
+                        } else if meta.path.is_ident("desc") {
+                            order = -1;
+                        } else {
+                            return Err(meta.error("Unknown index option, expected `unique` or `desc`"));
+                        }
+                        Ok(())
+                    })?;
+                }
+                index.fields.push((name.clone(), order));
+                indexes.push(index);
+            } else if attr.path().is_ident("ttl") {
+                if ttl.is_some() {
+                    return Err(syn::Error::new_spanned(attr, "Only one field can be the TTL field"));
+                }
+                let mut expire_after = None;
+                attr.parse_nested_meta(|meta| {
+                    if meta.path.is_ident("expire_after") {
+                        expire_after = Some(meta.value()?.parse::<LitInt>()?.base10_parse::<u64>()?);
+                        Ok(())
+                    } else {
+                        Err(meta.error("Unknown ttl option, expected `expire_after`"))
+                    }
+                })?;
+                let expire_after = expire_after.ok_or_else(|| syn::Error::new_spanned(attr, "The TTL needs `expire_after = <seconds>`"))?;
+                ttl = Some((name.clone(), expire_after));
+            }
+        }
+    }
+
+    let index_calls = indexes.iter().map(|index| {
+        let names = index.fields.iter().map(|(name, _)| name);
+        let orders = index.fields.iter().map(|(_, order)| order);
+        let unique = index.unique;
+        quote! { schema.index(&[#((#names, #orders)),*], #unique); }
+    });
+    let ttl_call = ttl.map(|(name, expire_after)| quote! { schema.ttl(#name, #expire_after); });
+    let validator_call = validator.map(|json| quote! { schema.validator_json(#json); });
+
+    let ident = &input.ident;
+    let (impl_generics, type_generics, where_clause) = input.generics.split_for_impl();
+    Ok(quote! {
+        impl #impl_generics ::hoardbase::schema::HoardDocument for #ident #type_generics #where_clause {
+            fn schema() -> ::hoardbase::schema::Schema {
+                let mut schema = ::hoardbase::schema::Schema::new(#collection);
+                #(#index_calls)*
+                #ttl_call
+                #validator_call
+                schema
+            }
+        }
+    })
+}
+
+/// Calls `f` with the string-valued options of a `#[serde(...)]` attribute, skipping the others.
+fn parse_serde(attr: &syn::Attribute, mut f: impl FnMut(&str, LitStr)) -> syn::Result<()> {
+    attr.parse_nested_meta(|meta| {
+        let key = meta.path.get_ident().map(|ident| ident.to_string()).unwrap_or_default();
+        if meta.input.peek(syn::Token![=]) {
+            let value: syn::Expr = meta.value()?.parse()?;
+            if let syn::Expr::Lit(syn::ExprLit { lit: syn::Lit::Str(value), .. }) = value {
+                f(&key, value);
+            }
+        } else if meta.input.peek(syn::token::Paren) {
+            // e.g. rename(serialize = "..."), which names the fields differently in each direction.
+            let content;
+            syn::parenthesized!(content in meta.input);
+            content.parse::<proc_macro2::TokenStream>()?;
+        }
+        Ok(())
+    })
+}
+
+fn snake_case(name: &str) -> String {
+    let mut result = String::new();
+    for (i, c) in name.char_indices() {
+        if c.is_uppercase() {
+            if i > 0 {
+                result.push('_');
+            }
+            result.extend(c.to_lowercase());
+        } else {
+            result.push(c);
+        }
+    }
+    result
+}
+
+/// Applies a serde `rename_all` rule to a field name, which is in snake case.
+fn rename(name: &str, rule: &LitStr) -> Option<String> {
+    let words: Vec<&str> = name.split('_').collect();
+    let capitalize = |word: &str| {
+        let mut chars = word.chars();
+        chars.next().map(|first| first.to_uppercase().chain(chars).collect::<String>()).unwrap_or_default()
+    };
+    Some(match rule.value().as_str() {
+        "lowercase" | "snake_case" => name.to_lowercase(),
+        "UPPERCASE" | "SCREAMING_SNAKE_CASE" => name.to_uppercase(),
+        "PascalCase" => words.iter().map(|word| capitalize(word)).collect(),
+        "camelCase" => words.iter().enumerate().map(|(i, word)| if i == 0 { word.to_string() } else { capitalize(word) }).collect(),
+        "kebab-case" => name.replace('_', "-"),
+        "SCREAMING-KEBAB-CASE" => name.replace('_', "-").to_uppercase(),
+        _ => return None,
+    })
+}
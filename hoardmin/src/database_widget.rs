@@ -1,28 +1,53 @@
+use hoardbase::base::{CollectionConfig, CollectionTrait, Record, SearchOption};
 use hoardbase::database::{Database, DatabaseConfig};
 // External Dependencies ------------------------------------------------------
-use cursive::align::HAlign;
 use cursive::direction::Orientation;
 use cursive::traits::*;
 use cursive::view::SizeConstraint;
 use cursive::views::Button;
 use cursive::views::DebugView;
-use cursive::views::EditView;
-use cursive::views::NamedView;
-use cursive::views::TextArea;
 use cursive::views::{Dialog, DummyView, LinearLayout, Panel, ResizedView, TextView};
 use cursive::Cursive;
-use cursive_tabs::TabPanel;
 // Modules --------------------------------------------------------------------
 use cursive_table_view::{TableView, TableViewItem};
 use cursive_tree_view::{Placement, TreeView};
 
-use std::collections::HashMap;
+use std::cmp::Ordering;
 use std::fmt::Display;
 
+/// The number of documents on a page of the documents table.
+const PAGE_SIZE: i64 = 50;
+
+/// Returned by the callback of a time-series scan to stop it once a page is read.
+const PAGE_READ: &str = "page read";
+
+/// What a collection of the tree is, which decides how its documents are read.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum CollectionKind {
+    Collection,
+    View,
+    TimeSeries,
+}
+
+impl CollectionKind {
+    fn of(config: &CollectionConfig) -> Self {
+        if config.view.is_some() {
+            CollectionKind::View
+        } else if config.time_series.is_some() {
+            CollectionKind::TimeSeries
+        } else {
+            CollectionKind::Collection
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 struct Collection {
     id: usize,
-    name: String
+    name: String,
+    kind: CollectionKind,
+    /// The number of documents, `None` if it couldn't be counted, e.g. for a view whose source was dropped.
+    count: Option<i64>,
 }
 
 #[derive(Debug,Clone)]
@@ -49,7 +74,12 @@ impl Display for TreeItem {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         match &self.payload {
             TreeItemPayload::Collection(collection) => {
-                write!(f, "{}", collection.name)
+                let count = collection.count.map_or("?".to_string(), |count| count.to_string());
+                match collection.kind {
+                    CollectionKind::Collection => write!(f, "{} ({})", collection.name, count),
+                    CollectionKind::View => write!(f, "{} (view, {})", collection.name, count),
+                    CollectionKind::TimeSeries => write!(f, "{} (time series, {})", collection.name, count),
+                }
             }
             TreeItemPayload::Index(index) => {
                 write!(f, "{}", index.name)
@@ -58,64 +88,277 @@ impl Display for TreeItem {
     }
 }
 
+#[derive(Copy, Clone, PartialEq, Eq, Hash)]
+enum DocumentColumn {
+    Id,
+    LastModified,
+    Document,
+}
+
+/// A row of the documents table.
+#[derive(Debug, Clone)]
+struct DocumentRow {
+    /// The record id. The documents of a view or a time-series collection have no id, so this is their position instead.
+    id: i64,
+    /// `None` for the documents of a view or a time-series collection, empty if the collection doesn't hash documents.
+    hash: Option<String>,
+    /// `None` for the documents of a view or a time-series collection, or if the collection doesn't log modification times.
+    last_modified: Option<String>,
+    data: bson::Document,
+}
+
+impl DocumentRow {
+    fn from_record(record: &Record) -> Self {
+        // the epoch stands for "not logged".
+        let last_modified = if record.last_modified.timestamp() == 0 { None } else { Some(record.last_modified.format("%Y-%m-%d %H:%M:%S UTC").to_string()) };
+        DocumentRow { id: record.id, hash: Some(record.hash.clone()), last_modified, data: record.data.clone() }
+    }
+
+    /// A document that isn't stored as a record, read from a view or a time-series collection.
+    fn from_document(position: i64, document: &bson::Document) -> Self {
+        DocumentRow { id: position, hash: None, last_modified: None, data: document.clone() }
+    }
+
+    /// The content of the detail pane: the metadata, then the document as pretty-printed extended JSON.
+    fn detail(&self) -> String {
+        let mut text = String::new();
+        match &self.hash {
+            Some(hash) => {
+                text.push_str(&format!("_id:           {}\n", self.id));
+                text.push_str(&format!("hash:          {}\n", if hash.is_empty() { "not hashed" } else { hash }));
+                text.push_str(&format!("last_modified: {}\n", self.last_modified.as_deref().unwrap_or("not logged")));
+            }
+            None => text.push_str(&format!("document {}\n", self.id)),
+        }
+        text.push('\n');
+        let json = bson::Bson::Document(self.data.clone()).into_relaxed_extjson();
+        text.push_str(&serde_json::to_string_pretty(&json).unwrap());
+        text
+    }
+}
+
+impl TableViewItem<DocumentColumn> for DocumentRow {
+    fn to_column(&self, column: DocumentColumn) -> String {
+        match column {
+            DocumentColumn::Id => self.id.to_string(),
+            DocumentColumn::LastModified => self.last_modified.clone().unwrap_or_default(),
+            DocumentColumn::Document => self.data.to_string(),
+        }
+    }
+
+    fn cmp(&self, other: &Self, column: DocumentColumn) -> Ordering
+    where
+        Self: Sized,
+    {
+        match column {
+            DocumentColumn::Id => self.id.cmp(&other.id),
+            DocumentColumn::LastModified => self.last_modified.cmp(&other.last_modified),
+            DocumentColumn::Document => self.data.to_string().cmp(&other.data.to_string()),
+        }
+    }
+}
+
+/// The state of the browser, kept as the user data of the cursive root so that the callbacks can reach it.
+struct Browser {
+    db: Database,
+    /// The name and the kind of the collection shown in the documents table.
+    collection: Option<(String, CollectionKind)>,
+    page: i64,
+    /// The number of documents of the shown collection.
+    count: i64,
+}
+
+fn count_documents(db: &mut Database, name: &str, kind: CollectionKind) -> Result<i64, String> {
+    match kind {
+        CollectionKind::Collection => Ok(db.collection(name)?.count_documents(&bson::doc! {}, &None)?),
+        CollectionKind::View => db.view(name)?.count_documents(&bson::doc! {}, &None),
+        CollectionKind::TimeSeries => db.time_series(name)?.count_documents(&bson::doc! {}),
+    }
+}
+
+/// Reads a page of the documents of a collection, a view or a time-series collection.
+fn read_page(db: &mut Database, name: &str, kind: CollectionKind, page: i64) -> Result<Vec<DocumentRow>, String> {
+    let mut options = SearchOption::default();
+    options.limit(PAGE_SIZE).skip(page * PAGE_SIZE);
+    let mut rows = Vec::new();
+    let mut position = page * PAGE_SIZE;
+    match kind {
+        CollectionKind::Collection => {
+            db.collection(name)?.find(&bson::doc! {}, &Some(options), &mut |record| {
+                rows.push(DocumentRow::from_record(record));
+                Ok(())
+            })?;
+        }
+        CollectionKind::View => {
+            db.view(name)?.find(&bson::doc! {}, &Some(options), &mut |document| {
+                position += 1;
+                rows.push(DocumentRow::from_document(position, document));
+                Ok(())
+            })?;
+        }
+        CollectionKind::TimeSeries => {
+            // measurements can't be skipped, so the scan counts its way to the page.
+            let mut seen = 0;
+            let result = db.time_series(name)?.find(&bson::doc! {}, &mut |measurement| {
+                seen += 1;
+                if seen > page * PAGE_SIZE {
+                    position += 1;
+                    rows.push(DocumentRow::from_document(position, measurement));
+                }
+                if rows.len() as i64 == PAGE_SIZE {
+                    return Err(PAGE_READ);
+                }
+                Ok(())
+            });
+            match result {
+                Err(e) if e != PAGE_READ => return Err(e),
+                _ => {}
+            }
+        }
+    }
+    Ok(rows)
+}
+
+fn page_count(count: i64) -> i64 {
+    ((count + PAGE_SIZE - 1) / PAGE_SIZE).max(1)
+}
+
+fn show_detail(siv: &mut Cursive, row: Option<DocumentRow>) {
+    let text = row.map(|row| row.detail()).unwrap_or_default();
+    siv.call_on_name("detail_view", move |view: &mut TextView| view.set_content(text));
+}
+
+/// Reads the current page of the selected collection into the documents table.
+fn show_page(siv: &mut Cursive) {
+    let result = siv
+        .with_user_data(|browser: &mut Browser| {
+            let (name, kind) = browser.collection.clone()?;
+            let result = count_documents(&mut browser.db, &name, kind).and_then(|count| {
+                browser.count = count;
+                browser.page = browser.page.min(page_count(count) - 1);
+                read_page(&mut browser.db, &name, kind, browser.page)
+            });
+            Some(result.map(|rows| (name, rows, browser.page, browser.count)))
+        })
+        .flatten();
+
+    match result {
+        None => {}
+        Some(Err(e)) => siv.add_layer(Dialog::info(e).title("Error")),
+        Some(Ok((name, rows, page, count))) => {
+            let first = rows.first().cloned();
+            siv.call_on_name("documents_view", move |table: &mut TableView<DocumentRow, DocumentColumn>| {
+                table.set_items(rows);
+                if !table.is_empty() {
+                    table.set_selected_row(0);
+                }
+            });
+            siv.call_on_name("page_view", move |view: &mut TextView| view.set_content(format!("{} [Page {}/{}] [Count {}]", name, page + 1, page_count(count), count)));
+            show_detail(siv, first);
+        }
+    }
+}
+
+fn change_page(siv: &mut Cursive, delta: i64) {
+    let changed = siv
+        .with_user_data(|browser: &mut Browser| {
+            let page = (browser.page + delta).clamp(0, page_count(browser.count) - 1);
+            let changed = page != browser.page;
+            browser.page = page;
+            changed && browser.collection.is_some()
+        })
+        .unwrap_or(false);
+    if changed {
+        show_page(siv);
+    }
+}
+
+fn open_collection(siv: &mut Cursive, row: usize) {
+    let item = siv.call_on_name("collections_view", move |tree: &mut TreeView<TreeItem>| tree.borrow_item(row).cloned()).flatten();
+    if let Some(TreeItem { payload: TreeItemPayload::Collection(collection) }) = item {
+        siv.with_user_data(|browser: &mut Browser| {
+            browser.collection = Some((collection.name, collection.kind));
+            browser.page = 0;
+        });
+        show_page(siv);
+    }
+}
+
 pub struct DatabaseWidget {
     path: String,
     db: hoardbase::database::Database,
 }
 
 impl DatabaseWidget {
-    pub fn new(path : &str) -> Self {
+    /// Opens the database at `path`, which must exist.
+    pub fn new(path: &str) -> Result<Self, String> {
         let mut config = DatabaseConfig::new(path);
         config.trace(false);
         config.profile(false);
-    
-        let mut db = Database::open(&config).unwrap();
+        config.create_if_missing(false);
 
-        let mut db_ui = DatabaseWidget {
-            path: path.to_string(),
-            db: db
-        };
+        let db = Database::open(&config)?;
 
-        return db_ui;    
+        Ok(DatabaseWidget { path: path.to_string(), db })
     }
 
-    pub fn run(&mut self) {
+    pub fn run(mut self) {
         let mut siv = cursive::default();
 
         let mut h_split = LinearLayout::new(Orientation::Horizontal);
         let mut tree_view = TreeView::new();
 
-        let collections = self.db.list_collections();
+        let mut collections = self.db.list_collections();
+        collections.sort_by(|a, b| a.0.cmp(&b.0));
 
-        for collection in collections {
-            let id = tree_view.insert_item( TreeItem{payload: TreeItemPayload::Collection(Collection{id:0, name: collection.0})} , Placement::LastChild, 0).unwrap();
+        for (id, (name, config)) in collections.into_iter().enumerate() {
+            let kind = CollectionKind::of(&config);
+            let count = count_documents(&mut self.db, &name, kind).ok();
+            tree_view.insert_item(TreeItem { payload: TreeItemPayload::Collection(Collection { id, name, kind, count }) }, Placement::LastChild, 0);
         }
 
+        tree_view.set_on_select(open_collection);
+        tree_view.set_on_submit(open_collection);
 
+        let mut left_panel = ResizedView::with_full_height(Panel::new(tree_view.with_name("collections_view").scrollable()).title(self.path.clone()));
+        left_panel.set_width(SizeConstraint::AtLeast(28));
+        h_split.add_child(left_panel);
 
-        tree_view.set_on_submit(|siv: &mut Cursive, row| {
-            let value = siv.call_on_name("collections_view", move |tree: &mut TreeView<TreeItem>| (*tree.borrow_item(row).unwrap()).clone()).unwrap();
-            
-
-            println!("selected collection: {}", value);
+        let mut toolbar = LinearLayout::new(Orientation::Horizontal);
+        toolbar.add_child(Button::new("Prev Page", |s| change_page(s, -1)));
+        toolbar.add_child(Button::new("Next Page", |s| change_page(s, 1)));
+        toolbar.add_child(ResizedView::with_full_width(DummyView {}));
+        toolbar.add_child(TextView::new("Select a collection").with_name("page_view"));
 
-            /*siv.add_layer(Dialog::around(TextView::new(value.unwrap())).title("Item submitted").button("Close", |s| {
-                s.pop_layer();
-            }));
-    
-            set_status(siv, row, "Submitted");*/
+        let mut table = TableView::<DocumentRow, DocumentColumn>::new()
+            .column(DocumentColumn::Id, "_id", |c| c.width(8))
+            .column(DocumentColumn::LastModified, "Last Modified", |c| c.width(24))
+            .column(DocumentColumn::Document, "Document", |c| c)
+            .default_column(DocumentColumn::Id);
 
-            
+        table.set_on_select(|siv: &mut Cursive, _row: usize, index: usize| {
+            let row = siv.call_on_name("documents_view", move |table: &mut TableView<DocumentRow, DocumentColumn>| table.borrow_item(index).cloned()).flatten();
+            show_detail(siv, row);
         });
 
-        let mut left_panel = ResizedView::with_full_height(Panel::new(tree_view.with_name("collections_view").scrollable()).title("Collection"));
-        left_panel.set_width(SizeConstraint::AtLeast(28));
-        h_split.add_child(left_panel);
+        let mut documents = LinearLayout::new(Orientation::Vertical);
+        documents.add_child(ResizedView::with_full_width(toolbar));
+        documents.add_child(ResizedView::with_full_height(Panel::new(table.with_name("documents_view").min_size((50, 20))).title("Documents")));
+        h_split.add_child(ResizedView::with_full_width(documents));
+
+        let detail = Panel::new(TextView::new("").with_name("detail_view").scrollable()).title("Document");
+        h_split.add_child(ResizedView::new(SizeConstraint::AtLeast(48), SizeConstraint::Full, detail));
+
         let mut main_panel = LinearLayout::new(Orientation::Vertical);
         main_panel.add_child(h_split);
         main_panel.add_child(ResizedView::with_fixed_height(8, Panel::new(DebugView::new().scrollable()).title("Log")));
-    
+
+        siv.add_global_callback('q', |s| s.quit());
+        siv.add_global_callback('n', |s| change_page(s, 1));
+        siv.add_global_callback('p', |s| change_page(s, -1));
+
+        siv.set_user_data(Browser { db: self.db, collection: None, page: 0, count: 0 });
         siv.add_fullscreen_layer(main_panel);
         siv.run();
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,137 @@
+/**
+ * A value stored in a document.
+ *
+ * Numbers are stored as 32-bit integers when they are integers that fit, as doubles otherwise. A `bigint` is stored as a 64-bit integer, and
+ * 64-bit integers are always read back as `bigint`. A `Buffer` is stored as binary data, a `Date` as a date. Other bson types, like object ids
+ * and decimals, are read as their relaxed extended JSON, e.g. `{ $oid: "..." }` or `{ $numberDecimal: "1.50" }`, and can be written in that form.
+ */
+export type Value = null | boolean | number | bigint | string | Date | Buffer | Value[] | Document;
+
+export interface Document {
+    [key: string]: Value | undefined;
+}
+
+/** A query document, e.g. `{ age: { $gte: 18 } }`. */
+export type Query = Document;
+
+/** An update document, e.g. `{ $set: { name: "Ada" } }`, or an aggregation pipeline. */
+export type Update = Document | Document[];
+
+export interface Record {
+    _id: number;
+    /** The hash of the document, empty if the collection doesn't hash documents. */
+    _hash: string;
+    /** When the document was last written, the epoch if the collection doesn't log it. */
+    _last_modified: Date;
+    data: Document;
+}
+
+export interface DatabaseConfig {
+    trace?: boolean;
+    profile?: boolean;
+    readOnly?: boolean;
+}
+
+export interface CollectionConfig {
+    hashDocument?: boolean;
+    logLastModified?: boolean;
+    hashUnique?: boolean;
+    /** The maximum number of documents of a capped collection, 0 for no limit. */
+    cappedMaxDocuments?: number;
+    /** The maximum total size in bytes of the documents of a capped collection, 0 for no limit. */
+    cappedMaxBytes?: number;
+}
+
+export interface FindOptions {
+    /** The maximum number of documents to return, negative for no limit. */
+    limit?: number;
+    skip?: number;
+    /** How many documents a cursor reads at a time. */
+    batchSize?: number;
+}
+
+export interface UpdateOptions {
+    skip?: number;
+    upsert?: boolean;
+    arrayFilters?: Document[] | null;
+}
+
+export interface UpdateManyOptions extends UpdateOptions {
+    /** The maximum number of documents to update, 0 for no limit. */
+    limit?: number;
+}
+
+export interface IndexInfo {
+    name: string;
+    unique: boolean;
+    partial: boolean;
+}
+
+export class Database {
+    private constructor(db: unknown);
+    /** Opens the database at `path`, creating it if needed. The path `:memory:` opens a private in-memory database. */
+    static open(path: string, config?: DatabaseConfig): Promise<Database>;
+    /** Closes the database once the calls already made have completed. */
+    close(): Promise<void>;
+    /** Creates a collection, or returns it if it exists. */
+    createCollection(name: string, config?: CollectionConfig): Promise<Collection>;
+    /** Returns an existing collection. */
+    collection(name: string): Promise<Collection>;
+    listCollections(): Promise<string[]>;
+    dropCollection(name: string): Promise<void>;
+    renameCollection(oldName: string, newName: string): Promise<void>;
+    /** Runs `callback` and applies the writes it queued through `batch.collection()` together, or none of them if it throws. */
+    writeBatch(callback: (batch: WriteBatch) => void | Promise<void>): Promise<void>;
+}
+
+export class Collection {
+    private constructor(db: unknown, name: string);
+    readonly name: string;
+    /** Returns the inserted record. With `hashUnique`, inserting a document identical to an existing one fails. */
+    insertOne(document: Document): Promise<Record>;
+    insertMany(documents: Document[]): Promise<void>;
+    find(query?: Query, options?: FindOptions): Cursor;
+    findOne(query?: Query, options?: { skip?: number }): Promise<Record | null>;
+    countDocuments(query?: Query, options?: { limit?: number; skip?: number }): Promise<number>;
+    /** Returns the number of distinct values of `field` among the documents matching `query`. */
+    distinct(field: string, query?: Query | null): Promise<number>;
+    /** Returns the updated record, or the inserted one with `upsert`. */
+    updateOne(query: Query, update: Update, options?: UpdateOptions): Promise<Record | null>;
+    /** Returns the number of updated documents. */
+    updateMany(query: Query, update: Update, options?: UpdateManyOptions): Promise<number>;
+    replaceOne(query: Query, replacement: Document, options?: { skip?: number }): Promise<Record | null>;
+    /** Returns the number of deleted documents. */
+    deleteOne(query: Query): Promise<number>;
+    deleteMany(query?: Query): Promise<number>;
+    findOneAndDelete(query: Query): Promise<Record | null>;
+    createIndex(keys: { [field: string]: 1 | -1 }, options?: { unique?: boolean }): Promise<void>;
+    dropIndex(name: string): Promise<void>;
+    getIndexes(): Promise<IndexInfo[]>;
+    reindex(): Promise<void>;
+}
+
+/** The results of `Collection.find()`, read in batches as the cursor is iterated with `for await`. */
+export class Cursor implements AsyncIterable<Record> {
+    private constructor();
+    [Symbol.asyncIterator](): AsyncIterator<Record>;
+    toArray(): Promise<Record[]>;
+}
+
+/** A write batch. It can't read, and its writes return nothing. */
+export class WriteBatch {
+    private constructor();
+    collection(name: string): BatchCollection;
+}
+
+/** A collection in a write batch. Its writes are queued, and applied when the batch is. */
+export class BatchCollection {
+    private constructor();
+    readonly name: string;
+    insertOne(document: Document): void;
+    insertMany(documents: Document[]): void;
+    updateOne(query: Query, update: Update, options?: UpdateOptions): void;
+    updateMany(query: Query, update: Update, options?: UpdateManyOptions): void;
+    replaceOne(query: Query, replacement: Document, options?: { skip?: number }): void;
+    deleteOne(query: Query): void;
+    deleteMany(query?: Query): void;
+}
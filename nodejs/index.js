@@ -1,27 +1,245 @@
 "use strict";
 
-const { databaseNew, databaseCreateCollection, collectionInsertOne, databaseGetById } = require("./index.node");
+const native = require("./index.node");
 
+/**
+ * A database file. Open it with `Database.open()`. Every method returns a promise; the work runs on a thread of the database, never on the
+ * JavaScript main thread.
+ */
 class Database {
-    constructor(path) {
-        this.db = databaseNew(path);
+    constructor(db) {
+        this.db = db;
     }
 
-    createCollection(name) {
-        let binded = databaseCreateCollection.bind(this.db);
-        return new Collection(binded(name));
+    /**
+     * Opens the database at `path`, creating it if needed. The path `:memory:` opens a private in-memory database. `config` can set `trace`,
+     * `profile` and `readOnly`.
+     */
+    static async open(path, config = {}) {
+        return new Database(await native.databaseOpen(path, config));
+    }
+
+    /** Closes the database once the calls already made have completed. */
+    close() {
+        return native.databaseClose(this.db);
+    }
+
+    /**
+     * Creates a collection, or returns it if it exists. `config` can set `hashDocument`, `logLastModified`, `hashUnique` and, for a capped
+     * collection, `cappedMaxDocuments` and `cappedMaxBytes`.
+     */
+    async createCollection(name, config = {}) {
+        await native.databaseCreateCollection(this.db, name, config);
+        return new Collection(this.db, name);
+    }
+
+    /** Returns an existing collection. */
+    async collection(name) {
+        await native.databaseCollection(this.db, name);
+        return new Collection(this.db, name);
+    }
+
+    listCollections() {
+        return native.databaseListCollections(this.db);
+    }
+
+    dropCollection(name) {
+        return native.databaseDropCollection(this.db, name);
+    }
+
+    renameCollection(oldName, newName) {
+        return native.databaseRenameCollection(this.db, oldName, newName);
+    }
+
+    /**
+     * Runs `callback` with a write batch, and applies the writes it queued through `batch.collection()` in a single sqlite transaction:
+     *
+     *     await db.writeBatch((batch) => {
+     *         batch.collection("orders").insertOne({ item: "apple" });
+     *         batch.collection("stock").updateOne({ item: "apple" }, { $inc: { qty: -1 } });
+     *     });
+     *
+     * The writes are queued and applied when `callback` returns, or when the promise it returns resolves. They are discarded if it throws or
+     * rejects, and the error is rethrown. A batch is not a transaction: it can't read, its writes return nothing, and other calls may change
+     * the database between the queueing and the applying.
+     */
+    async writeBatch(callback) {
+        const batch = new WriteBatch();
+        await callback(batch);
+        await native.databaseWriteBatch(this.db, batch.operations);
     }
 }
 
 class Collection {
-    constructor(collection) {
+    constructor(db, name) {
+        this.db = db;
+        this.name = name;
+    }
+
+    insertOne(document) {
+        return native.collectionInsertOne(this.db, this.name, document);
+    }
+
+    insertMany(documents) {
+        return native.collectionInsertMany(this.db, this.name, documents);
+    }
+
+    /**
+     * Returns a cursor over the documents matching `query`. Nothing is read until the cursor is iterated with `for await`, or read with
+     * `toArray()`.
+     */
+    find(query = {}, { limit = -1, skip = 0, batchSize = 100 } = {}) {
+        return new Cursor(this, query, limit, skip, batchSize);
+    }
+
+    /** Returns the first document matching `query` after skipping `skip` of them, or `null`. */
+    findOne(query = {}, { skip = 0 } = {}) {
+        return native.collectionFindOne(this.db, this.name, query, skip);
+    }
+
+    countDocuments(query = {}, { limit = -1, skip = 0 } = {}) {
+        return native.collectionCountDocuments(this.db, this.name, query, limit, skip);
+    }
+
+    /** Returns the number of distinct values of `field` among the documents matching `query`. */
+    distinct(field, query = null) {
+        return native.collectionDistinct(this.db, this.name, field, query);
+    }
+
+    /** Updates the first document matching `query`. `update` is an update document or an array of pipeline stages. */
+    updateOne(query, update, { skip = 0, upsert = false, arrayFilters = null } = {}) {
+        return native.collectionUpdateOne(this.db, this.name, query, update, skip, upsert, arrayFilters);
+    }
+
+    /** Updates the documents matching `query` and returns how many were updated. A `limit` of 0 is no limit. */
+    updateMany(query, update, { limit = 0, skip = 0, upsert = false, arrayFilters = null } = {}) {
+        return native.collectionUpdateMany(this.db, this.name, query, update, limit, skip, upsert, arrayFilters);
+    }
+
+    replaceOne(query, replacement, { skip = 0 } = {}) {
+        return native.collectionReplaceOne(this.db, this.name, query, replacement, skip);
+    }
+
+    deleteOne(query) {
+        return native.collectionDeleteOne(this.db, this.name, query);
+    }
+
+    deleteMany(query = {}) {
+        return native.collectionDeleteMany(this.db, this.name, query);
+    }
+
+    findOneAndDelete(query) {
+        return native.collectionFindOneAndDelete(this.db, this.name, query);
+    }
+
+    /** Creates an index, e.g. `createIndex({ age: 1, name: -1 }, { unique: false })`. */
+    createIndex(keys, { unique = false } = {}) {
+        return native.collectionCreateIndex(this.db, this.name, keys, unique);
+    }
+
+    dropIndex(name) {
+        return native.collectionDropIndex(this.db, this.name, name);
+    }
+
+    /** Returns the indexes as objects with the keys `name`, `unique` and `partial`. */
+    getIndexes() {
+        return native.collectionGetIndexes(this.db, this.name);
+    }
+
+    reindex() {
+        return native.collectionReindex(this.db, this.name);
+    }
+}
+
+/**
+ * The results of `Collection.find()`. The documents are read `batchSize` at a time as the cursor is iterated, so other calls can be made while
+ * iterating; writes made in between may be seen by the later batches.
+ */
+class Cursor {
+    constructor(collection, query, limit, skip, batchSize) {
         this.collection = collection;
+        this.query = query;
+        /** How many documents are left to read, negative for no limit. */
+        this.remaining = limit;
+        this.skip = skip;
+        this.batchSize = Math.max(1, batchSize);
+    }
+
+    async *[Symbol.asyncIterator]() {
+        while (this.remaining !== 0) {
+            const limit = this.remaining < 0 ? this.batchSize : Math.min(this.remaining, this.batchSize);
+            const batch = await native.collectionFind(this.collection.db, this.collection.name, this.query, limit, this.skip);
+            this.skip += batch.length;
+            if (this.remaining > 0) {
+                this.remaining -= batch.length;
+            }
+            yield* batch;
+            if (batch.length < limit) {
+                break;
+            }
+        }
+    }
+
+    /** Reads the remaining documents into an array. */
+    async toArray() {
+        const records = [];
+        for await (const record of this) {
+            records.push(record);
+        }
+        return records;
+    }
+}
+
+/** A write batch, see `Database.writeBatch()`. */
+class WriteBatch {
+    constructor() {
+        this.operations = [];
+    }
+
+    /** Returns a collection whose writes are queued in this batch. The collection is looked up when the batch is applied. */
+    collection(name) {
+        return new BatchCollection(this, name);
+    }
+}
+
+/** A collection in a `WriteBatch`. Its writes return nothing, and take effect when the batch is applied. */
+class BatchCollection {
+    constructor(batch, name) {
+        this.batch = batch;
+        this.name = name;
+    }
+
+    queue(method, ...args) {
+        this.batch.operations.push([method, this.name, ...args]);
+    }
+
+    insertOne(document) {
+        this.queue("insertOne", document);
+    }
+
+    insertMany(documents) {
+        this.queue("insertMany", documents);
+    }
+
+    updateOne(query, update, { skip = 0, upsert = false, arrayFilters = null } = {}) {
+        this.queue("updateOne", query, update, skip, upsert, arrayFilters);
+    }
+
+    updateMany(query, update, { limit = 0, skip = 0, upsert = false, arrayFilters = null } = {}) {
+        this.queue("updateMany", query, update, limit, skip, upsert, arrayFilters);
+    }
+
+    replaceOne(query, replacement, { skip = 0 } = {}) {
+        this.queue("replaceOne", query, replacement, skip);
+    }
+
+    deleteOne(query) {
+        this.queue("deleteOne", query);
     }
 
-    insertOne(data) {
-        let binded = collectionInsertOne.bind(this.collection);
-        return binded(data);
+    deleteMany(query = {}) {
+        this.queue("deleteMany", query);
     }
 }
 
-module.exports = Database;
\ No newline at end of file
+module.exports = { Database, Collection, Cursor, WriteBatch, BatchCollection };
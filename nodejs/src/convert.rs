@@ -0,0 +1,176 @@
+//! Conversion between bson and JavaScript values.
+//!
+//! | bson | JavaScript |
+//! |------|------------|
+//! | null | `null` (`undefined` is converted to null, and skipped in objects) |
+//! | boolean | `boolean` |
+//! | int32 | `number` |
+//! | int64 | `bigint` |
+//! | double | `number` |
+//! | string | `string` |
+//! | array | `Array` |
+//! | document | `Object` |
+//! | binary | `Buffer` |
+//! | datetime | `Date` |
+//!
+//! Numbers are stored as int32 when they are integers that fit, as doubles otherwise. Other bson types are returned as their relaxed extended
+//! JSON, e.g. `{ $oid: "..." }` or `{ $numberDecimal: "1.50" }`, and an object in one of these forms is converted back to the bson type, so these
+//! values survive a round trip.
+
+use neon::prelude::*;
+use neon::types::buffer::TypedArray;
+use neon::types::{JsBigInt, JsDate};
+
+/// The keys of the extended JSON forms of the bson types without a JavaScript counterpart.
+const EXTENDED_JSON_KEYS: [&str; 13] = ["$oid", "$numberDecimal", "$numberLong", "$numberInt", "$numberDouble", "$binary", "$date", "$regularExpression", "$timestamp", "$symbol", "$code", "$minKey", "$maxKey"];
+
+pub fn to_document<'a>(cx: &mut impl Context<'a>, object: Handle<'a, JsObject>) -> NeonResult<bson::Document> {
+    let mut document = bson::Document::new();
+    let keys = object.get_own_property_names(cx)?.to_vec(cx)?;
+    for key in keys {
+        let key = key.downcast_or_throw::<JsString, _>(cx)?.value(cx);
+        let value = object.get_value(cx, key.as_str())?;
+        if value.is_a::<JsUndefined, _>(cx) {
+            continue;
+        }
+        let value = to_bson(cx, value)?;
+        document.insert(key, value);
+    }
+    Ok(document)
+}
+
+/// Converts a query, `undefined` or `null` being the query that matches everything.
+pub fn to_query<'a>(cx: &mut impl Context<'a>, value: Handle<'a, JsValue>) -> NeonResult<bson::Document> {
+    if value.is_a::<JsUndefined, _>(cx) || value.is_a::<JsNull, _>(cx) {
+        return Ok(bson::Document::new());
+    }
+    let object = value.downcast_or_throw::<JsObject, _>(cx)?;
+    to_document(cx, object)
+}
+
+pub fn to_documents<'a>(cx: &mut impl Context<'a>, value: Handle<'a, JsValue>) -> NeonResult<Vec<bson::Document>> {
+    let items = value.downcast_or_throw::<JsArray, _>(cx)?.to_vec(cx)?;
+    let mut documents = Vec::with_capacity(items.len());
+    for item in items {
+        let object = item.downcast_or_throw::<JsObject, _>(cx)?;
+        documents.push(to_document(cx, object)?);
+    }
+    Ok(documents)
+}
+
+pub fn to_bson<'a>(cx: &mut impl Context<'a>, value: Handle<'a, JsValue>) -> NeonResult<bson::Bson> {
+    // Dates, buffers and arrays are objects too, so they are checked before plain objects.
+    if value.is_a::<JsNull, _>(cx) || value.is_a::<JsUndefined, _>(cx) {
+        Ok(bson::Bson::Null)
+    } else if let Ok(boolean) = value.downcast::<JsBoolean, _>(cx) {
+        Ok(bson::Bson::Boolean(boolean.value(cx)))
+    } else if let Ok(number) = value.downcast::<JsNumber, _>(cx) {
+        let number = number.value(cx);
+        if number.fract() == 0.0 && number >= i32::MIN as f64 && number <= i32::MAX as f64 {
+            Ok(bson::Bson::Int32(number as i32))
+        } else {
+            Ok(bson::Bson::Double(number))
+        }
+    } else if let Ok(bigint) = value.downcast::<JsBigInt, _>(cx) {
+        match bigint.to_i64(cx) {
+            Ok(integer) => Ok(bson::Bson::Int64(integer)),
+            Err(_) => cx.throw_range_error("A BigInt stored in a document has to fit in 64 bits"),
+        }
+    } else if let Ok(string) = value.downcast::<JsString, _>(cx) {
+        Ok(bson::Bson::String(string.value(cx)))
+    } else if let Ok(date) = value.downcast::<JsDate, _>(cx) {
+        let millis = date.value(cx);
+        if millis.is_nan() {
+            return cx.throw_range_error("Invalid Date");
+        }
+        Ok(bson::Bson::DateTime(bson::DateTime::from_millis(millis as i64)))
+    } else if let Ok(buffer) = value.downcast::<JsBuffer, _>(cx) {
+        Ok(bson::Bson::Binary(bson::Binary { subtype: bson::spec::BinarySubtype::Generic, bytes: buffer.as_slice(cx).to_vec() }))
+    } else if let Ok(array) = value.downcast::<JsArray, _>(cx) {
+        let items = array.to_vec(cx)?;
+        let mut output = bson::Array::with_capacity(items.len());
+        for item in items {
+            output.push(to_bson(cx, item)?);
+        }
+        Ok(bson::Bson::Array(output))
+    } else if value.is_a::<JsFunction, _>(cx) {
+        cx.throw_type_error("Functions can't be stored in a document")
+    } else if let Ok(object) = value.downcast::<JsObject, _>(cx) {
+        let document = to_document(cx, object)?;
+        if document.keys().next().map_or(false, |key| EXTENDED_JSON_KEYS.contains(&key.as_str())) {
+            match bson::Bson::try_from(bson::Bson::Document(document).into_relaxed_extjson()) {
+                Ok(value) => Ok(value),
+                Err(e) => cx.throw_type_error(e.to_string()),
+            }
+        } else {
+            Ok(bson::Bson::Document(document))
+        }
+    } else {
+        cx.throw_type_error("Symbols can't be stored in a document")
+    }
+}
+
+pub fn to_object<'a>(cx: &mut impl Context<'a>, document: &bson::Document) -> JsResult<'a, JsObject> {
+    let object = cx.empty_object();
+    for (key, value) in document {
+        let value = to_js(cx, value)?;
+        object.set(cx, key.as_str(), value)?;
+    }
+    Ok(object)
+}
+
+pub fn to_js<'a>(cx: &mut impl Context<'a>, value: &bson::Bson) -> JsResult<'a, JsValue> {
+    Ok(match value {
+        bson::Bson::Null | bson::Bson::Undefined => cx.null().upcast(),
+        bson::Bson::Boolean(boolean) => cx.boolean(*boolean).upcast(),
+        bson::Bson::Int32(integer) => cx.number(*integer).upcast(),
+        bson::Bson::Int64(integer) => JsBigInt::from_i64(cx, *integer).upcast(),
+        bson::Bson::Double(float) => cx.number(*float).upcast(),
+        bson::Bson::String(string) => cx.string(string).upcast(),
+        bson::Bson::Document(document) => to_object(cx, document)?.upcast(),
+        bson::Bson::Array(array) => {
+            let output = cx.empty_array();
+            for (i, item) in array.iter().enumerate() {
+                let item = to_js(cx, item)?;
+                output.set(cx, i as u32, item)?;
+            }
+            output.upcast()
+        }
+        bson::Bson::Binary(binary) => JsBuffer::from_slice(cx, &binary.bytes)?.upcast(),
+        bson::Bson::DateTime(datetime) => date(cx, datetime.timestamp_millis())?.upcast(),
+        other => json_to_js(cx, &other.clone().into_relaxed_extjson())?,
+    })
+}
+
+/// Converts milliseconds since the epoch to a `Date`. bson dates reach further than JavaScript's, which end 100 million days from the epoch.
+pub fn date<'a>(cx: &mut impl Context<'a>, millis: i64) -> JsResult<'a, JsDate> {
+    match cx.date(millis as f64) {
+        Ok(date) => Ok(date),
+        Err(_) => cx.throw_range_error(format!("The date {} ms from the epoch is out of the range of a JavaScript Date", millis)),
+    }
+}
+
+fn json_to_js<'a>(cx: &mut impl Context<'a>, value: &serde_json::Value) -> JsResult<'a, JsValue> {
+    Ok(match value {
+        serde_json::Value::Null => cx.null().upcast(),
+        serde_json::Value::Bool(boolean) => cx.boolean(*boolean).upcast(),
+        serde_json::Value::Number(number) => cx.number(number.as_f64().unwrap_or(f64::NAN)).upcast(),
+        serde_json::Value::String(string) => cx.string(string).upcast(),
+        serde_json::Value::Array(array) => {
+            let output = cx.empty_array();
+            for (i, item) in array.iter().enumerate() {
+                let item = json_to_js(cx, item)?;
+                output.set(cx, i as u32, item)?;
+            }
+            output.upcast()
+        }
+        serde_json::Value::Object(object) => {
+            let output = cx.empty_object();
+            for (key, value) in object {
+                let value = json_to_js(cx, value)?;
+                output.set(cx, key.as_str(), value)?;
+            }
+            output.upcast()
+        }
+    })
+}
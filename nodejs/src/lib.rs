@@ -1,232 +1,550 @@
+//! The native part of the Node.js binding. It exports functions that take the database as their first argument, and `index.js` wraps them in
+//! the classes of the public API.
+//!
+//! Every function returns a promise. The database lives on the thread of a [`hoardbase::async_database::AsyncDatabase`], and the work of each
+//! call is sent to that thread as a job, which settles the promise through a neon [`Channel`] once it is done. sqlite therefore never blocks the
+//! JavaScript main thread, and the calls made on one database run one at a time, in the order they were made.
+
+use hoardbase::async_database::AsyncDatabase;
+use hoardbase::base::{CollectionTrait, SearchOption, UpdateOption};
 use neon::prelude::*;
 use std::cell::RefCell;
-use std::convert::TryInto;
-use std::sync::{Arc, Mutex};
-use hoardbase::base::CollectionTrait;
 
-fn hello(mut cx: FunctionContext) -> JsResult<JsString> {
-    Ok(cx.string("hello node"))
-}
+mod convert;
 
 struct Database {
-    pub db: Arc<Mutex<hoardbase::database::Database>>,
+    /// `None` once the database is closed.
+    db: RefCell<Option<AsyncDatabase>>,
 }
 
-struct Collection {
-    name: String,
-    db: Arc<Mutex<hoardbase::database::Database>>,
+impl Finalize for Database {}
+
+fn handle(cx: &mut FunctionContext) -> NeonResult<AsyncDatabase> {
+    let db = cx.argument::<JsBox<Database>>(0)?;
+    let handle = db.db.borrow().clone();
+    match handle {
+        Some(handle) => Ok(handle),
+        None => cx.throw_error("The database is closed"),
+    }
 }
 
-impl Finalize for Database {}
+/// Runs `work` on the database thread, and returns a promise of its result converted by `to_js` on the main thread.
+fn schedule<T, W, J>(cx: &mut FunctionContext, work: W, to_js: J) -> JsResult<JsPromise>
+where
+    T: Send + 'static,
+    W: FnOnce(&mut hoardbase::database::Database) -> Result<T, String> + Send + 'static,
+    J: for<'a> FnOnce(&mut TaskContext<'a>, T) -> JsResult<'a, JsValue> + Send + 'static,
+{
+    let db = handle(cx)?;
+    let channel = cx.channel();
+    let (deferred, promise) = cx.promise();
+    // The job settles the promise itself, so the future returned by run() isn't needed.
+    let _ = db.run(move |db| {
+        let result = work(db);
+        deferred.settle_with(&channel, move |mut cx| match result {
+            Ok(value) => to_js(&mut cx, value),
+            Err(e) => cx.throw_error(e),
+        });
+        Ok(())
+    });
+    Ok(promise)
+}
 
-impl Finalize for Collection {}
-
-impl Database {
-    // Create a new instance of `Database` and place it inside a `JsBox`
-    // JavaScript can hold a reference to a `JsBox`, but the contents are opaque
-    fn js_new(mut cx: FunctionContext) -> JsResult<JsBox<Database>> {
-        let path = cx.argument::<JsString>(0)?.value(&mut cx);
-        let mut db_config = hoardbase::database::DatabaseConfig::new(&path);
-        db_config.trace(true);
-        db_config.profile(true);
-        let db = hoardbase::database::Database::open(&db_config).unwrap();
-
-        let result = Database { db: Arc::new(Mutex::new(db)) };
-
-        Ok(cx.boxed(result))
-    }
-
-    fn js_create_collection(mut cx: FunctionContext) -> JsResult<JsBox<Collection>> {
-        let collection_name = cx.argument::<JsString>(0)?.value(&mut cx);
-        println!("collection name: {}", collection_name);
-        let db = cx.this().downcast_or_throw::<JsBox<Database>, _>(&mut cx)?;
-        let mut ccol = hoardbase::base::CollectionConfig::default(&collection_name);
-        ccol.hash_document(true);
-        ccol.log_last_modified(true);
-        let mut x = db.db.lock().unwrap();
-        let r = x.create_collection(&collection_name, &ccol);
-        match r {
-            Ok(collection) => Ok(cx.boxed(Collection { name: collection_name.to_string(), db: db.db.clone() })),
-            Err(e) => cx.throw_error(format!("{}", e)),
-        }
+/// Like [`schedule()`], with the collection named by the second argument.
+fn schedule_collection<T, W, J>(cx: &mut FunctionContext, work: W, to_js: J) -> JsResult<JsPromise>
+where
+    T: Send + 'static,
+    W: FnOnce(&mut hoardbase::collection::Collection) -> Result<T, String> + Send + 'static,
+    J: for<'a> FnOnce(&mut TaskContext<'a>, T) -> JsResult<'a, JsValue> + Send + 'static,
+{
+    let name = cx.argument::<JsString>(1)?.value(cx);
+    schedule(
+        cx,
+        move |db| {
+            let mut collection = db.collection(&name).map_err(|e| e.to_string())?;
+            work(&mut collection)
+        },
+        to_js,
+    )
+}
+
+fn undefined<'a>(cx: &mut TaskContext<'a>, _: ()) -> JsResult<'a, JsValue> {
+    Ok(cx.undefined().upcast())
+}
+
+fn number<'a, N: Into<f64>>(cx: &mut TaskContext<'a>, value: N) -> JsResult<'a, JsValue> {
+    Ok(cx.number(value).upcast())
+}
+
+fn record<'a>(cx: &mut TaskContext<'a>, record: Option<hoardbase::base::Record>) -> JsResult<'a, JsValue> {
+    match record {
+        Some(record) => Ok(record_object(cx, &record)?.upcast()),
+        None => Ok(cx.null().upcast()),
     }
 }
 
-impl Collection {
-    fn convert_js_obj2bson_document(cx: &mut FunctionContext, obj: &Handle<'_, JsObject>, output: &mut bson::Document) {
-        let properties = obj.get_own_property_names(cx).unwrap();
-        let properties = properties.downcast_or_throw::<JsArray, _>(cx).unwrap();
-        
-        for idx in 0..properties.len(cx) {
-            let inner_name = properties.get( cx, idx).unwrap().downcast_or_throw::<JsString, _>(cx).unwrap();
-            let name_str: String = inner_name.value(cx);
-            println!("name: {}", name_str);
-            let val = obj.get(cx, name_str.as_str()).unwrap();
-
-            if val.is_a::<JsString, _>(cx) {
-                output.insert(name_str, bson::Bson::String(val.downcast_or_throw::<JsString, _>(cx).unwrap().value(cx)));
-            } else if val.is_a::<JsNumber, _>(cx) {
-                output.insert(name_str, bson::Bson::Double(val.downcast_or_throw::<JsNumber, _>(cx).unwrap().value(cx)));
-            } else if val.is_a::<JsBoolean, _>(cx) {
-                output.insert(name_str, bson::Bson::Boolean(val.downcast_or_throw::<JsBoolean, _>(cx).unwrap().value(cx)));
-            } else if val.is_a::<JsNull, _>(cx) {
-                output.insert(name_str, bson::Bson::Null);
-            } else if val.is_a::<JsArray, _>(cx) {
-                //array has to come before object, otherwise it will be treated as object
-                let mut json = bson::Array::new();
-                let inner_arr = val.downcast_or_throw::<JsArray, _>(cx).unwrap();
-                Collection::convert_js_arr2bson_array(cx, &inner_arr, &mut json);
-                output.insert(name_str,bson::Bson::Array(json));
-            } else if val.is_a::<JsObject, _>(cx) {
-                let mut json = bson::Document::new();
-                let inner_obj = val.downcast_or_throw::<JsObject, _>(cx).unwrap();
-                Collection::convert_js_obj2bson_document(cx, &inner_obj, &mut json);
-                output.insert(name_str, bson::Bson::Document(json));
-            } else {
-                println!("Unrecognized field type: {}", name_str);
-            }
-        }
+fn records<'a>(cx: &mut TaskContext<'a>, records: Vec<hoardbase::base::Record>) -> JsResult<'a, JsValue> {
+    let output = cx.empty_array();
+    for (i, record) in records.iter().enumerate() {
+        let record = record_object(cx, record)?;
+        output.set(cx, i as u32, record)?;
     }
+    Ok(output.upcast())
+}
 
-    fn convert_js_arr2bson_array(cx: &mut FunctionContext, arr: &Handle<'_, JsArray>, output: &mut bson::Array) {
-        let vals = arr.downcast_or_throw::<JsArray, _>(cx).unwrap();
-        
-        for index in 0..vals.len(cx) {
-            let val = vals.get(cx, index).unwrap();
-            if val.is_a::<JsString, _>(cx) {
-                output.push(bson::Bson::String(val.downcast_or_throw::<JsString, _>(cx).unwrap().value(cx)));
-            } else if val.is_a::<JsNumber, _>(cx) {
-                output.push(bson::Bson::Double(val.downcast_or_throw::<JsNumber, _>(cx).unwrap().value(cx)));
-            } else if val.is_a::<JsBoolean, _>(cx) {
-                output.push(bson::Bson::Boolean(val.downcast_or_throw::<JsBoolean, _>(cx).unwrap().value(cx)));
-            } else if val.is_a::<JsNull, _>(cx) {
-                output.push( bson::Bson::Null);
-            } else if val.is_a::<JsArray, _>(cx) {
-                //array has to come before object, otherwise it will be treated as object
-                let mut json = bson::Array::new();
-                let inner_arr = val.downcast_or_throw::<JsArray, _>(cx).unwrap();
-                Collection::convert_js_arr2bson_array(cx, &inner_arr, &mut json);
-                output.push(bson::Bson::Array(json));
-            } else if val.is_a::<JsObject, _>(cx) {
-                let mut json = bson::Document::new();
-                let inner_obj = val.downcast_or_throw::<JsObject, _>(cx).unwrap();
-                Collection::convert_js_obj2bson_document(cx, &inner_obj, &mut json);
-                output.push(bson::Bson::Document(json));
-            } else {
-                println!("Unrecognized field type");
-            }
-        }
+fn record_object<'a>(cx: &mut TaskContext<'a>, record: &hoardbase::base::Record) -> JsResult<'a, JsObject> {
+    let output = cx.empty_object();
+    let id = cx.number(record.id as f64);
+    output.set(cx, "_id", id)?;
+    let hash = cx.string(&record.hash);
+    output.set(cx, "_hash", hash)?;
+    let last_modified = convert::date(cx, record.last_modified.timestamp_millis())?;
+    output.set(cx, "_last_modified", last_modified)?;
+    let data = convert::to_object(cx, &record.data)?;
+    output.set(cx, "data", data)?;
+    Ok(output)
+}
+
+/// Reads an optional boolean from a config object.
+fn flag(cx: &mut FunctionContext, config: Handle<JsObject>, key: &str) -> NeonResult<Option<bool>> {
+    Ok(config.get_opt::<JsBoolean, _, _>(cx, key)?.map(|value| value.value(cx)))
+}
+
+fn integer(cx: &mut FunctionContext, value: Handle<JsValue>) -> NeonResult<i64> {
+    Ok(value.downcast_or_throw::<JsNumber, _>(cx)?.value(cx) as i64)
+}
+
+fn boolean(cx: &mut FunctionContext, value: Handle<JsValue>) -> NeonResult<bool> {
+    Ok(value.downcast_or_throw::<JsBoolean, _>(cx)?.value(cx))
+}
+
+fn search_option(limit: i64, skip: i64) -> Option<SearchOption> {
+    let mut options = SearchOption::default();
+    options.limit(limit).skip(skip);
+    Some(options)
+}
+
+fn update_option<'a>(cx: &mut FunctionContext<'a>, array_filters: Handle<'a, JsValue>) -> NeonResult<Option<UpdateOption>> {
+    if array_filters.is_a::<JsUndefined, _>(cx) || array_filters.is_a::<JsNull, _>(cx) {
+        return Ok(None);
     }
+    let mut options = UpdateOption::default();
+    options.array_filters(convert::to_documents(cx, array_filters)?);
+    Ok(Some(options))
+}
 
-    fn bson_document2js_obj(cx: &mut FunctionContext, json: &bson::Document, output: &mut Handle<JsObject>){
-        for (key, value) in json {
-            if bson::Bson::Null == *value {
-                let null_handle = cx.null();
-                output.set(cx, key.as_str(), null_handle ).unwrap();
-            }
-            else if let bson::Bson::Int32(val) = value {
-                let val = cx.number(*val);
-                output.set(cx, key.as_str(), val ).unwrap();
-            }
-            else if let bson::Bson::Int64(val) = value {
-                let val = cx.number(*val as i32);
-                output.set(cx, key.as_str(), val ).unwrap();
+/// An update is either an update document or an aggregation pipeline.
+enum UpdateSpec {
+    Document(bson::Document),
+    Pipeline(Vec<bson::Document>),
+}
+
+fn update_spec<'a>(cx: &mut FunctionContext<'a>, update: Handle<'a, JsValue>) -> NeonResult<UpdateSpec> {
+    if update.is_a::<JsArray, _>(cx) {
+        Ok(UpdateSpec::Pipeline(convert::to_documents(cx, update)?))
+    } else if let Ok(object) = update.downcast::<JsObject, _>(cx) {
+        Ok(UpdateSpec::Document(convert::to_document(cx, object)?))
+    } else {
+        cx.throw_type_error("An update is an object or an array of pipeline stages")
+    }
+}
+
+fn update_one(collection: &mut impl CollectionTrait, query: &bson::Document, update: &UpdateSpec, skip: i64, upsert: bool, options: &Option<UpdateOption>) -> Result<Option<hoardbase::base::Record>, String> {
+    match update {
+        UpdateSpec::Document(update) => collection.update_one(query, update, skip, upsert, options),
+        UpdateSpec::Pipeline(pipeline) => collection.update_one_pipeline(query, pipeline, skip, upsert),
+    }
+}
+
+fn update_many(collection: &mut impl CollectionTrait, query: &bson::Document, update: &UpdateSpec, limit: i64, skip: i64, upsert: bool, options: &Option<UpdateOption>) -> Result<i64, String> {
+    match update {
+        UpdateSpec::Document(update) => collection.update_many(query, update, limit, skip, upsert, options),
+        UpdateSpec::Pipeline(pipeline) => collection.update_many_pipeline(query, pipeline, limit, skip, upsert),
+    }
+}
+
+/// `databaseOpen(path, config)`. `config` can set `trace`, `profile` and `readOnly`, and the path `:memory:` opens a private in-memory database.
+fn database_open(mut cx: FunctionContext) -> JsResult<JsPromise> {
+    let path = cx.argument::<JsString>(0)?.value(&mut cx);
+    let config = cx.argument::<JsObject>(1)?;
+    let mut db_config = if path == ":memory:" { hoardbase::database::DatabaseConfig::in_memory() } else { hoardbase::database::DatabaseConfig::new(&path) };
+    if let Some(trace) = flag(&mut cx, config, "trace")? {
+        db_config.trace(trace);
+    }
+    if let Some(profile) = flag(&mut cx, config, "profile")? {
+        db_config.profile(profile);
+    }
+    if let Some(read_only) = flag(&mut cx, config, "readOnly")? {
+        db_config.read_only(read_only);
+    }
+
+    let channel = cx.channel();
+    let (deferred, promise) = cx.promise();
+    // Opening waits for the database thread to open the file, which happens on a short-lived thread of its own.
+    std::thread::spawn(move || {
+        let result = futures::executor::block_on(AsyncDatabase::open(&db_config));
+        deferred.settle_with(&channel, move |mut cx| match result {
+            Ok(db) => Ok(cx.boxed(Database { db: RefCell::new(Some(db)) })),
+            Err(e) => cx.throw_error(e),
+        });
+    });
+    Ok(promise)
+}
+
+/// Closes the database once the calls already made have completed.
+fn database_close(mut cx: FunctionContext) -> JsResult<JsPromise> {
+    let promise = schedule(&mut cx, |_| Ok(()), undefined)?;
+    cx.argument::<JsBox<Database>>(0)?.db.borrow_mut().take();
+    Ok(promise)
+}
+
+/// `databaseCreateCollection(db, name, config)`. `config` can set `hashDocument`, `logLastModified`, `hashUnique` and, for a capped collection,
+/// `cappedMaxDocuments` and `cappedMaxBytes`.
+fn database_create_collection(mut cx: FunctionContext) -> JsResult<JsPromise> {
+    let name = cx.argument::<JsString>(1)?.value(&mut cx);
+    let config = cx.argument::<JsObject>(2)?;
+    let mut ccol = hoardbase::base::CollectionConfig::default(&name);
+    if let Some(hash_document) = flag(&mut cx, config, "hashDocument")? {
+        ccol.hash_document(hash_document);
+    }
+    if let Some(log_last_modified) = flag(&mut cx, config, "logLastModified")? {
+        ccol.log_last_modified(log_last_modified);
+    }
+    if let Some(hash_unique) = flag(&mut cx, config, "hashUnique")? {
+        ccol.hash_unique(hash_unique);
+    }
+    let max_documents = config.get_opt::<JsNumber, _, _>(&mut cx, "cappedMaxDocuments")?.map_or(0.0, |value| value.value(&mut cx));
+    let max_bytes = config.get_opt::<JsNumber, _, _>(&mut cx, "cappedMaxBytes")?.map_or(0.0, |value| value.value(&mut cx));
+    ccol.capped(max_documents as u64, max_bytes as u64);
+
+    schedule(&mut cx, move |db| db.create_collection(&name, &ccol).map(|_| ()).map_err(|e| e.to_string()), undefined)
+}
+
+/// Fails if the collection doesn't exist.
+fn database_collection(mut cx: FunctionContext) -> JsResult<JsPromise> {
+    schedule_collection(&mut cx, |_| Ok(()), undefined)
+}
+
+fn database_list_collections(mut cx: FunctionContext) -> JsResult<JsPromise> {
+    schedule(
+        &mut cx,
+        |db| {
+            let mut names: Vec<String> = db.list_collections().into_iter().map(|(name, _)| name).collect();
+            names.sort();
+            Ok(names)
+        },
+        |cx, names| {
+            let output = cx.empty_array();
+            for (i, name) in names.iter().enumerate() {
+                let name = cx.string(name);
+                output.set(cx, i as u32, name)?;
             }
-            else if let bson::Bson::Double(val) = value {
-                let val = cx.number(*val as f32);
-                output.set(cx, key.as_str(), val ).unwrap();
+            Ok(output.upcast())
+        },
+    )
+}
+
+fn database_drop_collection(mut cx: FunctionContext) -> JsResult<JsPromise> {
+    let name = cx.argument::<JsString>(1)?.value(&mut cx);
+    schedule(&mut cx, move |db| db.drop_collection(&name).map_err(|e| e.to_string()), undefined)
+}
+
+fn database_rename_collection(mut cx: FunctionContext) -> JsResult<JsPromise> {
+    let old_name = cx.argument::<JsString>(1)?.value(&mut cx);
+    let new_name = cx.argument::<JsString>(2)?.value(&mut cx);
+    schedule(&mut cx, move |db| db.rename_collection(&old_name, &new_name).map_err(|e| e.to_string()), undefined)
+}
+
+/// A write queued by a JavaScript write batch.
+enum Operation {
+    InsertOne(String, bson::Document),
+    InsertMany(String, Vec<bson::Document>),
+    UpdateOne(String, bson::Document, UpdateSpec, i64, bool, Option<UpdateOption>),
+    UpdateMany(String, bson::Document, UpdateSpec, i64, i64, bool, Option<UpdateOption>),
+    ReplaceOne(String, bson::Document, bson::Document, i64),
+    DeleteOne(String, bson::Document),
+    DeleteMany(String, bson::Document),
+}
+
+impl Operation {
+    /// Reads an operation queued by `index.js`, an array of the method name, the collection name and the arguments of the method.
+    fn from_js<'a>(cx: &mut FunctionContext<'a>, operation: Handle<'a, JsValue>) -> NeonResult<Operation> {
+        let items = operation.downcast_or_throw::<JsArray, _>(cx)?.to_vec(cx)?;
+        if items.len() < 2 {
+            return cx.throw_type_error("An operation is [method, collection, ...arguments]");
+        }
+        let method = items[0].downcast_or_throw::<JsString, _>(cx)?.value(cx);
+        let name = items[1].downcast_or_throw::<JsString, _>(cx)?.value(cx);
+        let undefined: Handle<JsValue> = cx.undefined().upcast();
+        let arg = |i: usize| items.get(i + 2).copied().unwrap_or(undefined);
+        Ok(match method.as_str() {
+            "insertOne" => {
+                let document = arg(0).downcast_or_throw::<JsObject, _>(cx)?;
+                Operation::InsertOne(name, convert::to_document(cx, document)?)
             }
-            else if let bson::Bson::String(val_str) = value {
-                let val = cx.string(val_str);
-                output.set(cx, key.as_str(), val ).unwrap();
+            "insertMany" => Operation::InsertMany(name, convert::to_documents(cx, arg(0))?),
+            "updateOne" => {
+                let query = convert::to_query(cx, arg(0))?;
+                let update = update_spec(cx, arg(1))?;
+                Operation::UpdateOne(name, query, update, integer(cx, arg(2))?, boolean(cx, arg(3))?, update_option(cx, arg(4))?)
             }
-            else if let bson::Bson::Document(doc) = value {
-                let mut obj_out: Handle<JsObject> = cx.empty_object();
-                Self::bson_document2js_obj(cx, doc, &mut obj_out);
-                output.set(cx, key.as_str(), obj_out ).unwrap();
+            "updateMany" => {
+                let query = convert::to_query(cx, arg(0))?;
+                let update = update_spec(cx, arg(1))?;
+                Operation::UpdateMany(name, query, update, integer(cx, arg(2))?, integer(cx, arg(3))?, boolean(cx, arg(4))?, update_option(cx, arg(5))?)
             }
-            else if let bson::Bson::Array(arr) = value {
-                let mut arr_out = Handle::from(JsArray::new(cx, arr.len() as u32));
-                Self::serde_json_arr2js_arr(cx, arr, &mut arr_out);
-                output.set(cx, key.as_str(), arr_out).unwrap();
+            "replaceOne" => {
+                let query = convert::to_query(cx, arg(0))?;
+                let replacement = arg(1).downcast_or_throw::<JsObject, _>(cx)?;
+                Operation::ReplaceOne(name, query, convert::to_document(cx, replacement)?, integer(cx, arg(2))?)
             }
+            "deleteOne" => Operation::DeleteOne(name, convert::to_query(cx, arg(0))?),
+            "deleteMany" => Operation::DeleteMany(name, convert::to_query(cx, arg(0))?),
+            _ => return cx.throw_type_error(format!("{} can't be used in a write batch", method)),
+        })
+    }
+
+    fn apply(&self, tx: &hoardbase::database::Transaction) -> Result<(), String> {
+        let collection = |name: &str| tx.collection(name).map_err(|e| e.to_string());
+        match self {
+            Operation::InsertOne(name, document) => collection(name)?.insert_one(document).map(|_| ()),
+            Operation::InsertMany(name, documents) => collection(name)?.insert_many(documents),
+            Operation::UpdateOne(name, query, update, skip, upsert, options) => update_one(&mut collection(name)?, query, update, *skip, *upsert, options).map(|_| ()),
+            Operation::UpdateMany(name, query, update, limit, skip, upsert, options) => update_many(&mut collection(name)?, query, update, *limit, *skip, *upsert, options).map(|_| ()),
+            Operation::ReplaceOne(name, query, replacement, skip) => collection(name)?.replace_one(query, replacement, *skip).map(|_| ()),
+            Operation::DeleteOne(name, query) => collection(name)?.delete_one(query).map(|_| ()),
+            Operation::DeleteMany(name, query) => collection(name)?.delete_many(query).map(|_| ()),
         }
     }
+}
 
-    fn serde_json_arr2js_arr(cx: &mut FunctionContext, json: &bson::Array, output: &mut JsArray) {
-        for (i, val) in json.iter().enumerate()  {
-            if bson::Bson::Null == *val {
-                let val = cx.null();
-                output.set(cx, i as u32, val).unwrap();
-            }
-            else if let bson::Bson::Int32(val_int) = val {
-                let val = cx.number(*val_int as f64);
-                output.set(cx, i as u32, val).unwrap();
-            }
-            else if let bson::Bson::Int64(val_int) = val {
-                let val = cx.number(*val_int as f64);
-                output.set(cx, i as u32, val).unwrap();
-            }
-            else if let bson::Bson::String(val_str) = val {
-                let val = cx.string(val_str);
-                output.set(cx, i as u32, val).unwrap();
-            }
-            else if let bson::Bson::Double(val_f64) = val {
-                let val = cx.number(*val_f64);
-                output.set(cx, i as u32, val).unwrap();
-            }
-            else if let bson::Bson::Document(doc) = val {
-                let mut obj_out: Handle<JsObject> = cx.empty_object();
-                Self::bson_document2js_obj(cx, doc, &mut obj_out);
-                output.set(cx, i as u32, obj_out).unwrap();
+/// `databaseWriteBatch(db, operations)` applies the operations queued by a JavaScript write batch in a single sqlite transaction.
+fn database_write_batch(mut cx: FunctionContext) -> JsResult<JsPromise> {
+    let queued = cx.argument::<JsArray>(1)?.to_vec(&mut cx)?;
+    let mut operations = Vec::with_capacity(queued.len());
+    for operation in queued {
+        operations.push(Operation::from_js(&mut cx, operation)?);
+    }
+    schedule(
+        &mut cx,
+        move |db| {
+            if operations.is_empty() {
+                return Ok(());
             }
-            else if let bson::Bson::Array(arr) = val {
-                let mut arr_out = Handle::from(JsArray::new(cx, arr.len() as u32));
-                Self::serde_json_arr2js_arr(cx, arr, &mut arr_out);
-                output.set(cx, i as u32, arr_out).unwrap();
+            let mut failure = None;
+            let result = db.transaction(|tx| {
+                for operation in &operations {
+                    if let Err(e) = operation.apply(tx) {
+                        failure = Some(e);
+                        return Err("The write batch was rolled back");
+                    }
+                }
+                Ok(())
+            });
+            match (result, failure) {
+                (_, Some(e)) => Err(e),
+                (Err(e), None) => Err(e.to_string()),
+                (Ok(()), None) => Ok(()),
             }
-        }
-    }
+        },
+        undefined,
+    )
+}
 
-    fn js_insert_one(mut cx: FunctionContext) -> JsResult<JsObject> {
-        let collection = cx.this().downcast_or_throw::<JsBox<Collection>, _>(&mut cx)?;
-        let obj = cx.argument::<JsObject>(0)?;
-        let mut json = bson::Document::new();
-        Self::convert_js_obj2bson_document(&mut cx, &obj, &mut json);
+fn collection_insert_one(mut cx: FunctionContext) -> JsResult<JsPromise> {
+    let document = cx.argument::<JsObject>(2)?;
+    let document = convert::to_document(&mut cx, document)?;
+    schedule_collection(&mut cx, move |c| c.insert_one(&document), record)
+}
 
-        let result = collection.db.lock().unwrap().collection(collection.name.as_str()).unwrap().insert_one( &json).unwrap();
+fn collection_insert_many(mut cx: FunctionContext) -> JsResult<JsPromise> {
+    let documents = cx.argument::<JsValue>(2)?;
+    let documents = convert::to_documents(&mut cx, documents)?;
+    schedule_collection(&mut cx, move |c| c.insert_many(&documents), undefined)
+}
 
-        let obj_out: Handle<JsObject> = cx.empty_object();
+/// `collectionFind(db, name, query, limit, skip)` returns an array of records. `index.js` calls it once per batch of a cursor.
+fn collection_find(mut cx: FunctionContext) -> JsResult<JsPromise> {
+    let query = cx.argument::<JsValue>(2)?;
+    let query = convert::to_query(&mut cx, query)?;
+    let limit = cx.argument::<JsValue>(3)?;
+    let limit = integer(&mut cx, limit)?;
+    let skip = cx.argument::<JsValue>(4)?;
+    let skip = integer(&mut cx, skip)?;
+    schedule_collection(
+        &mut cx,
+        move |c| {
+            let mut found = Vec::new();
+            c.find(&query, &search_option(limit, skip), &mut |r| {
+                found.push(r.clone());
+                Ok(())
+            })
+            .map_err(|e| e.to_string())?;
+            Ok(found)
+        },
+        records,
+    )
+}
 
-        if let Some(record) = result {
-            let id = cx.number(record.id as f64);
+fn collection_find_one(mut cx: FunctionContext) -> JsResult<JsPromise> {
+    let query = cx.argument::<JsValue>(2)?;
+    let query = convert::to_query(&mut cx, query)?;
+    let skip = cx.argument::<JsValue>(3)?;
+    let skip = integer(&mut cx, skip)?;
+    schedule_collection(
+        &mut cx,
+        move |c| {
+            let mut found = None;
+            c.find(&query, &search_option(1, skip), &mut |r| {
+                found = Some(r.clone());
+                Ok(())
+            })
+            .map_err(|e| e.to_string())?;
+            Ok(found)
+        },
+        record,
+    )
+}
 
-            obj_out.set(&mut cx, "_id", id).unwrap();
-            
-            let hash = cx.string(record.hash);
+fn collection_count_documents(mut cx: FunctionContext) -> JsResult<JsPromise> {
+    let query = cx.argument::<JsValue>(2)?;
+    let query = convert::to_query(&mut cx, query)?;
+    let limit = cx.argument::<JsValue>(3)?;
+    let limit = integer(&mut cx, limit)?;
+    let skip = cx.argument::<JsValue>(4)?;
+    let skip = integer(&mut cx, skip)?;
+    schedule_collection(&mut cx, move |c| c.count_documents(&query, &search_option(limit, skip)).map_err(|e| e.to_string()), |cx, count| number(cx, count as f64))
+}
 
-            let mut data_out: Handle<JsObject> = cx.empty_object();
-            Self::bson_document2js_obj(&mut cx, &record.data, &mut data_out);
-            obj_out.set(&mut cx, "data", data_out ).unwrap();
+fn collection_distinct(mut cx: FunctionContext) -> JsResult<JsPromise> {
+    let field = cx.argument::<JsString>(2)?.value(&mut cx);
+    let query = cx.argument::<JsValue>(3)?;
+    let query = if query.is_a::<JsUndefined, _>(&mut cx) || query.is_a::<JsNull, _>(&mut cx) { None } else { Some(convert::to_query(&mut cx, query)?) };
+    schedule_collection(&mut cx, move |c| c.distinct(&field, &query, &None).map_err(|e| e.to_string()), |cx, count| number(cx, count as f64))
+}
 
+fn collection_update_one(mut cx: FunctionContext) -> JsResult<JsPromise> {
+    let query = cx.argument::<JsValue>(2)?;
+    let query = convert::to_query(&mut cx, query)?;
+    let update = cx.argument::<JsValue>(3)?;
+    let update = update_spec(&mut cx, update)?;
+    let skip = cx.argument::<JsValue>(4)?;
+    let skip = integer(&mut cx, skip)?;
+    let upsert = cx.argument::<JsValue>(5)?;
+    let upsert = boolean(&mut cx, upsert)?;
+    let array_filters = cx.argument::<JsValue>(6)?;
+    let options = update_option(&mut cx, array_filters)?;
+    schedule_collection(&mut cx, move |c| update_one(c, &query, &update, skip, upsert, &options), record)
+}
 
-            obj_out.set(&mut cx, "_hash", hash).unwrap();
+fn collection_update_many(mut cx: FunctionContext) -> JsResult<JsPromise> {
+    let query = cx.argument::<JsValue>(2)?;
+    let query = convert::to_query(&mut cx, query)?;
+    let update = cx.argument::<JsValue>(3)?;
+    let update = update_spec(&mut cx, update)?;
+    let limit = cx.argument::<JsValue>(4)?;
+    let limit = integer(&mut cx, limit)?;
+    let skip = cx.argument::<JsValue>(5)?;
+    let skip = integer(&mut cx, skip)?;
+    let upsert = cx.argument::<JsValue>(6)?;
+    let upsert = boolean(&mut cx, upsert)?;
+    let array_filters = cx.argument::<JsValue>(7)?;
+    let options = update_option(&mut cx, array_filters)?;
+    schedule_collection(&mut cx, move |c| update_many(c, &query, &update, limit, skip, upsert, &options), |cx, count| number(cx, count as f64))
+}
 
-            let last_modified = cx.date(record.last_modified.timestamp() as f64 * 1000.0).unwrap();
+fn collection_replace_one(mut cx: FunctionContext) -> JsResult<JsPromise> {
+    let query = cx.argument::<JsValue>(2)?;
+    let query = convert::to_query(&mut cx, query)?;
+    let replacement = cx.argument::<JsObject>(3)?;
+    let replacement = convert::to_document(&mut cx, replacement)?;
+    let skip = cx.argument::<JsValue>(4)?;
+    let skip = integer(&mut cx, skip)?;
+    schedule_collection(&mut cx, move |c| c.replace_one(&query, &replacement, skip), record)
+}
 
-            obj_out.set(&mut cx, "_last_modified", last_modified).unwrap();
-        }
+fn collection_delete_one(mut cx: FunctionContext) -> JsResult<JsPromise> {
+    let query = cx.argument::<JsValue>(2)?;
+    let query = convert::to_query(&mut cx, query)?;
+    schedule_collection(&mut cx, move |c| c.delete_one(&query), |cx, count| number(cx, count as f64))
+}
 
-    
-        Ok(obj_out)
-    }
+fn collection_delete_many(mut cx: FunctionContext) -> JsResult<JsPromise> {
+    let query = cx.argument::<JsValue>(2)?;
+    let query = convert::to_query(&mut cx, query)?;
+    schedule_collection(&mut cx, move |c| c.delete_many(&query), |cx, count| number(cx, count as f64))
+}
+
+fn collection_find_one_and_delete(mut cx: FunctionContext) -> JsResult<JsPromise> {
+    let query = cx.argument::<JsValue>(2)?;
+    let query = convert::to_query(&mut cx, query)?;
+    schedule_collection(&mut cx, move |c| c.find_one_and_delete(&query), record)
+}
+
+fn collection_create_index(mut cx: FunctionContext) -> JsResult<JsPromise> {
+    let keys = cx.argument::<JsObject>(2)?;
+    let keys = convert::to_document(&mut cx, keys)?;
+    let unique = cx.argument::<JsValue>(3)?;
+    let unique = boolean(&mut cx, unique)?;
+    schedule_collection(&mut cx, move |c| c.create_index(&keys, unique), undefined)
+}
+
+fn collection_drop_index(mut cx: FunctionContext) -> JsResult<JsPromise> {
+    let name = cx.argument::<JsString>(2)?.value(&mut cx);
+    schedule_collection(&mut cx, move |c| c.drop_index(&name), undefined)
+}
+
+/// Returns the indexes as objects with the keys `name`, `unique` and `partial`.
+fn collection_get_indexes(mut cx: FunctionContext) -> JsResult<JsPromise> {
+    schedule_collection(
+        &mut cx,
+        |c| c.get_indexes(),
+        |cx, indexes| {
+            let output = cx.empty_array();
+            for (i, index) in indexes.iter().enumerate() {
+                let object = cx.empty_object();
+                let name = cx.string(&index.name);
+                object.set(cx, "name", name)?;
+                let unique = cx.boolean(index.is_unique);
+                object.set(cx, "unique", unique)?;
+                let partial = cx.boolean(index.is_partial);
+                object.set(cx, "partial", partial)?;
+                output.set(cx, i as u32, object)?;
+            }
+            Ok(output.upcast())
+        },
+    )
+}
+
+fn collection_reindex(mut cx: FunctionContext) -> JsResult<JsPromise> {
+    schedule_collection(&mut cx, |c| c.reindex(), undefined)
 }
 
 #[neon::main]
 fn main(mut cx: ModuleContext) -> NeonResult<()> {
-    //  cx.export_function("hello", hello)?;
-    cx.export_function("databaseNew", Database::js_new)?;
-    cx.export_function("databaseCreateCollection", Database::js_create_collection)?;
-    cx.export_function("collectionInsertOne", Collection::js_insert_one)?;
+    cx.export_function("databaseOpen", database_open)?;
+    cx.export_function("databaseClose", database_close)?;
+    cx.export_function("databaseCreateCollection", database_create_collection)?;
+    cx.export_function("databaseCollection", database_collection)?;
+    cx.export_function("databaseListCollections", database_list_collections)?;
+    cx.export_function("databaseDropCollection", database_drop_collection)?;
+    cx.export_function("databaseRenameCollection", database_rename_collection)?;
+    cx.export_function("databaseWriteBatch", database_write_batch)?;
+    cx.export_function("collectionInsertOne", collection_insert_one)?;
+    cx.export_function("collectionInsertMany", collection_insert_many)?;
+    cx.export_function("collectionFind", collection_find)?;
+    cx.export_function("collectionFindOne", collection_find_one)?;
+    cx.export_function("collectionCountDocuments", collection_count_documents)?;
+    cx.export_function("collectionDistinct", collection_distinct)?;
+    cx.export_function("collectionUpdateOne", collection_update_one)?;
+    cx.export_function("collectionUpdateMany", collection_update_many)?;
+    cx.export_function("collectionReplaceOne", collection_replace_one)?;
+    cx.export_function("collectionDeleteOne", collection_delete_one)?;
+    cx.export_function("collectionDeleteMany", collection_delete_many)?;
+    cx.export_function("collectionFindOneAndDelete", collection_find_one_and_delete)?;
+    cx.export_function("collectionCreateIndex", collection_create_index)?;
+    cx.export_function("collectionDropIndex", collection_drop_index)?;
+    cx.export_function("collectionGetIndexes", collection_get_indexes)?;
+    cx.export_function("collectionReindex", collection_reindex)?;
     Ok(())
 }
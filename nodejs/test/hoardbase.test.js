@@ -0,0 +1,202 @@
+"use strict";
+
+// Runs with `node --test` once the addon is built with `npm run build`. It needs no other package and no network.
+
+const { describe, test, before, after } = require("node:test");
+const assert = require("node:assert/strict");
+const fs = require("node:fs");
+const os = require("node:os");
+const path = require("node:path");
+
+const { Database } = require("..");
+
+describe("Hoardbase", () => {
+    let dir = null;
+    let db = null;
+
+    before(async () => {
+        dir = fs.mkdtempSync(path.join(os.tmpdir(), "hoardbase-node-"));
+        db = await Database.open(path.join(dir, "test.db"));
+    });
+
+    after(async () => {
+        await db.close();
+        fs.rmSync(dir, { recursive: true, force: true });
+    });
+
+    test("insertOne and findOne", async () => {
+        const col = await db.createCollection("people");
+        const record = await col.insertOne({ name: "Ada", age: 36, tags: ["math", ["nested", 1]], address: { city: "London" } });
+        assert.equal(record._id, 1);
+        assert.equal(record._hash.length, 64);
+        assert.ok(record._last_modified instanceof Date);
+        assert.deepEqual(record.data, { name: "Ada", age: 36, tags: ["math", ["nested", 1]], address: { city: "London" } });
+
+        assert.equal((await col.findOne({ name: "Ada" })).data.age, 36);
+        assert.equal(await col.findOne({ name: "Bob" }), null);
+    });
+
+    test("value conversion", async () => {
+        const col = await db.createCollection("values");
+        const when = new Date("2021-12-24T10:30:00.123Z");
+        const document = {
+            integer: 42,
+            double: 1.5,
+            big: 9007199254740993n,
+            negativeBig: -5n,
+            when,
+            bytes: Buffer.from([0, 1, 2, 255]),
+            nested: [[1, 2], [when, [Buffer.from("a")]], { deep: [null, true] }],
+            missing: undefined,
+            id: { $oid: "61c5b7a8e1d2c3b4a5f60718" },
+            price: { $numberDecimal: "1.50" },
+        };
+        const { data } = await col.insertOne(document);
+        assert.equal(data.integer, 42);
+        assert.equal(data.double, 1.5);
+        assert.equal(data.big, 9007199254740993n);
+        assert.equal(data.negativeBig, -5n);
+        assert.ok(data.when instanceof Date);
+        assert.equal(data.when.getTime(), when.getTime());
+        assert.ok(Buffer.isBuffer(data.bytes));
+        assert.deepEqual([...data.bytes], [0, 1, 2, 255]);
+        assert.deepEqual(data.nested[0], [1, 2]);
+        assert.equal(data.nested[1][0].getTime(), when.getTime());
+        assert.equal(data.nested[1][1][0].toString(), "a");
+        assert.deepEqual(data.nested[2], { deep: [null, true] });
+        assert.ok(!("missing" in data));
+        assert.deepEqual(data.id, { $oid: "61c5b7a8e1d2c3b4a5f60718" });
+        assert.deepEqual(data.price, { $numberDecimal: "1.50" });
+
+        await assert.rejects(col.insertOne({ big: 2n ** 64n }), RangeError);
+        await assert.rejects(col.insertOne({ f: () => 1 }), TypeError);
+    });
+
+    test("find with cursors", async () => {
+        const col = await db.createCollection("numbers");
+        await col.insertMany(Array.from({ length: 25 }, (_, i) => ({ n: i, even: i % 2 === 0 })));
+
+        const seen = [];
+        for await (const record of col.find({ even: true }, { batchSize: 4 })) {
+            seen.push(record.data.n);
+        }
+        assert.deepEqual(seen, [0, 2, 4, 6, 8, 10, 12, 14, 16, 18, 20, 22, 24]);
+
+        const page = await col.find({}, { skip: 5, limit: 7, batchSize: 3 }).toArray();
+        assert.deepEqual(page.map((record) => record.data.n), [5, 6, 7, 8, 9, 10, 11]);
+        assert.equal(await col.countDocuments({ n: { $gte: 20 } }), 5);
+        assert.equal(await col.countDocuments(), 25);
+        assert.equal(await col.distinct("even"), 2);
+    });
+
+    test("updates and deletes", async () => {
+        const col = await db.createCollection("stock");
+        await col.insertMany([
+            { item: "apple", qty: 5, sizes: [1, 2, 3] },
+            { item: "pear", qty: 2, sizes: [2, 4] },
+        ]);
+
+        const updated = await col.updateOne({ item: "apple" }, { $inc: { qty: 1 } });
+        assert.equal(updated.data.qty, 6);
+        const upserted = await col.updateOne({ item: "plum" }, { $set: { qty: 1 } }, { upsert: true });
+        assert.equal(upserted.data.item, "plum");
+
+        assert.equal(await col.updateMany({}, { $set: { checked: true } }), 3);
+        const filtered = await col.updateOne({ item: "pear" }, { $set: { "sizes.$[s]": 0 } }, { arrayFilters: [{ s: { $gt: 3 } }] });
+        assert.deepEqual(filtered.data.sizes, [2, 0]);
+        const piped = await col.updateOne({ item: "apple" }, [{ $set: { total: { $multiply: ["$qty", 2] } } }]);
+        assert.equal(piped.data.total, 12);
+
+        const replaced = await col.replaceOne({ item: "plum" }, { item: "plum", qty: 10 });
+        assert.deepEqual(replaced.data, { item: "plum", qty: 10 });
+
+        assert.equal((await col.findOneAndDelete({ item: "plum" })).data.qty, 10);
+        assert.equal(await col.deleteOne({ item: "pear" }), 1);
+        assert.equal(await col.deleteMany(), 1);
+        assert.equal(await col.countDocuments(), 0);
+
+        await assert.rejects(col.updateOne({ item: "apple" }, { $increment: { qty: 1 } }));
+        await assert.rejects(col.findOne({ qty: { $gtee: 1 } }));
+    });
+
+    test("indexes", async () => {
+        const col = await db.createCollection("users");
+        await col.createIndex({ email: 1 }, { unique: true });
+        const names = (await col.getIndexes()).map((index) => index.name);
+        assert.ok(names.includes("users_email"), names.join(", "));
+        assert.ok((await col.getIndexes()).find((index) => index.name === "users_email").unique);
+
+        await col.insertOne({ email: "ada@example.com" });
+        await assert.rejects(col.insertOne({ email: "ada@example.com" }));
+        await col.reindex();
+        await col.dropIndex("users_email");
+        await col.insertOne({ email: "ada@example.com" });
+        assert.equal(await col.countDocuments({ email: "ada@example.com" }), 2);
+    });
+
+    test("collections", async () => {
+        await db.createCollection("old_name");
+        await db.renameCollection("old_name", "new_name");
+        let names = await db.listCollections();
+        assert.ok(names.includes("new_name"));
+        assert.ok(!names.includes("old_name"));
+        await db.dropCollection("new_name");
+        names = await db.listCollections();
+        assert.ok(!names.includes("new_name"));
+        await assert.rejects(db.collection("new_name"));
+        assert.equal((await db.collection("people")).name, "people");
+    });
+
+    test("write batches", async () => {
+        const orders = await db.createCollection("orders");
+        const stock = await db.createCollection("inventory");
+        await stock.insertOne({ item: "apple", qty: 3 });
+
+        await db.writeBatch(async (batch) => {
+            assert.equal(batch.collection("orders").insertOne({ item: "apple" }), undefined);
+            batch.collection("inventory").updateOne({ item: "apple" }, { $inc: { qty: -1 } });
+            // the writes are only queued.
+            assert.equal(await orders.countDocuments(), 0);
+        });
+        assert.equal(await orders.countDocuments(), 1);
+        assert.equal((await stock.findOne({ item: "apple" })).data.qty, 2);
+
+        // a failing write rolls back the ones before it.
+        await assert.rejects(
+            db.writeBatch((batch) => {
+                batch.collection("orders").insertOne({ item: "pear" });
+                batch.collection("inventory").updateOne({ item: "apple" }, { $increment: { qty: -1 } });
+            })
+        );
+        assert.equal(await orders.countDocuments(), 1);
+
+        // an exception in the callback discards the queued writes.
+        await assert.rejects(
+            db.writeBatch(async (batch) => {
+                batch.collection("orders").insertOne({ item: "plum" });
+                throw new Error("cancelled");
+            }),
+            /cancelled/
+        );
+        assert.equal(await orders.countDocuments(), 1);
+    });
+
+    test("calls run off the main thread, in order", async () => {
+        const col = await db.createCollection("concurrent");
+        const pending = Array.from({ length: 200 }, (_, i) => col.insertOne({ i }));
+        assert.ok(pending.every((promise) => promise instanceof Promise));
+        const records = await Promise.all(pending);
+        assert.deepEqual(records.map((record) => record._id), Array.from({ length: 200 }, (_, i) => i + 1));
+        const values = (await col.find().toArray()).map((record) => record.data.i);
+        assert.deepEqual(values, Array.from({ length: 200 }, (_, i) => i));
+    });
+
+    test("in-memory databases and closing", async () => {
+        const memory = await Database.open(":memory:");
+        const col = await memory.createCollection("scratch");
+        await col.insertOne({ a: 1 });
+        assert.equal(await col.countDocuments(), 1);
+        await memory.close();
+        assert.throws(() => col.countDocuments(), /closed/);
+    });
+});
@@ -0,0 +1,208 @@
+//! Conversion between bson and Python values.
+//!
+//! | bson | Python |
+//! |------|--------|
+//! | null | `None` |
+//! | boolean | `bool` |
+//! | int32, int64 | `int` |
+//! | double | `float` |
+//! | string | `str` |
+//! | array | `list` (from a `list` or a `tuple`) |
+//! | document | `dict` |
+//! | binary | `bytes` (from `bytes` or `bytearray`) |
+//! | datetime | `datetime.datetime` in UTC. A naive datetime is taken to be in UTC. |
+//! | decimal128 | `decimal.Decimal` |
+//! | object id | `hoardbase.ObjectId` (pymongo's `bson.ObjectId` is accepted too) |
+//!
+//! Python ints are stored as int32 when they fit, as int64 otherwise. Other bson types are returned as their relaxed extended JSON, e.g.
+//! `{"$regularExpression": {"pattern": "^a", "options": "i"}}`.
+
+use pyo3::exceptions::PyTypeError;
+use pyo3::prelude::*;
+use pyo3::types::{IntoPyDict, PyBool, PyByteArray, PyBytes, PyDateTime, PyDict, PyFloat, PyList, PyLong, PyString, PyTuple};
+
+use crate::ObjectId;
+
+fn is_instance(py: Python, value: &PyAny, module: &str, class: &str) -> PyResult<bool> {
+    let class = py.import(module)?.getattr(class)?;
+    py.import("builtins")?.getattr("isinstance")?.call1((value, class))?.is_true()
+}
+
+fn utc(py: Python) -> PyResult<&PyAny> {
+    py.import("datetime")?.getattr("timezone")?.getattr("utc")
+}
+
+pub fn to_document(py: Python, dict: &PyDict) -> PyResult<bson::Document> {
+    let mut document = bson::Document::new();
+    for (key, value) in dict.iter() {
+        let key = key.downcast::<PyString>().map_err(|_| PyTypeError::new_err(format!("Document keys must be strings, found {}", key)))?;
+        document.insert(key.to_str()?, to_bson(py, value)?);
+    }
+    Ok(document)
+}
+
+/// Converts an optional query, `None` being the query that matches everything.
+pub fn to_query(py: Python, dict: Option<&PyDict>) -> PyResult<bson::Document> {
+    match dict {
+        Some(dict) => to_document(py, dict),
+        None => Ok(bson::Document::new()),
+    }
+}
+
+pub fn to_documents(py: Python, list: &PyList) -> PyResult<Vec<bson::Document>> {
+    list.iter()
+        .map(|item| match item.downcast::<PyDict>() {
+            Ok(dict) => to_document(py, dict),
+            Err(_) => Err(PyTypeError::new_err(format!("Expected a dict, found {}", item))),
+        })
+        .collect()
+}
+
+pub fn to_bson(py: Python, value: &PyAny) -> PyResult<bson::Bson> {
+    // bool is a subclass of int, so it is checked first.
+    if value.is_none() {
+        Ok(bson::Bson::Null)
+    } else if let Ok(boolean) = value.downcast::<PyBool>() {
+        Ok(bson::Bson::Boolean(boolean.is_true()))
+    } else if value.is_instance::<PyLong>()? {
+        let integer: i64 = value.extract().map_err(|_| PyTypeError::new_err(format!("{} doesn't fit in 64 bits", value)))?;
+        Ok(match i32::try_from(integer) {
+            Ok(integer) => bson::Bson::Int32(integer),
+            Err(_) => bson::Bson::Int64(integer),
+        })
+    } else if let Ok(float) = value.downcast::<PyFloat>() {
+        Ok(bson::Bson::Double(float.value()))
+    } else if let Ok(string) = value.downcast::<PyString>() {
+        Ok(bson::Bson::String(string.to_str()?.to_string()))
+    } else if let Ok(dict) = value.downcast::<PyDict>() {
+        Ok(bson::Bson::Document(to_document(py, dict)?))
+    } else if let Ok(list) = value.downcast::<PyList>() {
+        Ok(bson::Bson::Array(list.iter().map(|item| to_bson(py, item)).collect::<PyResult<_>>()?))
+    } else if let Ok(tuple) = value.downcast::<PyTuple>() {
+        Ok(bson::Bson::Array(tuple.iter().map(|item| to_bson(py, item)).collect::<PyResult<_>>()?))
+    } else if let Ok(bytes) = value.downcast::<PyBytes>() {
+        Ok(bson::Bson::Binary(bson::Binary { subtype: bson::spec::BinarySubtype::Generic, bytes: bytes.as_bytes().to_vec() }))
+    } else if let Ok(bytes) = value.downcast::<PyByteArray>() {
+        Ok(bson::Bson::Binary(bson::Binary { subtype: bson::spec::BinarySubtype::Generic, bytes: bytes.to_vec() }))
+    } else if let Ok(datetime) = value.downcast::<PyDateTime>() {
+        let aware: &PyAny = if datetime.getattr("tzinfo")?.is_none() { datetime.call_method("replace", (), Some([("tzinfo", utc(py)?)].into_py_dict(py)))? } else { datetime };
+        let seconds: f64 = aware.call_method0("timestamp")?.extract()?;
+        Ok(bson::Bson::DateTime(bson::DateTime::from_millis((seconds * 1000.0).round() as i64)))
+    } else if let Ok(oid) = value.extract::<PyRef<ObjectId>>() {
+        Ok(bson::Bson::ObjectId(oid.oid))
+    } else if is_instance(py, value, "decimal", "Decimal")? {
+        let text: String = value.str()?.extract()?;
+        Ok(bson::Bson::Decimal128(bson::Decimal128::from_bytes(parse_decimal128(&text).map_err(PyTypeError::new_err)?)))
+    } else if value.get_type().name()? == "ObjectId" {
+        let hex: String = value.str()?.extract()?;
+        Ok(bson::Bson::ObjectId(bson::oid::ObjectId::parse_str(&hex).map_err(|e| PyTypeError::new_err(e.to_string()))?))
+    } else {
+        Err(PyTypeError::new_err(format!("Cannot convert {} of type {} to bson", value, value.get_type().name()?)))
+    }
+}
+
+pub fn to_dict(py: Python, document: &bson::Document) -> PyResult<PyObject> {
+    let dict = PyDict::new(py);
+    for (key, value) in document.iter() {
+        dict.set_item(key, to_python(py, value)?)?;
+    }
+    Ok(dict.to_object(py))
+}
+
+pub fn to_python(py: Python, value: &bson::Bson) -> PyResult<PyObject> {
+    Ok(match value {
+        bson::Bson::Null | bson::Bson::Undefined => py.None(),
+        bson::Bson::Boolean(boolean) => boolean.to_object(py),
+        bson::Bson::Int32(integer) => integer.to_object(py),
+        bson::Bson::Int64(integer) => integer.to_object(py),
+        bson::Bson::Double(float) => float.to_object(py),
+        bson::Bson::String(string) => string.to_object(py),
+        bson::Bson::Document(document) => to_dict(py, document)?,
+        bson::Bson::Array(array) => {
+            let items = array.iter().map(|item| to_python(py, item)).collect::<PyResult<Vec<PyObject>>>()?;
+            PyList::new(py, items).to_object(py)
+        }
+        bson::Bson::Binary(binary) => PyBytes::new(py, &binary.bytes).to_object(py),
+        bson::Bson::DateTime(datetime) => {
+            let seconds = datetime.timestamp_millis() as f64 / 1000.0;
+            py.import("datetime")?.getattr("datetime")?.call_method1("fromtimestamp", (seconds, utc(py)?))?.to_object(py)
+        }
+        bson::Bson::Decimal128(decimal) => py.import("decimal")?.getattr("Decimal")?.call1((format_decimal128(decimal.bytes()),))?.to_object(py),
+        bson::Bson::ObjectId(oid) => Py::new(py, ObjectId { oid: *oid })?.to_object(py),
+        other => json_to_python(py, &other.clone().into_relaxed_extjson())?,
+    })
+}
+
+fn json_to_python(py: Python, value: &serde_json::Value) -> PyResult<PyObject> {
+    Ok(match value {
+        serde_json::Value::Null => py.None(),
+        serde_json::Value::Bool(boolean) => boolean.to_object(py),
+        serde_json::Value::Number(number) => match number.as_i64() {
+            Some(integer) => integer.to_object(py),
+            None => number.as_f64().unwrap_or(f64::NAN).to_object(py),
+        },
+        serde_json::Value::String(string) => string.to_object(py),
+        serde_json::Value::Array(array) => PyList::new(py, array.iter().map(|item| json_to_python(py, item)).collect::<PyResult<Vec<PyObject>>>()?).to_object(py),
+        serde_json::Value::Object(object) => {
+            let dict = PyDict::new(py);
+            for (key, value) in object {
+                dict.set_item(key, json_to_python(py, value)?)?;
+            }
+            dict.to_object(py)
+        }
+    })
+}
+
+const EXPONENT_BIAS: i32 = 6176;
+const MAX_EXPONENT: i32 = 6111;
+const MIN_EXPONENT: i32 = -6176;
+
+/// Encodes the text of a Python `Decimal`, like `-1.50`, `2E+3`, `NaN` or `-Infinity`, as an IEEE 754-2008 decimal128 in the binary integer
+/// decimal encoding bson uses. Precision and exponent are kept, so `1.50` stays `1.50`.
+pub fn parse_decimal128(text: &str) -> Result<[u8; 16], String> {
+    let (negative, unsigned) = match text.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, text.strip_prefix('+').unwrap_or(text)),
+    };
+    let sign = if negative { 1u128 << 127 } else { 0 };
+    match unsigned.to_ascii_lowercase().as_str() {
+        "infinity" | "inf" => return Ok((sign | 0x1e << 122).to_le_bytes()),
+        "nan" | "snan" => return Ok((0x1f_u128 << 122).to_le_bytes()),
+        _ => {}
+    }
+
+    let (mantissa, exponent) = match unsigned.find(|c| c == 'e' || c == 'E') {
+        Some(position) => (&unsigned[..position], unsigned[position + 1..].parse::<i32>().map_err(|_| format!("Invalid decimal: {}", text))?),
+        None => (unsigned, 0),
+    };
+    let (integer, fraction) = mantissa.split_once('.').unwrap_or((mantissa, ""));
+    let digits = format!("{}{}", integer, fraction);
+    if digits.is_empty() || !digits.bytes().all(|b| b.is_ascii_digit()) {
+        return Err(format!("Invalid decimal: {}", text));
+    }
+    let digits = digits.trim_start_matches('0');
+    if digits.len() > 34 {
+        return Err(format!("{} has more than the 34 significant digits of a decimal128", text));
+    }
+    let coefficient: u128 = if digits.is_empty() { 0 } else { digits.parse().unwrap() };
+    let exponent = exponent - fraction.len() as i32;
+    if !(MIN_EXPONENT..=MAX_EXPONENT).contains(&exponent) {
+        return Err(format!("The exponent of {} is out of the range of a decimal128", text));
+    }
+    Ok((sign | ((exponent + EXPONENT_BIAS) as u128) << 113 | coefficient).to_le_bytes())
+}
+
+/// Formats a decimal128 in the scientific notation Python's `Decimal` parses, e.g. `-150E-2`.
+pub fn format_decimal128(bytes: [u8; 16]) -> String {
+    let bits = u128::from_le_bytes(bytes);
+    let sign = if bits >> 127 == 1 { "-" } else { "" };
+    match (bits >> 122) & 0x1f {
+        0x1f => return "NaN".to_string(),
+        0x1e => return format!("{}Infinity", sign),
+        _ => {}
+    }
+    // the form with the implicit 100 prefix can only hold coefficients above the maximum of 10^34 - 1, which are read as 0.
+    let (exponent, coefficient) = if (bits >> 125) & 0b11 == 0b11 { ((bits >> 111) & 0x3fff, 0) } else { ((bits >> 113) & 0x3fff, bits & ((1u128 << 113) - 1)) };
+    let coefficient = if coefficient > 9_999_999_999_999_999_999_999_999_999_999_999 { 0 } else { coefficient };
+    format!("{}{}E{}", sign, coefficient, exponent as i32 - EXPONENT_BIAS)
+}
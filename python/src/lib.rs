@@ -1,94 +1,169 @@
-use hoardbase::base::CollectionTrait;
-use pyo3::exceptions::PyValueError;
+use hoardbase::base::{CollectionTrait, SearchOption, UpdateOption};
+use pyo3::class::basic::CompareOp;
+use pyo3::exceptions::{PyException, PyTypeError};
 use pyo3::prelude::*;
-use pyo3::types::{PyBool, PyDateTime, PyDict, PyInt, PyList, PyString, PyTuple};
-use std::cell::RefCell;
-use std::sync::{Arc, Mutex};
-use bson::*;
+use pyo3::types::{PyBool, PyDict, PyList};
+use pyo3::{PyIterProtocol, PyObjectProtocol};
+use std::collections::VecDeque;
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
 
-#[pyclass]
-struct Database {
-    db: Arc<Mutex<hoardbase::database::Database>>,
+mod convert;
+
+pyo3::create_exception!(hoardbase, HoardbaseError, PyException);
+
+fn error(e: impl ToString) -> PyErr {
+    HoardbaseError::new_err(e.to_string())
 }
 
-#[pyclass]
-struct Collection {
-    name: String,
-    db: Arc<Mutex<hoardbase::database::Database>>,
+/// Reads an optional boolean from a config dict.
+fn flag(config: &PyDict, key: &str) -> PyResult<Option<bool>> {
+    match config.get_item(key) {
+        Some(value) => Ok(Some(value.downcast::<PyBool>().map_err(|_| PyTypeError::new_err(format!("{} must be a bool", key)))?.is_true())),
+        None => Ok(None),
+    }
+}
+
+/// Runs `f` under the database lock. The GIL is released while waiting for the lock, which a [`Transaction`] holds until it ends, so that the
+/// Python thread running the transaction isn't blocked by another one waiting for the database.
+fn locked<T: Send>(py: Python, db: &Mutex<hoardbase::database::Database>, f: impl FnOnce(&mut hoardbase::database::Database) -> PyResult<T> + Send) -> PyResult<T> {
+    py.allow_threads(|| f(&mut db.lock().unwrap()))
 }
 
-fn pydict2bson_document(dict: &PyDict, py: pyo3::prelude::Python<'_>) -> bson::Document {
-    let mut map = bson::Document::new();
-
-    for (key, value) in dict.iter() {
-        let key = key.to_string();
-        if value.is_instance::<pyo3::types::PyString>().unwrap() {
-            let value_str = value.downcast::<pyo3::types::PyString>().unwrap().to_string();
-            map.insert(key, bson::Bson::String(value_str));
-        } else if value.is_instance::<pyo3::types::PyList>().unwrap() || value.is_instance::<pyo3::types::PySet>().unwrap() {
-        } else if value.is_instance::<pyo3::types::PyFloat>().unwrap() {
-            let val = value.downcast::<pyo3::types::PyFloat>().unwrap();
-            map.insert(key, bson::Bson::Double(val.value()));
-        } else if value.is_instance::<pyo3::types::PyInt>().unwrap() {
-            let val = value.downcast::<pyo3::types::PyInt>().unwrap().to_object(py).extract::<i64>(py).unwrap();
-            map.insert(key, bson::Bson::Int64(val));
-        } else if value.is_instance::<pyo3::types::PyBool>().unwrap() {
-            let val = value.downcast::<pyo3::types::PyBool>().unwrap();
-            map.insert(key, bson::Bson::Boolean(val.is_true()));
-        } else if value.is_instance::<pyo3::types::PyDict>().unwrap() {
-            let nested = pydict2bson_document(&value.downcast::<PyDict>().unwrap(), py);
-            map.insert(key, bson::Bson::Document(nested));
-        } else {
-            // return Err(0);
+fn search_option(limit: Option<i64>, skip: Option<i64>) -> Option<SearchOption> {
+    if limit.is_none() && skip.is_none() {
+        return None;
+    }
+    let mut options = SearchOption::default();
+    options.limit(limit.unwrap_or(-1)).skip(skip.unwrap_or(0));
+    Some(options)
+}
+
+fn update_option(py: Python, array_filters: Option<&PyList>) -> PyResult<Option<UpdateOption>> {
+    match array_filters {
+        Some(filters) => {
+            let mut options = UpdateOption::default();
+            options.array_filters(convert::to_documents(py, filters)?);
+            Ok(Some(options))
         }
+        None => Ok(None),
+    }
+}
+
+/// An update is either an update document or an aggregation pipeline.
+enum UpdateSpec {
+    Document(bson::Document),
+    Pipeline(Vec<bson::Document>),
+}
+
+fn update_spec(py: Python, update: &PyAny) -> PyResult<UpdateSpec> {
+    if let Ok(dict) = update.downcast::<PyDict>() {
+        Ok(UpdateSpec::Document(convert::to_document(py, dict)?))
+    } else if let Ok(list) = update.downcast::<PyList>() {
+        Ok(UpdateSpec::Pipeline(convert::to_documents(py, list)?))
+    } else {
+        Err(PyTypeError::new_err("An update is a dict or a list of pipeline stages"))
     }
-    return map;
 }
-/*
-fn pylist2serde_json_vec(list: &PyList) -> Vec<serde_json::Value> {
 
+fn update_one(collection: &mut impl CollectionTrait, query: &bson::Document, update: &UpdateSpec, skip: i64, upsert: bool, options: &Option<UpdateOption>) -> Result<Option<hoardbase::base::Record>, String> {
+    match update {
+        UpdateSpec::Document(update) => collection.update_one(query, update, skip, upsert, options),
+        UpdateSpec::Pipeline(pipeline) => collection.update_one_pipeline(query, pipeline, skip, upsert),
+    }
 }
 
-fn pytuple2serde_json_vec(tuple: &PyTuple) -> Vec<serde_json::Value> {
+fn update_many(collection: &mut impl CollectionTrait, query: &bson::Document, update: &UpdateSpec, limit: i64, skip: i64, upsert: bool, options: &Option<UpdateOption>) -> Result<i64, String> {
+    match update {
+        UpdateSpec::Document(update) => collection.update_many(query, update, limit, skip, upsert, options),
+        UpdateSpec::Pipeline(pipeline) => collection.update_many_pipeline(query, pipeline, limit, skip, upsert),
+    }
+}
 
-}*/
+#[pyclass]
+struct Database {
+    db: Arc<Mutex<hoardbase::database::Database>>,
+}
 
 #[pymethods]
 impl Database {
+    /// Opens the database file at `path`, creating it if needed. `config` can set `trace`, `profile` and `read_only`.
     #[staticmethod]
     fn open(path: &str, config: Option<&PyDict>) -> PyResult<Self> {
         let mut db_config = hoardbase::database::DatabaseConfig::new(path);
-        if config.is_some() {
-            if let Some(config_dict) = config {
-                if let Some(trace) = config_dict.get_item("trace") {
-                    let should_trace = trace.downcast::<PyBool>();
-                    db_config.trace(should_trace.unwrap().is_true());
-                }
-                if let Some(profile) = config_dict.get_item("profile") {
-                    let should_profile = profile.downcast::<PyBool>();
-                    db_config.profile(should_profile.unwrap().is_true());
-                }
+        if let Some(config) = config {
+            if let Some(trace) = flag(config, "trace")? {
+                db_config.trace(trace);
+            }
+            if let Some(profile) = flag(config, "profile")? {
+                db_config.profile(profile);
             }
-            for (key, value) in config.unwrap().iter() {
-                println!("{} = {}", key, value);
+            if let Some(read_only) = flag(config, "read_only")? {
+                db_config.read_only(read_only);
             }
         }
 
-        println!("{}", path);
-
-        let db = hoardbase::database::Database::open(&db_config).unwrap();
+        let db = hoardbase::database::Database::open(&db_config).map_err(error)?;
         Ok(Database { db: Arc::new(Mutex::new(db)) })
     }
 
-    pub fn create_collection(&mut self, collection_name: &str, config: Option<&PyDict>) -> PyResult<Collection> {
+    /// Creates a collection, or returns it if it exists. `config` can set `hash_document`, `log_last_modified`, `hash_unique` and, for a capped
+    /// collection, `capped_max_documents` and `capped_max_bytes`.
+    pub fn create_collection(&mut self, py: Python, collection_name: &str, config: Option<&PyDict>) -> PyResult<Collection> {
         let mut ccol = hoardbase::base::CollectionConfig::default(collection_name);
-        ccol.hash_document(true);
-        ccol.log_last_modified(true);
-
-        match self.db.lock().unwrap().create_collection(collection_name, &ccol) {
-            Ok(collection) => Ok(Collection { name: collection_name.to_string(), db: self.db.clone() }),
-            Err(e) => Err(PyValueError::new_err(format!("{}", e))),
+        if let Some(config) = config {
+            if let Some(hash_document) = flag(config, "hash_document")? {
+                ccol.hash_document(hash_document);
+            }
+            if let Some(log_last_modified) = flag(config, "log_last_modified")? {
+                ccol.log_last_modified(log_last_modified);
+            }
+            if let Some(hash_unique) = flag(config, "hash_unique")? {
+                ccol.hash_unique(hash_unique);
+            }
+            let max_documents: u64 = config.get_item("capped_max_documents").map(|v| v.extract()).transpose()?.unwrap_or(0);
+            let max_bytes: u64 = config.get_item("capped_max_bytes").map(|v| v.extract()).transpose()?.unwrap_or(0);
+            ccol.capped(max_documents, max_bytes);
         }
+
+        locked(py, &self.db, |db| db.create_collection(collection_name, &ccol).map(|_| ()).map_err(error))?;
+        Ok(Collection { name: collection_name.to_string(), db: self.db.clone() })
+    }
+
+    /// Returns an existing collection.
+    pub fn collection(&self, py: Python, collection_name: &str) -> PyResult<Collection> {
+        locked(py, &self.db, |db| db.collection(collection_name).map(|_| ()).map_err(error))?;
+        Ok(Collection { name: collection_name.to_string(), db: self.db.clone() })
+    }
+
+    /// The names of the collections.
+    pub fn list_collections(&self, py: Python) -> PyResult<Vec<String>> {
+        let mut names: Vec<String> = locked(py, &self.db, |db| Ok(db.list_collections().into_iter().map(|(name, _)| name).collect()))?;
+        names.sort();
+        Ok(names)
+    }
+
+    pub fn drop_collection(&self, py: Python, collection_name: &str) -> PyResult<()> {
+        locked(py, &self.db, |db| db.drop_collection(collection_name).map_err(error))
+    }
+
+    pub fn rename_collection(&self, py: Python, old_name: &str, new_name: &str) -> PyResult<()> {
+        locked(py, &self.db, |db| db.rename_collection(old_name, new_name).map_err(error))
+    }
+
+    /// Returns a transaction, to be used as a context manager:
+    ///
+    /// ```python
+    /// with db.transaction() as tx:
+    ///     order = tx.collection('orders').insert_one({'item': 'apple'})
+    ///     tx.collection('stock').update_one({'item': 'apple'}, {'$inc': {'qty': -1}})
+    /// ```
+    ///
+    /// The transaction begins when the block is entered, and is committed when it exits, or rolled back if it raises. Its collections read
+    /// and write inside the transaction and return their results right away. The database is locked for the whole block: other calls on it,
+    /// including those of other threads, wait until the transaction ends, so the block should only use the collections of the transaction.
+    pub fn transaction(&self) -> Transaction {
+        Transaction { db: self.db.clone(), requests: Arc::new(Mutex::new(None)), status: None }
     }
 }
 
@@ -110,24 +185,482 @@ impl Record {
     }
 
     #[getter]
-    fn get_last_modified<'a>(&self, py: pyo3::prelude::Python<'a>) -> PyResult<&'a PyDateTime> {
-        Ok(PyDateTime::from_timestamp(py, self.record.last_modified.timestamp() as f64, None).unwrap())
+    fn get_last_modified(&self, py: Python) -> PyResult<PyObject> {
+        convert::to_python(py, &bson::Bson::DateTime(bson::DateTime::from_chrono(self.record.last_modified)))
     }
 
-    /*#[getter]
-    fn get_data(&self) -> PyResult<PyDict> {
-        Ok(self.record.data.clone())
-    }*/
+    #[getter]
+    fn get_data(&self, py: Python) -> PyResult<PyObject> {
+        convert::to_dict(py, &self.record.data)
+    }
+}
+
+#[pyproto]
+impl PyObjectProtocol for Record {
+    fn __repr__(&self) -> String {
+        format!("Record(id={}, data={})", self.record.id, self.record.data)
+    }
+}
+
+fn record(record: Option<hoardbase::base::Record>) -> Option<Record> {
+    record.map(|record| Record { record })
+}
+
+#[pyclass]
+struct Collection {
+    name: String,
+    db: Arc<Mutex<hoardbase::database::Database>>,
+}
+
+impl Collection {
+    fn with<T: Send>(&self, py: Python, f: impl FnOnce(&mut hoardbase::collection::Collection) -> Result<T, String> + Send) -> PyResult<T> {
+        locked(py, &self.db, |db| {
+            let mut collection = db.collection(&self.name).map_err(error)?;
+            f(&mut collection).map_err(error)
+        })
+    }
 }
 
 #[pymethods]
 impl Collection {
-    pub fn insert_one(&self, py: pyo3::prelude::Python<'_>, document: &PyDict) -> PyResult<Record> {
-        let val = pydict2bson_document(document, py);
+    #[getter]
+    fn get_name(&self) -> String {
+        self.name.clone()
+    }
+
+    pub fn insert_one(&self, py: Python, document: &PyDict) -> PyResult<Option<Record>> {
+        let document = convert::to_document(py, document)?;
+        Ok(record(self.with(py, |c| c.insert_one(&document))?))
+    }
+
+    pub fn insert_many(&self, py: Python, documents: &PyList) -> PyResult<()> {
+        let documents = convert::to_documents(py, documents)?;
+        self.with(py, |c| c.insert_many(&documents))
+    }
+
+    /// Returns a cursor over the documents matching `query`. The documents are read `batch_size` at a time, as the cursor is iterated.
+    #[args(batch_size = "100")]
+    pub fn find(&self, py: Python, query: Option<&PyDict>, limit: Option<i64>, skip: Option<i64>, batch_size: i64) -> PyResult<Cursor> {
+        Ok(Cursor {
+            name: self.name.clone(),
+            db: self.db.clone(),
+            query: convert::to_query(py, query)?,
+            remaining: limit.filter(|limit| *limit >= 0),
+            skip: skip.unwrap_or(0),
+            batch_size: batch_size.max(1),
+            batch: VecDeque::new(),
+            exhausted: false,
+        })
+    }
+
+    /// Returns the first document matching `query` after skipping `skip` of them, or `None`.
+    #[args(skip = "0")]
+    pub fn find_one(&self, py: Python, query: Option<&PyDict>, skip: i64) -> PyResult<Option<Record>> {
+        let query = convert::to_query(py, query)?;
+        let mut found = None;
+        self.with(py, |c| {
+            c.find(&query, &search_option(Some(1), Some(skip)), &mut |r| {
+                found = Some(r.clone());
+                Ok(())
+            })
+            .map_err(|e| e.to_string())
+        })?;
+        Ok(record(found))
+    }
+
+    pub fn count_documents(&self, py: Python, query: Option<&PyDict>, limit: Option<i64>, skip: Option<i64>) -> PyResult<i64> {
+        let query = convert::to_query(py, query)?;
+        self.with(py, |c| c.count_documents(&query, &search_option(limit, skip)).map_err(|e| e.to_string()))
+    }
+
+    /// Returns the number of distinct values of `field` among the documents matching `query`.
+    pub fn distinct(&self, py: Python, field: &str, query: Option<&PyDict>) -> PyResult<i64> {
+        let query = query.map(|query| convert::to_document(py, query)).transpose()?;
+        self.with(py, |c| c.distinct(field, &query, &None).map_err(|e| e.to_string()))
+    }
+
+    /// Updates the first document matching `query`. `update` is an update document or a list of pipeline stages.
+    #[args(skip = "0", upsert = "false")]
+    pub fn update_one(&self, py: Python, query: &PyDict, update: &PyAny, skip: i64, upsert: bool, array_filters: Option<&PyList>) -> PyResult<Option<Record>> {
+        let query = convert::to_document(py, query)?;
+        let update = update_spec(py, update)?;
+        let options = update_option(py, array_filters)?;
+        Ok(record(self.with(py, |c| update_one(c, &query, &update, skip, upsert, &options))?))
+    }
+
+    /// Updates the documents matching `query` and returns how many were updated. A `limit` of 0 is no limit.
+    #[args(limit = "0", skip = "0", upsert = "false")]
+    pub fn update_many(&self, py: Python, query: &PyDict, update: &PyAny, limit: i64, skip: i64, upsert: bool, array_filters: Option<&PyList>) -> PyResult<i64> {
+        let query = convert::to_document(py, query)?;
+        let update = update_spec(py, update)?;
+        let options = update_option(py, array_filters)?;
+        self.with(py, |c| update_many(c, &query, &update, limit, skip, upsert, &options))
+    }
+
+    #[args(skip = "0")]
+    pub fn replace_one(&self, py: Python, query: &PyDict, replacement: &PyDict, skip: i64) -> PyResult<Option<Record>> {
+        let query = convert::to_document(py, query)?;
+        let replacement = convert::to_document(py, replacement)?;
+        Ok(record(self.with(py, |c| c.replace_one(&query, &replacement, skip))?))
+    }
+
+    pub fn delete_one(&self, py: Python, query: &PyDict) -> PyResult<usize> {
+        let query = convert::to_document(py, query)?;
+        self.with(py, |c| c.delete_one(&query))
+    }
+
+    pub fn delete_many(&self, py: Python, query: Option<&PyDict>) -> PyResult<usize> {
+        let query = convert::to_query(py, query)?;
+        self.with(py, |c| c.delete_many(&query))
+    }
+
+    pub fn find_one_and_delete(&self, py: Python, query: &PyDict) -> PyResult<Option<Record>> {
+        let query = convert::to_document(py, query)?;
+        Ok(record(self.with(py, |c| c.find_one_and_delete(&query))?))
+    }
+
+    /// Creates an index, e.g. `create_index({'age': 1, 'name': -1}, unique=False)`.
+    #[args(unique = "false")]
+    pub fn create_index(&self, py: Python, keys: &PyDict, unique: bool) -> PyResult<()> {
+        let keys = convert::to_document(py, keys)?;
+        self.with(py, |c| c.create_index(&keys, unique))
+    }
+
+    pub fn drop_index(&self, py: Python, name: &str) -> PyResult<()> {
+        self.with(py, |c| c.drop_index(name))
+    }
+
+    /// Returns the indexes as dicts with the keys `name`, `unique` and `partial`.
+    pub fn get_indexes(&self, py: Python) -> PyResult<Vec<PyObject>> {
+        let indexes = self.with(py, |c| c.get_indexes())?;
+        indexes
+            .into_iter()
+            .map(|index| {
+                let dict = PyDict::new(py);
+                dict.set_item("name", index.name)?;
+                dict.set_item("unique", index.is_unique)?;
+                dict.set_item("partial", index.is_partial)?;
+                Ok(dict.to_object(py))
+            })
+            .collect()
+    }
 
-        let r = self.db.lock().unwrap().collection(&self.name).unwrap().insert_one(&val).unwrap();
+    pub fn reindex(&self, py: Python) -> PyResult<()> {
+        self.with(py, |c| c.reindex())
+    }
+}
+
+/// An iterator over the results of [`Collection::find()`]. It reads the documents in batches, each under the database lock, so other calls can
+/// be made while iterating; writes made in between may be seen by the later batches.
+#[pyclass]
+struct Cursor {
+    name: String,
+    db: Arc<Mutex<hoardbase::database::Database>>,
+    query: bson::Document,
+    /// How many documents are left to return, `None` for no limit.
+    remaining: Option<i64>,
+    skip: i64,
+    batch_size: i64,
+    batch: VecDeque<hoardbase::base::Record>,
+    exhausted: bool,
+}
+
+impl Cursor {
+    fn fetch(&mut self, py: Python) -> PyResult<()> {
+        let limit = self.remaining.map_or(self.batch_size, |remaining| remaining.min(self.batch_size));
+        if limit == 0 {
+            self.exhausted = true;
+            return Ok(());
+        }
+        let (name, query, skip, batch) = (&self.name, &self.query, self.skip, &mut self.batch);
+        locked(py, &self.db, |db| {
+            let mut collection = db.collection(name).map_err(error)?;
+            collection
+                .find(query, &search_option(Some(limit), Some(skip)), &mut |r| {
+                    batch.push_back(r.clone());
+                    Ok(())
+                })
+                .map_err(error)
+        })?;
+        let fetched = batch.len() as i64;
+        self.skip += fetched;
+        self.remaining = self.remaining.map(|remaining| remaining - fetched);
+        self.exhausted = fetched < limit;
+        Ok(())
+    }
+
+    fn next_record(&mut self, py: Python) -> PyResult<Option<Record>> {
+        if self.batch.is_empty() && !self.exhausted {
+            self.fetch(py)?;
+        }
+        Ok(self.batch.pop_front().map(|record| Record { record }))
+    }
+}
+
+#[pymethods]
+impl Cursor {
+    /// Reads the remaining documents into a list.
+    fn to_list(&mut self, py: Python) -> PyResult<Vec<Record>> {
+        let mut records = Vec::new();
+        while let Some(record) = self.next_record(py)? {
+            records.push(record);
+        }
+        Ok(records)
+    }
+}
+
+#[pyproto]
+impl PyIterProtocol for Cursor {
+    fn __iter__(slf: PyRef<Self>) -> PyRef<Self> {
+        slf
+    }
+
+    fn __next__(mut slf: PyRefMut<Self>) -> PyResult<Option<Record>> {
+        let py = slf.py();
+        slf.next_record(py)
+    }
+}
+
+/// A request to the thread of a [`Transaction`].
+enum Request {
+    /// Runs on the open transaction.
+    Run(Box<dyn FnOnce(&hoardbase::database::Transaction) + Send>),
+    /// Ends the transaction, committing it if `true` and rolling it back otherwise.
+    Finish(bool),
+}
+
+/// A transaction, see [`Database::transaction()`].
+///
+/// A transaction of the core borrows the database for the duration of a closure, which a Python `with` block can't express. So `__enter__`
+/// starts a thread that locks the database and runs the closure, and the collections of the transaction send it the operations to run.
+#[pyclass]
+struct Transaction {
+    db: Arc<Mutex<hoardbase::database::Database>>,
+    /// `None` outside of the `with` block.
+    requests: Arc<Mutex<Option<mpsc::Sender<Request>>>>,
+    /// Reports whether the transaction began, then whether it was committed.
+    status: Option<mpsc::Receiver<Result<(), String>>>,
+}
+
+#[pymethods]
+impl Transaction {
+    /// Begins the transaction.
+    fn __enter__(mut slf: PyRefMut<Self>, py: Python) -> PyResult<PyRefMut<Self>> {
+        if slf.status.is_some() {
+            return Err(error("The transaction has already begun"));
+        }
+        let (requests, received) = mpsc::channel::<Request>();
+        let (report, status) = mpsc::channel();
+        let db = slf.db.clone();
+        thread::spawn(move || {
+            let mut db = db.lock().unwrap();
+            let result = db.transaction(|tx| {
+                let _ = report.send(Ok(()));
+                for request in received {
+                    match request {
+                        Request::Run(run) => run(tx),
+                        Request::Finish(true) => return Ok(()),
+                        Request::Finish(false) => break,
+                    }
+                }
+                Err("The transaction was rolled back")
+            });
+            let _ = report.send(result.map_err(|e| e.to_string()));
+        });
+
+        py.allow_threads(|| status.recv()).map_err(error)?.map_err(error)?;
+        *slf.requests.lock().unwrap() = Some(requests);
+        slf.status = Some(status);
+        Ok(slf)
+    }
+
+    /// Commits the transaction, or rolls it back if the block raised. The exception, if any, is propagated.
+    fn __exit__(&mut self, py: Python, exc_type: Option<&PyAny>, _exc_value: Option<&PyAny>, _traceback: Option<&PyAny>) -> PyResult<bool> {
+        let commit = exc_type.map_or(true, |exc_type| exc_type.is_none());
+        let requests = self.requests.lock().unwrap().take().ok_or_else(|| error("The transaction hasn't begun"))?;
+        let status = self.status.take().ok_or_else(|| error("The transaction hasn't begun"))?;
+        requests.send(Request::Finish(commit)).map_err(error)?;
+        let result = py.allow_threads(|| status.recv()).map_err(error)?;
+        if commit {
+            result.map_err(error)?;
+        }
+        Ok(false)
+    }
+
+    /// Returns a collection that reads and writes in this transaction.
+    pub fn collection(&self, py: Python, collection_name: &str) -> PyResult<TransactionCollection> {
+        let collection = TransactionCollection { name: collection_name.to_string(), requests: self.requests.clone() };
+        collection.with(py, |_| Ok(()))?;
+        Ok(collection)
+    }
+}
+
+/// A collection in a [`Transaction`]. It can only be used inside the `with` block.
+#[pyclass]
+struct TransactionCollection {
+    name: String,
+    requests: Arc<Mutex<Option<mpsc::Sender<Request>>>>,
+}
+
+impl TransactionCollection {
+    /// Runs `f` on the collection, in the thread of the transaction.
+    fn with<T: Send + 'static>(&self, py: Python, f: impl FnOnce(&mut hoardbase::transaction::TransactionCollection) -> Result<T, String> + Send + 'static) -> PyResult<T> {
+        let (reply, result) = mpsc::channel();
+        let name = self.name.clone();
+        let request = Request::Run(Box::new(move |tx| {
+            let _ = reply.send(tx.collection(&name).map_err(|e| e.to_string()).and_then(|mut collection| f(&mut collection)));
+        }));
+        match self.requests.lock().unwrap().as_ref() {
+            Some(requests) => requests.send(request).map_err(error)?,
+            None => return Err(error("The transaction isn't active")),
+        }
+        py.allow_threads(|| result.recv()).map_err(error)?.map_err(error)
+    }
+}
+
+#[pymethods]
+impl TransactionCollection {
+    #[getter]
+    fn get_name(&self) -> String {
+        self.name.clone()
+    }
+
+    pub fn insert_one(&self, py: Python, document: &PyDict) -> PyResult<Option<Record>> {
+        let document = convert::to_document(py, document)?;
+        Ok(record(self.with(py, move |c| c.insert_one(&document))?))
+    }
+
+    pub fn insert_many(&self, py: Python, documents: &PyList) -> PyResult<()> {
+        let documents = convert::to_documents(py, documents)?;
+        self.with(py, move |c| c.insert_many(&documents))
+    }
+
+    /// Returns the documents matching `query` as a list, since they have to be read before the transaction ends.
+    pub fn find(&self, py: Python, query: Option<&PyDict>, limit: Option<i64>, skip: Option<i64>) -> PyResult<Vec<Record>> {
+        let query = convert::to_query(py, query)?;
+        let records = self.with(py, move |c| {
+            let mut records = Vec::new();
+            c.find(&query, &search_option(limit, skip), &mut |r| {
+                records.push(r.clone());
+                Ok(())
+            })
+            .map_err(|e| e.to_string())?;
+            Ok(records)
+        })?;
+        Ok(records.into_iter().map(|record| Record { record }).collect())
+    }
+
+    #[args(skip = "0")]
+    pub fn find_one(&self, py: Python, query: Option<&PyDict>, skip: i64) -> PyResult<Option<Record>> {
+        let query = convert::to_query(py, query)?;
+        let found = self.with(py, move |c| {
+            let mut found = None;
+            c.find(&query, &search_option(Some(1), Some(skip)), &mut |r| {
+                found = Some(r.clone());
+                Ok(())
+            })
+            .map_err(|e| e.to_string())?;
+            Ok(found)
+        })?;
+        Ok(record(found))
+    }
+
+    pub fn count_documents(&self, py: Python, query: Option<&PyDict>, limit: Option<i64>, skip: Option<i64>) -> PyResult<i64> {
+        let query = convert::to_query(py, query)?;
+        self.with(py, move |c| c.count_documents(&query, &search_option(limit, skip)).map_err(|e| e.to_string()))
+    }
+
+    #[args(skip = "0", upsert = "false")]
+    pub fn update_one(&self, py: Python, query: &PyDict, update: &PyAny, skip: i64, upsert: bool, array_filters: Option<&PyList>) -> PyResult<Option<Record>> {
+        let query = convert::to_document(py, query)?;
+        let update = update_spec(py, update)?;
+        let options = update_option(py, array_filters)?;
+        Ok(record(self.with(py, move |c| update_one(c, &query, &update, skip, upsert, &options))?))
+    }
+
+    #[args(limit = "0", skip = "0", upsert = "false")]
+    pub fn update_many(&self, py: Python, query: &PyDict, update: &PyAny, limit: i64, skip: i64, upsert: bool, array_filters: Option<&PyList>) -> PyResult<i64> {
+        let query = convert::to_document(py, query)?;
+        let update = update_spec(py, update)?;
+        let options = update_option(py, array_filters)?;
+        self.with(py, move |c| update_many(c, &query, &update, limit, skip, upsert, &options))
+    }
+
+    #[args(skip = "0")]
+    pub fn replace_one(&self, py: Python, query: &PyDict, replacement: &PyDict, skip: i64) -> PyResult<Option<Record>> {
+        let query = convert::to_document(py, query)?;
+        let replacement = convert::to_document(py, replacement)?;
+        Ok(record(self.with(py, move |c| c.replace_one(&query, &replacement, skip))?))
+    }
+
+    pub fn delete_one(&self, py: Python, query: &PyDict) -> PyResult<usize> {
+        let query = convert::to_document(py, query)?;
+        self.with(py, move |c| c.delete_one(&query))
+    }
+
+    pub fn delete_many(&self, py: Python, query: Option<&PyDict>) -> PyResult<usize> {
+        let query = convert::to_query(py, query)?;
+        self.with(py, move |c| c.delete_many(&query))
+    }
+
+    pub fn find_one_and_delete(&self, py: Python, query: &PyDict) -> PyResult<Option<Record>> {
+        let query = convert::to_document(py, query)?;
+        Ok(record(self.with(py, move |c| c.find_one_and_delete(&query))?))
+    }
+}
+
+/// A bson ObjectId. `ObjectId()` generates a new one, `ObjectId(hex)` parses one.
+#[pyclass]
+#[derive(Clone)]
+pub struct ObjectId {
+    pub oid: bson::oid::ObjectId,
+}
+
+#[pymethods]
+impl ObjectId {
+    #[new]
+    fn new(hex: Option<&str>) -> PyResult<Self> {
+        match hex {
+            Some(hex) => Ok(ObjectId { oid: bson::oid::ObjectId::parse_str(hex).map_err(|e| PyTypeError::new_err(e.to_string()))? }),
+            None => Ok(ObjectId { oid: bson::oid::ObjectId::new() }),
+        }
+    }
+
+    /// The time the ObjectId was generated at.
+    #[getter]
+    fn get_generation_time(&self, py: Python) -> PyResult<PyObject> {
+        let millis = i64::from(self.oid.timestamp().timestamp_millis());
+        convert::to_python(py, &bson::Bson::DateTime(bson::DateTime::from_millis(millis)))
+    }
+}
+
+#[pyproto]
+impl PyObjectProtocol for ObjectId {
+    fn __str__(&self) -> String {
+        self.oid.to_hex()
+    }
+
+    fn __repr__(&self) -> String {
+        format!("ObjectId('{}')", self.oid.to_hex())
+    }
+
+    fn __hash__(&self) -> isize {
+        use std::hash::{Hash, Hasher};
+        let mut hasher = std::collections::hash_map::DefaultHasher::new();
+        self.oid.hash(&mut hasher);
+        hasher.finish() as isize
+    }
 
-        Ok(Record { record: r.unwrap() })
+    fn __richcmp__(&self, other: PyRef<ObjectId>, op: CompareOp) -> PyResult<bool> {
+        let (a, b) = (self.oid.bytes(), other.oid.bytes());
+        Ok(match op {
+            CompareOp::Lt => a < b,
+            CompareOp::Le => a <= b,
+            CompareOp::Eq => a == b,
+            CompareOp::Ne => a != b,
+            CompareOp::Gt => a > b,
+            CompareOp::Ge => a >= b,
+        })
     }
 }
 
@@ -135,10 +668,15 @@ impl Collection {
 /// the `lib.name` setting in the `Cargo.toml`, else Python will not be able to
 /// import the module.
 #[pymodule]
-fn hoardbase(_py: Python, m: &PyModule) -> PyResult<()> {
+fn hoardbase(py: Python, m: &PyModule) -> PyResult<()> {
     m.add_class::<Database>()?;
     m.add_class::<Collection>()?;
     m.add_class::<Record>()?;
+    m.add_class::<Cursor>()?;
+    m.add_class::<Transaction>()?;
+    m.add_class::<TransactionCollection>()?;
+    m.add_class::<ObjectId>()?;
+    m.add("HoardbaseError", py.get_type::<HoardbaseError>())?;
 
     Ok(())
 }
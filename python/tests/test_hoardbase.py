@@ -0,0 +1,177 @@
+import datetime
+import decimal
+
+import pytest
+
+import hoardbase
+
+
+@pytest.fixture
+def db(tmp_path):
+    return hoardbase.Database.open(str(tmp_path / 'test.db'))
+
+
+@pytest.fixture
+def people(db):
+    col = db.create_collection('people')
+    col.insert_many([
+        {'name': 'Ada', 'age': 36, 'tags': ['math']},
+        {'name': 'Alan', 'age': 41, 'tags': ['math', 'crypto']},
+        {'name': 'Grace', 'age': 85, 'tags': ['navy']},
+        {'name': 'Linus', 'age': 12},
+    ])
+    return col
+
+
+def test_insert_one(db):
+    col = db.create_collection('test')
+    r = col.insert_one({'name': 'test'})
+    assert r.id == 1
+    assert len(r.hash) > 0
+    assert r.last_modified.tzinfo is not None
+    assert r.data == {'name': 'test'}
+
+
+def test_collections(db, people):
+    assert db.list_collections() == ['people']
+    assert db.collection('people').count_documents() == 4
+    db.rename_collection('people', 'persons')
+    assert db.list_collections() == ['persons']
+    with pytest.raises(hoardbase.HoardbaseError):
+        db.collection('people')
+    db.drop_collection('persons')
+    assert db.list_collections() == []
+
+
+def test_find(people):
+    names = [r.data['name'] for r in people.find({'age': {'$gt': 20}})]
+    assert names == ['Ada', 'Alan', 'Grace']
+    assert [r.data['name'] for r in people.find(limit=2, skip=1)] == ['Alan', 'Grace']
+    assert len(people.find().to_list()) == 4
+    # batches smaller than the results are read as the cursor goes.
+    cursor = people.find(batch_size=1)
+    assert next(cursor).data['name'] == 'Ada'
+    assert [r.id for r in cursor] == [2, 3, 4]
+
+
+def test_find_one_and_count(people):
+    assert people.find_one({'name': 'Grace'}).data['age'] == 85
+    assert people.find_one({'age': {'$lt': 50}}, skip=1).data['name'] == 'Alan'
+    assert people.find_one({'name': 'Eve'}) is None
+    assert people.count_documents({'age': {'$lt': 40}}) == 2
+    assert people.count_documents() == 4
+
+
+def test_unknown_operator(people):
+    with pytest.raises(hoardbase.HoardbaseError):
+        people.count_documents({'age': {'$gtee': 18}})
+
+
+def test_updates(people):
+    r = people.update_one({'name': 'Ada'}, {'$inc': {'age': 1}})
+    assert r.data['age'] == 37
+    assert people.update_many({'age': {'$gt': 18}}, {'$set': {'adult': True}}) == 3
+    assert people.count_documents({'adult': True}) == 3
+    r = people.update_one({'name': 'Edsger'}, {'$set': {'age': 72}}, upsert=True)
+    assert r.data == {'name': 'Edsger', 'age': 72}
+    people.update_one({'name': 'Alan'}, {'$set': {'tags.$[t]': 'cryptography'}}, array_filters=[{'t': 'crypto'}])
+    assert people.find_one({'name': 'Alan'}).data['tags'] == ['math', 'cryptography']
+    r = people.update_one({'name': 'Linus'}, [{'$set': {'double': {'$multiply': ['$age', 2]}}}])
+    assert r.data['double'] == 24
+    r = people.replace_one({'name': 'Linus'}, {'name': 'Linus', 'age': 13})
+    assert r.data == {'name': 'Linus', 'age': 13}
+
+
+def test_deletes(people):
+    assert people.delete_one({'name': 'Ada'}) == 1
+    assert people.find_one_and_delete({'name': 'Alan'}).data['age'] == 41
+    assert people.delete_many({'age': {'$lt': 100}}) == 2
+    assert people.count_documents() == 0
+
+
+def test_indexes(people):
+    people.create_index({'name': 1}, unique=True)
+    names = [index['name'] for index in people.get_indexes()]
+    assert 'people_name' in names
+    with pytest.raises(hoardbase.HoardbaseError):
+        people.insert_one({'name': 'Ada'})
+    people.reindex()
+    people.drop_index('people_name')
+    people.insert_one({'name': 'Ada'})
+
+
+def test_transaction(db, people):
+    db.create_collection('log')
+    with db.transaction() as tx:
+        ada = tx.collection('people').update_one({'name': 'Ada'}, {'$set': {'age': 40}})
+        assert ada.data['age'] == 40
+        tx.collection('log').insert_one({'event': 'birthday', 'of': ada.id})
+        # reads see the writes of the transaction.
+        assert tx.collection('log').count_documents() == 1
+        assert [r.data['name'] for r in tx.collection('people').find({'age': {'$gte': 40}})] == ['Ada', 'Alan', 'Grace']
+    assert people.find_one({'name': 'Ada'}).data['age'] == 40
+    assert db.collection('log').find_one().data == {'event': 'birthday', 'of': ada.id}
+
+    with pytest.raises(RuntimeError):
+        with db.transaction() as tx:
+            tx.collection('log').insert_one({'event': 'discarded'})
+            assert tx.collection('log').count_documents() == 2
+            raise RuntimeError('abort')
+    assert db.collection('log').count_documents() == 1
+    # the collections of a transaction can't be used after it ends.
+    with pytest.raises(hoardbase.HoardbaseError):
+        tx.collection('log').count_documents()
+
+    # a failing write raises inside the block, and the transaction is rolled back when the exception leaves it.
+    people.create_index({'name': 1}, unique=True)
+    with pytest.raises(hoardbase.HoardbaseError):
+        with db.transaction() as tx:
+            tx.collection('log').insert_one({'event': 'duplicate'})
+            tx.collection('people').insert_one({'name': 'Ada'})
+    assert db.collection('log').count_documents() == 1
+
+
+def test_conversion(db):
+    col = db.create_collection('types')
+    when = datetime.datetime(2021, 12, 24, 10, 30, 15, 250000, tzinfo=datetime.timezone.utc)
+    oid = hoardbase.ObjectId()
+    document = {
+        'none': None,
+        'bool': True,
+        'int': 7,
+        'big': 2 ** 40,
+        'float': 1.5,
+        'str': 'text',
+        'list': [1, 'a', [2]],
+        'tuple': (1, 2),
+        'dict': {'nested': {'deep': 1}},
+        'bytes': b'\x00\x01',
+        'bytearray': bytearray(b'\x02'),
+        'when': when,
+        'naive': datetime.datetime(2021, 1, 1),
+        'decimal': decimal.Decimal('-1.50'),
+        'infinity': decimal.Decimal('Infinity'),
+        'oid': oid,
+    }
+    data = col.insert_one(document).data
+    assert data['none'] is None
+    assert data['bool'] is True
+    assert data['int'] == 7 and data['big'] == 2 ** 40
+    assert data['float'] == 1.5
+    assert data['list'] == [1, 'a', [2]]
+    assert data['tuple'] == [1, 2]
+    assert data['dict'] == {'nested': {'deep': 1}}
+    assert data['bytes'] == b'\x00\x01' and data['bytearray'] == b'\x02'
+    assert data['when'] == when
+    assert data['naive'] == datetime.datetime(2021, 1, 1, tzinfo=datetime.timezone.utc)
+    assert str(data['decimal']) == '-1.50'
+    assert data['infinity'] == decimal.Decimal('Infinity')
+    assert data['oid'] == oid and str(data['oid']) == str(oid)
+    assert hoardbase.ObjectId(str(oid)) == oid
+
+    with pytest.raises(TypeError):
+        col.insert_one({'set': {1, 2}})
+    with pytest.raises(TypeError):
+        col.insert_one({1: 'not a string key'})
+    with pytest.raises(TypeError):
+        col.insert_one({'huge': 2 ** 70})
@@ -0,0 +1,582 @@
+//! This module runs mongodb [aggregation pipelines](https://docs.mongodb.com/manual/core/aggregation-pipeline/) on documents in memory, for
+//! example `[{"$match": {"qty": {"$gt": 5}}}, {"$group": {"_id": "$kind", "total": {"$sum": "$qty"}}}]`. The callers filter the documents with
+//! SQL first, where they can, and hand the rest of the pipeline to [`aggregate()`].
+//!
+//! The supported stages are `$match`, `$group`, `$sort`, `$skip`, `$limit`, `$count`, `$unwind`, and the stages an update pipeline supports,
+//! see [`crate::update::apply_pipeline()`]. `$lookup` and `$graphLookup` read other collections, so they need a [`Resolver`], see
+//! [`aggregate_with()`].
+
+use std::cmp::Ordering;
+use std::collections::HashMap;
+
+use crate::expression;
+use crate::matcher;
+use crate::update;
+
+/// Gives `$lookup` and `$graphLookup` access to the other collections of a database.
+pub trait Resolver {
+    /// Runs `pipeline` on the collection called `collection`. A collection that doesn't exist has no documents.
+    fn aggregate(&self, collection: &str, pipeline: &[bson::Document]) -> Result<Vec<bson::Document>, String>;
+}
+
+/// Runs `pipeline` on `documents` and returns the resulting documents. `$lookup` and `$graphLookup` fail, unless there is no document to look
+/// up for.
+pub fn aggregate(documents: Vec<bson::Document>, pipeline: &[bson::Document]) -> Result<Vec<bson::Document>, String> {
+    run(documents, pipeline, None)
+}
+
+/// Runs `pipeline` on `documents` and returns the resulting documents. `resolver` reads the collections of `$lookup` and `$graphLookup` stages.
+pub fn aggregate_with(documents: Vec<bson::Document>, pipeline: &[bson::Document], resolver: &dyn Resolver) -> Result<Vec<bson::Document>, String> {
+    run(documents, pipeline, Some(resolver))
+}
+
+fn run(documents: Vec<bson::Document>, pipeline: &[bson::Document], resolver: Option<&dyn Resolver>) -> Result<Vec<bson::Document>, String> {
+    let now = bson::DateTime::now();
+    let mut documents = documents;
+    for stage in pipeline {
+        let (name, spec) = match stage.iter().next() {
+            Some(first) if stage.len() == 1 => first,
+            _ => return Err(format!("A pipeline stage specification object must contain exactly one field: {}", stage)),
+        };
+        documents = match (name.as_str(), spec) {
+            ("$match", bson::Bson::Document(query)) => {
+                let mut matched = Vec::new();
+                for doc in documents {
+                    if matcher::matches(&doc, query)? {
+                        matched.push(doc);
+                    }
+                }
+                matched
+            }
+            ("$group", bson::Bson::Document(spec)) => group(documents, spec, now)?,
+            ("$sort", bson::Bson::Document(spec)) => {
+                sort(&mut documents, spec)?;
+                documents
+            }
+            ("$skip", count) => documents.into_iter().skip(count_argument(name, count)?).collect(),
+            ("$limit", count) => documents.into_iter().take(count_argument(name, count)?).collect(),
+            ("$count", bson::Bson::String(field)) => {
+                if field.is_empty() || field.starts_with('$') || field.contains('.') {
+                    return Err(format!("Invalid $count field name: {}", field));
+                }
+                // like mongodb, counting nothing returns no document rather than a count of 0.
+                if documents.is_empty() {
+                    Vec::new()
+                } else {
+                    vec![bson::doc! { field: documents.len() as i64 }]
+                }
+            }
+            ("$unwind", spec) => unwind(documents, spec)?,
+            ("$lookup", bson::Bson::Document(spec)) => {
+                let lookup = Lookup::parse(spec)?;
+                let mut result = Vec::with_capacity(documents.len());
+                for mut doc in documents {
+                    let resolver = resolver.ok_or("$lookup can only run on the collections of a database")?;
+                    let joined = lookup.run(&doc, resolver, now)?;
+                    update::set_path(&mut doc, lookup.as_field, Some(bson::Bson::Array(joined.into_iter().map(bson::Bson::Document).collect())))?;
+                    result.push(doc);
+                }
+                result
+            }
+            ("$graphLookup", bson::Bson::Document(spec)) => {
+                let lookup = GraphLookup::parse(spec)?;
+                let mut result = Vec::with_capacity(documents.len());
+                for mut doc in documents {
+                    let resolver = resolver.ok_or("$graphLookup can only run on the collections of a database")?;
+                    let found = lookup.run(&doc, resolver, now)?;
+                    update::set_path(&mut doc, lookup.as_field, Some(bson::Bson::Array(found.into_iter().map(bson::Bson::Document).collect())))?;
+                    result.push(doc);
+                }
+                result
+            }
+            ("$set" | "$addFields" | "$unset" | "$project" | "$replaceRoot" | "$replaceWith", _) => {
+                let context = update::UpdateContext { is_insert: false, query: None, array_filters: &[] };
+                let mut result = Vec::with_capacity(documents.len());
+                for mut doc in documents {
+                    update::apply_pipeline(&mut doc, std::slice::from_ref(stage), &context)?;
+                    result.push(doc);
+                }
+                result
+            }
+            ("$match" | "$group" | "$sort" | "$count" | "$lookup" | "$graphLookup", _) => return Err(format!("Invalid specification for {}: {}", name, spec)),
+            _ => return Err(format!("Unrecognized pipeline stage name: {}", name)),
+        };
+    }
+    Ok(documents)
+}
+
+/// A `$lookup` stage. The equality form `{"from", "localField", "foreignField", "as"}` joins the documents of `from` whose `foreignField`
+/// equals the `localField` of the document, or one of its elements if it is an array. The pipeline form `{"from", "let", "pipeline", "as"}`
+/// runs `pipeline` on `from` for each document, with the variables of `let` bound to values of the document. Both forms can be combined.
+pub(crate) struct Lookup<'a> {
+    pub(crate) from: &'a str,
+    pub(crate) local_field: Option<&'a str>,
+    pub(crate) foreign_field: Option<&'a str>,
+    variables: Option<&'a bson::Document>,
+    pipeline: Vec<bson::Document>,
+    pub(crate) as_field: &'a str,
+}
+
+impl<'a> Lookup<'a> {
+    pub(crate) fn parse(spec: &'a bson::Document) -> Result<Lookup<'a>, String> {
+        let string = |field: &str| -> Result<Option<&'a str>, String> {
+            match spec.get(field) {
+                None => Ok(None),
+                Some(bson::Bson::String(value)) => Ok(Some(value.as_str())),
+                Some(value) => Err(format!("$lookup argument '{}' must be a string, found: {}", field, value)),
+            }
+        };
+        for field in spec.keys() {
+            if !matches!(field.as_str(), "from" | "localField" | "foreignField" | "let" | "pipeline" | "as") {
+                return Err(format!("Unknown argument to $lookup: {}", field));
+            }
+        }
+        let lookup = Lookup {
+            from: string("from")?.ok_or("$lookup requires a 'from' collection")?,
+            local_field: string("localField")?,
+            foreign_field: string("foreignField")?,
+            variables: match spec.get("let") {
+                None => None,
+                Some(bson::Bson::Document(variables)) => Some(variables),
+                Some(value) => return Err(format!("$lookup argument 'let' must be an object, found: {}", value)),
+            },
+            pipeline: match spec.get("pipeline") {
+                None => Vec::new(),
+                Some(bson::Bson::Array(stages)) => stages.iter().map(|stage| stage.as_document().cloned().ok_or_else(|| format!("Each element of the 'pipeline' array must be an object: {}", stage))).collect::<Result<_, _>>()?,
+                Some(value) => return Err(format!("$lookup argument 'pipeline' must be an array, found: {}", value)),
+            },
+            as_field: string("as")?.ok_or("$lookup requires an 'as' field")?,
+        };
+        if lookup.local_field.is_some() != lookup.foreign_field.is_some() {
+            return Err("$lookup requires both or neither of 'localField' and 'foreignField'".to_string());
+        }
+        if lookup.local_field.is_none() && !spec.contains_key("pipeline") {
+            return Err("$lookup requires either 'localField' and 'foreignField', or 'pipeline'".to_string());
+        }
+        Ok(lookup)
+    }
+
+    /// Whether this is the equality form alone, which can be done with a join.
+    pub(crate) fn is_equality(&self) -> bool {
+        self.local_field.is_some() && self.variables.is_none() && self.pipeline.is_empty()
+    }
+
+    /// The query on `foreignField` that finds the documents joined to a value of `localField`.
+    pub(crate) fn equality_query(&self, local_value: Option<&bson::Bson>) -> bson::Document {
+        let foreign_field = self.foreign_field.unwrap_or_default();
+        match local_value {
+            Some(bson::Bson::Array(elements)) => bson::doc! { foreign_field: { "$in": elements.clone() } },
+            // a missing field is joined to the documents where the foreign field is null or missing.
+            Some(value) => bson::doc! { foreign_field: value.clone() },
+            None => bson::doc! { foreign_field: bson::Bson::Null },
+        }
+    }
+
+    /// Returns the documents of `from` that are joined to `doc`.
+    pub(crate) fn run(&self, doc: &bson::Document, resolver: &dyn Resolver, now: bson::DateTime) -> Result<Vec<bson::Document>, String> {
+        let mut pipeline = Vec::with_capacity(self.pipeline.len() + 1);
+        if let Some(local_field) = self.local_field {
+            pipeline.push(bson::doc! { "$match": self.equality_query(matcher::get_path(doc, local_field)) });
+        }
+        match self.variables {
+            Some(variables) if !variables.is_empty() => {
+                let vars = expression::Variables::new(doc, now);
+                let mut values = HashMap::new();
+                for (name, expr) in variables.iter() {
+                    values.insert(name.as_str(), expression::evaluate(expr, &vars)?.unwrap_or(bson::Bson::Null));
+                }
+                pipeline.extend(self.pipeline.iter().map(|stage| bind_stage(stage, &values)));
+            }
+            _ => pipeline.extend(self.pipeline.iter().cloned()),
+        }
+        resolver.aggregate(self.from, &pipeline)
+    }
+}
+
+/// Replaces the `$$name` variables of an expression with the values of `values`, as literals.
+fn bind(value: &bson::Bson, values: &HashMap<&str, bson::Bson>) -> bson::Bson {
+    match value {
+        bson::Bson::String(s) if s.starts_with("$$") => {
+            let (name, path) = match s[2..].split_once('.') {
+                Some((name, path)) => (name, Some(path)),
+                None => (&s[2..], None),
+            };
+            match (values.get(name), path) {
+                (Some(value), None) => bson::bson!({ "$literal": value.clone() }),
+                (Some(bson::Bson::Document(value)), Some(path)) => bson::bson!({ "$literal": matcher::get_path(value, path).cloned().unwrap_or(bson::Bson::Null) }),
+                (Some(_), Some(_)) => bson::bson!({ "$literal": bson::Bson::Null }),
+                (None, _) => value.clone(),
+            }
+        }
+        bson::Bson::Array(elements) => bson::Bson::Array(elements.iter().map(|element| bind(element, values)).collect()),
+        bson::Bson::Document(doc) => bson::Bson::Document(doc.iter().map(|(key, value)| (key.clone(), if key == "$literal" { value.clone() } else { bind(value, values) })).collect()),
+        _ => value.clone(),
+    }
+}
+
+/// Binds the variables of a pipeline stage. In a `$match`, only the expressions of `$expr` can use variables; the rest is a query.
+fn bind_stage(stage: &bson::Document, values: &HashMap<&str, bson::Bson>) -> bson::Document {
+    fn bind_query(query: &bson::Document, values: &HashMap<&str, bson::Bson>) -> bson::Document {
+        query
+            .iter()
+            .map(|(key, value)| {
+                let value = match (key.as_str(), value) {
+                    ("$expr", _) => bind(value, values),
+                    ("$and" | "$or" | "$nor", bson::Bson::Array(clauses)) => {
+                        bson::Bson::Array(clauses.iter().map(|clause| clause.as_document().map_or(clause.clone(), |clause| bson::Bson::Document(bind_query(clause, values)))).collect())
+                    }
+                    _ => value.clone(),
+                };
+                (key.clone(), value)
+            })
+            .collect()
+    }
+    match stage.get("$match") {
+        Some(bson::Bson::Document(query)) => bson::doc! { "$match": bind_query(query, values) },
+        _ => bind(&bson::Bson::Document(stage.clone()), values).as_document().cloned().unwrap_or_default(),
+    }
+}
+
+/// A `$graphLookup` stage, which follows the `connectFromField` of the documents of `from` to the documents whose `connectToField` equals it,
+/// starting with the values of `startWith`.
+struct GraphLookup<'a> {
+    from: &'a str,
+    start_with: &'a bson::Bson,
+    connect_from_field: &'a str,
+    connect_to_field: &'a str,
+    as_field: &'a str,
+    max_depth: Option<i64>,
+    depth_field: Option<&'a str>,
+    restrict_search_with_match: Option<&'a bson::Document>,
+}
+
+impl<'a> GraphLookup<'a> {
+    fn parse(spec: &'a bson::Document) -> Result<GraphLookup<'a>, String> {
+        let string = |field: &str| -> Result<Option<&'a str>, String> {
+            match spec.get(field) {
+                None => Ok(None),
+                Some(bson::Bson::String(value)) => Ok(Some(value.as_str())),
+                Some(value) => Err(format!("$graphLookup argument '{}' must be a string, found: {}", field, value)),
+            }
+        };
+        for field in spec.keys() {
+            if !matches!(field.as_str(), "from" | "startWith" | "connectFromField" | "connectToField" | "as" | "maxDepth" | "depthField" | "restrictSearchWithMatch") {
+                return Err(format!("Unknown argument to $graphLookup: {}", field));
+            }
+        }
+        Ok(GraphLookup {
+            from: string("from")?.ok_or("$graphLookup requires a 'from' collection")?,
+            start_with: spec.get("startWith").ok_or("$graphLookup requires a 'startWith' expression")?,
+            connect_from_field: string("connectFromField")?.ok_or("$graphLookup requires a 'connectFromField'")?,
+            connect_to_field: string("connectToField")?.ok_or("$graphLookup requires a 'connectToField'")?,
+            as_field: string("as")?.ok_or("$graphLookup requires an 'as' field")?,
+            max_depth: match spec.get("maxDepth") {
+                None => None,
+                Some(value) => Some(count_argument("maxDepth", value)? as i64),
+            },
+            depth_field: string("depthField")?,
+            restrict_search_with_match: match spec.get("restrictSearchWithMatch") {
+                None => None,
+                Some(bson::Bson::Document(query)) => Some(query),
+                Some(value) => return Err(format!("$graphLookup argument 'restrictSearchWithMatch' must be an object, found: {}", value)),
+            },
+        })
+    }
+
+    /// Returns the documents of `from` that are reachable from `doc`. Each document is returned once, at the depth it is first reached.
+    fn run(&self, doc: &bson::Document, resolver: &dyn Resolver, now: bson::DateTime) -> Result<Vec<bson::Document>, String> {
+        let mut frontier = Vec::new();
+        add_values(&mut frontier, expression::evaluate(self.start_with, &expression::Variables::new(doc, now))?);
+        let mut seen = std::collections::HashSet::new();
+        let mut visited_values = std::collections::HashSet::new();
+        let mut found = Vec::new();
+        let mut depth = 0;
+        while !frontier.is_empty() && self.max_depth.is_none_or(|max_depth| depth <= max_depth) {
+            // the values that were searched for already can't find anything new.
+            frontier.retain(|value| visited_values.insert(group_key(value)));
+            if frontier.is_empty() {
+                break;
+            }
+            let mut query = bson::doc! { self.connect_to_field: { "$in": std::mem::take(&mut frontier) } };
+            if let Some(restriction) = self.restrict_search_with_match {
+                query = bson::doc! { "$and": [query, restriction.clone()] };
+            }
+            for mut connected in resolver.aggregate(self.from, &[bson::doc! { "$match": query }])? {
+                // documents without an _id, e.g. from a view, are told apart by their content.
+                let key = match connected.get("_id") {
+                    Some(id) => group_key(id),
+                    None => group_key(&bson::Bson::Document(connected.clone())),
+                };
+                if !seen.insert(key) {
+                    continue;
+                }
+                add_values(&mut frontier, matcher::get_path(&connected, self.connect_from_field).cloned());
+                if let Some(depth_field) = self.depth_field {
+                    update::set_path(&mut connected, depth_field, Some(bson::Bson::Int64(depth)))?;
+                }
+                found.push(connected);
+            }
+            depth += 1;
+        }
+        Ok(found)
+    }
+}
+
+/// Adds a value to the values to search for, or its elements if it is an array. Null and missing values find nothing.
+fn add_values(values: &mut Vec<bson::Bson>, value: Option<bson::Bson>) {
+    match value {
+        Some(bson::Bson::Array(elements)) => values.extend(elements.into_iter().filter(|element| !matches!(element, bson::Bson::Null | bson::Bson::Undefined))),
+        None | Some(bson::Bson::Null) | Some(bson::Bson::Undefined) => {}
+        Some(value) => values.push(value),
+    }
+}
+
+fn count_argument(name: &str, value: &bson::Bson) -> Result<usize, String> {
+    match value {
+        bson::Bson::Int32(n) if *n >= 0 => Ok(*n as usize),
+        bson::Bson::Int64(n) if *n >= 0 => Ok(*n as usize),
+        bson::Bson::Double(n) if *n >= 0.0 && n.fract() == 0.0 => Ok(*n as usize),
+        _ => Err(format!("{} requires a non-negative integer, found: {}", name, value)),
+    }
+}
+
+/// Sorts by a sort specification like `{"age": -1, "name": 1}`. Missing fields sort like null. The sort is stable.
+pub(crate) fn sort(documents: &mut [bson::Document], spec: &bson::Document) -> Result<(), String> {
+    let mut keys = Vec::new();
+    for (path, direction) in spec.iter() {
+        let descending = match direction {
+            bson::Bson::Int32(1) | bson::Bson::Int64(1) => false,
+            bson::Bson::Int32(-1) | bson::Bson::Int64(-1) => true,
+            bson::Bson::Double(d) if *d == 1.0 || *d == -1.0 => *d < 0.0,
+            _ => return Err(format!("$sort key ordering must be 1 (for ascending) or -1 (for descending): {}", spec)),
+        };
+        keys.push((path.as_str(), descending));
+    }
+    if keys.is_empty() {
+        return Err("$sort stage must have at least one sort key".to_string());
+    }
+    documents.sort_by(|a, b| {
+        for (path, descending) in &keys {
+            let a = matcher::get_path(a, path).unwrap_or(&bson::Bson::Null);
+            let b = matcher::get_path(b, path).unwrap_or(&bson::Bson::Null);
+            let ordering = matcher::compare_bson(a, b);
+            if ordering != Ordering::Equal {
+                return if *descending { ordering.reverse() } else { ordering };
+            }
+        }
+        Ordering::Equal
+    });
+    Ok(())
+}
+
+fn unwind(documents: Vec<bson::Document>, spec: &bson::Bson) -> Result<Vec<bson::Document>, String> {
+    let (path, preserve) = match spec {
+        bson::Bson::String(path) => (path.as_str(), false),
+        bson::Bson::Document(options) => (options.get_str("path").map_err(|_| "$unwind requires a path")?, options.get_bool("preserveNullAndEmptyArrays").unwrap_or(false)),
+        _ => return Err(format!("$unwind requires a string or an object, found: {}", spec)),
+    };
+    let path = path.strip_prefix('$').ok_or_else(|| format!("$unwind path must be prefixed by a '$': {}", path))?;
+
+    let mut result = Vec::new();
+    for doc in documents {
+        match matcher::get_path(&doc, path).cloned() {
+            Some(bson::Bson::Array(elements)) if !elements.is_empty() => {
+                for element in elements {
+                    let mut unwound = doc.clone();
+                    update::set_path(&mut unwound, path, Some(element))?;
+                    result.push(unwound);
+                }
+            }
+            Some(bson::Bson::Array(_)) | Some(bson::Bson::Null) | None => {
+                if preserve {
+                    result.push(doc);
+                }
+            }
+            Some(_) => result.push(doc),
+        }
+    }
+    Ok(result)
+}
+
+fn as_f64(value: &bson::Bson) -> f64 {
+    match value {
+        bson::Bson::Int32(n) => *n as f64,
+        bson::Bson::Int64(n) => *n as f64,
+        bson::Bson::Double(n) => *n,
+        _ => f64::NAN,
+    }
+}
+
+/// The running state of a `$group` accumulator.
+enum Accumulator {
+    Sum(bson::Bson),
+    Avg(f64, i64),
+    Min(Option<bson::Bson>),
+    Max(Option<bson::Bson>),
+    First(Option<bson::Bson>),
+    Last(Option<bson::Bson>),
+    Push(Vec<bson::Bson>),
+    AddToSet(Vec<bson::Bson>),
+}
+
+impl Accumulator {
+    fn new(name: &str) -> Result<Accumulator, String> {
+        Ok(match name {
+            "$sum" | "$count" => Accumulator::Sum(bson::Bson::Int32(0)),
+            "$avg" => Accumulator::Avg(0.0, 0),
+            "$min" => Accumulator::Min(None),
+            "$max" => Accumulator::Max(None),
+            "$first" => Accumulator::First(None),
+            "$last" => Accumulator::Last(None),
+            "$push" => Accumulator::Push(Vec::new()),
+            "$addToSet" => Accumulator::AddToSet(Vec::new()),
+            _ => return Err(format!("unknown group operator '{}'", name)),
+        })
+    }
+
+    fn add(&mut self, value: Option<bson::Bson>) -> Result<(), String> {
+        let is_number = matches!(value, Some(bson::Bson::Int32(_) | bson::Bson::Int64(_) | bson::Bson::Double(_)));
+        let is_nullish = matches!(value, None | Some(bson::Bson::Null) | Some(bson::Bson::Undefined));
+        match self {
+            // non-numeric values are ignored by $sum and $avg.
+            Accumulator::Sum(sum) if is_number => *sum = update::add(sum, value.as_ref().unwrap())?,
+            Accumulator::Avg(sum, count) if is_number => {
+                *sum += as_f64(value.as_ref().unwrap());
+                *count += 1;
+            }
+            Accumulator::Min(min) if !is_nullish && min.as_ref().is_none_or(|min| matcher::compare_bson(value.as_ref().unwrap(), min) == Ordering::Less) => *min = value,
+            Accumulator::Max(max) if !is_nullish && max.as_ref().is_none_or(|max| matcher::compare_bson(value.as_ref().unwrap(), max) == Ordering::Greater) => *max = value,
+            Accumulator::First(first) if first.is_none() => *first = Some(value.unwrap_or(bson::Bson::Null)),
+            Accumulator::Last(last) => *last = Some(value.unwrap_or(bson::Bson::Null)),
+            Accumulator::Push(values) => {
+                if let Some(value) = value {
+                    values.push(value);
+                }
+            }
+            Accumulator::AddToSet(values) => {
+                if let Some(value) = value {
+                    if !values.iter().any(|existing| matcher::bson_equal(existing, &value)) {
+                        values.push(value);
+                    }
+                }
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn result(self) -> bson::Bson {
+        match self {
+            Accumulator::Sum(sum) => sum,
+            Accumulator::Avg(_, 0) => bson::Bson::Null,
+            Accumulator::Avg(sum, count) => bson::Bson::Double(sum / count as f64),
+            Accumulator::Min(value) | Accumulator::Max(value) | Accumulator::First(value) | Accumulator::Last(value) => value.unwrap_or(bson::Bson::Null),
+            Accumulator::Push(values) | Accumulator::AddToSet(values) => bson::Bson::Array(values),
+        }
+    }
+}
+
+/// Runs a `$group` stage. The groups are returned in the order they were first seen.
+fn group(documents: Vec<bson::Document>, spec: &bson::Document, now: bson::DateTime) -> Result<Vec<bson::Document>, String> {
+    let id = spec.get("_id").ok_or("a group specification must include an _id")?;
+    let mut fields = Vec::new();
+    for (field, accumulator) in spec.iter().filter(|(field, _)| *field != "_id") {
+        if field.contains('.') {
+            return Err(format!("the group aggregate field name '{}' cannot contain '.'", field));
+        }
+        let (name, expr) = match accumulator {
+            bson::Bson::Document(accumulator) if accumulator.len() == 1 => accumulator.iter().next().unwrap(),
+            _ => return Err(format!("the group aggregate field '{}' must be defined as an expression inside an object", field)),
+        };
+        Accumulator::new(name)?;
+        // `{"$count": {}}` counts the documents of the group.
+        let expr = if name == "$count" { bson::Bson::Int32(1) } else { expr.clone() };
+        fields.push((field.as_str(), name.as_str(), expr));
+    }
+
+    let mut positions: HashMap<String, usize> = HashMap::new();
+    let mut groups: Vec<(bson::Bson, Vec<Accumulator>)> = Vec::new();
+    for doc in &documents {
+        let vars = expression::Variables::new(doc, now);
+        let key = expression::evaluate(id, &vars)?.unwrap_or(bson::Bson::Null);
+        let position = match positions.get(&group_key(&key)) {
+            Some(position) => *position,
+            None => {
+                positions.insert(group_key(&key), groups.len());
+                groups.push((key, fields.iter().map(|(_, name, _)| Accumulator::new(name)).collect::<Result<_, _>>()?));
+                groups.len() - 1
+            }
+        };
+        for ((_, _, expr), accumulator) in fields.iter().zip(groups[position].1.iter_mut()) {
+            accumulator.add(expression::evaluate(expr, &vars)?)?;
+        }
+    }
+
+    Ok(groups
+        .into_iter()
+        .map(|(key, accumulators)| {
+            let mut result = bson::doc! { "_id": key };
+            for ((field, _, _), accumulator) in fields.iter().zip(accumulators) {
+                result.insert(*field, accumulator.result());
+            }
+            result
+        })
+        .collect())
+}
+
+/// A string that is the same for group keys that are equal. Numbers of different types but the same value fall into the same group.
+fn group_key(value: &bson::Bson) -> String {
+    match value {
+        bson::Bson::Int32(_) | bson::Bson::Int64(_) | bson::Bson::Double(_) => format!("n:{}", as_f64(value)),
+        bson::Bson::Document(doc) => format!("d:{{{}}}", doc.iter().map(|(key, value)| format!("{:?}:{}", key, group_key(value))).collect::<Vec<_>>().join(",")),
+        bson::Bson::Array(arr) => format!("a:[{}]", arr.iter().map(group_key).collect::<Vec<_>>().join(",")),
+        // an undefined or missing key groups with null.
+        bson::Bson::Undefined => group_key(&bson::Bson::Null),
+        _ => format!("{:?}", value),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bson::doc;
+
+    #[test]
+    fn test_aggregate() {
+        let documents = vec![
+            doc! { "kind": "apples", "qty": 5, "price": 1.5 },
+            doc! { "kind": "bananas", "qty": 7_i64, "price": 0.5 },
+            doc! { "kind": "apples", "qty": 3, "price": 2.0, "tags": ["red", "green"] },
+            doc! { "kind": "cherries", "price": 4.0 },
+        ];
+        let pipeline = vec![
+            doc! { "$group": { "_id": "$kind", "total": { "$sum": "$qty" }, "avg": { "$avg": "$price" }, "n": { "$count": {} }, "max": { "$max": "$qty" } } },
+            doc! { "$sort": { "total": -1, "_id": 1 } },
+        ];
+        assert_eq!(
+            aggregate(documents.clone(), &pipeline).unwrap(),
+            vec![
+                doc! { "_id": "apples", "total": 8, "avg": 1.75, "n": 2, "max": 5 },
+                doc! { "_id": "bananas", "total": 7_i64, "avg": 0.5, "n": 1, "max": 7_i64 },
+                doc! { "_id": "cherries", "total": 0, "avg": 4.0, "n": 1, "max": bson::Bson::Null },
+            ]
+        );
+
+        let pipeline = vec![doc! { "$match": { "kind": "apples" } }, doc! { "$unwind": "$tags" }, doc! { "$project": { "_id": 0, "tags": 1 } }];
+        assert_eq!(aggregate(documents.clone(), &pipeline).unwrap(), vec![doc! { "tags": "red" }, doc! { "tags": "green" }]);
+
+        let pipeline = vec![doc! { "$sort": { "price": 1 } }, doc! { "$skip": 1 }, doc! { "$limit": 2 }, doc! { "$set": { "cost": { "$multiply": ["$price", 2] } } }];
+        let result = aggregate(documents.clone(), &pipeline).unwrap();
+        assert_eq!(result.iter().map(|doc| doc.get_f64("cost").unwrap()).collect::<Vec<_>>(), vec![3.0, 4.0]);
+
+        // 1 and 1.0 are the same group.
+        let numbers = vec![doc! { "k": 1 }, doc! { "k": 1.0 }, doc! { "k": 2_i64 }];
+        assert_eq!(aggregate(numbers, &[doc! { "$group": { "_id": "$k" } }]).unwrap().len(), 2);
+
+        assert_eq!(aggregate(documents.clone(), &[doc! { "$count": "n" }]).unwrap(), vec![doc! { "n": 4_i64 }]);
+        assert!(aggregate(documents.clone(), &[doc! { "$match": { "kind": "kiwis" } }, doc! { "$count": "n" }]).unwrap().is_empty());
+        assert!(aggregate(documents.clone(), &[doc! { "$bogus": {} }]).is_err());
+        assert!(aggregate(documents.clone(), &[doc! { "$group": { "total": { "$sum": 1 } } }]).is_err());
+        assert!(aggregate(documents, &[doc! { "$group": { "_id": null, "x": { "$median": 1 } } }]).is_err());
+    }
+}
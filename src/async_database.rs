@@ -0,0 +1,315 @@
+//! An asynchronous interface to hoardbase, enabled by the `async` cargo feature.
+//!
+//! A rusqlite connection can't be shared between threads, and every call into it blocks. [`AsyncDatabase`] therefore owns a [`Database`] on a dedicated
+//! thread and sends it work as closures. Each operation returns a future that resolves once the database thread has executed it, so async code never
+//! blocks on sqlite. The futures don't depend on a particular runtime; they work with tokio as well as with `futures::executor::block_on`.
+//!
+//! ```rust
+//! use hoardbase::async_database::AsyncDatabase;
+//! use hoardbase::base::CollectionConfig;
+//! use hoardbase::database::DatabaseConfig;
+//! use futures::StreamExt;
+//!
+//! futures::executor::block_on(async {
+//!     let db = AsyncDatabase::open(&DatabaseConfig::in_memory()).await.unwrap();
+//!     let collection = db.create_collection("fruits", &CollectionConfig::default("fruits")).await.unwrap();
+//!     collection.insert_one(&bson::doc! {"kind": "apples", "qty": 5}).await.unwrap();
+//!
+//!     let mut records = collection.find(&bson::doc! {"kind": "apples"}, &None);
+//!     while let Some(record) = records.next().await {
+//!         println!("{}", record.unwrap());
+//!     }
+//! });
+//! ```
+
+use std::collections::VecDeque;
+
+use futures::channel::oneshot;
+use futures::{Future, Stream};
+
+use crate::base::*;
+use crate::database::{Database, DatabaseConfig};
+
+/// A unit of work executed on the database thread.
+type Job = Box<dyn FnOnce(&mut Database) + Send>;
+
+/// The number of records a [`AsyncCollection::find()`] stream reads from the database thread at a time.
+const FIND_BATCH_SIZE: i64 = 64;
+
+fn stopped() -> String {
+    "the database thread has stopped".to_string()
+}
+
+/// An asynchronous handle to a database. The handle is cheap to clone, and all clones send their work to the same database thread. The thread
+/// closes the database when the last handle, including the handles held by [`AsyncCollection`]s, is dropped.
+#[derive(Clone)]
+pub struct AsyncDatabase {
+    sender: std::sync::mpsc::Sender<Job>,
+    path: String,
+}
+
+impl AsyncDatabase {
+    /// Opens a database on a new thread.
+    pub async fn open(config: &DatabaseConfig) -> Result<AsyncDatabase, String> {
+        let (sender, receiver) = std::sync::mpsc::channel::<Job>();
+        let (ready_sender, ready_receiver) = oneshot::channel::<Result<(), String>>();
+        let config = config.clone();
+        let path = config.path.clone();
+
+        std::thread::Builder::new()
+            .name(format!("hoardbase {}", path))
+            .spawn(move || {
+                let mut db = match Database::open(&config) {
+                    Ok(db) => db,
+                    Err(e) => {
+                        let _ = ready_sender.send(Err(e));
+                        return;
+                    }
+                };
+                let _ = ready_sender.send(Ok(()));
+                for job in receiver {
+                    job(&mut db);
+                }
+            })
+            .map_err(|e| e.to_string())?;
+
+        ready_receiver.await.map_err(|_| stopped())??;
+        Ok(AsyncDatabase { sender, path })
+    }
+
+    /// Runs `f` on the database thread and returns a future of its result.
+    pub fn run<T, F>(&self, f: F) -> impl Future<Output = Result<T, String>> + Send + 'static
+    where
+        T: Send + 'static,
+        F: FnOnce(&mut Database) -> Result<T, String> + Send + 'static,
+    {
+        let (result_sender, result_receiver) = oneshot::channel();
+        let submitted = self
+            .sender
+            .send(Box::new(move |db: &mut Database| {
+                let _ = result_sender.send(f(db));
+            }))
+            .map_err(|_| stopped());
+
+        async move {
+            submitted?;
+            result_receiver.await.map_err(|_| stopped())?
+        }
+    }
+
+    /// Obtain the filepath of this database.
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    /// Create a collection given its config. See [`Database::create_collection()`].
+    pub fn create_collection(&self, collection_name: &str, config: &CollectionConfig) -> impl Future<Output = Result<AsyncCollection, String>> + Send + 'static {
+        let handle = self.clone();
+        let name = collection_name.to_string();
+        let config = config.clone();
+        let created = self.run(move |db| db.create_collection(&name, &config).map(|_| name.clone()).map_err(|e| e.to_string()));
+        async move { Ok(AsyncCollection { db: handle, name: created.await? }) }
+    }
+
+    /// Obtain an existing collection given a name.
+    pub fn collection(&self, collection_name: &str) -> impl Future<Output = Result<AsyncCollection, String>> + Send + 'static {
+        let handle = self.clone();
+        let name = collection_name.to_string();
+        let found = self.run(move |db| db.collection(&name).map(|_| name.clone()).map_err(|e| e.to_string()));
+        async move { Ok(AsyncCollection { db: handle, name: found.await? }) }
+    }
+
+    /// List existing collections
+    pub fn list_collections(&self) -> impl Future<Output = Result<Vec<(String, CollectionConfig)>, String>> + Send + 'static {
+        self.run(|db| Ok(db.list_collections()))
+    }
+
+    /// Drop a collection
+    pub fn drop_collection(&self, collection_name: &str) -> impl Future<Output = Result<(), String>> + Send + 'static {
+        let name = collection_name.to_string();
+        self.run(move |db| db.drop_collection(&name).map_err(|e| e.to_string()))
+    }
+
+    /// Rename collection
+    pub fn rename_collection(&self, collection_old_name: &str, collection_new_name: &str) -> impl Future<Output = Result<(), String>> + Send + 'static {
+        let old_name = collection_old_name.to_string();
+        let new_name = collection_new_name.to_string();
+        self.run(move |db| db.rename_collection(&old_name, &new_name).map_err(|e| e.to_string()))
+    }
+}
+
+/// The asynchronous counterpart of [`crate::collection::Collection`]. It provides the operations of [`CollectionTrait`], each returning a future.
+/// Unlike a `Collection`, it doesn't borrow the database, so it can be moved into spawned tasks.
+#[derive(Clone)]
+pub struct AsyncCollection {
+    db: AsyncDatabase,
+    name: String,
+}
+
+impl AsyncCollection {
+    /// Runs `f` with this collection on the database thread.
+    fn run<T, F>(&self, f: F) -> impl Future<Output = Result<T, String>> + Send + 'static
+    where
+        T: Send + 'static,
+        F: FnOnce(&mut dyn CollectionTrait) -> Result<T, String> + Send + 'static,
+    {
+        let name = self.name.clone();
+        self.db.run(move |db| {
+            let mut collection = db.collection(&name).map_err(|e| e.to_string())?;
+            f(&mut collection)
+        })
+    }
+
+    pub fn get_name(&self) -> &str {
+        &self.name
+    }
+
+    /// Returns the records matching `query` as a stream. The records are read in batches of [`FIND_BATCH_SIZE`], each batch a job of its own, so the
+    /// database thread never waits for the consumer and other operations can be awaited while the stream is consumed. A batch starts where the
+    /// previous one ended, so inserts and deletes made in between may shift the records seen by the later batches.
+    pub fn find(&self, query: &bson::Document, options: &Option<SearchOption>) -> impl Stream<Item = Result<Record, String>> + Send + Unpin + 'static {
+        let options = options.unwrap_or(SearchOption::default());
+        let state = FindState { collection: self.clone(), query: query.clone(), skip: options.skip, remaining: options.limit, batch: VecDeque::new(), done: false };
+
+        Box::pin(futures::stream::unfold(state, |mut state| async move {
+            if state.batch.is_empty() && !state.done {
+                let limit = if state.remaining < 0 { FIND_BATCH_SIZE } else { state.remaining.min(FIND_BATCH_SIZE) };
+                if limit == 0 {
+                    return None;
+                }
+                let (query, skip) = (state.query.clone(), state.skip);
+                let read = state
+                    .collection
+                    .run(move |c| {
+                        let mut records = Vec::new();
+                        c.find(&query, &Some(*SearchOption::default().limit(limit).skip(skip)), &mut |record: &Record| {
+                            records.push(record.clone());
+                            Ok(())
+                        })
+                        .map_err(|e| e.to_string())?;
+                        Ok(records)
+                    })
+                    .await;
+                match read {
+                    Ok(records) => {
+                        let read = records.len() as i64;
+                        state.done = read < limit;
+                        state.skip += read;
+                        if state.remaining > 0 {
+                            state.remaining -= read;
+                        }
+                        state.batch.extend(records);
+                    }
+                    Err(e) => {
+                        state.done = true;
+                        return Some((Err(e), state));
+                    }
+                }
+            }
+            state.batch.pop_front().map(|record| (Ok(record), state))
+        }))
+    }
+
+    pub fn explain(&self, query: &bson::Document, options: &Option<SearchOption>, verbosity: ExplainVerbosity) -> impl Future<Output = Result<QueryPlan, String>> + Send + 'static {
+        let (query, options) = (query.clone(), *options);
+        self.run(move |c| c.explain(&query, &options, verbosity))
+    }
+
+    pub fn count_documents(&self, query: &bson::Document, options: &Option<SearchOption>) -> impl Future<Output = Result<i64, String>> + Send + 'static {
+        let (query, options) = (query.clone(), *options);
+        self.run(move |c| c.count_documents(&query, &options).map_err(|e| e.to_string()))
+    }
+
+    pub fn create_index(&self, config: &bson::Document, is_unique: bool) -> impl Future<Output = Result<(), String>> + Send + 'static {
+        let config = config.clone();
+        self.run(move |c| c.create_index(&config, is_unique))
+    }
+
+    pub fn delete_one(&self, query: &bson::Document) -> impl Future<Output = Result<usize, String>> + Send + 'static {
+        let query = query.clone();
+        self.run(move |c| c.delete_one(&query))
+    }
+
+    pub fn changes(&self) -> impl Future<Output = Result<i64, String>> + Send + 'static {
+        self.run(|c| c.changes())
+    }
+
+    pub fn delete_many(&self, query: &bson::Document) -> impl Future<Output = Result<usize, String>> + Send + 'static {
+        let query = query.clone();
+        self.run(move |c| c.delete_many(&query))
+    }
+
+    pub fn distinct(&self, field: &str, query: &Option<bson::Document>, options: &Option<SearchOption>) -> impl Future<Output = Result<i64, String>> + Send + 'static {
+        let (field, query, options) = (field.to_string(), query.clone(), *options);
+        self.run(move |c| c.distinct(&field, &query, &options).map_err(|e| e.to_string()))
+    }
+
+    pub fn drop_index(&self, index_name: &str) -> impl Future<Output = Result<(), String>> + Send + 'static {
+        let index_name = index_name.to_string();
+        self.run(move |c| c.drop_index(&index_name))
+    }
+
+    pub fn find_one(&self, query: &bson::Document, skip: i64) -> impl Future<Output = Result<Record, String>> + Send + 'static {
+        let query = query.clone();
+        self.run(move |c| c.find_one(&query, skip).map_err(|e| e.to_string()))
+    }
+
+    pub fn find_one_and_delete(&self, query: &bson::Document) -> impl Future<Output = Result<Option<Record>, String>> + Send + 'static {
+        let query = query.clone();
+        self.run(move |c| c.find_one_and_delete(&query))
+    }
+
+    pub fn get_indexes(&self) -> impl Future<Output = Result<Vec<Index>, String>> + Send + 'static {
+        self.run(|c| c.get_indexes())
+    }
+
+    pub fn insert_one(&self, document: &bson::Document) -> impl Future<Output = Result<Option<Record>, String>> + Send + 'static {
+        let document = document.clone();
+        self.run(move |c| c.insert_one(&document))
+    }
+
+    pub fn insert_many(&self, documents: &Vec<bson::Document>) -> impl Future<Output = Result<(), String>> + Send + 'static {
+        let documents = documents.clone();
+        self.run(move |c| c.insert_many(&documents))
+    }
+
+    pub fn reindex(&self) -> impl Future<Output = Result<(), String>> + Send + 'static {
+        self.run(|c| c.reindex())
+    }
+
+    pub fn replace_one(&self, query: &bson::Document, replacement: &bson::Document, skip: i64) -> impl Future<Output = Result<Option<Record>, String>> + Send + 'static {
+        let (query, replacement) = (query.clone(), replacement.clone());
+        self.run(move |c| c.replace_one(&query, &replacement, skip))
+    }
+
+    pub fn update_one(&self, query: &bson::Document, update: &bson::Document, skip: i64, upsert: bool, options: &Option<UpdateOption>) -> impl Future<Output = Result<Option<Record>, String>> + Send + 'static {
+        let (query, update, options) = (query.clone(), update.clone(), options.clone());
+        self.run(move |c| c.update_one(&query, &update, skip, upsert, &options))
+    }
+
+    pub fn update_many(&self, query: &bson::Document, update: &bson::Document, limit: i64, skip: i64, upsert: bool, options: &Option<UpdateOption>) -> impl Future<Output = Result<i64, String>> + Send + 'static {
+        let (query, update, options) = (query.clone(), update.clone(), options.clone());
+        self.run(move |c| c.update_many(&query, &update, limit, skip, upsert, &options))
+    }
+
+    pub fn update_one_pipeline(&self, query: &bson::Document, pipeline: &Vec<bson::Document>, skip: i64, upsert: bool) -> impl Future<Output = Result<Option<Record>, String>> + Send + 'static {
+        let (query, pipeline) = (query.clone(), pipeline.clone());
+        self.run(move |c| c.update_one_pipeline(&query, &pipeline, skip, upsert))
+    }
+
+    pub fn update_many_pipeline(&self, query: &bson::Document, pipeline: &Vec<bson::Document>, limit: i64, skip: i64, upsert: bool) -> impl Future<Output = Result<i64, String>> + Send + 'static {
+        let (query, pipeline) = (query.clone(), pipeline.clone());
+        self.run(move |c| c.update_many_pipeline(&query, &pipeline, limit, skip, upsert))
+    }
+}
+
+/// The state of a [`AsyncCollection::find()`] stream between batches.
+struct FindState {
+    collection: AsyncCollection,
+    query: bson::Document,
+    skip: i64,
+    /// The number of records left to read, negative if there is no limit.
+    remaining: i64,
+    batch: VecDeque<Record>,
+    done: bool,
+}
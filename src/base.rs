@@ -12,7 +12,10 @@ use std::cell::RefCell;
 use std::rc::Rc;
 use std::rc::Weak;
 
+use crate::identifier::{field_literal, quote_identifier};
 use crate::query_translator::QueryTranslator;
+use crate::time_series::TimeSeriesConfig;
+use crate::view::ViewConfig;
 
 #[derive(Debug, Clone, Copy)]
 pub struct SearchOption {
@@ -47,6 +50,25 @@ macro_rules! search_option {
     };
 }
 
+/// Extra options for [`CollectionTrait::update_one()`] and [`CollectionTrait::update_many()`]. This struct uses the builder pattern.
+#[derive(Debug, Clone)]
+pub struct UpdateOption {
+    /// The filters that select the array elements updated by the filtered positional operator `$[<identifier>]`, for example
+    /// `{"elem": {"$gte": 100}}` for the path `grades.$[elem]`.
+    pub array_filters: Vec<bson::Document>,
+}
+
+impl UpdateOption {
+    pub fn default() -> Self {
+        UpdateOption { array_filters: Vec::new() }
+    }
+
+    pub fn array_filters<'a>(&'a mut self, args: Vec<bson::Document>) -> &'a mut UpdateOption {
+        self.array_filters = args;
+        self
+    }
+}
+
 #[derive(Clone, Debug)]
 pub struct CollectionConfig {
     pub name: String,
@@ -54,6 +76,14 @@ pub struct CollectionConfig {
     pub should_hash_document: bool,
     pub should_log_last_modified: bool,
     pub should_hash_unique: bool,
+    /// The maximum number of documents of a capped collection, 0 for no limit. See [`CollectionConfig::capped()`].
+    pub capped_max_documents: u64,
+    /// The maximum total size in bytes of the documents of a capped collection, 0 for no limit.
+    pub capped_max_bytes: u64,
+    /// The options of a time-series collection, see [`crate::database::Database::create_time_series()`]. `None` for other collections.
+    pub time_series: Option<TimeSeriesConfig>,
+    /// The definition of a view, see [`crate::database::Database::create_view()`]. `None` for other collections.
+    pub view: Option<ViewConfig>,
 }
 
 impl CollectionConfig {
@@ -64,6 +94,10 @@ impl CollectionConfig {
             should_hash_document: true,
             should_log_last_modified: true,
             should_hash_unique: false,
+            capped_max_documents: 0,
+            capped_max_bytes: 0,
+            time_series: None,
+            view: None,
         }
     }
 
@@ -81,6 +115,20 @@ impl CollectionConfig {
         self.should_hash_unique = args;
         self
     }
+
+    /// Makes the collection a capped collection, which holds at most `max_documents` documents of at most `max_bytes` bytes in total, like a ring
+    /// buffer. When an insert goes over either limit, the oldest documents are evicted. A limit of 0 is no limit. Capped collections are read in
+    /// insertion order, and can be followed with a [`crate::capped::TailableCursor`].
+    pub fn capped<'a>(&'a mut self, max_documents: u64, max_bytes: u64) -> &'a mut CollectionConfig {
+        self.capped_max_documents = max_documents;
+        self.capped_max_bytes = max_bytes;
+        self
+    }
+
+    /// Whether this is the config of a capped collection.
+    pub fn is_capped(&self) -> bool {
+        self.capped_max_documents > 0 || self.capped_max_bytes > 0
+    }
 }
 
 #[derive(Clone, Debug)]
@@ -98,6 +146,21 @@ impl std::fmt::Display for Index {
     }
 }
 
+/// Storage statistics of a collection, see [`crate::database::Database::collection_stats()`].
+#[derive(Clone, Debug)]
+pub struct CollectionStats {
+    /// The number of documents.
+    pub count: i64,
+    /// The total size of the documents' bson in bytes.
+    pub size: i64,
+    /// The size of the pages the collection's table occupies in bytes, including their unused space.
+    pub storage_size: i64,
+    /// The size of the pages of each index in bytes, by index name.
+    pub index_sizes: Vec<(String, i64)>,
+    /// The sum of the index sizes.
+    pub total_index_size: i64,
+}
+
 #[derive(Debug, Clone)]
 pub struct Record {
     pub id: i64,
@@ -112,8 +175,85 @@ impl std::fmt::Display for Record {
     }
 }
 
+/// How much information [`CollectionTrait::explain()`] collects. The names follow mongodb's explain verbosity modes.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ExplainVerbosity {
+    /// Only plan the query. The query is not executed.
+    QueryPlanner,
+    /// Plan the query and execute it to collect [`ExecutionStats`].
+    ExecutionStats,
+}
+
+/// A node of the tree printed by sqlite's `EXPLAIN QUERY PLAN`, for example `SEARCH test USING INDEX age (<expr>>?)`.
+#[derive(Debug, Clone)]
+pub struct PlanStep {
+    pub id: i64,
+    pub parent: i64,
+    pub detail: String,
+    pub children: Vec<PlanStep>,
+}
+
+/// Statistics collected by executing a query. They are read from sqlite's statement status counters.
+#[derive(Debug, Clone)]
+pub struct ExecutionStats {
+    /// The number of documents returned by the query.
+    pub rows_returned: i64,
+    /// The number of rows stepped through in full table scans. This is 0 if every table was searched with an index.
+    pub rows_scanned: i64,
+    /// The number of sort operations sqlite performed. A non-zero value means there is no index that can provide the order.
+    pub sorts: i64,
+    /// The number of rows inserted into automatic indexes, which sqlite creates on the fly when an index is missing.
+    pub auto_index_rows: i64,
+    /// The number of virtual machine operations, which is a rough measure of the total work done.
+    pub vm_steps: i64,
+    pub elapsed: std::time::Duration,
+}
+
+/// The result of [`CollectionTrait::explain()`].
+#[derive(Debug, Clone)]
+pub struct QueryPlan {
+    /// The SQL statement that [`CollectionTrait::find()`] would execute.
+    pub sql: String,
+    /// The parameters bound to the statement.
+    pub params: Vec<rusqlite::types::Value>,
+    /// The top level steps of the query plan.
+    pub plan: Vec<PlanStep>,
+    /// The names of the indexes used by the query plan. Indexes created by [`CollectionTrait::create_index()`] are named by the table name followed by the
+    /// slugified indexed fields, for example `people_age` or `people_address_city`.
+    pub indexes: Vec<String>,
+    /// Only present if the query was explained with [`ExplainVerbosity::ExecutionStats`].
+    pub stats: Option<ExecutionStats>,
+}
+
+impl std::fmt::Display for QueryPlan {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        fn write_steps(f: &mut std::fmt::Formatter<'_>, steps: &[PlanStep], prefix: &str) -> std::fmt::Result {
+            for (i, step) in steps.iter().enumerate() {
+                let is_last = i + 1 == steps.len();
+                writeln!(f, "{}{}{}", prefix, if is_last { "`--" } else { "|--" }, step.detail)?;
+                write_steps(f, &step.children, &format!("{}{}", prefix, if is_last { "   " } else { "|  " }))?;
+            }
+            Ok(())
+        }
+
+        writeln!(f, "{}", self.sql)?;
+        writeln!(f, "QUERY PLAN")?;
+        write_steps(f, &self.plan, "")?;
+        if let Some(stats) = &self.stats {
+            write!(
+                f,
+                "rows returned: {}, rows scanned: {}, sorts: {}, auto index rows: {}, vm steps: {}, elapsed: {:?}",
+                stats.rows_returned, stats.rows_scanned, stats.sorts, stats.auto_index_rows, stats.vm_steps, stats.elapsed
+            )?;
+        }
+        Ok(())
+    }
+}
+
 pub trait CollectionTrait {
     fn find(&mut self, query: &bson::Document, options: &Option<SearchOption>, f: &mut dyn FnMut(&Record) -> std::result::Result<(), &'static str>) -> std::result::Result<(), &str>;
+    /// Explains how [`CollectionTrait::find()`] would execute a query: the generated SQL, its parameters, sqlite's query plan and the indexes it uses.
+    fn explain(&mut self, query: &bson::Document, options: &Option<SearchOption>, verbosity: ExplainVerbosity) -> std::result::Result<QueryPlan, String>;
     fn get_name(&self) -> &str;
     fn get_table_name(&self) -> &str;
 
@@ -139,9 +279,15 @@ pub trait CollectionTrait {
     fn reindex(&mut self) -> std::result::Result<(), String>;
     fn replace_one(&mut self, query: &bson::Document, replacement: &bson::Document, skip: i64) -> std::result::Result<Option<Record>, String>;
 
-    fn update_one(&mut self, query: &bson::Document, update: &bson::Document, skip: i64, upsert: bool) -> std::result::Result<Option<Record>, String>;
+    fn update_one(&mut self, query: &bson::Document, update: &bson::Document, skip: i64, upsert: bool, options: &Option<UpdateOption>) -> std::result::Result<Option<Record>, String>;
+
+    fn update_many(&mut self, query: &bson::Document, update: &bson::Document, limit: i64, skip: i64, upsert: bool, options: &Option<UpdateOption>) -> Result<i64, String>;
 
-    fn update_many(&mut self, query: &bson::Document, update: &bson::Document, limit: i64, skip: i64, upsert: bool) -> Result<i64, String>;
+    /// Same as [`CollectionTrait::update_one()`], but the update is an aggregation pipeline, for example `[{"$set": {"total": {"$add": ["$a", "$b"]}}}]`.
+    fn update_one_pipeline(&mut self, query: &bson::Document, pipeline: &Vec<bson::Document>, skip: i64, upsert: bool) -> std::result::Result<Option<Record>, String>;
+
+    /// Same as [`CollectionTrait::update_many()`], but the update is an aggregation pipeline.
+    fn update_many_pipeline(&mut self, query: &bson::Document, pipeline: &Vec<bson::Document>, limit: i64, skip: i64, upsert: bool) -> Result<i64, String>;
 }
 
 pub trait Adapter<A> {
@@ -178,10 +324,10 @@ impl<'conn> Adapter<rusqlite::Transaction<'conn>> for rusqlite::Transaction<'con
     }
 }
 
-#[inline]
-pub fn find_internal<A, C: Adapter<A>, const H: bool, const L: bool>(conn: &C, config: &CollectionConfig, query: &bson::Document, options: &Option<SearchOption>, f: &mut dyn FnMut(&Record) -> std::result::Result<(), &'static str>) -> std::result::Result<(), &'static str> {
-    let mut params = Vec::<rusqlite::types::Value>::new();
-    let where_str: String = QueryTranslator {}.query_document(query, &mut params).unwrap();
+/// Composes the SQL statement of a find. It is shared by [`find_internal()`] and [`explain_internal()`], so that the explained statement is always
+/// the one that gets executed.
+fn find_sql(config: &CollectionConfig, query: &bson::Document, options: &Option<SearchOption>, params: &mut Vec<rusqlite::types::Value>) -> std::result::Result<String, String> {
+    let where_str: String = QueryTranslator {}.query_document(query, params)?;
 
     let mut option_str = String::new();
 
@@ -189,107 +335,156 @@ pub fn find_internal<A, C: Adapter<A>, const H: bool, const L: bool>(conn: &C, c
         option_str = format!("LIMIT {} OFFSET {}", opt.limit, opt.skip);
     }
 
-    let mut stmt = conn.prepare_cached_wrapper(&format!("SELECT * FROM [{}] {} {};", &config.name, if where_str.len() > 0 { format!("WHERE {}", &where_str) } else { String::from("") }, option_str)).unwrap();
+    Ok(format!("SELECT * FROM [{}] {} {} {};", &config.table_name, if where_str.len() > 0 { format!("WHERE {}", &where_str) } else { String::from("") }, natural_order(config), option_str))
+}
 
-    let mut rows = stmt.query(params_from_iter(params.iter())).unwrap();
+/// Capped collections are read in insertion order, which is the order of `_id`. Other collections have no defined order.
+fn natural_order(config: &CollectionConfig) -> &'static str {
+    if config.is_capped() {
+        "ORDER BY _id"
+    } else {
+        ""
+    }
+}
 
-    while let Ok(row_result) = rows.next() {
-        if let Some(row) = row_result {
-            let id = row.get::<_, i64>(0).unwrap();
-            let bson_doc: bson::Document = bson::from_reader(row.get::<_, Vec<u8>>(1).unwrap().as_slice()).unwrap();
+/// Reads a record from a row of a collection's table, whose columns depend on the collection's config.
+pub(crate) fn read_record<const H: bool, const L: bool>(row: &rusqlite::Row) -> Record {
+    let id = row.get::<_, i64>(0).unwrap();
+    let bson_doc: bson::Document = bson::from_reader(row.get::<_, Vec<u8>>(1).unwrap().as_slice()).unwrap();
 
-            let record = match (H, L) {
-                (false, false) => Record {
-                    id: id,
-                    data: bson_doc,
-                    hash: String::new(),
-                    last_modified: Utc.timestamp(0, 0),
-                },
-                (true, false) => {
-                    let hash = row.get::<_, String>(2).unwrap();
-                    Record { id: id, data: bson_doc, hash: hash, last_modified: Utc.timestamp(0, 0) }
-                }
-                (true, true) => {
-                    let hash = row.get::<_, String>(2).unwrap();
-                    let last_modified = row.get::<_, DateTime<Utc>>(3).unwrap();
-                    Record { id: id, data: bson_doc, hash: hash, last_modified: last_modified }
-                }
-                (false, true) => {
-                    let last_modified = row.get::<_, DateTime<Utc>>(2).unwrap();
-                    Record {
-                        id: id,
-                        data: bson_doc,
-                        hash: String::new(),
-                        last_modified: last_modified,
-                    }
-                }
-            };
-            f(&record).unwrap();
-        } else {
-            break;
+    match (H, L) {
+        (false, false) => Record {
+            id: id,
+            data: bson_doc,
+            hash: String::new(),
+            last_modified: Utc.timestamp(0, 0),
+        },
+        (true, false) => {
+            let hash = row.get::<_, String>(2).unwrap();
+            Record { id: id, data: bson_doc, hash: hash, last_modified: Utc.timestamp(0, 0) }
+        }
+        (true, true) => {
+            let hash = row.get::<_, String>(2).unwrap();
+            let last_modified = row.get::<_, DateTime<Utc>>(3).unwrap();
+            Record { id: id, data: bson_doc, hash: hash, last_modified: last_modified }
+        }
+        (false, true) => {
+            let last_modified = row.get::<_, DateTime<Utc>>(2).unwrap();
+            Record {
+                id: id,
+                data: bson_doc,
+                hash: String::new(),
+                last_modified: last_modified,
+            }
         }
     }
-
-    Ok(())
 }
 
 #[inline]
-pub fn find_one_internal<A, C: Adapter<A>, const H: bool, const L: bool>(conn: &C, config: &CollectionConfig, query: &bson::Document, skip: i64) -> std::result::Result<Record, &'static str> {
+pub fn find_internal<A, C: Adapter<A>, const H: bool, const L: bool>(conn: &C, config: &CollectionConfig, query: &bson::Document, options: &Option<SearchOption>, f: &mut dyn FnMut(&Record) -> std::result::Result<(), &'static str>) -> std::result::Result<(), &'static str> {
     let mut params = Vec::<rusqlite::types::Value>::new();
-    let where_str: String = QueryTranslator {}.query_document(query, &mut params).unwrap();
+    let sql = find_sql(config, query, options, &mut params).map_err(|_| "Invalid query")?;
 
-    let mut stmt = conn
-        .prepare_cached_wrapper(&format!("SELECT * FROM [{}] {} LIMIT 1 {};", &config.name, if where_str.len() > 0 { format!("WHERE {}", &where_str) } else { String::from("") }, if skip != 0 { format!("OFFSET {}", skip) } else { String::from("") }))
-        .unwrap();
+    let mut stmt = conn.prepare_cached_wrapper(&sql).map_err(|_| "Invalid query")?;
 
-    match (H, L) {
-        (false, false) => {
-            let row = stmt
-                .query_row(params_from_iter(params.iter()), |row| {
-                    Ok((row.get::<_, i64>(0).unwrap(), row.get::<_, Vec<u8>>(1).unwrap() /*, row.get::<_, String>(2).unwrap()*/))
-                })
-                .unwrap();
+    let mut rows = stmt.query(params_from_iter(params.iter())).map_err(|_| "Invalid query")?;
 
-            let bson_doc: bson::Document = bson::from_reader(row.1.as_slice()).unwrap();
+    while let Some(row) = rows.next().map_err(|_| "Invalid query")? {
+        let record = read_record::<H, L>(row);
+        f(&record)?;
+    }
 
-            Ok(Record {
-                id: row.0,
-                data: bson_doc,
-                hash: String::new(),
-                last_modified: Utc.timestamp(0, 0),
-            })
-        }
-        (true, false) => {
-            let row = stmt.query_row(params_from_iter(params.iter()), |row| Ok((row.get::<_, i64>(0).unwrap(), row.get::<_, Vec<u8>>(1).unwrap(), row.get::<_, String>(2).unwrap()))).unwrap();
-            let bson_doc: bson::Document = bson::from_reader(row.1.as_slice()).unwrap();
+    Ok(())
+}
 
-            Ok(Record {
-                id: row.0,
-                data: bson_doc,
-                hash: row.2,
-                last_modified: Utc.timestamp(0, 0),
-            })
-        }
-        (true, true) => {
-            let row = stmt.query_row(params_from_iter(params.iter()), |row| Ok((row.get::<_, i64>(0).unwrap(), row.get::<_, Vec<u8>>(1).unwrap(), row.get::<_, String>(2).unwrap(), row.get::<_, DateTime<Utc>>(3).unwrap()))).unwrap();
-            let bson_doc: bson::Document = bson::from_reader(row.1.as_slice()).unwrap();
-            Ok(Record { id: row.0, data: bson_doc, hash: row.2, last_modified: row.3 })
+#[inline]
+pub fn explain_internal<A, C: Adapter<A>>(conn: &C, config: &CollectionConfig, query: &bson::Document, options: &Option<SearchOption>, verbosity: ExplainVerbosity) -> std::result::Result<QueryPlan, String> {
+    let mut params = Vec::<rusqlite::types::Value>::new();
+    let sql = find_sql(config, query, options, &mut params)?;
+
+    // Each row of EXPLAIN QUERY PLAN is (id, parent, notused, detail). A step is a child of the step whose id equals its parent.
+    let mut steps = Vec::<PlanStep>::new();
+    {
+        let mut stmt = conn.prepare_wrapper(&format!("EXPLAIN QUERY PLAN {}", sql)).map_err(|e| e.to_string())?;
+        let mut rows = stmt.query(params_from_iter(params.iter())).map_err(|e| e.to_string())?;
+        while let Some(row) = rows.next().map_err(|e| e.to_string())? {
+            steps.push(PlanStep {
+                id: row.get::<_, i64>(0).map_err(|e| e.to_string())?,
+                parent: row.get::<_, i64>(1).map_err(|e| e.to_string())?,
+                detail: row.get::<_, String>(3).map_err(|e| e.to_string())?,
+                children: Vec::new(),
+            });
         }
-        (false, true) => {
-            let row = stmt.query_row(params_from_iter(params.iter()), |row| Ok((row.get::<_, i64>(0).unwrap(), row.get::<_, Vec<u8>>(1).unwrap(), row.get::<_, DateTime<Utc>>(2).unwrap()))).unwrap();
-            let bson_doc: bson::Document = bson::from_reader(row.1.as_slice()).unwrap();
-            Ok(Record { id: row.0, data: bson_doc, hash: String::new(), last_modified: row.2 })
+    }
+
+    let mut indexes = Vec::<String>::new();
+    for step in &steps {
+        if let Some(position) = step.detail.find("INDEX ") {
+            if let Some(name) = step.detail[position + "INDEX ".len()..].split_whitespace().next() {
+                if !indexes.iter().any(|index| index == name) {
+                    indexes.push(name.to_string());
+                }
+            }
         }
     }
+
+    fn build_tree(steps: &[PlanStep], parent: i64) -> Vec<PlanStep> {
+        steps
+            .iter()
+            .filter(|step| step.parent == parent)
+            .map(|step| PlanStep { children: build_tree(steps, step.id), ..step.clone() })
+            .collect()
+    }
+    let plan = build_tree(&steps, 0);
+
+    let stats = if verbosity == ExplainVerbosity::ExecutionStats {
+        let mut stmt = conn.prepare_wrapper(&sql).map_err(|e| e.to_string())?;
+        let start = std::time::Instant::now();
+        let mut rows_returned = 0;
+        {
+            let mut rows = stmt.query(params_from_iter(params.iter())).map_err(|e| e.to_string())?;
+            while rows.next().map_err(|e| e.to_string())?.is_some() {
+                rows_returned += 1;
+            }
+        }
+        let elapsed = start.elapsed();
+        Some(ExecutionStats {
+            rows_returned,
+            rows_scanned: stmt.get_status(rusqlite::StatementStatus::FullscanStep) as i64,
+            sorts: stmt.get_status(rusqlite::StatementStatus::Sort) as i64,
+            auto_index_rows: stmt.get_status(rusqlite::StatementStatus::AutoIndex) as i64,
+            vm_steps: stmt.get_status(rusqlite::StatementStatus::VmStep) as i64,
+            elapsed,
+        })
+    } else {
+        None
+    };
+
+    Ok(QueryPlan { sql, params, plan, indexes, stats })
+}
+
+#[inline]
+pub fn find_one_internal<A, C: Adapter<A>, const H: bool, const L: bool>(conn: &C, config: &CollectionConfig, query: &bson::Document, skip: i64) -> std::result::Result<Record, &'static str> {
+    let mut params = Vec::<rusqlite::types::Value>::new();
+    let where_str: String = QueryTranslator {}.query_document(query, &mut params).map_err(|_| "Invalid query")?;
+
+    let mut stmt = conn
+        .prepare_cached_wrapper(&format!("SELECT * FROM [{}] {} {} LIMIT 1 {};", &config.table_name, if where_str.len() > 0 { format!("WHERE {}", &where_str) } else { String::from("") }, natural_order(config), if skip != 0 { format!("OFFSET {}", skip) } else { String::from("") }))
+        .map_err(|_| "Invalid query")?;
+
+    stmt.query_row(params_from_iter(params.iter()), |row| Ok(read_record::<H, L>(row))).map_err(|e| match e {
+        rusqlite::Error::QueryReturnedNoRows => "Query returned no rows",
+        _ => "Invalid query",
+    })
 }
 
 #[inline]
 pub fn find_one_and_delete_internal<A, C: Adapter<A>, const H: bool, const L: bool>(conn: &C, config: &CollectionConfig, query: &bson::Document) -> std::result::Result<Option<Record>, String> {
     let mut params = Vec::<rusqlite::types::Value>::new();
-    let where_str: String = QueryTranslator {}.query_document(&query, &mut params).unwrap();
+    let where_str: String = QueryTranslator {}.query_document(&query, &mut params)?;
 
     // an alternative solution is SQLITE_ENABLE_UPDATE_DELETE_LIMIT
-    let mut stmt = conn.prepare_cached_wrapper(&format!("DELETE FROM [{}] WHERE _id = (SELECT _id FROM [{}] {} LIMIT 1) RETURNING *;", &config.name, &config.name, if where_str.len() > 0 { format!("WHERE {}", &where_str) } else { String::from("") })).unwrap();
+    let mut stmt = conn.prepare_cached_wrapper(&format!("DELETE FROM [{}] WHERE _id = (SELECT _id FROM [{}] {} LIMIT 1) RETURNING *;", &config.table_name, &config.table_name, if where_str.len() > 0 { format!("WHERE {}", &where_str) } else { String::from("") })).map_err(|e| e.to_string())?;
 
     match stmt.query_row(params_from_iter(params.iter()), |row| {
         let id = row.get::<_, i64>(0).unwrap();
@@ -331,14 +526,14 @@ pub fn find_one_and_delete_internal<A, C: Adapter<A>, const H: bool, const L: bo
 pub fn count_documents_internal<A, C: Adapter<A>>(conn: &C, config: &CollectionConfig, query: &bson::Document, options: &Option<SearchOption>) -> std::result::Result<i64, &'static str> {
     //todo implement skip limit
     let mut params = Vec::<rusqlite::types::Value>::new();
-    let where_str: String = QueryTranslator {}.query_document(&query, &mut params).unwrap();
+    let where_str: String = QueryTranslator {}.query_document(&query, &mut params).map_err(|_| "Invalid query")?;
     let mut option_str = String::new();
     if let Some(opt) = options {
         option_str = format!("LIMIT {} OFFSET {}", opt.limit, opt.skip);
     }
 
-    let mut stmt = conn.prepare_cached_wrapper(&format!("SELECT COUNT(1) FROM [{}] {} {};", &config.name, if where_str.len() > 0 { format!("WHERE {}", &where_str) } else { String::from("") }, option_str)).unwrap();
-    let count = stmt.query_row(params_from_iter(params.iter()), |row| Ok(row.get::<_, i64>(0).unwrap())).unwrap();
+    let mut stmt = conn.prepare_cached_wrapper(&format!("SELECT COUNT(1) FROM [{}] {} {};", &config.table_name, if where_str.len() > 0 { format!("WHERE {}", &where_str) } else { String::from("") }, option_str)).map_err(|_| "Invalid query")?;
+    let count = stmt.query_row(params_from_iter(params.iter()), |row| row.get::<_, i64>(0)).map_err(|_| "Invalid query")?;
     Ok(count)
 }
 
@@ -347,7 +542,7 @@ fn translate_index_config(config: &bson::Document, scope: &str, fields: &mut Vec
     for (key, value) in config.iter() {
         match value {
             bson::Bson::Document(doc) => {
-                return translate_index_config(&doc, &format!("{}{}.", scope, key), fields);
+                translate_index_config(&doc, &format!("{}{}.", scope, key), fields)?;
             }
             bson::Bson::Int32(order) => {
                 if *order != -1 && *order != 1 {
@@ -355,7 +550,6 @@ fn translate_index_config(config: &bson::Document, scope: &str, fields: &mut Vec
                 }
 
                 fields.push((format!("{}{}", scope, key), *order as i8));
-                return Ok(());
             }
             bson::Bson::Int64(order) => {
                 if *order != -1 && *order != 1 {
@@ -363,14 +557,16 @@ fn translate_index_config(config: &bson::Document, scope: &str, fields: &mut Vec
                 }
 
                 fields.push((format!("{}{}", scope, key), *order as i8));
-                return Ok(());
             }
             _ => {
                 return Err("Invalid index config");
             }
         }
     }
-    Err("no members in index config")
+    if fields.is_empty() {
+        return Err("no members in index config");
+    }
+    Ok(())
 }
 
 #[inline]
@@ -390,14 +586,15 @@ pub fn create_index_internal<A, C: Adapter<A>>(conn: &C, config: &CollectionConf
         if config_str.len() > 0 {
             config_str.push_str(",");
         }
-        config_str.push_str(&format!("json_field('{}', raw) {}", field.0, if field.1 == 1 { "ASC" } else { "DESC" }));
+        config_str.push_str(&format!("json_field({}, raw) {}", field_literal(&field.0)?, if field.1 == 1 { "ASC" } else { "DESC" }));
         index_name.push_str(field.0.as_str());
         index_name.push_str("_");
     }
 
-    index_name = slugify!(index_name.as_str(), separator = "_");
+    // index names are global in sqlite, so they are prefixed with the table name.
+    index_name = format!("{}_{}", config.table_name, slugify!(index_name.as_str(), separator = "_"));
 
-    match conn.execute_wrapper(&format!("CREATE {} INDEX IF NOT EXISTS {} ON [{}]({});", if is_unique { "UNIQUE" } else { "" }, index_name, &config.name, &config_str), []) {
+    match conn.execute_wrapper(&format!("CREATE {} INDEX IF NOT EXISTS {} ON [{}]({});", if is_unique { "UNIQUE" } else { "" }, index_name, &config.table_name, &config_str), []) {
         Ok(_) => Ok(()),
         Err(e) => Err(e.to_string()),
     }
@@ -406,38 +603,30 @@ pub fn create_index_internal<A, C: Adapter<A>>(conn: &C, config: &CollectionConf
 #[inline]
 pub fn delete_one_internal<A, C: Adapter<A>>(conn: &C, config: &CollectionConfig, query: &bson::Document) -> std::result::Result<usize, String> {
     let mut params = Vec::<rusqlite::types::Value>::new();
-    let where_str: String = QueryTranslator {}.query_document(query, &mut params).unwrap();
+    let where_str: String = QueryTranslator {}.query_document(query, &mut params)?;
     // an alternative solution is SQLITE_ENABLE_UPDATE_DELETE_LIMIT
-    let mut stmt = conn.prepare_cached_wrapper(&format!("DELETE FROM [{}] WHERE _id = (SELECT _id FROM [{}] {} LIMIT 1);", &config.name, &config.name, if where_str.len() > 0 { format!("WHERE {}", &where_str) } else { String::from("") })).unwrap();
+    let mut stmt = conn.prepare_cached_wrapper(&format!("DELETE FROM [{}] WHERE _id = (SELECT _id FROM [{}] {} LIMIT 1);", &config.table_name, &config.table_name, if where_str.len() > 0 { format!("WHERE {}", &where_str) } else { String::from("") })).map_err(|e| e.to_string())?;
 
     match stmt.execute(params_from_iter(params.iter())) {
         Ok(count) => Ok(count),
-        Err(_) => Err("Error".to_string()),
+        Err(e) => Err(e.to_string()),
     }
 }
 
 #[inline]
 pub fn changes_internal<A, C: Adapter<A>>(conn: &C) -> std::result::Result<i64, String> {
-    let mut stmt = conn.prepare_cached_wrapper("SELECT changes();").unwrap();
-
-    let rows = stmt
-        .query_row([], |row| {
-            let count = row.get::<_, i64>(0).unwrap();
-            Ok(count)
-        })
-        .unwrap();
-
-    Ok(rows)
+    let mut stmt = conn.prepare_cached_wrapper("SELECT changes();").map_err(|e| e.to_string())?;
+    stmt.query_row([], |row| row.get::<_, i64>(0)).map_err(|e| e.to_string())
 }
 
 #[inline]
 pub fn delete_many_internal<A, C: Adapter<A>>(conn: &C, config: &CollectionConfig, query: &bson::Document) -> std::result::Result<usize, String> {
     let mut params = Vec::<rusqlite::types::Value>::new();
-    let where_str: String = QueryTranslator {}.query_document(query, &mut params).unwrap();
+    let where_str: String = QueryTranslator {}.query_document(query, &mut params)?;
 
     // an alternative solution is SQLITE_ENABLE_UPDATE_DELETE_LIMIT
 
-    let mut stmt = conn.prepare_cached_wrapper(&format!("DELETE FROM [{}] {};", &config.name, if where_str.len() > 0 { format!("WHERE {}", &where_str) } else { String::from("") })).unwrap();
+    let mut stmt = conn.prepare_cached_wrapper(&format!("DELETE FROM [{}] {};", &config.table_name, if where_str.len() > 0 { format!("WHERE {}", &where_str) } else { String::from("") })).map_err(|e| e.to_string())?;
     match stmt.execute(params_from_iter(params.iter())) {
         Ok(count) => Ok(count),
         Err(e) => Err(e.to_string()),
@@ -450,21 +639,30 @@ pub fn distinct_internal<A, C: Adapter<A>>(conn: &C, config: &CollectionConfig,
     let mut params = Vec::<rusqlite::types::Value>::new();
     let mut where_str: String = String::new();
     if let Some(q) = query {
-        where_str = QueryTranslator {}.query_document(q, &mut params).unwrap();
+        where_str = QueryTranslator {}.query_document(q, &mut params).map_err(|_| "Invalid query")?;
     }
     let mut option_str = String::new();
     if let Some(opt) = options {
         option_str = format!("LIMIT {} OFFSET {}", opt.limit, opt.skip);
     }
 
-    let mut stmt = conn.prepare_cached_wrapper(&format!("SELECT COUNT(DISTINCT json_field('{}', raw)) FROM [{}] {} {};", field, &config.name, if where_str.len() > 0 { format!("WHERE {}", &where_str) } else { String::from("") }, option_str)).unwrap();
-    let count = stmt.query_row(params_from_iter(params.iter()), |row| Ok(row.get::<_, i64>(0).unwrap())).unwrap();
+    let field = field_literal(field).map_err(|_| "Invalid field path")?;
+    let mut stmt = conn.prepare_cached_wrapper(&format!("SELECT COUNT(DISTINCT json_field({}, raw)) FROM [{}] {} {};", field, &config.table_name, if where_str.len() > 0 { format!("WHERE {}", &where_str) } else { String::from("") }, option_str)).map_err(|_| "Invalid query")?;
+    let count = stmt.query_row(params_from_iter(params.iter()), |row| row.get::<_, i64>(0)).map_err(|_| "Invalid query")?;
     Ok(count)
 }
 
 #[inline]
 pub fn drop_index_internal<A, C: Adapter<A>>(conn: &C, config: &CollectionConfig, index_name: &str) -> std::result::Result<(), String> {
-    match conn.execute_wrapper(&format!("DROP INDEX IF EXISTS {} ;", index_name), []) {
+    // only drop the index if it belongs to this collection.
+    let belongs = conn
+        .prepare_wrapper("SELECT COUNT(1) FROM sqlite_master WHERE type = 'index' AND name = ?1 AND tbl_name = ?2")
+        .and_then(|mut stmt| stmt.query_row([index_name, config.table_name.as_str()], |row| row.get::<_, i64>(0)))
+        .map_err(|e| e.to_string())?;
+    if belongs == 0 {
+        return Err(format!("No index named {} found", index_name));
+    }
+    match conn.execute_wrapper(&format!("DROP INDEX IF EXISTS {} ;", quote_identifier(index_name)), []) {
         Ok(_) => Ok(()),
         Err(e) => Err(e.to_string()),
     }
@@ -473,8 +671,8 @@ pub fn drop_index_internal<A, C: Adapter<A>>(conn: &C, config: &CollectionConfig
 #[inline]
 pub fn get_indexes_internal<A, C: Adapter<A>>(conn: &C, config: &CollectionConfig) -> Result<Vec<Index>, String> {
 
-    let mut stmt = conn.prepare_wrapper(&format!("SELECT * FROM pragma_index_list('{}');", config.name)).unwrap();
-    let mut rows = stmt.query([]).unwrap();
+    let mut stmt = conn.prepare_wrapper("SELECT * FROM pragma_index_list(?1);").map_err(|e| e.to_string())?;
+    let mut rows = stmt.query([&config.table_name]).map_err(|e| e.to_string())?;
 
     let mut result= Vec::new();
     while let Ok(row_result) = rows.next() {
@@ -503,8 +701,8 @@ pub fn insert_one_internal<A, C: Adapter<A>, const H: bool, const L: bool>(conn:
     bson_doc.to_writer(&mut bytes).unwrap();
 
     let mut stmt = conn
-        .prepare_cached_wrapper(&format!("INSERT INTO [{}] (raw {}) VALUES (?1 {}) RETURNING *", &config.name, if L { ", _last_modified" } else { "" }, if L { ", datetime('now')" } else { "" }))
-        .unwrap();
+        .prepare_cached_wrapper(&format!("INSERT INTO [{}] (raw {}) VALUES (?1 {}) RETURNING *", &config.table_name, if L { ", _last_modified" } else { "" }, if L { ", datetime('now')" } else { "" }))
+        .map_err(|e| e.to_string())?;
     let bytes_ref: &[u8] = bytes.as_ref();
 
     match stmt.query_row(&[bytes_ref], |row| {
@@ -546,25 +744,31 @@ pub fn insert_one_internal<A, C: Adapter<A>, const H: bool, const L: bool>(conn:
 
 #[inline]
 pub fn insert_many_internal<A, C: Adapter<A>, const H: bool, const L: bool>(conn: &C, config: &CollectionConfig, documents: &Vec<bson::Document>) -> std::result::Result<(), String> {
-    let mut stmt = conn
-        .prepare_cached_wrapper(&format!("INSERT INTO [{}] (raw {}) VALUES (?1 {})", &config.name, if L { ", _last_modified" } else { "" }, if L { ", datetime('now')" } else { "" }))
-        .unwrap();
-    for doc in documents {
-        let bson_doc = bson::ser::to_document(&doc).unwrap();
-        let mut bytes: Vec<u8> = Vec::new();
-        bson_doc.to_writer(&mut bytes).unwrap();
-
-        let bytes_ref: &[u8] = bytes.as_ref();
-        stmt.execute(&[bytes_ref]).unwrap();
+    // a savepoint rather than a transaction, so that this also works inside a transaction. A rejected document rolls the whole batch back.
+    conn.execute_wrapper("SAVEPOINT insert_many", []).map_err(|e| e.to_string())?;
+    let result = (|| -> std::result::Result<(), String> {
+        let mut stmt = conn
+            .prepare_cached_wrapper(&format!("INSERT INTO [{}] (raw {}) VALUES (?1 {})", &config.table_name, if L { ", _last_modified" } else { "" }, if L { ", datetime('now')" } else { "" }))
+            .map_err(|e| e.to_string())?;
+        for doc in documents {
+            let mut bytes: Vec<u8> = Vec::new();
+            doc.to_writer(&mut bytes).map_err(|e| e.to_string())?;
+
+            let bytes_ref: &[u8] = bytes.as_ref();
+            stmt.execute([bytes_ref]).map_err(|e| e.to_string())?;
+        }
+        Ok(())
+    })();
+    if result.is_err() {
+        conn.execute_wrapper("ROLLBACK TO insert_many", []).map_err(|e| e.to_string())?;
     }
-    // todo: handle error
-    Ok(())
+    conn.execute_wrapper("RELEASE insert_many", []).map_err(|e| e.to_string())?;
+    result
 }
 
 #[inline]
 pub fn reindex_internal<A, C: Adapter<A>>(conn: &C, config: &CollectionConfig) -> std::result::Result<(), String> {
-    // todo: handle error
-    conn.execute_wrapper(&format!("REINDEX [{}]", &config.name), []).unwrap();
+    conn.execute_wrapper(&format!("REINDEX [{}]", &config.table_name), []).map_err(|e| e.to_string())?;
 
     Ok(())
 }
@@ -577,7 +781,7 @@ pub fn replace_one_internal<A, C: Adapter<A>, const H: bool, const L: bool>(conn
     bson_doc.to_writer(&mut bytes).unwrap();
     params.push(rusqlite::types::Value::Blob(bytes));
 
-    let where_str: String = QueryTranslator {}.query_document(query, &mut params).unwrap();
+    let where_str: String = QueryTranslator {}.query_document(query, &mut params)?;
 
     let mut stmt = conn
         .prepare_cached_wrapper(&format!(
@@ -588,13 +792,13 @@ pub fn replace_one_internal<A, C: Adapter<A>, const H: bool, const L: bool>(conn
                     [{}] 
                 {} LIMIT 1 {}
             ) RETURNING *;",
-            &config.name,
+            &config.table_name,
             if L { ", _last_modified = datetime('now')" } else { "" },
-            &config.name,
+            &config.table_name,
             if where_str.len() > 0 { format!("WHERE {}", &where_str) } else { String::from("") },
             if skip != 0 { format!("OFFSET {}", skip) } else { String::from("") }
         ))
-        .unwrap();
+        .map_err(|e| e.to_string())?;
 
     match stmt.query_row(params_from_iter(params.iter()), |row| {
         let id = row.get::<_, i64>(0).unwrap();
@@ -632,29 +836,64 @@ pub fn replace_one_internal<A, C: Adapter<A>, const H: bool, const L: bool>(conn
     }
 }
 
-#[inline]
-pub fn update_one_internal<A, C: Adapter<A>, const H: bool, const L: bool>(conn: &C, config: &CollectionConfig,  query: &bson::Document, update: &bson::Document, skip: i64, upsert: bool) -> std::result::Result<Option<Record>, String> {
+/// Composes the first three parameters of an update statement. They are the arguments of `json_patch(raw, ?1, ?2, ?3)`: the update document,
+/// the query, which the positional operator `$` needs, and the update options holding the `arrayFilters`.
+fn update_params(query: &bson::Document, update: &bson::Document, options: &Option<UpdateOption>) -> Vec<rusqlite::types::Value> {
+    let to_blob = |doc: &bson::Document| {
+        let mut bytes: Vec<u8> = Vec::new();
+        doc.to_writer(&mut bytes).unwrap();
+        rusqlite::types::Value::Blob(bytes)
+    };
+
     let mut params = Vec::<rusqlite::types::Value>::new();
-    let update_bson_doc = bson::ser::to_document(&update).unwrap();
-    let mut bytes: Vec<u8> = Vec::new();
-    update_bson_doc.to_writer(&mut bytes).unwrap();
-    params.push(rusqlite::types::Value::Blob(bytes));
+    params.push(to_blob(update));
+    params.push(to_blob(query));
+    match options {
+        Some(options) if !options.array_filters.is_empty() => {
+            let array_filters: Vec<bson::Bson> = options.array_filters.iter().map(|f| bson::Bson::Document(f.clone())).collect();
+            params.push(to_blob(&bson::doc! {"arrayFilters": array_filters}));
+        }
+        _ => params.push(rusqlite::types::Value::Null),
+    }
+    params
+}
 
-    let where_str: String = QueryTranslator {}.query_document(query, &mut params).unwrap();
+/// The parameters of a pipeline update. The pipeline is wrapped in a document, `{"pipeline": [...]}`, because a bson blob is always a document.
+fn pipeline_params(query: &bson::Document, pipeline: &Vec<bson::Document>) -> Vec<rusqlite::types::Value> {
+    let stages: Vec<bson::Bson> = pipeline.iter().map(|stage| bson::Bson::Document(stage.clone())).collect();
+    update_params(query, &bson::doc! {"pipeline": stages}, &None)
+}
+
+#[inline]
+pub fn update_one_internal<A, C: Adapter<A>, const H: bool, const L: bool>(conn: &C, config: &CollectionConfig,  query: &bson::Document, update: &bson::Document, skip: i64, upsert: bool, options: &Option<UpdateOption>) -> std::result::Result<Option<Record>, String> {
+    update_one_with::<A, C, H, L>(conn, config, query, "json_patch", update_params(query, update, options), skip, upsert)
+}
+
+#[inline]
+pub fn update_one_pipeline_internal<A, C: Adapter<A>, const H: bool, const L: bool>(conn: &C, config: &CollectionConfig, query: &bson::Document, pipeline: &Vec<bson::Document>, skip: i64, upsert: bool) -> std::result::Result<Option<Record>, String> {
+    update_one_with::<A, C, H, L>(conn, config, query, "json_pipeline", pipeline_params(query, pipeline), skip, upsert)
+}
+
+/// Updates the first matching document with `patch_function`, which is either `json_patch` for update operators or `json_pipeline` for pipelines.
+fn update_one_with<A, C: Adapter<A>, const H: bool, const L: bool>(conn: &C, config: &CollectionConfig, query: &bson::Document, patch_function: &str, mut params: Vec<rusqlite::types::Value>, skip: i64, upsert: bool) -> std::result::Result<Option<Record>, String> {
+
+    let where_str: String = QueryTranslator {}.query_document(query, &mut params)?;
 
     if upsert {
         let mut stmt = conn
             .prepare_cached_wrapper(&format!(
-                "INSERT INTO [{}] (_id, raw {}) VALUES ( (SELECT _id FROM [{}] {} LIMIT 1 {}) ,json_patch(NULL, ?1) {}) ON CONFLICT (_id) DO UPDATE SET raw=json_patch(raw,?1) {} RETURNING *;",
-                &config.name,
+                "INSERT INTO [{}] (_id, raw {}) VALUES ( (SELECT _id FROM [{}] {} LIMIT 1 {}) ,{}(NULL, ?1, ?2, ?3) {}) ON CONFLICT (_id) DO UPDATE SET raw={}(raw, ?1, ?2, ?3) {} RETURNING *;",
+                &config.table_name,
                 if L { ", _last_modified" } else { "" },
-                &config.name,
+                &config.table_name,
                 if where_str.len() > 0 { format!("WHERE {}", &where_str) } else { String::from("") },
                 if skip != 0 { format!("OFFSET {}", skip) } else { String::from("") },
+                patch_function,
                 if L { ", datetime('now')" } else { "" },
+                patch_function,
                 if L { ", _last_modified=datetime('now')" } else { "" }
             ))
-            .unwrap();
+            .map_err(|e| e.to_string())?;
 
         match stmt.query_row(params_from_iter(params.iter()), |row| {
             let id = row.get::<_, i64>(0).unwrap();
@@ -693,20 +932,21 @@ pub fn update_one_internal<A, C: Adapter<A>, const H: bool, const L: bool>(conn:
     } else {
         let mut stmt = conn
             .prepare_cached_wrapper(&format!(
-                "UPDATE [{}] SET raw=json_patch(raw,?1) {} WHERE _id = (
+                "UPDATE [{}] SET raw={}(raw, ?1, ?2, ?3) {} WHERE _id = (
                 SELECT
                     _id
                 FROM
                     [{}] 
                 {} LIMIT 1 {}
             ) RETURNING *;",
-                &config.name,
+                &config.table_name,
+                patch_function,
                 if L { ", _last_modified=datetime('now')" } else { "" },
-                &config.name,
+                &config.table_name,
                 if where_str.len() > 0 { format!("WHERE {}", &where_str) } else { String::from("") },
                 if skip != 0 { format!("OFFSET {}", skip) } else { String::from("") }
             ))
-            .unwrap();
+            .map_err(|e| e.to_string())?;
 
         match stmt.query_row(params_from_iter(params.iter()), |row| {
             let id = row.get::<_, i64>(0).unwrap();
@@ -746,54 +986,56 @@ pub fn update_one_internal<A, C: Adapter<A>, const H: bool, const L: bool>(conn:
 }
 
 #[inline]
-pub fn update_many_internal<A, C: Adapter<A>, const H: bool, const L: bool>(conn: &C, config: &CollectionConfig, query: &bson::Document, update: &bson::Document, limit: i64, skip: i64, upsert: bool) -> Result<i64, String> {
-    let mut params = Vec::<rusqlite::types::Value>::new();
-    let update_bson_doc = bson::ser::to_document(update).unwrap();
-    let mut bytes: Vec<u8> = Vec::new();
-    update_bson_doc.to_writer(&mut bytes).unwrap();
-    params.push(rusqlite::types::Value::Blob(bytes));
+pub fn update_many_internal<A, C: Adapter<A>, const H: bool, const L: bool>(conn: &C, config: &CollectionConfig, query: &bson::Document, update: &bson::Document, limit: i64, skip: i64, upsert: bool, options: &Option<UpdateOption>) -> Result<i64, String> {
+    update_many_with::<A, C, H, L>(conn, config, query, "json_patch", update_params(query, update, options), limit, skip, upsert)
+}
 
-    let where_str: String = QueryTranslator {}.query_document(&query, &mut params).unwrap();
+#[inline]
+pub fn update_many_pipeline_internal<A, C: Adapter<A>, const H: bool, const L: bool>(conn: &C, config: &CollectionConfig, query: &bson::Document, pipeline: &Vec<bson::Document>, limit: i64, skip: i64, upsert: bool) -> Result<i64, String> {
+    update_many_with::<A, C, H, L>(conn, config, query, "json_pipeline", pipeline_params(query, pipeline), limit, skip, upsert)
+}
 
-    let mut stmt = conn
-        .prepare_cached_wrapper(&format!(
-            "SAVEPOINT updatemany; UPDATE [{}] SET raw=json_patch(raw,?1) {} 
-                {} {} {}; SELECT changes(); RELEASE SAVEPOINT updatemany;",
-            &config.name,
-            if L { ", _last_modified=datetime('now')" } else { "" },
-            if where_str.len() > 0 { format!("WHERE {}", &where_str) } else { String::from("") },
-            if limit != 0 { format!("LIMIT {}", limit) } else { String::from("") },
-            if skip != 0 { format!("OFFSET {}", skip) } else { String::from("") }
-        ))
-        .unwrap();
+fn update_many_with<A, C: Adapter<A>, const H: bool, const L: bool>(conn: &C, config: &CollectionConfig, query: &bson::Document, patch_function: &str, mut params: Vec<rusqlite::types::Value>, limit: i64, skip: i64, upsert: bool) -> Result<i64, String> {
 
-    match stmt.query_row(params_from_iter(params.iter()), |row| {
-        let id = row.get::<_, i64>(0).unwrap();
-        Ok(id)
-    }) {
-        Ok(record) => {
-            if record == 0 && upsert {
-                let mut stmt = conn
-                    .prepare_cached_wrapper(&format!(
-                        "INSERT INTO [{}] (raw {}) VALUES (json_patch(NULL, ?1) {}) RETURNING _id;",
-                        &config.name,
-                        if L { ", _last_modified" } else { "" },
-                        if L { ", datetime('now')" } else { "" }
-                    ))
-                    .unwrap();
-
-                match stmt.query_row(params_from_iter(params.iter()), |row| {
-                    let id = row.get::<_, i64>(0).unwrap();
-                    Ok(id)
-                }) {
-                    Ok(_) => Ok(1),
-                    Err(e) => Err(e.to_string()),
-                }
-            } else {
-                Ok(record)
-            }
+    let where_str: String = QueryTranslator {}.query_document(&query, &mut params)?;
+
+    // sqlite only supports LIMIT and OFFSET on UPDATE when it is compiled with SQLITE_ENABLE_UPDATE_DELETE_LIMIT, hence the subquery.
+    let changes = conn
+        .execute_wrapper(
+            &format!(
+                "UPDATE [{}] SET raw={}(raw, ?1, ?2, ?3) {} WHERE _id IN (SELECT _id FROM [{}] {} {} {});",
+                &config.table_name,
+                patch_function,
+                if L { ", _last_modified=datetime('now')" } else { "" },
+                &config.table_name,
+                if where_str.len() > 0 { format!("WHERE {}", &where_str) } else { String::from("") },
+                if limit > 0 { format!("LIMIT {}", limit) } else if skip != 0 { String::from("LIMIT -1") } else { String::from("") },
+                if skip != 0 { format!("OFFSET {}", skip) } else { String::from("") }
+            ),
+            params_from_iter(params.iter()),
+        )
+        .map_err(|e| e.to_string())?;
+
+    if changes == 0 && upsert {
+        let mut stmt = conn
+            .prepare_cached_wrapper(&format!(
+                "INSERT INTO [{}] (raw {}) VALUES ({}(NULL, ?1, ?2, ?3) {}) RETURNING _id;",
+                &config.table_name,
+                if L { ", _last_modified" } else { "" },
+                patch_function,
+                if L { ", datetime('now')" } else { "" }
+            ))
+            .map_err(|e| e.to_string())?;
+
+        match stmt.query_row(params_from_iter(params[..3].iter()), |row| {
+            let id = row.get::<_, i64>(0).unwrap();
+            Ok(id)
+        }) {
+            Ok(_) => Ok(1),
+            Err(e) => Err(e.to_string()),
         }
-        Err(e) => Err(e.to_string()),
+    } else {
+        Ok(changes as i64)
     }
 }
 
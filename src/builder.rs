@@ -0,0 +1,453 @@
+//! Builders for query and update documents.
+//!
+//! Writing queries as raw documents makes it easy to misspell an operator or to nest a document at the wrong level. [`Filter`] and [`Update`]
+//! build the same documents out of Rust calls, and the [`filter!`](crate::filter!) and [`update!`](crate::update!) macros provide a shorter
+//! syntax for the common cases. Both produce plain [`bson::Document`]s, so they can be used anywhere a query or an update is accepted.
+//!
+//! ```rust
+//! use hoardbase::builder::{Filter, Update};
+//! use hoardbase::{filter, update};
+//!
+//! let adults = Filter::field("age").gte(18).and(Filter::field("name").ne("Bob"));
+//! assert_eq!(adults.document(), &bson::doc! { "$and": [{ "age": { "$gte": 18 } }, { "name": { "$ne": "Bob" } }] });
+//! assert_eq!(filter!("age" >= 18, "name" != "Bob"), adults);
+//!
+//! let birthday = Update::inc("age", 1).and(Update::set("celebrated", true));
+//! assert_eq!(birthday.document(), &bson::doc! { "$inc": { "age": 1 }, "$set": { "celebrated": true } });
+//! assert_eq!(update!(inc { "age": 1 }, set { "celebrated": true }), birthday);
+//! ```
+
+use bson::{Bson, Document};
+
+/// A query document, built with [`Filter::field()`] and combined with [`Filter::and()`], [`Filter::or()`], [`Filter::nor()`] and `!`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Filter {
+    document: Document,
+}
+
+/// A field of a [`Filter`] waiting for its condition.
+#[derive(Clone, Debug)]
+pub struct FieldFilter {
+    path: String,
+}
+
+impl Filter {
+    /// A filter that matches every document.
+    pub fn all() -> Filter {
+        Filter { document: Document::new() }
+    }
+
+    /// Starts a condition on a field. `path` can be a dotted path into embedded documents and arrays.
+    pub fn field(path: &str) -> FieldFilter {
+        FieldFilter { path: path.to_string() }
+    }
+
+    /// Matches documents for which the aggregation expression evaluates to true. SQL can't evaluate the expression, so sqlite hands each
+    /// document to the matcher, and no index is used.
+    pub fn expr(expression: impl Into<Bson>) -> Filter {
+        Filter { document: bson::doc! { "$expr": expression.into() } }
+    }
+
+    /// Matches documents matching both filters.
+    pub fn and(self, other: Filter) -> Filter {
+        self.combine("$and", other)
+    }
+
+    /// Matches documents matching either filter.
+    pub fn or(self, other: Filter) -> Filter {
+        self.combine("$or", other)
+    }
+
+    /// Matches documents matching neither filter.
+    pub fn nor(self, other: Filter) -> Filter {
+        self.combine("$nor", other)
+    }
+
+    pub fn document(&self) -> &Document {
+        &self.document
+    }
+
+    pub fn into_document(self) -> Document {
+        self.document
+    }
+
+    /// Chains of the same operator are flattened, so `a.and(b).and(c)` gives `{"$and": [a, b, c]}`. An empty filter is dropped from `$and`.
+    fn combine(self, operator: &str, other: Filter) -> Filter {
+        if operator == "$and" {
+            if other.document.is_empty() {
+                return self;
+            }
+            if self.document.is_empty() {
+                return other;
+            }
+        }
+        let mut clauses = match self.document.get_array(operator) {
+            Ok(clauses) if self.document.len() == 1 => clauses.clone(),
+            _ => vec![Bson::Document(self.document)],
+        };
+        clauses.push(Bson::Document(other.document));
+        let mut document = Document::new();
+        document.insert(operator, clauses);
+        Filter { document }
+    }
+}
+
+/// `!filter` matches the documents not matching `filter`.
+impl std::ops::Not for Filter {
+    type Output = Filter;
+
+    fn not(self) -> Filter {
+        Filter { document: bson::doc! { "$nor": [self.document] } }
+    }
+}
+
+impl FieldFilter {
+    fn condition(self, operator: &str, value: impl Into<Bson>) -> Filter {
+        let mut condition = Document::new();
+        condition.insert(operator, value.into());
+        let mut document = Document::new();
+        document.insert(self.path, condition);
+        Filter { document }
+    }
+
+    pub fn eq(self, value: impl Into<Bson>) -> Filter {
+        let mut document = Document::new();
+        document.insert(self.path, value.into());
+        Filter { document }
+    }
+
+    pub fn ne(self, value: impl Into<Bson>) -> Filter {
+        self.condition("$ne", value)
+    }
+
+    pub fn gt(self, value: impl Into<Bson>) -> Filter {
+        self.condition("$gt", value)
+    }
+
+    pub fn gte(self, value: impl Into<Bson>) -> Filter {
+        self.condition("$gte", value)
+    }
+
+    pub fn lt(self, value: impl Into<Bson>) -> Filter {
+        self.condition("$lt", value)
+    }
+
+    pub fn lte(self, value: impl Into<Bson>) -> Filter {
+        self.condition("$lte", value)
+    }
+
+    /// Matches documents whose field equals one of `values`.
+    pub fn is_in<V: Into<Bson>>(self, values: impl IntoIterator<Item = V>) -> Filter {
+        self.condition("$in", values.into_iter().map(Into::into).collect::<Vec<Bson>>())
+    }
+
+    pub fn not_in<V: Into<Bson>>(self, values: impl IntoIterator<Item = V>) -> Filter {
+        self.condition("$nin", values.into_iter().map(Into::into).collect::<Vec<Bson>>())
+    }
+
+    pub fn exists(self, exists: bool) -> Filter {
+        self.condition("$exists", exists)
+    }
+
+    /// Matches documents whose field has the given bson type, either a number or an alias like `"string"`.
+    pub fn has_type(self, bson_type: impl Into<Bson>) -> Filter {
+        self.condition("$type", bson_type)
+    }
+
+    /// Matches documents whose field is an array of `size` elements.
+    pub fn size(self, size: i64) -> Filter {
+        self.condition("$size", size)
+    }
+
+    /// Matches documents whose field is an array containing all of `values`.
+    pub fn contains_all<V: Into<Bson>>(self, values: impl IntoIterator<Item = V>) -> Filter {
+        self.condition("$all", values.into_iter().map(Into::into).collect::<Vec<Bson>>())
+    }
+
+    /// Matches documents whose field is an array with an element matching `filter`.
+    pub fn elem_match(self, filter: Filter) -> Filter {
+        self.condition("$elemMatch", filter.document)
+    }
+
+    /// Matches documents whose field divided by `divisor` leaves `remainder`.
+    pub fn modulo(self, divisor: i64, remainder: i64) -> Filter {
+        self.condition("$mod", vec![divisor, remainder])
+    }
+
+    /// Matches documents whose field matches the regular expression. `options` are the mongodb regex options, like `"i"`.
+    pub fn regex(self, pattern: &str, options: &str) -> Filter {
+        let mut condition = bson::doc! { "$regex": pattern };
+        if !options.is_empty() {
+            condition.insert("$options", options);
+        }
+        let mut document = Document::new();
+        document.insert(self.path, condition);
+        Filter { document }
+    }
+
+    pub fn bits_all_set(self, mask: i64) -> Filter {
+        self.condition("$bitsAllSet", mask)
+    }
+
+    pub fn bits_all_clear(self, mask: i64) -> Filter {
+        self.condition("$bitsAllClear", mask)
+    }
+
+    pub fn bits_any_set(self, mask: i64) -> Filter {
+        self.condition("$bitsAnySet", mask)
+    }
+
+    pub fn bits_any_clear(self, mask: i64) -> Filter {
+        self.condition("$bitsAnyClear", mask)
+    }
+}
+
+impl From<Filter> for Document {
+    fn from(filter: Filter) -> Document {
+        filter.document
+    }
+}
+
+/// An update document, built with one of the operator functions and combined with [`Update::and()`].
+#[derive(Clone, Debug, PartialEq)]
+pub struct Update {
+    document: Document,
+}
+
+impl Update {
+    /// An update that changes nothing.
+    pub fn default() -> Update {
+        Update { document: Document::new() }
+    }
+
+    /// Applies any update operator to a field, for operators without a function of their own like `$bit`.
+    pub fn operator(operator: &str, path: &str, value: impl Into<Bson>) -> Update {
+        let mut fields = Document::new();
+        fields.insert(path, value.into());
+        let mut document = Document::new();
+        document.insert(operator, fields);
+        Update { document }
+    }
+
+    pub fn set(path: &str, value: impl Into<Bson>) -> Update {
+        Update::operator("$set", path, value)
+    }
+
+    /// Sets the field only when the update inserts a new document, as part of an upsert.
+    pub fn set_on_insert(path: &str, value: impl Into<Bson>) -> Update {
+        Update::operator("$setOnInsert", path, value)
+    }
+
+    pub fn unset(path: &str) -> Update {
+        Update::operator("$unset", path, "")
+    }
+
+    pub fn inc(path: &str, amount: impl Into<Bson>) -> Update {
+        Update::operator("$inc", path, amount)
+    }
+
+    pub fn mul(path: &str, factor: impl Into<Bson>) -> Update {
+        Update::operator("$mul", path, factor)
+    }
+
+    pub fn min(path: &str, value: impl Into<Bson>) -> Update {
+        Update::operator("$min", path, value)
+    }
+
+    pub fn max(path: &str, value: impl Into<Bson>) -> Update {
+        Update::operator("$max", path, value)
+    }
+
+    pub fn rename(path: &str, new_path: &str) -> Update {
+        Update::operator("$rename", path, new_path)
+    }
+
+    /// Sets the field to the current date.
+    pub fn current_date(path: &str) -> Update {
+        Update::operator("$currentDate", path, true)
+    }
+
+    /// Appends a value to an array. The value can also be a document of modifiers like `{"$each": [...], "$sort": 1}`.
+    pub fn push(path: &str, value: impl Into<Bson>) -> Update {
+        Update::operator("$push", path, value)
+    }
+
+    pub fn add_to_set(path: &str, value: impl Into<Bson>) -> Update {
+        Update::operator("$addToSet", path, value)
+    }
+
+    /// Removes the array elements equal to the value or, if the value is a query document, matching it.
+    pub fn pull(path: &str, value: impl Into<Bson>) -> Update {
+        Update::operator("$pull", path, value)
+    }
+
+    pub fn pull_all<V: Into<Bson>>(path: &str, values: impl IntoIterator<Item = V>) -> Update {
+        Update::operator("$pullAll", path, values.into_iter().map(Into::into).collect::<Vec<Bson>>())
+    }
+
+    /// Removes the first element of an array if `first` is true, the last one otherwise.
+    pub fn pop(path: &str, first: bool) -> Update {
+        Update::operator("$pop", path, if first { -1 } else { 1 })
+    }
+
+    /// Combines two updates. Fields of the same operator are merged; a field set twice by the same operator keeps the later value.
+    pub fn and(mut self, other: Update) -> Update {
+        for (operator, fields) in other.document {
+            match (self.document.get_mut(&operator), fields) {
+                (Some(Bson::Document(existing)), Bson::Document(fields)) => existing.extend(fields),
+                (_, fields) => {
+                    self.document.insert(operator, fields);
+                }
+            }
+        }
+        self
+    }
+
+    pub fn document(&self) -> &Document {
+        &self.document
+    }
+
+    pub fn into_document(self) -> Document {
+        self.document
+    }
+}
+
+impl From<Update> for Document {
+    fn from(update: Update) -> Document {
+        update.document
+    }
+}
+
+/// Builds a [`Filter`](crate::builder::Filter) out of comparisons, which are all required to hold, for example
+/// `filter!("age" >= 18, "name" == "Ada")`. The supported comparisons are `==`, `!=`, `<`, `<=`, `>` and `>=`.
+#[macro_export]
+macro_rules! filter {
+    () => {
+        $crate::builder::Filter::all()
+    };
+    ($($field:literal $op:tt $value:expr),+ $(,)?) => {
+        $crate::builder::Filter::all()$(.and($crate::filter!(@condition $field $op $value)))+
+    };
+    (@condition $field:literal == $value:expr) => {
+        $crate::builder::Filter::field($field).eq($value)
+    };
+    (@condition $field:literal != $value:expr) => {
+        $crate::builder::Filter::field($field).ne($value)
+    };
+    (@condition $field:literal < $value:expr) => {
+        $crate::builder::Filter::field($field).lt($value)
+    };
+    (@condition $field:literal <= $value:expr) => {
+        $crate::builder::Filter::field($field).lte($value)
+    };
+    (@condition $field:literal > $value:expr) => {
+        $crate::builder::Filter::field($field).gt($value)
+    };
+    (@condition $field:literal >= $value:expr) => {
+        $crate::builder::Filter::field($field).gte($value)
+    };
+}
+
+/// Builds an [`Update`](crate::builder::Update) in the style of [`bson::doc!`], naming each operator without its `$`, for example
+/// `update!(set { "name": "Ada" }, inc { "age": 1 })`.
+#[macro_export]
+macro_rules! update {
+    ($($operator:ident { $($field:literal : $value:expr),* $(,)? }),+ $(,)?) => {
+        $crate::builder::Update::default()$($(.and($crate::builder::Update::operator($crate::update!(@operator $operator), $field, $value)))*)+
+    };
+    (@operator set) => { "$set" };
+    (@operator set_on_insert) => { "$setOnInsert" };
+    (@operator unset) => { "$unset" };
+    (@operator inc) => { "$inc" };
+    (@operator mul) => { "$mul" };
+    (@operator min) => { "$min" };
+    (@operator max) => { "$max" };
+    (@operator rename) => { "$rename" };
+    (@operator current_date) => { "$currentDate" };
+    (@operator push) => { "$push" };
+    (@operator add_to_set) => { "$addToSet" };
+    (@operator pull) => { "$pull" };
+    (@operator pull_all) => { "$pullAll" };
+    (@operator pop) => { "$pop" };
+    (@operator bit) => { "$bit" };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::base::{CollectionConfig, CollectionTrait};
+    use crate::database::{Database, DatabaseConfig};
+
+    #[test]
+    fn test_filter() {
+        assert_eq!(Filter::field("name").eq("Ada").into_document(), bson::doc! { "name": "Ada" });
+        assert_eq!(Filter::field("age").gt(1).or(Filter::field("age").lt(0)).or(Filter::field("age").exists(false)).into_document(), bson::doc! { "$or": [{ "age": { "$gt": 1 } }, { "age": { "$lt": 0 } }, { "age": { "$exists": false } }] });
+        assert_eq!(Filter::all().and(Filter::field("a").size(2)), Filter::field("a").size(2));
+        assert_eq!((!Filter::field("tags").is_in(vec!["a", "b"])).into_document(), bson::doc! { "$nor": [{ "tags": { "$in": ["a", "b"] } }] });
+        assert_eq!(Filter::field("name").regex("^a", "i").into_document(), bson::doc! { "name": { "$regex": "^a", "$options": "i" } });
+        assert_eq!(Filter::field("n").modulo(4, 1).into_document(), bson::doc! { "n": { "$mod": [4_i64, 1_i64] } });
+        assert_eq!(Filter::field("scores").elem_match(Filter::field("value").gte(80)).into_document(), bson::doc! { "scores": { "$elemMatch": { "value": { "$gte": 80 } } } });
+        assert_eq!(filter!(), Filter::all());
+        assert_eq!(filter!("age" >= 18), Filter::field("age").gte(18));
+        assert_eq!(filter!("a" == 1, "b" != "x", "c" < 2.5).into_document(), bson::doc! { "$and": [{ "a": 1 }, { "b": { "$ne": "x" } }, { "c": { "$lt": 2.5 } }] });
+    }
+
+    #[test]
+    fn test_update_builder() {
+        assert_eq!(Update::set("a", 1).and(Update::set("b", "x")).and(Update::unset("c")).into_document(), bson::doc! { "$set": { "a": 1, "b": "x" }, "$unset": { "c": "" } });
+        assert_eq!(Update::pop("queue", true).into_document(), bson::doc! { "$pop": { "queue": -1 } });
+        assert_eq!(update!(set { "a": 1, "b": "x" }, unset { "c": "" }), Update::set("a", 1).and(Update::set("b", "x")).and(Update::unset("c")));
+        assert_eq!(update!(set_on_insert { "created": true }).into_document(), bson::doc! { "$setOnInsert": { "created": true } });
+    }
+
+    #[test]
+    fn test_builders_on_collection() {
+        let mut db = Database::open(&DatabaseConfig::in_memory()).unwrap();
+        let mut people = db.create_collection("people", &CollectionConfig::default("people")).unwrap();
+        for (name, age) in [("Ada", 36), ("Alan", 41), ("Grace", 85), ("Linus", 12)] {
+            people.insert_one(&bson::doc! { "name": name, "age": age }).unwrap();
+        }
+
+        let adults = Filter::field("age").gte(18).and(Filter::field("name").ne("Grace"));
+        assert_eq!(people.count_documents(adults.document(), &None).unwrap(), 2);
+        assert_eq!(people.count_documents(filter!("age" < 40).or(Filter::field("name").is_in(vec!["Grace"])).document(), &None).unwrap(), 3);
+
+        people.update_many(filter!("age" >= 18).document(), update!(inc { "age": 1 }, set { "adult": true }).document(), 0, 0, false, &None).unwrap();
+        let grace = people.find_one(filter!("name" == "Grace").document(), 0).unwrap();
+        assert_eq!(grace.data.get_i32("age").unwrap(), 86);
+        assert!(grace.data.get_bool("adult").unwrap());
+
+        // every operator on a field applies.
+        assert_eq!(people.count_documents(&bson::doc! { "age": { "$gte": 18, "$lt": 65 } }, &None).unwrap(), 2);
+
+        // expressions are evaluated by the matcher inside the query.
+        let older_than_name = Filter::expr(bson::doc! { "$gt": ["$age", { "$strLenCP": "$name" }] }).and(!Filter::field("name").eq("Linus"));
+        assert_eq!(people.count_documents(older_than_name.document(), &None).unwrap(), 3);
+        let mut found = Vec::new();
+        people.find(Filter::expr(bson::doc! { "$lt": ["$age", 40] }).document(), &None, &mut |record| {
+            found.push(record.data.get_str("name").unwrap().to_string());
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(found, vec!["Ada", "Linus"]);
+
+        // a misspelled operator is an error rather than a filter that matches everything.
+        assert!(people.count_documents(&bson::doc! { "age": { "$gtee": 18 } }, &None).is_err());
+        assert!(people.count_documents(&bson::doc! { "age": { "$gte": 20, "$ltt": 5 } }, &None).is_err());
+        assert!(people.find_one(&bson::doc! { "$nand": [{ "age": 1 }] }, 0).is_err());
+        assert!(people.update_one(&bson::doc! { "name": "Ada" }, &bson::doc! { "$increment": { "age": 1 } }, 0, false, &None).is_err());
+    }
+
+    #[test]
+    fn test_negation_on_collection() {
+        let mut db = Database::open(&DatabaseConfig::in_memory()).unwrap();
+        let mut people = db.create_collection("people", &CollectionConfig::default("people")).unwrap();
+        for age in [10, 20, 30, 70] {
+            people.insert_one(&bson::doc! { "age": age }).unwrap();
+        }
+
+        assert_eq!(people.count_documents((!Filter::field("age").gte(20)).document(), &None).unwrap(), 1);
+        assert_eq!(people.count_documents((!Filter::field("age").gte(20).or(Filter::field("age").gt(60))).document(), &None).unwrap(), 1);
+        assert_eq!(people.count_documents((!!Filter::field("age").lt(30)).document(), &None).unwrap(), 2);
+    }
+}
@@ -0,0 +1,174 @@
+//! Capped collections.
+//!
+//! A capped collection, see [`CollectionConfig::capped()`], keeps at most a given number of documents, or of bytes, and evicts the oldest
+//! documents when an insert goes over the limit, which makes it a ring buffer for logs and events. The eviction is done by triggers on the
+//! collection's table, so it doesn't matter which connection or transaction inserts.
+//!
+//! The ids of a capped collection follow the insertion order, which is also the order it is read in. A [`TailableCursor`] uses this to follow the
+//! collection, returning the documents inserted since it last looked.
+
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+use rusqlite::params_from_iter;
+
+use crate::base::*;
+use crate::collection::Collection;
+use crate::handle::CollectionHandle;
+use crate::query_translator::QueryTranslator;
+
+/// Converts a limit to the integer sqlite stores.
+pub(crate) fn limit(value: u64) -> i64 {
+    i64::try_from(value).unwrap_or(i64::MAX)
+}
+
+/// Creates the triggers that keep a capped collection within its limits.
+pub(crate) fn create_triggers(tx: &rusqlite::Transaction, config: &CollectionConfig) -> rusqlite::Result<()> {
+    let table = &config.table_name;
+    let mut evict = String::new();
+    if config.capped_max_documents > 0 {
+        evict.push_str(&format!("DELETE FROM [{}] WHERE _id IN (SELECT _id FROM [{}] ORDER BY _id DESC LIMIT -1 OFFSET {});", table, table, limit(config.capped_max_documents)));
+    }
+    if config.capped_max_bytes > 0 {
+        let max_bytes = limit(config.capped_max_bytes);
+        let evict_bytes = format!(
+            "DELETE FROM [{}] WHERE _id IN (SELECT _id FROM (SELECT _id, SUM(length(raw)) OVER (ORDER BY _id DESC) AS total FROM [{}]) WHERE total > {});",
+            table, table, max_bytes
+        );
+        evict.push_str(&evict_bytes);
+
+        // a document that doesn't fit on its own would evict everything, itself included.
+        for event in ["INSERT", "UPDATE OF raw"] {
+            tx.execute(
+                &format!(
+                    "CREATE TRIGGER [{}_capped_{}] BEFORE {} ON [{}] WHEN length(NEW.raw) > {} BEGIN SELECT RAISE(ABORT, 'The document is larger than the capped collection'); END",
+                    table,
+                    if event == "INSERT" { "too_large_insert" } else { "too_large_update" },
+                    event,
+                    table,
+                    max_bytes
+                ),
+                [],
+            )?;
+        }
+        tx.execute(&format!("CREATE TRIGGER [{}_capped_update] AFTER UPDATE OF raw ON [{}] BEGIN {} END", table, table, evict_bytes), [])?;
+    }
+    tx.execute(&format!("CREATE TRIGGER [{}_capped_insert] AFTER INSERT ON [{}] BEGIN {} END", table, table, evict), [])?;
+    Ok(())
+}
+
+/// A cursor that follows a capped collection, like a MongoDB tailable cursor. It remembers the last document it returned, and every call to
+/// [`Collection::tail()`] returns the matching documents inserted since. Documents that are evicted before the cursor gets to them are skipped.
+#[derive(Clone, Debug)]
+pub struct TailableCursor {
+    query: bson::Document,
+    last_id: i64,
+    batch_size: usize,
+}
+
+impl TailableCursor {
+    /// Creates a cursor over the documents matching `query`, starting with the oldest one.
+    pub fn new(query: &bson::Document) -> Self {
+        TailableCursor { query: query.clone(), last_id: 0, batch_size: 100 }
+    }
+
+    /// The maximum number of documents returned at a time, 100 by default.
+    pub fn batch_size<'a>(&'a mut self, arg: usize) -> &'a mut TailableCursor {
+        self.batch_size = arg.max(1);
+        self
+    }
+
+    /// The id of the last document returned, 0 if there was none.
+    pub fn last_id(&self) -> i64 {
+        self.last_id
+    }
+}
+
+#[inline]
+fn tail_internal<A, C: Adapter<A>, const H: bool, const L: bool>(conn: &C, config: &CollectionConfig, cursor: &mut TailableCursor) -> Result<Vec<Record>, String> {
+    let mut params = Vec::<rusqlite::types::Value>::new();
+    let where_str = QueryTranslator {}.query_document(&cursor.query, &mut params)?;
+    params.push(rusqlite::types::Value::Integer(cursor.last_id));
+
+    let sql = format!(
+        "SELECT * FROM [{}] WHERE _id > ?{} {} ORDER BY _id LIMIT {};",
+        config.table_name,
+        params.len(),
+        if where_str.is_empty() { String::new() } else { format!("AND ({})", where_str) },
+        cursor.batch_size
+    );
+    let mut stmt = conn.prepare_cached_wrapper(&sql).map_err(|e| e.to_string())?;
+    let records: Vec<Record> = stmt.query_map(params_from_iter(params.iter()), |row| Ok(read_record::<H, L>(row))).and_then(|rows| rows.collect()).map_err(|e| e.to_string())?;
+    if let Some(record) = records.last() {
+        cursor.last_id = record.id;
+    }
+    Ok(records)
+}
+
+impl<'a> Collection<'a> {
+    /// Returns the next documents of a tailable cursor, in insertion order. An empty batch means there is no new document yet; the cursor can be
+    /// polled again later.
+    pub fn tail(&mut self, cursor: &mut TailableCursor) -> Result<Vec<Record>, String> {
+        if !self.config.is_capped() {
+            return Err("Only capped collections can be tailed".to_string());
+        }
+        match (self.config.should_hash_document, self.config.should_log_last_modified) {
+            (true, true) => tail_internal::<_, _, true, true>(self.db, &self.config, cursor),
+            (true, false) => tail_internal::<_, _, true, false>(self.db, &self.config, cursor),
+            (false, false) => tail_internal::<_, _, false, false>(self.db, &self.config, cursor),
+            (false, true) => tail_internal::<_, _, false, true>(self.db, &self.config, cursor),
+        }
+    }
+}
+
+/// A blocking iterator over the documents of a capped collection as they arrive, see [`CollectionHandle::follow()`]. It never ends by
+/// itself; [`Tail::next_timeout()`] waits for a limited time instead.
+pub struct Tail {
+    collection: CollectionHandle,
+    cursor: TailableCursor,
+    poll_interval: Duration,
+    buffer: VecDeque<Record>,
+}
+
+impl Tail {
+    pub(crate) fn new(collection: CollectionHandle, cursor: TailableCursor, poll_interval: Duration) -> Self {
+        Tail { collection, cursor, poll_interval, buffer: VecDeque::new() }
+    }
+
+    /// Waits up to `timeout` for the next document. `Ok(None)` means no document arrived in time.
+    pub fn next_timeout(&mut self, timeout: Duration) -> Result<Option<Record>, String> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            if let Some(record) = self.buffer.pop_front() {
+                return Ok(Some(record));
+            }
+            self.buffer.extend(self.collection.tail(&mut self.cursor)?);
+            if self.buffer.is_empty() {
+                let now = Instant::now();
+                if now >= deadline {
+                    return Ok(None);
+                }
+                std::thread::sleep(self.poll_interval.min(deadline - now));
+            }
+        }
+    }
+
+    /// The cursor, which holds the position of the last document returned.
+    pub fn cursor(&self) -> &TailableCursor {
+        &self.cursor
+    }
+}
+
+impl Iterator for Tail {
+    type Item = Result<Record, String>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            match self.next_timeout(self.poll_interval) {
+                Ok(Some(record)) => return Some(Ok(record)),
+                Ok(None) => continue,
+                Err(e) => return Some(Err(e)),
+            }
+        }
+    }
+}
@@ -0,0 +1,291 @@
+//! CSV and TSV import and export.
+//!
+//! Columns are mapped to documents with dotted field paths, the same syntax `json_field` and queries use. An export writes one row per document
+//! found and one column per path; nested documents and arrays are written as JSON. An import builds a document per row, putting each column at
+//! its path, so the headers `name.first,name.last` produce `{"name": {"first": ..., "last": ...}}`. Column values are converted according to their
+//! declared [`ColumnType`], or inferred.
+//!
+//! Quoting follows RFC 4180: fields containing the delimiter, quotes or line breaks are quoted, and quotes are doubled.
+
+use std::io::{BufRead, Write};
+
+use crate::base::*;
+use crate::collection::Collection;
+use crate::dump::{to_plain_json, ImportError, ImportReport, IMPORT_BATCH_SIZE};
+
+/// How an imported column value is converted.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ColumnType {
+    /// `true` and `false` become booleans, integers become `Int32`, or `Int64` if they don't fit, other numbers become doubles, and everything
+    /// else is a string. Empty values are left out of the document.
+    Infer,
+    String,
+    Int32,
+    Int64,
+    Double,
+    Boolean,
+    /// An RFC 3339 date, e.g. `2021-12-01T10:00:00Z`.
+    DateTime,
+    /// A hex encoded ObjectId.
+    ObjectId,
+    /// A JSON value, which may use extended JSON.
+    Json,
+}
+
+/// A column of a CSV file.
+#[derive(Clone, Debug)]
+pub struct CsvColumn {
+    /// The column's header.
+    pub header: String,
+    /// The dotted path of the field the column holds.
+    pub path: String,
+    /// The type the column is converted to on import.
+    pub column_type: ColumnType,
+}
+
+/// The options of a CSV import or export. This struct uses the builder pattern.
+#[derive(Clone, Debug)]
+pub struct CsvOption {
+    /// The field delimiter, `,` by default.
+    pub delimiter: char,
+    /// Whether the first row holds the headers. On export, a header row is written.
+    pub has_header: bool,
+    /// The columns. An export writes these columns. An import uses the column whose header matches for each header of the file, and reads
+    /// unlisted headers as paths with [`ColumnType::Infer`]. Without a header row, the columns are taken in order.
+    pub columns: Vec<CsvColumn>,
+}
+
+impl CsvOption {
+    pub fn default() -> Self {
+        CsvOption { delimiter: ',', has_header: true, columns: Vec::new() }
+    }
+
+    /// Options for tab separated values.
+    pub fn tsv() -> Self {
+        CsvOption { delimiter: '\t', has_header: true, columns: Vec::new() }
+    }
+
+    pub fn delimiter<'a>(&'a mut self, arg: char) -> &'a mut CsvOption {
+        self.delimiter = arg;
+        self
+    }
+
+    pub fn has_header<'a>(&'a mut self, arg: bool) -> &'a mut CsvOption {
+        self.has_header = arg;
+        self
+    }
+
+    /// Adds a column whose header is its path and whose type is inferred.
+    pub fn column<'a>(&'a mut self, path: &str) -> &'a mut CsvOption {
+        self.typed_column(path, path, ColumnType::Infer)
+    }
+
+    /// Adds a column with a header that differs from its path, and a declared type.
+    pub fn typed_column<'a>(&'a mut self, header: &str, path: &str, column_type: ColumnType) -> &'a mut CsvOption {
+        self.columns.push(CsvColumn { header: header.to_string(), path: path.to_string(), column_type });
+        self
+    }
+}
+
+fn write_row(writer: &mut dyn Write, fields: &[String], delimiter: char) -> std::io::Result<()> {
+    let mut line = String::new();
+    for (i, field) in fields.iter().enumerate() {
+        if i > 0 {
+            line.push(delimiter);
+        }
+        if field.contains([delimiter, '"', '\n', '\r']) {
+            line.push('"');
+            line.push_str(&field.replace('"', "\"\""));
+            line.push('"');
+        } else {
+            line.push_str(field);
+        }
+    }
+    line.push_str("\r\n");
+    writer.write_all(line.as_bytes())
+}
+
+/// Reads the next row. A quoted field may span several lines, so `line_number` is advanced by the number of lines read.
+fn read_row(reader: &mut dyn BufRead, delimiter: char, line_number: &mut usize) -> Result<Option<Vec<String>>, String> {
+    let mut fields = Vec::new();
+    let mut field = String::new();
+    let mut in_quotes = false;
+    let mut line = String::new();
+    loop {
+        line.clear();
+        if reader.read_line(&mut line).map_err(|e| e.to_string())? == 0 {
+            if in_quotes {
+                return Err("the file ends inside a quoted field".to_string());
+            }
+            if fields.is_empty() && field.is_empty() {
+                return Ok(None);
+            }
+            fields.push(field);
+            return Ok(Some(fields));
+        }
+        *line_number += 1;
+
+        let mut chars = line.chars().peekable();
+        while let Some(c) = chars.next() {
+            if in_quotes {
+                if c == '"' {
+                    if chars.peek() == Some(&'"') {
+                        chars.next();
+                        field.push('"');
+                    } else {
+                        in_quotes = false;
+                    }
+                } else {
+                    field.push(c);
+                }
+            } else if c == '"' {
+                in_quotes = true;
+            } else if c == delimiter {
+                fields.push(std::mem::take(&mut field));
+            } else if c == '\n' || c == '\r' {
+                // the line break ends the row, a '\r' is followed by '\n' at most.
+                break;
+            } else {
+                field.push(c);
+            }
+        }
+        if !in_quotes {
+            fields.push(field);
+            return Ok(Some(fields));
+        }
+    }
+}
+
+/// Formats a field value as a CSV cell.
+fn to_cell(value: Option<&bson::Bson>) -> String {
+    match value.map(to_plain_json) {
+        None | Some(serde_json::Value::Null) => String::new(),
+        Some(serde_json::Value::String(s)) => s,
+        Some(other) => other.to_string(),
+    }
+}
+
+/// Converts a cell to a value. `None` leaves the field out.
+fn parse_cell(cell: &str, column_type: ColumnType) -> Result<Option<bson::Bson>, String> {
+    let invalid = |type_name: &str| format!("'{}' is not a valid {}", cell, type_name);
+    if cell.is_empty() && column_type != ColumnType::String {
+        return Ok(None);
+    }
+    let value = match column_type {
+        ColumnType::Infer => {
+            if cell == "true" || cell == "false" {
+                bson::Bson::Boolean(cell == "true")
+            } else if let Ok(i) = cell.parse::<i64>() {
+                i32::try_from(i).map(bson::Bson::Int32).unwrap_or(bson::Bson::Int64(i))
+            } else if let Some(f) = cell.parse::<f64>().ok().filter(|f| f.is_finite()) {
+                bson::Bson::Double(f)
+            } else {
+                bson::Bson::String(cell.to_string())
+            }
+        }
+        ColumnType::String => bson::Bson::String(cell.to_string()),
+        ColumnType::Int32 => bson::Bson::Int32(cell.trim().parse().map_err(|_| invalid("32-bit integer"))?),
+        ColumnType::Int64 => bson::Bson::Int64(cell.trim().parse().map_err(|_| invalid("64-bit integer"))?),
+        ColumnType::Double => bson::Bson::Double(cell.trim().parse().map_err(|_| invalid("number"))?),
+        ColumnType::Boolean => bson::Bson::Boolean(cell.trim().parse().map_err(|_| invalid("boolean"))?),
+        ColumnType::DateTime => bson::Bson::DateTime(bson::DateTime::parse_rfc3339_str(cell.trim()).map_err(|_| invalid("date"))?),
+        ColumnType::ObjectId => bson::Bson::ObjectId(bson::oid::ObjectId::parse_str(cell.trim()).map_err(|_| invalid("ObjectId"))?),
+        ColumnType::Json => {
+            let json: serde_json::Value = serde_json::from_str(cell).map_err(|e| e.to_string())?;
+            bson::Bson::try_from(json).map_err(|e| e.to_string())?
+        }
+    };
+    Ok(Some(value))
+}
+
+impl<'a> Collection<'a> {
+    /// Writes the documents matching `query` to `writer` as CSV, one column per column of `csv`.
+    pub fn export_csv(&mut self, writer: &mut dyn Write, query: &bson::Document, options: &Option<SearchOption>, csv: &CsvOption) -> Result<usize, String> {
+        if csv.columns.is_empty() {
+            return Err("a CSV export needs at least one column".to_string());
+        }
+        if csv.has_header {
+            let headers: Vec<String> = csv.columns.iter().map(|column| column.header.clone()).collect();
+            write_row(writer, &headers, csv.delimiter).map_err(|e| e.to_string())?;
+        }
+
+        let mut count = 0;
+        let mut write_error: Option<String> = None;
+        let result = self.find(query, options, &mut |record| {
+            let cells: Vec<String> = csv.columns.iter().map(|column| to_cell(crate::matcher::get_path(&record.data, &column.path))).collect();
+            match write_row(writer, &cells, csv.delimiter) {
+                Ok(()) => {
+                    count += 1;
+                    Ok(())
+                }
+                Err(e) => {
+                    write_error = Some(e.to_string());
+                    Err("export failed")
+                }
+            }
+        });
+        if let Some(e) = write_error {
+            return Err(e);
+        }
+        result.map_err(|e| e.to_string())?;
+        writer.flush().map_err(|e| e.to_string())?;
+        Ok(count)
+    }
+
+    /// Reads CSV rows from `reader` and inserts a document per row, [`IMPORT_BATCH_SIZE`] documents per transaction. Rows that can't be
+    /// converted or inserted are skipped and listed in the report by the line they start on.
+    pub fn import_csv(&mut self, reader: &mut dyn BufRead, csv: &CsvOption) -> Result<ImportReport, String> {
+        let mut line_number = 0;
+        let columns: Vec<CsvColumn> = if csv.has_header {
+            let headers = read_row(reader, csv.delimiter, &mut line_number)?.ok_or("the CSV file has no header row")?;
+            headers
+                .into_iter()
+                .map(|header| match csv.columns.iter().find(|column| column.header == header) {
+                    Some(column) => column.clone(),
+                    None => CsvColumn { path: header.clone(), header, column_type: ColumnType::Infer },
+                })
+                .collect()
+        } else if csv.columns.is_empty() {
+            return Err("a CSV file without a header row needs the columns to be listed".to_string());
+        } else {
+            csv.columns.clone()
+        };
+
+        let mut report = ImportReport::default();
+        let mut batch: Vec<(usize, bson::Document)> = Vec::with_capacity(IMPORT_BATCH_SIZE);
+        loop {
+            let line = line_number + 1;
+            let row = match read_row(reader, csv.delimiter, &mut line_number) {
+                Ok(Some(row)) => row,
+                Ok(None) => break,
+                Err(message) => {
+                    report.errors.push(ImportError { line, message });
+                    break;
+                }
+            };
+            if row.len() == 1 && row[0].is_empty() {
+                continue;
+            }
+            if row.len() > columns.len() {
+                report.errors.push(ImportError { line, message: format!("the row has {} fields, but there are only {} columns", row.len(), columns.len()) });
+                continue;
+            }
+
+            let mut doc = bson::Document::new();
+            let converted = row.iter().zip(columns.iter()).try_for_each(|(cell, column)| match parse_cell(cell, column.column_type) {
+                Ok(Some(value)) => crate::update::set_path(&mut doc, &column.path, Some(value)).map_err(|e| format!("{}: {}", column.header, e)),
+                Ok(None) => Ok(()),
+                Err(e) => Err(format!("{}: {}", column.header, e)),
+            });
+            match converted {
+                Ok(()) => batch.push((line, doc)),
+                Err(message) => report.errors.push(ImportError { line, message }),
+            }
+            if batch.len() == IMPORT_BATCH_SIZE {
+                self.insert_batch(&mut batch, &mut report)?;
+            }
+        }
+        self.insert_batch(&mut batch, &mut report)?;
+        Ok(report)
+    }
+}
@@ -1,8 +1,16 @@
 use crate::base::*;
+use crate::capped;
 use crate::collection::Collection;
+use crate::identifier::{quote_identifier, validate_collection_name, validate_field_path};
+use crate::migration::{self, DocumentMigration};
+use crate::schema::{self, HoardDocument, Schema};
+use crate::time_series::{self, Granularity, TimeSeries, TimeSeriesConfig};
 use crate::transaction::TransactionCollection;
+use crate::typed::TypedCollection;
+use crate::update;
+use crate::view::{self, View, ViewConfig};
+use slugify::slugify;
 use bson::Bson;
-use chrono::prelude::*;
 use std::cell::RefCell;
 use std::collections::HashMap;
 use std::error::Error;
@@ -11,40 +19,163 @@ use std::marker::PhantomData;
 use std::rc::Rc;
 use std::rc::Weak;
 
-/// This is the operations that can be performed on a bson document. These operations are corresponding to the mongodb operations found on this page.
-enum UpdateOperator {
-    Set,
-    Unset,
-    Inc,
-    Min,
-    Max,
-    CurrentDate,
-    Mul,
-    Rename,
-    SetOnInsert,
-    AddToSet,
-    Pop,
-    Pull,
-    Push,
-    PullAll,
-    Bit,
-}
-
-/// This struct can config a database. This struct uses the builder pattern.
+/// The journal mode of a database, see [the sqlite documentation](https://www.sqlite.org/pragma.html#pragma_journal_mode).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum JournalMode {
+    /// The rollback journal is deleted at the end of each transaction. This is sqlite's default.
+    Delete,
+    /// The rollback journal is truncated instead of deleted.
+    Truncate,
+    /// The rollback journal's header is zeroed instead of deleting the file.
+    Persist,
+    /// The rollback journal is kept in memory. A crash in the middle of a transaction may corrupt the database.
+    Memory,
+    /// Write-ahead logging. Readers don't block the writer and the writer doesn't block readers.
+    Wal,
+    /// No journal. Transactions can't be rolled back reliably.
+    Off,
+}
+
+impl JournalMode {
+    fn as_str(&self) -> &'static str {
+        match self {
+            JournalMode::Delete => "DELETE",
+            JournalMode::Truncate => "TRUNCATE",
+            JournalMode::Persist => "PERSIST",
+            JournalMode::Memory => "MEMORY",
+            JournalMode::Wal => "WAL",
+            JournalMode::Off => "OFF",
+        }
+    }
+}
+
+/// How often sqlite waits for data to reach the disk, see [the sqlite documentation](https://www.sqlite.org/pragma.html#pragma_synchronous).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Synchronous {
+    Off,
+    /// Safe from corruption in WAL mode, but a power loss may roll back the last transactions.
+    Normal,
+    /// sqlite's default.
+    Full,
+    Extra,
+}
+
+impl Synchronous {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Synchronous::Off => "OFF",
+            Synchronous::Normal => "NORMAL",
+            Synchronous::Full => "FULL",
+            Synchronous::Extra => "EXTRA",
+        }
+    }
+}
+
+/// Whether other processes can access the database while it is open, see [the sqlite documentation](https://www.sqlite.org/pragma.html#pragma_locking_mode).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LockingMode {
+    /// Locks are released at the end of each transaction. This is sqlite's default.
+    Normal,
+    /// Once the database has been written, it stays locked until it is closed. Other processes can't access it, and fewer system calls are needed.
+    Exclusive,
+}
+
+impl LockingMode {
+    fn as_str(&self) -> &'static str {
+        match self {
+            LockingMode::Normal => "NORMAL",
+            LockingMode::Exclusive => "EXCLUSIVE",
+        }
+    }
+}
+
+/// Where a database lives.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Storage {
+    /// A database file at [`DatabaseConfig::path`].
+    File,
+    /// A private in-memory database. It disappears when it is closed.
+    Memory,
+    /// A named in-memory database that all connections of this process opening the same name share. It disappears when the last of them is closed.
+    SharedMemory(String),
+    /// A database file in the system's temporary directory that is deleted when the [`Database`] is dropped.
+    Temporary,
+}
+
+/// This struct can config a database. This struct uses the builder pattern. The options left as `None` keep sqlite's defaults.
 #[derive(Clone, Debug)]
 pub struct DatabaseConfig {
-    /// The filepath of the database.
+    /// The filepath of the database. For in-memory databases this is the name sqlite opens, e.g. `:memory:`.
     pub path: String,
+    /// Whether the database is a file, in memory, or temporary.
+    pub storage: Storage,
     /// Setting this to true will enable the tracing function. All composed SQL statements will be printed to the console.
     pub should_trace: bool,
     /// Setting this to true will profile each SQL execution.
     pub should_profile: bool,
+    /// The maximum number of reader connections a [`crate::handle::DatabaseHandle`] opens.
+    pub reader_pool_size: usize,
+    /// The journal mode. The journal mode is stored in the database file once it is set to [`JournalMode::Wal`].
+    pub journal_mode: Option<JournalMode>,
+    /// The synchronous level.
+    pub synchronous: Option<Synchronous>,
+    /// The page size in bytes, a power of two between 512 and 65536. It only takes effect when the database file is created.
+    pub page_size: Option<u32>,
+    /// The page cache size. A positive value is a number of pages, a negative value is a size in KiB, as in sqlite's `cache_size` pragma.
+    pub cache_size: Option<i64>,
+    /// The maximum number of bytes of the database file that are accessed with memory-mapped I/O. 0 disables memory-mapped I/O.
+    pub mmap_size: Option<u64>,
+    /// How long to wait for a lock held by another connection before failing with "database is locked". rusqlite waits 5 seconds by default.
+    pub busy_timeout: Option<std::time::Duration>,
+    /// Open the database in read-only mode.
+    pub read_only: bool,
+    /// Create the database file if it doesn't exist. This is enabled by default.
+    pub create_if_missing: bool,
+    /// The locking mode.
+    pub locking_mode: Option<LockingMode>,
+    /// The document migrations that run when the database is opened.
+    pub migrations: Vec<DocumentMigration>,
 }
 
 impl DatabaseConfig {
     /// Creates a new DatabaseConfig with the given path.
     pub fn new(path: &str) -> Self {
-        DatabaseConfig { path: String::from(path), should_trace: false, should_profile: false }
+        Self::with_storage(path, Storage::File)
+    }
+    /// Creates a DatabaseConfig for a private in-memory database.
+    pub fn in_memory() -> Self {
+        Self::with_storage(":memory:", Storage::Memory)
+    }
+    /// Creates a DatabaseConfig for a named in-memory database that is shared by every connection of this process that opens the same name.
+    pub fn shared_memory(name: &str) -> Self {
+        Self::with_storage(&format!("file:{}?mode=memory&cache=shared", name), Storage::SharedMemory(name.to_string()))
+    }
+    /// Creates a DatabaseConfig for a new database file with a unique name in the system's temporary directory. The file is deleted when the
+    /// [`Database`] is dropped.
+    pub fn temporary() -> Self {
+        static COUNTER: std::sync::atomic::AtomicUsize = std::sync::atomic::AtomicUsize::new(0);
+        let nanos = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).map(|d| d.subsec_nanos()).unwrap_or(0);
+        let name = format!("hoardbase-{}-{}-{}.db", std::process::id(), COUNTER.fetch_add(1, std::sync::atomic::Ordering::Relaxed), nanos);
+        Self::with_storage(&std::env::temp_dir().join(name).to_string_lossy(), Storage::Temporary)
+    }
+    fn with_storage(path: &str, storage: Storage) -> Self {
+        DatabaseConfig {
+            path: String::from(path),
+            storage,
+            should_trace: false,
+            should_profile: false,
+            reader_pool_size: 4,
+            journal_mode: None,
+            synchronous: None,
+            page_size: None,
+            cache_size: None,
+            mmap_size: None,
+            busy_timeout: None,
+            read_only: false,
+            create_if_missing: true,
+            locking_mode: None,
+            migrations: Vec::new(),
+        }
     }
     /// Enables tracing.
     pub fn trace<'a>(&'a mut self, arg: bool) -> &'a mut DatabaseConfig {
@@ -56,6 +187,78 @@ impl DatabaseConfig {
         self.should_profile = args;
         self
     }
+    /// Sets the maximum number of reader connections of a [`crate::handle::DatabaseHandle`]. The default is 4.
+    pub fn reader_pool_size<'a>(&'a mut self, size: usize) -> &'a mut DatabaseConfig {
+        self.reader_pool_size = size;
+        self
+    }
+    /// Sets the journal mode.
+    pub fn journal_mode<'a>(&'a mut self, mode: JournalMode) -> &'a mut DatabaseConfig {
+        self.journal_mode = Some(mode);
+        self
+    }
+    /// Sets the synchronous level.
+    pub fn synchronous<'a>(&'a mut self, level: Synchronous) -> &'a mut DatabaseConfig {
+        self.synchronous = Some(level);
+        self
+    }
+    /// Sets the page size of a new database.
+    pub fn page_size<'a>(&'a mut self, bytes: u32) -> &'a mut DatabaseConfig {
+        self.page_size = Some(bytes);
+        self
+    }
+    /// Sets the page cache size, see [`DatabaseConfig::cache_size`].
+    pub fn cache_size<'a>(&'a mut self, size: i64) -> &'a mut DatabaseConfig {
+        self.cache_size = Some(size);
+        self
+    }
+    /// Sets the memory-mapped I/O size.
+    pub fn mmap_size<'a>(&'a mut self, bytes: u64) -> &'a mut DatabaseConfig {
+        self.mmap_size = Some(bytes);
+        self
+    }
+    /// Sets how long to wait for locks held by other connections.
+    pub fn busy_timeout<'a>(&'a mut self, timeout: std::time::Duration) -> &'a mut DatabaseConfig {
+        self.busy_timeout = Some(timeout);
+        self
+    }
+    /// Opens the database in read-only mode.
+    pub fn read_only<'a>(&'a mut self, arg: bool) -> &'a mut DatabaseConfig {
+        self.read_only = arg;
+        self
+    }
+    /// Whether to create the database file if it doesn't exist.
+    pub fn create_if_missing<'a>(&'a mut self, arg: bool) -> &'a mut DatabaseConfig {
+        self.create_if_missing = arg;
+        self
+    }
+    /// Sets the locking mode.
+    pub fn locking_mode<'a>(&'a mut self, mode: LockingMode) -> &'a mut DatabaseConfig {
+        self.locking_mode = Some(mode);
+        self
+    }
+
+    /// Registers a document migration. When the database is opened, `migrate` is called on every document of the collection, unless a
+    /// migration of this or a higher version has run on the collection before. Collections created afterwards start at the highest registered
+    /// version, since they have no old documents.
+    pub fn migration<'a>(&'a mut self, collection: &str, version: i64, migrate: impl Fn(&mut bson::Document) -> Result<(), String> + Send + Sync + 'static) -> &'a mut DatabaseConfig {
+        self.migrations.push(DocumentMigration { collection: collection.to_string(), version, migrate: std::sync::Arc::new(migrate) });
+        self
+    }
+
+    /// The flags to open the database file with.
+    pub(crate) fn open_flags(&self) -> rusqlite::OpenFlags {
+        let mut flags = rusqlite::OpenFlags::SQLITE_OPEN_URI | rusqlite::OpenFlags::SQLITE_OPEN_NO_MUTEX;
+        if self.read_only {
+            flags |= rusqlite::OpenFlags::SQLITE_OPEN_READ_ONLY;
+        } else {
+            flags |= rusqlite::OpenFlags::SQLITE_OPEN_READ_WRITE;
+            if self.create_if_missing {
+                flags |= rusqlite::OpenFlags::SQLITE_OPEN_CREATE;
+            }
+        }
+        flags
+    }
 }
 
 /// This struct represents a custom error that can be thrown from a user defined sqlite function.
@@ -89,6 +292,21 @@ pub struct Database {
     /// The reason that we want to dynamically construct a collection object, instead of storing pre-constructed collection objects in this hash map, is
     /// that a collection object needs to reference to the underlying sqlite connection. Self reference [is not easy](https://arunanshub.hashnode.dev/self-referential-structs-in-rust) in Rust.
     collections: HashMap<String, (String, CollectionConfig)>,
+    /// Deletes the database file of a [`Storage::Temporary`] database. Fields are dropped in declaration order, so the connection is closed by then.
+    _temporary: Option<TemporaryFile>,
+}
+
+/// Deletes a temporary database file, together with its journal files, when dropped.
+struct TemporaryFile {
+    path: String,
+}
+
+impl Drop for TemporaryFile {
+    fn drop(&mut self) {
+        for suffix in ["", "-journal", "-wal", "-shm"] {
+            std::fs::remove_file(format!("{}{}", self.path, suffix)).unwrap_or(());
+        }
+    }
 }
 
 /// If a user wants to execute multiple statements in a Transaction, she needs to obtain a Transaction object first. This object provides a similar interface
@@ -105,16 +323,45 @@ impl<'a> Transaction<'a> {
     pub fn collection(&'a self, collection_name: &str) -> Result<TransactionCollection<'a>, &str> {
         if self.collections.contains_key(collection_name) {
             let (collection_name, collection_config) = self.collections.get(collection_name).unwrap();
+            if collection_config.time_series.is_some() {
+                return Err("The collection is a time-series collection");
+            }
+            if collection_config.view.is_some() {
+                return Err("The collection is a view, which is read-only");
+            }
             Ok(TransactionCollection::<'a> {
                 config: collection_config.clone(),
                 name: collection_name.clone(),
                 db: &self.connection,
-                table_name: collection_name.clone(),
+                table_name: collection_config.table_name.clone(),
             })
         } else {
             Err("No collection found")
         }
     }
+
+    /// Access a time-series collection given its name.
+    pub fn time_series(&'a self, collection_name: &str) -> Result<TimeSeries<'a>, &str> {
+        match self.collections.get(collection_name) {
+            Some((collection_name, config)) if config.time_series.is_some() => Ok(TimeSeries { name: collection_name.clone(), config: config.clone(), db: &self.connection }),
+            Some(_) => Err("The collection isn't a time-series collection"),
+            None => Err("No collection found"),
+        }
+    }
+
+    /// Access a collection given its name, as a collection of `T` whose documents are converted with serde.
+    pub fn typed_collection<T: serde::Serialize + serde::de::DeserializeOwned>(&'a self, collection_name: &str) -> Result<TypedCollection<TransactionCollection<'a>, T>, &str> {
+        Ok(TypedCollection::new(self.collection(collection_name)?))
+    }
+
+    /// Access a view given its name.
+    pub fn view(&'a self, view_name: &str) -> Result<View<'a>, String> {
+        match self.collections.get(view_name) {
+            Some((view_name, config)) if config.view.is_some() => View::new(view_name, config, &self.connection, &|name| self.collections.get(name).map(|(_, config)| config.clone())),
+            Some(_) => Err("The collection isn't a view".to_string()),
+            None => Err("No collection found".to_string()),
+        }
+    }
 }
 
 /// This macro is for convenience. The purpose of this macro is to construct a callback function to process find results.
@@ -128,194 +375,346 @@ macro_rules! process_record {
     };
 }
 
-/// This function is called by the [`Collection::update_many()`] and [`Collection::update_one()`] functions. We use this function to recursively search for a json field by a path string. Then, based on the operator and value, we perform
-/// different operations on the document.
-fn recursive_process(search_doc: &mut bson::Bson, split: &mut std::str::Split<&str>, operator: &UpdateOperator, value: &bson::Bson) -> Result<bool, String> {
-    if let Some(part) = split.next() {
-        if let Some(inner_doc) = search_doc.as_document() {
-            if !inner_doc.contains_key(part) {
-                search_doc.as_document_mut().unwrap().insert(part.to_string(), bson::Bson::Document(bson::Document::new()));
+/// Decodes the arguments shared by the `json_patch` and `json_pipeline` sqlite functions and applies the update with `apply`. A NULL document
+/// means the update is inserting a new document for an upsert.
+fn patch_document(ctx: &rusqlite::functions::Context, apply: fn(&mut bson::Document, &bson::Document, &update::UpdateContext) -> Result<(), String>) -> rusqlite::Result<Option<rusqlite::types::Value>> {
+    let to_error = |message: String| rusqlite::Error::UserFunctionError(Box::new(UserFunctionError { message }));
+    let read_document = |index: usize| -> rusqlite::Result<Option<bson::Document>> {
+        match ctx.get_raw(index) {
+            rusqlite::types::ValueRef::Null => Ok(None),
+            value => {
+                let blob = value.as_blob().map_err(|e| to_error(e.to_string()))?;
+                bson::Document::from_reader(blob).map(Some).map_err(|e| to_error(e.to_string()))
             }
-        } else {
-            search_doc.as_document_mut().unwrap().remove(part.to_string());
-            search_doc.as_document_mut().unwrap().insert(part.to_string(), bson::Bson::Document(bson::Document::new()));
-        }
-
-        if let Some(bson_doc) = search_doc.as_document_mut().unwrap().get_mut(part) {
-            if let Ok(r) = recursive_process(bson_doc, split, operator, value) {
-                if r {
-                    match operator {
-                        UpdateOperator::Set => {
-                            search_doc.as_document_mut().unwrap().remove(part);
-                            search_doc.as_document_mut().unwrap().insert(part.to_string(), value);
-                            return Ok(false);
-                        }
-
-                        UpdateOperator::Inc => {
-                            let original_data = search_doc.as_document().unwrap().get(part).unwrap();
-                            if original_data.element_type() == bson::spec::ElementType::Double {
-                                let d1: f64 = original_data.as_f64().unwrap();
-                                if let bson::Bson::Double(d2) = value {
-                                    search_doc.as_document_mut().unwrap().remove(part);
-                                    search_doc.as_document_mut().unwrap().insert(part.to_string(), bson::Bson::Double(d1 + d2));
-                                    return Ok(false);
-                                }
-                            } else if original_data.element_type() == bson::spec::ElementType::Int64 {
-                                let i1: i64 = original_data.as_i64().unwrap();
-                                if let bson::Bson::Int64(i2) = value {
-                                    search_doc.as_document_mut().unwrap().remove(part);
-                                    search_doc.as_document_mut().unwrap().insert(part.to_string(), bson::Bson::Int64(i1 + i2));
-                                    return Ok(false);
-                                }
-                            } else {
-                                return Err("incorrect data type for operator inc".to_string());
-                            }
-                        }
-
-                        UpdateOperator::Min => {
-                            let original_data = search_doc.as_document().unwrap().get(part).unwrap();
-                            if original_data.element_type() == bson::spec::ElementType::Double {
-                                let d1: f64 = original_data.as_f64().unwrap();
-                                if let bson::Bson::Double(d2) = value {
-                                    search_doc.as_document_mut().unwrap().remove(part);
-                                    search_doc.as_document_mut().unwrap().insert(part.to_string(), bson::Bson::Double(d1.min(*d2)));
-                                    return Ok(false);
-                                }
-                            } else if original_data.element_type() == bson::spec::ElementType::Int64 {
-                                let i1: i64 = original_data.as_i64().unwrap();
-                                if let bson::Bson::Int64(i2) = value {
-                                    search_doc.as_document_mut().unwrap().remove(part);
-                                    search_doc.as_document_mut().unwrap().insert(part.to_string(), bson::Bson::Int64(i1.min(*i2)));
-                                    return Ok(false);
-                                }
-                            } else {
-                                return Err("incorrect data type for operator min".to_string());
-                            }
-                        }
-
-                        UpdateOperator::Max => {
-                            let original_data = search_doc.as_document().unwrap().get(part).unwrap();
-                            if original_data.element_type() == bson::spec::ElementType::Double {
-                                let d1: f64 = original_data.as_f64().unwrap();
-                                if let bson::Bson::Double(d2) = value {
-                                    search_doc.as_document_mut().unwrap().remove(part);
-                                    search_doc.as_document_mut().unwrap().insert(part.to_string(), bson::Bson::Double(d1.max(*d2)));
-                                    return Ok(false);
-                                }
-                            } else if original_data.element_type() == bson::spec::ElementType::Int64 {
-                                let i1: i64 = original_data.as_i64().unwrap();
-                                if let bson::Bson::Int64(i2) = value {
-                                    search_doc.as_document_mut().unwrap().remove(part);
-                                    search_doc.as_document_mut().unwrap().insert(part.to_string(), bson::Bson::Int64(i1.max(*i2)));
-                                    return Ok(false);
-                                }
-                            } else {
-                                return Err("incorrect data type for operator max".to_string());
-                            }
-                        }
-
-                        UpdateOperator::Mul => {
-                            let original_data = search_doc.as_document().unwrap().get(part).unwrap();
-                            if original_data.element_type() == bson::spec::ElementType::Double {
-                                let d1: f64 = original_data.as_f64().unwrap();
-                                if let bson::Bson::Double(d2) = value {
-                                    search_doc.as_document_mut().unwrap().remove(part);
-                                    search_doc.as_document_mut().unwrap().insert(part.to_string(), bson::Bson::Double(d1 * d2));
-                                    return Ok(false);
-                                }
-                            } else if original_data.element_type() == bson::spec::ElementType::Int64 {
-                                let i1: i64 = original_data.as_i64().unwrap();
-                                if let bson::Bson::Int64(i2) = value {
-                                    search_doc.as_document_mut().unwrap().remove(part);
-                                    search_doc.as_document_mut().unwrap().insert(part.to_string(), bson::Bson::Int64(i1 * i2));
-                                    return Ok(false);
-                                }
-                            } else {
-                                return Err("incorrect data type for operator max".to_string());
-                            }
-                        }
-
-                        UpdateOperator::CurrentDate => {
-                            if value.element_type() == bson::spec::ElementType::String {
-                                let date_type = value.as_str().unwrap();
-                                // todo timestamp is not implemented yet
-                                if date_type == "date" || date_type == "timestamp" {
-                                    search_doc.as_document_mut().unwrap().remove(part);
-                                    let utc: DateTime<Utc> = Utc::now();
-                                    search_doc.as_document_mut().unwrap().insert(part.to_string(), bson::DateTime::from(utc));
-                                    return Ok(false);
-                                } else {
-                                    return Err("incorrect date type for operator CurrentDate".to_string());
-                                }
-                            } else {
-                                return Err("incorrect data type for operator CurrentDate".to_string());
-                            }
-                        }
-
-                        UpdateOperator::Unset => {
-                            search_doc.as_document_mut().unwrap().remove(part);
-                            return Ok(false);
-                        }
-
-                        UpdateOperator::Rename => {
-                            if let bson::Bson::String(new_name) = value {
-                                let original_bson: bson::Bson = search_doc.as_document().unwrap().get(part).unwrap().clone();
-                                search_doc.as_document_mut().unwrap().remove(part);
-                                search_doc.as_document_mut().unwrap().insert(new_name, original_bson);
-                            } else {
-                                return Err("incorrect data type for operator Rename".to_string());
-                            }
-                        }
-
-                        UpdateOperator::Pop => {
-                            if let bson::Bson::Int32(pos) = value {
-                                if *pos == 1 {
-                                    search_doc.as_array_mut().unwrap().pop();
-                                } else if *pos == -1 {
-                                    search_doc.as_array_mut().unwrap().remove(0);
-                                } else {
-                                    return Err("incorrect position for operator Pop".to_string());
-                                }
-                            } else {
-                                return Err("incorrect data type for operator Pop".to_string());
-                            }
-                        }
-
-                        UpdateOperator::Push => {
-                            let original_data = search_doc.as_document().unwrap().get(part).unwrap().clone();
-
-                            search_doc.as_array_mut().unwrap().push(original_data);
-                        }
-                        //https://docs.mongodb.com/manual/reference/operator/update-array/
-                        // todo: implement PushAll/pull $in, $each $position etc.
-                        // todo: implement bitwise operators $bit
-                        _ => {}
+        }
+    };
+
+    let original_doc = read_document(0)?;
+    let is_insert = original_doc.is_none();
+    let mut doc = original_doc.unwrap_or_default();
+    let update_doc = read_document(1)?.ok_or_else(|| to_error("update document is missing".to_string()))?;
+
+    let (query, options) = if ctx.len() == 4 { (read_document(2)?, read_document(3)?) } else { (None, None) };
+    let array_filters: Vec<bson::Document> = match options.as_ref().and_then(|o| o.get("arrayFilters")) {
+        Some(bson::Bson::Array(filters)) => filters.iter().filter_map(|f| f.as_document().cloned()).collect(),
+        _ => Vec::new(),
+    };
+
+    let context = update::UpdateContext { is_insert, query: query.as_ref(), array_filters: &array_filters };
+    apply(&mut doc, &update_doc, &context).map_err(to_error)?;
+
+    let mut bytes: Vec<u8> = Vec::new();
+    doc.to_writer(&mut bytes).unwrap();
+    Ok(Some(rusqlite::types::Value::from(bytes)))
+}
+
+/// The implementation of the `json_patch` sqlite function, which applies update operators.
+fn json_patch(ctx: &rusqlite::functions::Context) -> rusqlite::Result<Option<rusqlite::types::Value>> {
+    //https://docs.mongodb.com/manual/reference/operator/update/#std-label-update-operators
+    patch_document(ctx, update::apply_update)
+}
+
+/// The implementation of the `json_pipeline` sqlite function, which applies an aggregation pipeline. The pipeline is passed in as `{"pipeline": [...]}`.
+fn json_pipeline(ctx: &rusqlite::functions::Context) -> rusqlite::Result<Option<rusqlite::types::Value>> {
+    patch_document(ctx, |doc, update_doc, context| {
+        let pipeline: Vec<bson::Document> = match update_doc.get("pipeline") {
+            Some(bson::Bson::Array(stages)) => stages.iter().map(|stage| stage.as_document().cloned().ok_or_else(|| format!("Each element of the 'pipeline' array must be an object: {}", stage))).collect::<Result<_, _>>()?,
+            _ => return Err("pipeline update must be an array of stages".to_string()),
+        };
+        update::apply_pipeline(doc, &pipeline, context)
+    })
+}
+
+/// Installs the tracing and profiling callbacks, and the application-defined functions hoardbase's SQL relies on: `json_field` for extracting a bson
+/// field, `blake3` for hashing documents, and `json_patch` and `json_pipeline` for updating documents. Every connection to a hoardbase database needs
+/// them, including the reader connections of a [`crate::pool::ConnectionPool`].
+pub(crate) fn register_functions(connection: &mut rusqlite::Connection, config: &DatabaseConfig) {
+    if config.should_trace {
+        connection.trace(Some(|statement| {
+            println!("trace: {}", statement);
+        }));
+    }
+
+    if config.should_profile {
+        connection.profile(Some(|statement, duration| {
+            println!("profile: {} {} nanos", statement, duration.as_nanos());
+        }));
+    }
+    // todo: need to change to bson_field
+    connection
+        .create_scalar_function("json_field", 2, rusqlite::functions::FunctionFlags::SQLITE_UTF8 | rusqlite::functions::FunctionFlags::SQLITE_DETERMINISTIC, move |ctx| {
+            assert_eq!(ctx.len(), 2, "called with unexpected number of arguments");
+
+            let field_name = ctx.get_raw(0).as_str().unwrap();
+            let blob = ctx.get_raw(1).as_blob().unwrap();
+
+            let mut doc: bson::Bson = bson::from_reader(blob).unwrap();
+
+            let split = field_name.split(".");
+
+            for part in split {
+                if let bson::Bson::Document(inner_doc) = doc {
+                    if let Some(bson_doc) = inner_doc.get(part) {
+                        doc = bson_doc.clone();
+                    } else {
+                        return Ok(Some(rusqlite::types::Value::from(rusqlite::types::Null)));
                     }
                 } else {
-                    return Ok(false);
+                    return Ok(Some(rusqlite::types::Value::from(rusqlite::types::Null)));
+                }
+            }
+
+            match doc {
+                bson::Bson::Double(f) => Ok(Some(rusqlite::types::Value::from(f))),
+                bson::Bson::String(string) => Ok(Some(rusqlite::types::Value::from(string.clone()))),
+                bson::Bson::Array(_array) => Ok(Some(rusqlite::types::Value::from(rusqlite::types::Null))),
+                bson::Bson::Document(_doc) => Ok(Some(rusqlite::types::Value::from(rusqlite::types::Null))),
+                bson::Bson::Boolean(boolean) => Ok(Some(rusqlite::types::Value::from(boolean))),
+                bson::Bson::Null => Ok(Some(rusqlite::types::Value::from(rusqlite::types::Null))),
+                bson::Bson::RegularExpression(regex) => Ok(Some(rusqlite::types::Value::from(rusqlite::types::Null))),
+                bson::Bson::Int32(i) => Ok(Some(rusqlite::types::Value::from(i))),
+                bson::Bson::Int64(i) => Ok(Some(rusqlite::types::Value::from(i))),
+                bson::Bson::Timestamp(t) => {
+                    let mut integer: i64 = t.increment.into();
+                    integer <<= 32;
+                    let time: i64 = t.time.into();
+                    integer += time;
+                    Ok(Some(rusqlite::types::Value::from(integer)))
                 }
+                bson::Bson::Binary(t) => Ok(Some(rusqlite::types::Value::from(t.bytes.clone()))),
+                bson::Bson::ObjectId(id) => Ok(Some(rusqlite::types::Value::from(id.to_hex()))),
+                bson::Bson::DateTime(dt) => Ok(Some(rusqlite::types::Value::from(dt.timestamp_millis()))),
+                bson::Bson::Decimal128(d) => Ok(Some(rusqlite::types::Value::from(Vec::from(d.bytes().clone())))),
+                _ => Ok(Some(rusqlite::types::Value::from(rusqlite::types::Null))),
             }
+        })
+        .unwrap();
+    
+    // blake3 is chosen as the hash function because it appears to be faster than other choices.
+    // however, this is not verified by the author.
+    // https://crates.io/crates/blake3
+    connection
+        .create_scalar_function("blake3", 1, rusqlite::functions::FunctionFlags::SQLITE_UTF8 | rusqlite::functions::FunctionFlags::SQLITE_DETERMINISTIC, move |ctx| {
+            assert_eq!(ctx.len(), 1, "called with unexpected number of arguments");
+
+            let blob = ctx.get_raw(0).as_blob().unwrap();
+            let mut hasher = blake3::Hasher::new();
+            hasher.update(blob);
+            let result = hasher.finalize();
+            let hex_string = hex::encode(result.as_bytes());
+            Ok(Some(hex_string))
+        })
+        .unwrap();
+    // todo: need to change to bson_patch
+    // json_patch(raw, update) applies an update document. The 4 argument version json_patch(raw, update, query, options) additionally
+    // receives the query, which is needed by the positional operator `$`, and the options document holding the `arrayFilters`.
+    connection.create_scalar_function("json_patch", 2, rusqlite::functions::FunctionFlags::SQLITE_UTF8, json_patch).unwrap();
+    connection.create_scalar_function("json_patch", 4, rusqlite::functions::FunctionFlags::SQLITE_UTF8, json_patch).unwrap();
+    // json_pipeline(raw, pipeline, query, options) is the counterpart of json_patch for aggregation pipeline updates.
+    connection.create_scalar_function("json_pipeline", 4, rusqlite::functions::FunctionFlags::SQLITE_UTF8, json_pipeline).unwrap();
+    // bson_matches(raw, query) is 1 if the document matches the query document. The validators of schemas are checked with it.
+    connection
+        .create_scalar_function("bson_matches", 2, rusqlite::functions::FunctionFlags::SQLITE_UTF8 | rusqlite::functions::FunctionFlags::SQLITE_DETERMINISTIC, |ctx| {
+            let to_error = |message: String| rusqlite::Error::UserFunctionError(Box::new(UserFunctionError { message }));
+            let doc = bson::Document::from_reader(ctx.get_raw(0).as_blob().map_err(|e| to_error(e.to_string()))?).map_err(|e| to_error(e.to_string()))?;
+            let query = bson::Document::from_reader(ctx.get_raw(1).as_blob().map_err(|e| to_error(e.to_string()))?).map_err(|e| to_error(e.to_string()))?;
+            crate::matcher::matches(&doc, &query).map_err(to_error)
+        })
+        .unwrap();
+}
+
+/// Applies the options of `config` that are per connection: the busy timeout, the synchronous level, the cache size, the memory-mapped I/O size
+/// and the locking mode.
+pub(crate) fn configure_connection(connection: &rusqlite::Connection, config: &DatabaseConfig) -> Result<(), &'static str> {
+    if let Some(timeout) = config.busy_timeout {
+        connection.busy_timeout(timeout).map_err(|_| "Failed to set the busy timeout")?;
+    }
+    if let Some(synchronous) = config.synchronous {
+        connection.pragma_update(None, "synchronous", synchronous.as_str()).map_err(|_| "Failed to set the synchronous level")?;
+    }
+    if let Some(cache_size) = config.cache_size {
+        connection.pragma_update(None, "cache_size", cache_size).map_err(|_| "Failed to set the cache size")?;
+    }
+    if let Some(mmap_size) = config.mmap_size {
+        let mmap_size = i64::try_from(mmap_size).map_err(|_| "The mmap size is too large")?;
+        connection.pragma_update_and_check(None, "mmap_size", mmap_size, |row| row.get::<_, i64>(0)).map_err(|_| "Failed to set the mmap size")?;
+    }
+    if let Some(locking_mode) = config.locking_mode {
+        connection.pragma_update_and_check(None, "locking_mode", locking_mode.as_str(), |row| row.get::<_, String>(0)).map_err(|_| "Failed to set the locking mode")?;
+    }
+    Ok(())
+}
+
+/// The progress of a [`Database::backup_to()`] or a [`Database::restore_from()`].
+#[derive(Clone, Copy, Debug)]
+pub struct BackupProgress {
+    /// The number of pages that still need to be copied.
+    pub remaining: i32,
+    /// The total number of pages of the source database.
+    pub page_count: i32,
+}
+
+impl BackupProgress {
+    /// The fraction of the pages copied so far, between 0 and 1.
+    pub fn fraction(&self) -> f64 {
+        if self.page_count <= 0 {
+            1.0
+        } else {
+            (self.page_count - self.remaining) as f64 / self.page_count as f64
+        }
+    }
+}
+
+/// The number of pages an online backup copies per step. Between steps the source database isn't locked, so other connections can keep using it.
+const BACKUP_PAGES_PER_STEP: i32 = 256;
+
+/// How long an online backup waits before retrying a step that couldn't get a lock.
+const BACKUP_RETRY_DELAY: std::time::Duration = std::time::Duration::from_millis(10);
+
+/// Copies the database of `from` into the database of `to` page by page with sqlite's online backup API, calling `progress` after each step.
+/// If another connection writes to the source in the meantime, sqlite restarts the copy, so the result is always a consistent snapshot.
+pub(crate) fn copy_database(from: &rusqlite::Connection, to: &mut rusqlite::Connection, progress: &mut dyn FnMut(BackupProgress)) -> Result<(), String> {
+    use rusqlite::backup::{Backup, StepResult};
+
+    let backup = Backup::new(from, to).map_err(|e| e.to_string())?;
+    loop {
+        let result = backup.step(BACKUP_PAGES_PER_STEP).map_err(|e| e.to_string())?;
+        let current = backup.progress();
+        progress(BackupProgress { remaining: current.remaining, page_count: current.pagecount });
+        match result {
+            StepResult::Done => return Ok(()),
+            StepResult::More => {}
+            _ => std::thread::sleep(BACKUP_RETRY_DELAY),
         }
-        return Ok(false);
-    } else {
-        return Ok(true);
     }
 }
 
+/// Writes a compacted copy of the database of `connection` to a new file at `path` with `VACUUM INTO`.
+pub(crate) fn vacuum_into(connection: &rusqlite::Connection, path: &str) -> Result<(), String> {
+    if std::path::Path::new(path).exists() {
+        return Err(format!("The snapshot file {} exists already", path));
+    }
+    connection.execute("VACUUM INTO ?1", [path]).map(|_| ()).map_err(|e| e.to_string())
+}
+
+/// Renames an index or a trigger, as given by `kind`, by recreating it from its SQL under the new name. sqlite has no `ALTER INDEX` or
+/// `ALTER TRIGGER`.
+pub(crate) fn rename_schema_object(connection: &rusqlite::Connection, kind: &str, name: &str, sql: &str, new_name: &str) -> rusqlite::Result<()> {
+    let create = regex::Regex::new(r"(?i)^(CREATE\s+(?:UNIQUE\s+)?(?:INDEX|TRIGGER)\s+(?:IF\s+NOT\s+EXISTS\s+)?)(\S+)").unwrap();
+    let sql = create.replace(sql, |captures: &regex::Captures| format!("{}{}", &captures[1], quote_identifier(new_name))).into_owned();
+    connection.execute(&format!("DROP {} {}", kind, quote_identifier(name)), [])?;
+    connection.execute(&sql, [])?;
+    Ok(())
+}
+
+/// Computes the statistics of a collection, see [`Database::collection_stats()`].
+pub(crate) fn collection_stats(connection: &rusqlite::Connection, config: &CollectionConfig) -> Result<CollectionStats, String> {
+    if config.view.is_some() {
+        return Err("A view has no storage of its own".to_string());
+    }
+    // the rows of a time-series collection are buckets of measurements.
+    let count = if config.time_series.is_some() { "IFNULL(SUM(count), 0)" } else { "COUNT(1)" };
+    let (count, size): (i64, i64) = connection
+        .query_row(&format!("SELECT {}, IFNULL(SUM(length(raw)), 0) FROM [{}]", count, config.table_name), [], |row| Ok((row.get(0)?, row.get(1)?)))
+        .map_err(|e| e.to_string())?;
+    let mut page_size = connection.prepare_cached("SELECT IFNULL(SUM(pgsize), 0) FROM dbstat WHERE name = ?1").map_err(|e| e.to_string())?;
+    let storage_size: i64 = page_size.query_row([&config.table_name], |row| row.get(0)).map_err(|e| e.to_string())?;
+
+    let mut stmt = connection.prepare("SELECT name FROM sqlite_master WHERE type = 'index' AND tbl_name = ?1 ORDER BY name").map_err(|e| e.to_string())?;
+    let index_names: Vec<String> = stmt.query_map([&config.table_name], |row| row.get(0)).and_then(|rows| rows.collect()).map_err(|e| e.to_string())?;
+    let mut index_sizes = Vec::new();
+    for name in index_names {
+        let index_size: i64 = page_size.query_row([&name], |row| row.get(0)).map_err(|e| e.to_string())?;
+        index_sizes.push((name, index_size));
+    }
+    let total_index_size = index_sizes.iter().map(|(_, size)| size).sum();
+    Ok(CollectionStats { count, size, storage_size, index_sizes, total_index_size })
+}
+
+/// The columns of the collection meta table that [`read_collection_config()`] reads.
+const CATALOG_COLUMNS: &str =
+    "collection, table_name, hash_document, log_last_modified, hash_unique, capped_max_documents, capped_max_bytes, type, time_field, meta_field, granularity, expire_after_seconds, view_on, pipeline";
+
+/// Reads a collection's config from a row of the collection meta table with the [`CATALOG_COLUMNS`].
+fn read_collection_config(row: &rusqlite::Row) -> Result<CollectionConfig, &'static str> {
+    let collection: String = row.get(0).unwrap();
+    let table_name: String = row.get(1).unwrap();
+    // table names are written into SQL in brackets, which can't be escaped, so a catalog that was tampered with is refused.
+    if table_name.contains(']') {
+        return Err("The collection table has an invalid table name");
+    }
+
+    let mut collection_config: CollectionConfig = CollectionConfig {
+        name: collection,
+        table_name,
+        should_hash_document: row.get(2).unwrap(),
+        should_log_last_modified: row.get(3).unwrap(),
+        should_hash_unique: row.get(4).unwrap(),
+        capped_max_documents: row.get::<_, i64>(5).unwrap() as u64,
+        capped_max_bytes: row.get::<_, i64>(6).unwrap() as u64,
+        time_series: None,
+        view: None,
+    };
+    if row.get::<_, i64>(7).unwrap() == 1 {
+        let granularity: String = row.get(10).unwrap();
+        collection_config.time_series = Some(TimeSeriesConfig {
+            time_field: row.get(8).unwrap(),
+            meta_field: row.get(9).unwrap(),
+            granularity: Granularity::from_str(&granularity).ok_or("The time-series collection has an invalid granularity")?,
+            expire_after_seconds: row.get::<_, i64>(11).unwrap() as u64,
+        });
+    }
+    if row.get::<_, i64>(7).unwrap() == 2 {
+        let pipeline: Vec<u8> = row.get(13).unwrap();
+        let pipeline = bson::Document::from_reader(pipeline.as_slice()).map_err(|_| "The view has an invalid pipeline")?;
+        collection_config.view = Some(ViewConfig {
+            view_on: row.get(12).unwrap(),
+            pipeline: pipeline.get_array("pipeline").map_err(|_| "The view has an invalid pipeline")?.iter().filter_map(|stage| stage.as_document().cloned()).collect(),
+        });
+    }
+    Ok(collection_config)
+}
+
+/// Looks up the config of a collection in the collection meta table. Unlike [`Database::collection()`], this only needs a connection, so it
+/// also works for the connections of a [`crate::handle::DatabaseHandle`] and inside a transaction.
+pub(crate) fn collection_config(connection: &rusqlite::Connection, collection_name: &str) -> Result<Option<CollectionConfig>, String> {
+    let mut stmt = connection.prepare_cached(&format!("SELECT {} FROM _hoardbase WHERE type IN (0, 1, 2) AND collection = ?1", CATALOG_COLUMNS)).map_err(|e| e.to_string())?;
+    let mut rows = stmt.query([collection_name]).map_err(|e| e.to_string())?;
+    match rows.next().map_err(|e| e.to_string())? {
+        Some(row) => Ok(Some(read_collection_config(row)?)),
+        None => Ok(None),
+    }
+}
+
+/// Whether `name` can be used as a table name as it is: a plain identifier that isn't reserved for hoardbase's or sqlite's own tables.
+fn is_plain_table_name(name: &str) -> bool {
+    let lowercase = name.to_ascii_lowercase();
+    let mut chars = name.chars();
+    matches!(chars.next(), Some(c) if c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+        && !lowercase.starts_with("_hoardbase")
+        && !lowercase.starts_with("sqlite_")
+}
+
 impl Database {
-    pub fn open(config: &DatabaseConfig) -> std::result::Result<Database, &str> {
+    /// Opens a database. This fails if the database file was written by a newer version of hoardbase, or if a migration fails.
+    pub fn open(config: &DatabaseConfig) -> std::result::Result<Database, String> {
         let mut connection = Database {
             config: config.clone(),
-            internal: rusqlite::Connection::open(config.path.clone()).unwrap(),
+            internal: rusqlite::Connection::open_with_flags(config.path.clone(), config.open_flags()).map_err(|e| format!("Failed to open the database: {}", e))?,
             collections: HashMap::new(),
+            _temporary: if config.storage == Storage::Temporary { Some(TemporaryFile { path: config.path.clone() }) } else { None },
         };
-        connection.init();
+        connection.init()?;
         Ok(connection)
     }
 
-    /// Obtain the filepath of this database.
+    /// Obtain the filepath of this database. In-memory databases have none.
     pub fn path(&self) -> Option<String> {
-        Some(self.config.path.clone())
+        match self.config.storage {
+            Storage::File | Storage::Temporary => Some(self.config.path.clone()),
+            Storage::Memory | Storage::SharedMemory(_) => None,
+        }
     }
 
     /// This is an internal function to initialize an empty database. The initialization steps include:
@@ -324,283 +723,277 @@ impl Database {
     ///
     /// 2. Installing application-defined functions that are used for extracting bson field or patching bson document.
     ///
-    /// 3. Creating meta tables. There are two meta tables. One contains global info, such as database version. Another table
+    /// 3. Applying the pragmas of the [`DatabaseConfig`]. The page size goes first, because it can't be changed once the database is in WAL mode.
+    ///
+    /// 4. Creating meta tables, unless the database is opened read-only, and upgrading the format of an older database file, see [`crate::migration`]. There are two meta tables. One contains global info, such as database version. Another table
     /// contains the list of existing collections and their configurations.
     ///
-    /// 4. Fetching exisiting collections from the collection meta table and populate the collection hashmap.
-    fn init<'b>(&'b mut self) {
-        if self.config.should_trace {
-            self.internal.trace(Some(|statement| {
-                println!("trace: {}", statement);
-            }));
-        }
-
-        if self.config.should_profile {
-            self.internal.profile(Some(|statement, duration| {
-                println!("profile: {} {} nanos", statement, duration.as_nanos());
-            }));
-        }
-        // todo: need to change to bson_field
-        self.internal
-            .create_scalar_function("json_field", 2, rusqlite::functions::FunctionFlags::SQLITE_UTF8 | rusqlite::functions::FunctionFlags::SQLITE_DETERMINISTIC, move |ctx| {
-                assert_eq!(ctx.len(), 2, "called with unexpected number of arguments");
-
-                let field_name = ctx.get_raw(0).as_str().unwrap();
-                let blob = ctx.get_raw(1).as_blob().unwrap();
-
-                let mut doc: bson::Bson = bson::from_reader(blob).unwrap();
-
-                let split = field_name.split(".");
-
-                for part in split {
-                    if let bson::Bson::Document(inner_doc) = doc {
-                        if let Some(bson_doc) = inner_doc.get(part) {
-                            doc = bson_doc.clone();
-                        } else {
-                            return Ok(Some(rusqlite::types::Value::from(rusqlite::types::Null)));
-                        }
-                    } else {
-                        return Ok(Some(rusqlite::types::Value::from(rusqlite::types::Null)));
-                    }
-                }
-
-                match doc {
-                    bson::Bson::Double(f) => Ok(Some(rusqlite::types::Value::from(f))),
-                    bson::Bson::String(string) => Ok(Some(rusqlite::types::Value::from(string.clone()))),
-                    bson::Bson::Array(_array) => Ok(Some(rusqlite::types::Value::from(rusqlite::types::Null))),
-                    bson::Bson::Document(_doc) => Ok(Some(rusqlite::types::Value::from(rusqlite::types::Null))),
-                    bson::Bson::Boolean(boolean) => Ok(Some(rusqlite::types::Value::from(boolean))),
-                    bson::Bson::Null => Ok(Some(rusqlite::types::Value::from(rusqlite::types::Null))),
-                    bson::Bson::RegularExpression(regex) => Ok(Some(rusqlite::types::Value::from(rusqlite::types::Null))),
-                    bson::Bson::Int32(i) => Ok(Some(rusqlite::types::Value::from(i))),
-                    bson::Bson::Int64(i) => Ok(Some(rusqlite::types::Value::from(i))),
-                    bson::Bson::Timestamp(t) => {
-                        let mut integer: i64 = t.increment.into();
-                        integer <<= 32;
-                        let time: i64 = t.time.into();
-                        integer += time;
-                        Ok(Some(rusqlite::types::Value::from(integer)))
-                    }
-                    bson::Bson::Binary(t) => Ok(Some(rusqlite::types::Value::from(t.bytes.clone()))),
-                    bson::Bson::ObjectId(id) => Ok(Some(rusqlite::types::Value::from(id.to_hex()))),
-                    bson::Bson::DateTime(dt) => Ok(Some(rusqlite::types::Value::from(dt.timestamp_millis()))),
-                    bson::Bson::Decimal128(d) => Ok(Some(rusqlite::types::Value::from(Vec::from(d.bytes().clone())))),
-                    _ => Ok(Some(rusqlite::types::Value::from(rusqlite::types::Null))),
-                }
-            })
-            .unwrap();
-        
-        // blake3 is chosen as the hash function because it appears to be faster than other choices.
-        // however, this is not verified by the author.
-        // https://crates.io/crates/blake3
-        self.internal
-            .create_scalar_function("blake3", 1, rusqlite::functions::FunctionFlags::SQLITE_UTF8 | rusqlite::functions::FunctionFlags::SQLITE_DETERMINISTIC, move |ctx| {
-                assert_eq!(ctx.len(), 1, "called with unexpected number of arguments");
-
-                let blob = ctx.get_raw(0).as_blob().unwrap();
-                let mut hasher = blake3::Hasher::new();
-                hasher.update(blob);
-                let result = hasher.finalize();
-                let hex_string = hex::encode(result.as_bytes());
-                Ok(Some(hex_string))
-            })
-            .unwrap();
-        // todo: need to change to bson_patch
-        self.internal
-            .create_scalar_function("json_patch", 2, rusqlite::functions::FunctionFlags::SQLITE_UTF8 | rusqlite::functions::FunctionFlags::SQLITE_DETERMINISTIC, move |ctx| {
-                let mut original_doc: bson::Bson = bson::Bson::Document(bson::Document::new());
-                let mut is_insert = false;
-                if ctx.get_raw(0) != rusqlite::types::ValueRef::Null {
-                    let original_blob = ctx.get_raw(0).as_blob().unwrap();
-                    original_doc = bson::from_reader(original_blob).unwrap();
-                } else {
-                    is_insert = true;
-                }
+    /// 5. Fetching exisiting collections from the collection meta table and populate the collection hashmap.
+    ///
+    /// 6. Running the pending document migrations.
+    fn init<'b>(&'b mut self) -> Result<(), String> {
+        register_functions(&mut self.internal, &self.config);
 
-                let update_blob = ctx.get_raw(1).as_blob().unwrap();
-
-                let update_doc: bson::Document = bson::from_reader(update_blob).unwrap();
-                //https://docs.mongodb.com/manual/reference/operator/update/#std-label-update-operators
-                for (key, value) in update_doc.iter() {
-                    let operation: UpdateOperator = match key.as_str() {
-                        "$currentDate" => UpdateOperator::CurrentDate,
-                        "$inc" => UpdateOperator::Inc,
-                        "$min" => UpdateOperator::Min,
-                        "$max" => UpdateOperator::Max,
-                        "$mul" => UpdateOperator::Mul,
-                        "$rename" => UpdateOperator::Rename,
-                        "$set" => UpdateOperator::Set,
-                        "$setOnInsert" => {
-                            if is_insert {
-                                UpdateOperator::Set
-                            } else {
-                                continue;
-                            }
-                        }
-                        "$unset" => UpdateOperator::Unset,
-                        "$addToSet" => UpdateOperator::AddToSet,
-                        "$pop" => UpdateOperator::Pop,
-                        "$pull" => UpdateOperator::Pull,
-                        "$push" => UpdateOperator::Push,
-                        "$pullAll" => UpdateOperator::PullAll,
-                        "$bit" => UpdateOperator::Bit,
-                        _ => {
-                            return Err(rusqlite::Error::UserFunctionError(Box::new(UserFunctionError { message: "unknown update operator".to_string() })));
-                        }
-                    };
-
-                    if let bson::Bson::Document(doc) = value {
-                        for (key2, new_value) in doc.iter() {
-                            let mut split = key2.split(".");
-                            if let Err(e) = recursive_process(&mut original_doc, &mut split, &operation, &new_value) {
-                                return Err(rusqlite::Error::UserFunctionError(Box::new(UserFunctionError { message: e })));
-                            }
-                        }
-                    }
-                }
+        if let Some(page_size) = self.config.page_size {
+            self.internal.pragma_update(None, "page_size", page_size).map_err(|_| "Failed to set the page size")?;
+        }
+        if let Some(journal_mode) = self.config.journal_mode {
+            let mode: String = self.internal.pragma_update_and_check(None, "journal_mode", journal_mode.as_str(), |row| row.get(0)).map_err(|_| "Failed to set the journal mode")?;
+            // sqlite keeps the current mode when the requested one isn't supported, e.g. WAL for an in-memory database.
+            if !mode.eq_ignore_ascii_case(journal_mode.as_str()) {
+                return Err("The journal mode isn't supported by this database".to_string());
+            }
+        }
+        configure_connection(&self.internal, &self.config)?;
 
-                let bson_doc = bson::ser::to_document(&original_doc).unwrap();
-                let mut bytes: Vec<u8> = Vec::new();
-                bson_doc.to_writer(&mut bytes).unwrap();
-                return Ok(Some(rusqlite::types::Value::from(bytes)));
-            })
-            .unwrap();
+        migration::upgrade_format(&mut self.internal, self.config.read_only)?;
+        self.load_collections()?;
+        migration::migrate_documents(&mut self.internal, &self.config.migrations, &self.collections, self.config.read_only)
+    }
 
-        let tx = self.internal.transaction().unwrap();
-        {
-            tx.execute(
-                "CREATE TABLE IF NOT EXISTS _hoardbase (
-                      id              INTEGER PRIMARY KEY,
-                      collection      TEXT NOT NULL,
-                      type            INTEGER NOT NULL,
-                      table_name      TEXT UNIQUE NOT NULL,
-                      hash_document   BOOLEAN NOT NULL,
-                      log_last_modified BOOLEAN NOT NULL,
-                      encrypt          BOOLEAN NOT NULL,
-                      compress         BOOLEAN NOT NULL,
-                      serialization_method         TEXT NOT NULL
-                      )",
-                [],
-            )
-            .unwrap();
-
-            tx.execute(&format!("CREATE UNIQUE INDEX IF NOT EXISTS collection ON _hoardbase(collection);"), []).unwrap();
-
-            tx.execute(
-                "CREATE TABLE IF NOT EXISTS _hoardbase_meta (
-                      id              INTEGER PRIMARY KEY,
-                      version      TEXT NOT NULL,
-                      git_hash             TEXT NOT NULL,
-                      format_version      INTEGER NOT NULL,
-                      build_time   DATETIME NOT NULL
-                      )",
-                [],
-            )
-            .unwrap();
-
-            tx.execute(
-                "INSERT INTO _hoardbase_meta (version ,git_hash, format_version,
-            build_time) VALUES (?1, ?2, ?3, datetime('now') );",
-                [rusqlite::types::Value::from(env!("CARGO_PKG_VERSION").to_string()), rusqlite::types::Value::from(env!("GIT_HASH").to_string()), rusqlite::types::Value::from(0)],
-            )
-            .unwrap();
-        }
-
-        tx.commit().unwrap();
-
-        let mut stmt = self.internal.prepare("SELECT * FROM _hoardbase WHERE type=0").unwrap();
+    /// Populates the collection hashmap from the collection meta table.
+    fn load_collections(&mut self) -> Result<(), &'static str> {
+        self.collections.clear();
+        // a read-only database that was never initialized has no collection table.
+        let mut stmt = self.internal.prepare(&format!("SELECT {} FROM _hoardbase WHERE type IN (0, 1, 2)", CATALOG_COLUMNS)).map_err(|_| "The database is not a hoardbase database")?;
         let mut rows = stmt.query([]).unwrap();
         while let Ok(row_result) = rows.next() {
             if let Some(row) = row_result {
-                let collection: String = row.get(1).unwrap();
-                let table_name: String = row.get(3).unwrap();
-                let hash_document: bool = row.get(4).unwrap();
-                let log_last_modified: bool = row.get(5).unwrap();
-                let encrypt: bool = row.get(6).unwrap();
-                let compress: bool = row.get(7).unwrap();
-
-                let collection_config: CollectionConfig = CollectionConfig {
-                    name: collection.clone(),
-                    table_name: table_name,
-                    should_hash_document: hash_document,
-                    should_log_last_modified: log_last_modified,
-                    should_hash_unique: false,
-                };
-
-                // println!("{} {}", collection, table_name);
-
-                self.collections.insert(collection.to_string(), (collection.to_owned(), collection_config.to_owned()));
+                let collection_config = read_collection_config(row)?;
+                self.collections.insert(collection_config.name.clone(), (collection_config.name.clone(), collection_config));
             } else {
                 break;
             }
         }
+        Ok(())
     }
 
-    /// Create and return a collection given its config. The collection's properties ([`CollectionConfig::should_log_last_modified`], [`CollectionConfig::should_hash_document`]) can't be changed once created.
-    pub fn create_collection<'a>(&'a mut self, collection_name: &str, config: &CollectionConfig) -> Result<Collection<'a>, &str> {
-        if self.collections.contains_key(collection_name) {
-            let (collection_name, collection_config) = self.collections.get(collection_name).unwrap();
-            Ok(Collection::<'a> {
-                config: collection_config.clone(),
-                name: collection_name.clone(),
-                db: &self.internal,
-                table_name: collection_name.clone(),
-            })
+    /// Picks the table name of a new collection. Collection names can be any string, but a table name is kept to a plain identifier so it can be
+    /// used in SQL and index names safely. The collection name is used if it already is one, otherwise a slug of it, made unique with a suffix.
+    /// Views have no table, but get a table name all the same, which the catalog keeps unique.
+    fn new_table_name(&self, collection_name: &str) -> Result<String, &'static str> {
+        let base = if is_plain_table_name(collection_name) {
+            collection_name.to_string()
         } else {
-            let tx = self.internal.transaction().unwrap();
-            {
+            let slug = slugify!(collection_name, separator = "_");
+            if is_plain_table_name(&slug) {
+                slug
+            } else {
+                format!("c_{}", slug)
+            }
+        };
+
+        let mut stmt = self.internal.prepare_cached("SELECT (SELECT COUNT(1) FROM sqlite_master WHERE name = ?1 COLLATE NOCASE) + (SELECT COUNT(1) FROM _hoardbase WHERE table_name = ?1 COLLATE NOCASE)").map_err(|_| "Failed to look up the table names")?;
+        let mut table_name = base.clone();
+        let mut suffix = 1;
+        while stmt.query_row([&table_name], |row| row.get::<_, i64>(0)).map_err(|_| "Failed to look up the table names")? > 0 {
+            suffix += 1;
+            table_name = format!("{}_{}", base, suffix);
+        }
+        Ok(table_name)
+    }
+
+    /// Copies the database to the file at `path` with sqlite's online backup API, replacing the file's content if it exists. The copy is made in
+    /// small steps, `progress` is called after each of them, and the database stays usable by other connections in between. Unlike
+    /// [`Database::snapshot_to()`], the copy isn't compacted.
+    pub fn backup_to(&self, path: &str, mut progress: impl FnMut(BackupProgress)) -> Result<(), String> {
+        let mut destination = rusqlite::Connection::open(path).map_err(|e| e.to_string())?;
+        copy_database(&self.internal, &mut destination, &mut progress)
+    }
+
+    /// Replaces the content of this database with that of the database file at `path`, usually a backup made by [`Database::backup_to()`] or
+    /// [`Database::snapshot_to()`]. `progress` is called after each step of the copy.
+    pub fn restore_from(&mut self, path: &str, mut progress: impl FnMut(BackupProgress)) -> Result<(), String> {
+        let source = rusqlite::Connection::open_with_flags(path, rusqlite::OpenFlags::SQLITE_OPEN_READ_ONLY | rusqlite::OpenFlags::SQLITE_OPEN_URI).map_err(|e| e.to_string())?;
+        copy_database(&source, &mut self.internal, &mut progress)?;
+        // the backup may be from an older version.
+        migration::upgrade_format(&mut self.internal, self.config.read_only)?;
+        self.load_collections()?;
+        migration::migrate_documents(&mut self.internal, &self.config.migrations, &self.collections, self.config.read_only)
+    }
+
+    /// Writes a compacted copy of the database to a new file at `path` using `VACUUM INTO`. The copy is made in a single read transaction, so it
+    /// doesn't block readers, nor writers in WAL mode. The file must not exist.
+    pub fn snapshot_to(&self, path: &str) -> Result<(), String> {
+        vacuum_into(&self.internal, path)
+    }
+
+    /// Create and return a collection given its config. The collection's properties ([`CollectionConfig::should_log_last_modified`], [`CollectionConfig::should_hash_document`], [`CollectionConfig::should_hash_unique`]) can't be changed once created.
+    /// The name and table name of `config` are ignored: the collection is called `collection_name`, and its table name is derived from it.
+    pub fn create_collection<'a>(&'a mut self, collection_name: &str, config: &CollectionConfig) -> Result<Collection<'a>, &str> {
+        validate_collection_name(collection_name)?;
+        if config.time_series.is_some() {
+            return Err("Time-series collections are created with create_time_series()");
+        }
+        if config.view.is_some() {
+            return Err("Views are created with create_view()");
+        }
+        if !self.collections.contains_key(collection_name) {
+            self.register_collection(collection_name, config)?;
+        }
+        self.collection(collection_name)
+    }
+
+    /// Applies the schema of `T`, see [`crate::schema`], and returns its collection. The collection is created if it doesn't exist.
+    pub fn ensure_schema<'a, T: HoardDocument>(&'a mut self) -> Result<TypedCollection<Collection<'a>, T>, String> {
+        let schema = T::schema();
+        self.apply_schema(&schema)?;
+        self.typed_collection(&schema.collection).map_err(|e| e.to_string())
+    }
+
+    /// Creates the collection of `schema` if it doesn't exist, creates its missing indexes and replaces its validator and TTL.
+    pub fn apply_schema(&mut self, schema: &Schema) -> Result<(), String> {
+        if !self.collections.contains_key(&schema.collection) {
+            self.create_collection(&schema.collection, &CollectionConfig::default(&schema.collection)).map_err(|e| e.to_string())?;
+        }
+        let (_, config) = self.collections.get(&schema.collection).unwrap();
+        if config.time_series.is_some() || config.view.is_some() {
+            return Err("Schemas apply to regular collections only".to_string());
+        }
+        let config = config.clone();
+
+        let tx = self.internal.transaction().map_err(|e| e.to_string())?;
+        for index in schema.all_indexes() {
+            create_index_internal(&tx, &config, &index.keys, index.unique)?;
+        }
+        schema::create_triggers(&tx, &config, schema)?;
+        tx.commit().map_err(|e| e.to_string())
+    }
+
+    /// Create and return a time-series collection, see [`crate::time_series`]. If the time-series collection exists already, it is returned as
+    /// it is.
+    pub fn create_time_series<'a>(&'a mut self, collection_name: &str, options: &TimeSeriesConfig) -> Result<TimeSeries<'a>, &str> {
+        validate_collection_name(collection_name)?;
+        if !self.collections.contains_key(collection_name) {
+            validate_field_path(&options.time_field)?;
+            if let Some(meta_field) = &options.meta_field {
+                validate_field_path(meta_field)?;
+                if meta_field == &options.time_field {
+                    return Err("The meta field can't be the time field");
+                }
+            }
+            let mut config = CollectionConfig::default(collection_name);
+            config.hash_document(false).log_last_modified(false);
+            config.time_series = Some(options.clone());
+            self.register_collection(collection_name, &config)?;
+        }
+        self.time_series(collection_name)
+    }
+
+    /// Create and return a view, a read-only collection whose documents are the result of running `pipeline` on the collection or view called
+    /// `source`, see [`crate::view`]. Unlike [`Database::create_collection()`], this fails if a collection with the same name exists.
+    pub fn create_view<'a>(&'a mut self, view_name: &str, source: &str, pipeline: &Vec<bson::Document>) -> Result<View<'a>, String> {
+        validate_collection_name(view_name)?;
+        if self.collections.contains_key(view_name) {
+            return Err("A collection with the same name exists already".to_string());
+        }
+        if !self.collections.contains_key(source) {
+            return Err("No source collection found".to_string());
+        }
+        view::validate_pipeline(pipeline)?;
+        let mut config = CollectionConfig::default(view_name);
+        config.hash_document(false).log_last_modified(false);
+        config.view = Some(ViewConfig { view_on: source.to_string(), pipeline: pipeline.clone() });
+        self.register_collection(view_name, &config)?;
+        self.view(view_name)
+    }
+
+    /// Creates the table of a new collection and adds the collection to the catalog.
+    fn register_collection(&mut self, collection_name: &str, config: &CollectionConfig) -> Result<(), &'static str> {
+        if self.config.read_only {
+            return Err("The database is opened read-only");
+        }
+        let mut config = config.clone();
+        config.name = collection_name.to_string();
+        config.table_name = self.new_table_name(collection_name)?;
+
+        let tx = self.internal.transaction().map_err(|_| "Failed to start a transaction")?;
+        {
+            if config.view.is_some() {
+                // a view's documents are computed when it is read.
+            } else if config.time_series.is_some() {
+                time_series::create_table(&tx, &config.table_name).map_err(|_| "Failed to create the time-series collection")?;
+            } else {
                 tx.execute(
                     &format!(
                         "CREATE TABLE [{}] (
-                          _id              INTEGER PRIMARY KEY,
+                          _id              INTEGER PRIMARY KEY {},
                           raw             BLOB NOT NULL
                           {}
                           {}
                           )",
-                        collection_name,
+                        config.table_name,
+                        // the ids of a capped collection are never reused, so that they follow the insertion order.
+                        if config.is_capped() { "AUTOINCREMENT" } else { "" },
                         if config.should_hash_document { ", _hash NCHAR(40) GENERATED ALWAYS AS (blake3(raw)) STORED" } else { "" },
                         if config.should_log_last_modified { ", _last_modified DATETIME" } else { "" },
                     ),
                     [],
                 )
-                .unwrap();
+                .map_err(|_| "Failed to create the collection")?;
 
                 if config.should_hash_document {
-                    tx.execute(&format!("CREATE {} INDEX IF NOT EXISTS _hash ON [{}](_hash);", if config.should_hash_unique { "UNIQUE" } else { "" }, collection_name), []).unwrap();
+                    tx.execute(&format!("CREATE {} INDEX [{}__hash] ON [{}](_hash);", if config.should_hash_unique { "UNIQUE" } else { "" }, config.table_name, config.table_name), [])
+                        .map_err(|_| "Failed to create the hash index")?;
                 }
 
-                let mut stmt = tx
-                    .prepare_cached(
-                        "INSERT INTO _hoardbase (collection ,type, table_name,
+                if config.is_capped() {
+                    capped::create_triggers(&tx, &config).map_err(|_| "Failed to create the capped collection")?;
+                }
+            }
+
+            let mut stmt = tx
+                .prepare_cached(
+                    "INSERT INTO _hoardbase (collection ,type, table_name,
                     hash_document,
                     log_last_modified,
                     encrypt,
                     compress,
-                    serialization_method) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, 'bson') ON CONFLICT(collection) DO NOTHING",
-                    )
-                    .unwrap();
-                stmt.execute([
-                    rusqlite::types::Value::Text(String::from(collection_name)),
-                    rusqlite::types::Value::Integer(0),
-                    rusqlite::types::Value::Text(String::from(collection_name)),
-                    rusqlite::types::Value::from(config.should_hash_document),
-                    rusqlite::types::Value::from(config.should_log_last_modified),
-                    rusqlite::types::Value::from(false),
-                    rusqlite::types::Value::from(false),
-                ])
-                .unwrap();
-            }
-            tx.commit().unwrap();
-
-            self.collections.insert(collection_name.to_string(), (collection_name.to_owned(), config.to_owned()));
-
-            Ok(Collection::<'a> {
-                config: config.clone(),
-                name: collection_name.to_string(),
-                db: &self.internal,
-                table_name: collection_name.to_string(),
-            })
+                    serialization_method,
+                    user_version,
+                    hash_unique,
+                    capped_max_documents,
+                    capped_max_bytes,
+                    time_field,
+                    meta_field,
+                    granularity,
+                    expire_after_seconds,
+                    view_on,
+                    pipeline) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, 'bson', ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16, ?17) ON CONFLICT(collection) DO NOTHING",
+                )
+                .map_err(|_| "Failed to register the collection")?;
+            let time_series = config.time_series.as_ref();
+            let view = config.view.as_ref();
+            let pipeline = view.map(|view| {
+                let mut bytes: Vec<u8> = Vec::new();
+                bson::doc! { "pipeline": view.pipeline.clone() }.to_writer(&mut bytes).unwrap();
+                bytes
+            });
+            stmt.execute([
+                rusqlite::types::Value::Text(String::from(collection_name)),
+                rusqlite::types::Value::Integer(if view.is_some() { 2 } else if time_series.is_some() { 1 } else { 0 }),
+                rusqlite::types::Value::Text(config.table_name.clone()),
+                rusqlite::types::Value::from(config.should_hash_document),
+                rusqlite::types::Value::from(config.should_log_last_modified),
+                rusqlite::types::Value::from(false),
+                rusqlite::types::Value::from(time_series.is_some()),
+                rusqlite::types::Value::from(migration::latest_version(&self.config.migrations, collection_name)),
+                rusqlite::types::Value::from(config.should_hash_unique),
+                rusqlite::types::Value::from(capped::limit(config.capped_max_documents)),
+                rusqlite::types::Value::from(capped::limit(config.capped_max_bytes)),
+                rusqlite::types::Value::from(time_series.map(|options| options.time_field.clone())),
+                rusqlite::types::Value::from(time_series.and_then(|options| options.meta_field.clone())),
+                rusqlite::types::Value::from(time_series.map(|options| options.granularity.as_str().to_string())),
+                rusqlite::types::Value::from(capped::limit(time_series.map_or(0, |options| options.expire_after_seconds))),
+                rusqlite::types::Value::from(view.map(|view| view.view_on.clone())),
+                rusqlite::types::Value::from(pipeline),
+            ])
+            .map_err(|_| "Failed to register the collection")?;
         }
+        tx.commit().map_err(|_| "Failed to commit the collection")?;
+
+        self.collections.insert(collection_name.to_string(), (collection_name.to_owned(), config));
+        Ok(())
     }
 
     /// Obtain an existing collection given a name. This function assemble a [`Collection`] object by combining
@@ -608,17 +1001,47 @@ impl Database {
     pub fn collection<'a>(&'a mut self, collection_name: &str) -> Result<Collection<'a>, &str> {
         if self.collections.contains_key(collection_name) {
             let (collection_name, collection_config) = self.collections.get(collection_name).unwrap();
+            if collection_config.time_series.is_some() {
+                return Err("The collection is a time-series collection");
+            }
+            if collection_config.view.is_some() {
+                return Err("The collection is a view, which is read-only");
+            }
             Ok(Collection::<'a> {
                 config: collection_config.clone(),
                 name: collection_name.clone(),
                 db: &self.internal,
-                table_name: collection_name.clone(),
+                table_name: collection_config.table_name.clone(),
             })
         } else {
             Err("No collection found")
         }
     }
 
+    /// Obtain an existing collection given a name, as a [`TypedCollection`] that stores values of type `T` instead of documents.
+    pub fn typed_collection<'a, T: serde::Serialize + serde::de::DeserializeOwned>(&'a mut self, collection_name: &str) -> Result<TypedCollection<Collection<'a>, T>, &str> {
+        Ok(TypedCollection::new(self.collection(collection_name)?))
+    }
+
+    /// Obtain an existing time-series collection given a name.
+    pub fn time_series<'a>(&'a mut self, collection_name: &str) -> Result<TimeSeries<'a>, &str> {
+        match self.collections.get(collection_name) {
+            Some((collection_name, config)) if config.time_series.is_some() => Ok(TimeSeries { name: collection_name.clone(), config: config.clone(), db: &self.internal }),
+            Some(_) => Err("The collection isn't a time-series collection"),
+            None => Err("No collection found"),
+        }
+    }
+
+    /// Obtain an existing view given a name.
+    pub fn view<'a>(&'a mut self, view_name: &str) -> Result<View<'a>, String> {
+        let collections = &self.collections;
+        match collections.get(view_name) {
+            Some((view_name, config)) if config.view.is_some() => View::new(view_name, config, &self.internal, &|name| collections.get(name).map(|(_, config)| config.clone())),
+            Some(_) => Err("The collection isn't a view".to_string()),
+            None => Err("No collection found".to_string()),
+        }
+    }
+
     /// List existing collections
     pub fn list_collections(&self) -> Vec<(String, CollectionConfig)> {
         let mut collections = Vec::new();
@@ -628,24 +1051,14 @@ impl Database {
         collections
     }
 
-    /// Drop a collection
+    /// Drop a collection, together with its indexes.
     pub fn drop_collection(&mut self, collection_name: &str) -> Result<(), &str> {
-        if self.collections.contains_key(collection_name) {
-            let tx = self.internal.transaction().unwrap();
-            {
-                tx.execute(&format!("DROP TABLE IF EXISTS [{}];", collection_name), []).unwrap();
-
-                tx.execute(
-                    &format!(
-                        "DELETE FROM [{}]
-                    WHERE collection = '{}';",
-                        collection_name, collection_name
-                    ),
-                    [],
-                )
-                .unwrap();
-            }
-            tx.commit().unwrap();
+        if let Some((_, config)) = self.collections.get(collection_name) {
+            let tx = self.internal.transaction().map_err(|_| "Failed to begin the transaction")?;
+            // dropping the table drops its indexes too.
+            tx.execute(&format!("DROP TABLE IF EXISTS [{}];", config.table_name), []).map_err(|_| "Failed to drop the collection's table")?;
+            tx.execute("DELETE FROM _hoardbase WHERE collection = ?1;", [collection_name]).map_err(|_| "Failed to remove the collection from the catalog")?;
+            tx.commit().map_err(|_| "Failed to commit the transaction")?;
 
             self.collections.remove(collection_name);
             return Ok(());
@@ -653,55 +1066,72 @@ impl Database {
         Err("No collection found")
     }
 
-    /// Rename collection
+    /// Rename collection. The collection's table and indexes are renamed as well, to the table name derived from the new name.
     pub fn rename_collection(&mut self, collection_old_name: &str, collection_new_name: &str) -> Result<(), &str> {
-        if self.collections.contains_key(collection_old_name) {
-            let tx = self.internal.transaction().unwrap();
-            {
-                tx.execute(&format!("ALTER TABLE [{}] RENAME TO [{}];", collection_old_name, collection_new_name), []).unwrap();
+        validate_collection_name(collection_new_name)?;
+        if self.collections.contains_key(collection_new_name) {
+            return Err("A collection with the new name exists already");
+        }
+        let (_, mut config) = match self.collections.get(collection_old_name) {
+            Some(collection) => collection.clone(),
+            None => return Err("No collection found"),
+        };
+        let old_table_name = config.table_name.clone();
+        config.name = collection_new_name.to_string();
+        config.table_name = self.new_table_name(collection_new_name)?;
 
-                tx.execute(
-                    &format!(
-                        "UPDATE _hoardbase
-                    SET collection = '{}', table_name = '{}'
-                    WHERE collection = '{}';",
-                         collection_new_name, collection_new_name, collection_old_name
-                    ),
-                    [],
-                )
-                .unwrap();
+        let tx = self.internal.transaction().map_err(|_| "Failed to begin the transaction")?;
+        if config.view.is_none() {
+            tx.execute(&format!("ALTER TABLE [{}] RENAME TO [{}];", old_table_name, config.table_name), []).map_err(|_| "Failed to rename the collection's table")?;
+
+            // the names of indexes and triggers are prefixed with the table name, because they are global.
+            let prefix = format!("{}_", old_table_name);
+            let mut stmt = tx
+                .prepare("SELECT type, name, sql FROM sqlite_master WHERE type IN ('index', 'trigger') AND tbl_name = ?1 AND sql IS NOT NULL")
+                .map_err(|_| "Failed to list the collection's indexes")?;
+            let objects: Vec<(String, String, String)> = stmt
+                .query_map([&config.table_name], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))
+                .and_then(|rows| rows.collect())
+                .map_err(|_| "Failed to list the collection's indexes")?;
+            for (kind, name, sql) in objects {
+                if let Some(suffix) = name.strip_prefix(&prefix) {
+                    rename_schema_object(&tx, &kind, &name, &sql, &format!("{}_{}", config.table_name, suffix)).map_err(|_| "Failed to rename the collection's indexes")?;
+                }
             }
-            tx.commit().unwrap();
-            let old_collection = self.collections.get(collection_old_name).unwrap().clone();
-            self.collections.remove(collection_old_name);
-            self.collections.insert(collection_new_name.to_string(), (collection_new_name.to_owned(), old_collection.1.to_owned()));
-            return Ok(());
         }
-        Err("No collection found")
+        {
+            tx.execute("UPDATE _hoardbase SET collection = ?1, table_name = ?2 WHERE collection = ?3;", [collection_new_name, config.table_name.as_str(), collection_old_name])
+                .map_err(|_| "Failed to update the catalog")?;
+        }
+        tx.commit().map_err(|_| "Failed to commit the transaction")?;
+        self.collections.remove(collection_old_name);
+        self.collections.insert(collection_new_name.to_string(), (collection_new_name.to_owned(), config));
+        Ok(())
+    }
+
+    /// Returns the number of documents of a collection and the space it takes up. The sizes are computed by reading the collection's pages, so
+    /// this takes time on large collections.
+    pub fn collection_stats(&self, collection_name: &str) -> Result<CollectionStats, String> {
+        let (_, config) = self.collections.get(collection_name).ok_or("No collection found")?;
+        collection_stats(&self.internal, config)
     }
 
-    /// Create a transaction
+    /// Create a transaction. The transaction is committed if `f` succeeds, and rolled back if it returns an error.
     pub fn transaction<'a, F>(&'a mut self, f: F) -> Result<(), &str>
     where
         F: FnOnce(&Transaction) -> Result<(), &'static str>,
     {
-        {
-            // let mut conn = self.internal;
+        let t = self.internal.transaction().map_err(|_| "Failed to begin the transaction")?;
+        let mut transaction = Transaction { connection: t, collections: HashMap::new() };
 
-            let t = self.internal.transaction().unwrap();
-            let mut transaction = Transaction { connection: t, collections: HashMap::new() };
-            //  let tx =  TransactionInternal::<'a>{ connection: t  };
-
-            //  let tx_weak = std::rc::Rc::downgrade(&tx);
-
-            for (key, value) in &self.collections {
-                transaction.collections.insert(key.to_string(), (key.to_string(), value.1.clone()));
-            }
+        for (key, value) in &self.collections {
+            transaction.collections.insert(key.to_string(), (key.to_string(), value.1.clone()));
+        }
 
-            f(&transaction).unwrap();
-            transaction.connection.commit().unwrap();
+        match f(&transaction) {
+            Ok(()) => transaction.connection.commit().map_err(|_| "Failed to commit the transaction"),
+            // dropping a rusqlite transaction rolls it back.
+            Err(e) => Err(e),
         }
-        // tx.commit().unwrap();
-        Err("Not implemented")
     }
 }
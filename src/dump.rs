@@ -0,0 +1,279 @@
+//! Import and export of collections.
+//!
+//! A collection can be written to, and read from, three formats:
+//!
+//! * [`DumpFormat::JsonLines`]: one plain JSON document per line. Values JSON has no type for, such as ObjectIds and dates, are written as
+//!   strings, so the export is meant for tools that don't understand extended JSON.
+//! * [`DumpFormat::ExtendedJson`]: one [MongoDB Extended JSON v2](https://www.mongodb.com/docs/manual/reference/mongodb-extended-json/) document
+//!   per line, in canonical or relaxed mode, like `mongoexport` writes them.
+//! * [`DumpFormat::Bson`]: concatenated BSON documents, like the `.bson` files of `mongodump`. The indexes go to a separate metadata file, see
+//!   [`Collection::export_metadata()`].
+//!
+//! Both JSON formats are imported the way `mongoimport` does: extended JSON values like `{"$oid": "..."}` are recognized, and integers that fit
+//! into 32 bits become `Int32`.
+
+use std::io::{BufRead, Read, Write};
+
+use crate::base::*;
+use crate::capped;
+use crate::collection::Collection;
+
+/// The number of documents an import inserts per transaction.
+pub(crate) const IMPORT_BATCH_SIZE: usize = 1000;
+
+/// The largest BSON document an import accepts, the limit of mongodb. A longer length prefix is taken for a corrupt stream.
+const MAX_BSON_DOCUMENT_SIZE: i32 = 16 * 1024 * 1024;
+
+/// The mode of an extended JSON export.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ExtendedJsonMode {
+    /// Preserves every type, e.g. `{"$numberInt": "5"}`.
+    Canonical,
+    /// Writes numbers as plain JSON numbers where this loses no information.
+    Relaxed,
+}
+
+/// The format of an import or export.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DumpFormat {
+    /// Newline-delimited plain JSON.
+    JsonLines,
+    /// Newline-delimited MongoDB Extended JSON v2.
+    ExtendedJson(ExtendedJsonMode),
+    /// A `mongodump` compatible stream of BSON documents.
+    Bson,
+}
+
+/// A document that couldn't be imported.
+#[derive(Clone, Debug)]
+pub struct ImportError {
+    /// The 1-based line number for JSON imports, or the 1-based document number for BSON imports.
+    pub line: usize,
+    pub message: String,
+}
+
+/// The outcome of an import.
+#[derive(Clone, Debug, Default)]
+pub struct ImportReport {
+    /// The number of documents inserted.
+    pub inserted: usize,
+    /// The documents that were skipped, either because they couldn't be parsed or because inserting them failed.
+    pub errors: Vec<ImportError>,
+}
+
+/// Converts a value to plain JSON, turning the types JSON lacks into strings.
+pub(crate) fn to_plain_json(value: &bson::Bson) -> serde_json::Value {
+    use bson::Bson;
+    use serde_json::Value;
+
+    match value {
+        Bson::Double(f) => serde_json::Number::from_f64(*f).map(Value::Number).unwrap_or(Value::Null),
+        Bson::String(s) => Value::String(s.clone()),
+        Bson::Array(array) => Value::Array(array.iter().map(to_plain_json).collect()),
+        Bson::Document(doc) => Value::Object(doc.iter().map(|(k, v)| (k.clone(), to_plain_json(v))).collect()),
+        Bson::Boolean(b) => Value::Bool(*b),
+        Bson::Null | Bson::Undefined | Bson::MinKey | Bson::MaxKey => Value::Null,
+        Bson::Int32(i) => Value::from(*i),
+        Bson::Int64(i) => Value::from(*i),
+        Bson::ObjectId(id) => Value::String(id.to_hex()),
+        Bson::DateTime(dt) => Value::String(dt.try_to_rfc3339_string().unwrap_or_else(|_| dt.timestamp_millis().to_string())),
+        Bson::Binary(binary) => Value::String(hex::encode(&binary.bytes)),
+        Bson::Decimal128(d) => Value::String(d.to_string()),
+        Bson::RegularExpression(regex) => Value::String(format!("/{}/{}", regex.pattern, regex.options)),
+        Bson::JavaScriptCode(code) => Value::String(code.clone()),
+        Bson::JavaScriptCodeWithScope(code) => Value::String(code.code.clone()),
+        Bson::Symbol(symbol) => Value::String(symbol.clone()),
+        Bson::Timestamp(t) => Value::from((u64::from(t.time) << 32) | u64::from(t.increment)),
+        Bson::DbPointer(_) => Value::Null,
+    }
+}
+
+/// Parses one line of a JSON import.
+fn parse_json_line(line: &str) -> Result<bson::Document, String> {
+    let value: serde_json::Value = serde_json::from_str(line).map_err(|e| e.to_string())?;
+    match bson::Bson::try_from(value).map_err(|e| e.to_string())? {
+        bson::Bson::Document(doc) => Ok(doc),
+        other => Err(format!("expected a document, found {}", other)),
+    }
+}
+
+/// Reads the next document of a BSON stream. `Ok(None)` is the end of the stream; `Err` with `true` means the stream can't be read any further.
+fn read_bson_document(reader: &mut dyn Read) -> Result<Option<bson::Document>, (bool, String)> {
+    let mut length_bytes = [0u8; 4];
+    let mut read = 0;
+    while read < 4 {
+        match reader.read(&mut length_bytes[read..]) {
+            Ok(0) if read == 0 => return Ok(None),
+            Ok(0) => return Err((true, "the stream ends inside a document".to_string())),
+            Ok(n) => read += n,
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+            Err(e) => return Err((true, e.to_string())),
+        }
+    }
+    let length = i32::from_le_bytes(length_bytes);
+    if !(5..=MAX_BSON_DOCUMENT_SIZE).contains(&length) {
+        return Err((true, format!("invalid document length {}", length)));
+    }
+    let mut bytes = length_bytes.to_vec();
+    bytes.resize(length as usize, 0);
+    reader.read_exact(&mut bytes[4..]).map_err(|_| (true, "the stream ends inside a document".to_string()))?;
+    bson::Document::from_reader(bytes.as_slice()).map(Some).map_err(|e| (false, e.to_string()))
+}
+
+/// Turns the SQL of an index created by [`CollectionTrait::create_index()`] back into the index's key document.
+fn index_key(sql: &str) -> bson::Document {
+    let field = regex::Regex::new(r"json_field\('((?:[^']|'')*)', raw\) (ASC|DESC)").unwrap();
+    let mut key = bson::Document::new();
+    for captures in field.captures_iter(sql) {
+        key.insert(captures[1].replace("''", "'"), if &captures[2] == "ASC" { 1 } else { -1 });
+    }
+    key
+}
+
+impl<'a> Collection<'a> {
+    /// Writes every document of the collection to `writer` in the given format.
+    pub fn export(&mut self, writer: &mut dyn Write, format: DumpFormat) -> Result<usize, String> {
+        let mut count = 0;
+        let mut write_error: Option<String> = None;
+        let result = self.find(&bson::Document::new(), &None, &mut |record| {
+            let written = match format {
+                DumpFormat::Bson => record.data.to_writer(&mut *writer).map_err(|e| e.to_string()),
+                DumpFormat::JsonLines => writeln!(writer, "{}", to_plain_json(&bson::Bson::Document(record.data.clone()))).map_err(|e| e.to_string()),
+                DumpFormat::ExtendedJson(mode) => {
+                    let document = bson::Bson::Document(record.data.clone());
+                    let json = if mode == ExtendedJsonMode::Canonical { document.into_canonical_extjson() } else { document.into_relaxed_extjson() };
+                    writeln!(writer, "{}", json).map_err(|e| e.to_string())
+                }
+            };
+            match written {
+                Ok(()) => {
+                    count += 1;
+                    Ok(())
+                }
+                Err(e) => {
+                    write_error = Some(e);
+                    Err("export failed")
+                }
+            }
+        });
+        if let Some(e) = write_error {
+            return Err(e);
+        }
+        result.map_err(|e| e.to_string())?;
+        writer.flush().map_err(|e| e.to_string())?;
+        Ok(count)
+    }
+
+    /// Reads documents from `reader` and inserts them, [`IMPORT_BATCH_SIZE`] documents per transaction. Documents that can't be parsed or
+    /// inserted are skipped and listed in the report. Reading stops at the first I/O error, or at a BSON document whose length is corrupt.
+    pub fn import(&mut self, reader: &mut dyn BufRead, format: DumpFormat) -> Result<ImportReport, String> {
+        let mut report = ImportReport::default();
+        let mut batch: Vec<(usize, bson::Document)> = Vec::with_capacity(IMPORT_BATCH_SIZE);
+
+        match format {
+            DumpFormat::Bson => {
+                let mut position = 0;
+                loop {
+                    position += 1;
+                    match read_bson_document(reader) {
+                        Ok(Some(doc)) => batch.push((position, doc)),
+                        Ok(None) => break,
+                        Err((fatal, message)) => {
+                            report.errors.push(ImportError { line: position, message });
+                            if fatal {
+                                break;
+                            }
+                        }
+                    }
+                    if batch.len() == IMPORT_BATCH_SIZE {
+                        self.insert_batch(&mut batch, &mut report)?;
+                    }
+                }
+            }
+            DumpFormat::JsonLines | DumpFormat::ExtendedJson(_) => {
+                for (index, line) in reader.lines().enumerate() {
+                    let line = match line {
+                        Ok(line) => line,
+                        Err(e) => {
+                            report.errors.push(ImportError { line: index + 1, message: e.to_string() });
+                            break;
+                        }
+                    };
+                    if line.trim().is_empty() {
+                        continue;
+                    }
+                    match parse_json_line(&line) {
+                        Ok(doc) => batch.push((index + 1, doc)),
+                        Err(message) => report.errors.push(ImportError { line: index + 1, message }),
+                    }
+                    if batch.len() == IMPORT_BATCH_SIZE {
+                        self.insert_batch(&mut batch, &mut report)?;
+                    }
+                }
+            }
+        }
+        self.insert_batch(&mut batch, &mut report)?;
+        Ok(report)
+    }
+
+    pub(crate) fn insert_batch(&mut self, batch: &mut Vec<(usize, bson::Document)>, report: &mut ImportReport) -> Result<(), String> {
+        if batch.is_empty() {
+            return Ok(());
+        }
+        let tx = self.db.unchecked_transaction().map_err(|e| e.to_string())?;
+        for (line, doc) in batch.drain(..) {
+            // a failed insert only rolls back its own statement, the rest of the batch is still committed.
+            match self.insert_one(&doc) {
+                Ok(_) => report.inserted += 1,
+                Err(message) => report.errors.push(ImportError { line, message }),
+            }
+        }
+        tx.commit().map_err(|e| e.to_string())
+    }
+
+    /// Writes the collection's indexes to `writer` as the canonical extended JSON document `mongodump` stores in its `.metadata.json` files.
+    pub fn export_metadata(&mut self, writer: &mut dyn Write) -> Result<(), String> {
+        let mut stmt = self.db.prepare("SELECT name, sql FROM sqlite_master WHERE type = 'index' AND tbl_name = ?1 AND sql IS NOT NULL").map_err(|e| e.to_string())?;
+        let indexes = stmt.query_map([&self.config.table_name], |row| Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?))).map_err(|e| e.to_string())?;
+
+        let mut index_documents = vec![bson::Bson::Document(bson::doc! { "v": 2, "key": { "_id": 1 }, "name": "_id_" })];
+        for index in indexes {
+            let (name, sql) = index.map_err(|e| e.to_string())?;
+            let key = index_key(&sql);
+            // the index on the document hash is internal.
+            if key.is_empty() {
+                continue;
+            }
+            // the table name prefix is hoardbase's, see `create_index()`.
+            let name = name.strip_prefix(&format!("{}_", self.config.table_name)).unwrap_or(&name).to_string();
+            let mut index_document = bson::doc! { "v": 2, "key": key, "name": name };
+            if sql.starts_with("CREATE UNIQUE") {
+                index_document.insert("unique", true);
+            }
+            index_documents.push(bson::Bson::Document(index_document));
+        }
+
+        let mut metadata = bson::doc! { "indexes": index_documents, "collectionName": self.name.clone(), "type": "collection" };
+        if self.config.is_capped() {
+            metadata.insert("options", bson::doc! { "capped": true, "size": capped::limit(self.config.capped_max_bytes), "max": capped::limit(self.config.capped_max_documents) });
+        }
+        writeln!(writer, "{}", bson::Bson::Document(metadata).into_canonical_extjson()).map_err(|e| e.to_string())
+    }
+
+    /// Creates the indexes listed in a metadata document written by [`Collection::export_metadata()`] or `mongodump`.
+    pub fn import_metadata(&mut self, reader: &mut dyn Read) -> Result<(), String> {
+        let mut json = String::new();
+        reader.read_to_string(&mut json).map_err(|e| e.to_string())?;
+        let metadata = parse_json_line(&json)?;
+        let indexes = metadata.get_array("indexes").map_err(|e| e.to_string())?;
+        for index in indexes {
+            let index = index.as_document().ok_or("each index must be a document")?;
+            if index.get_str("name") == Ok("_id_") {
+                continue;
+            }
+            let key = index.get_document("key").map_err(|e| e.to_string())?;
+            self.create_index(key, index.get_bool("unique").unwrap_or(false))?;
+        }
+        Ok(())
+    }
+}
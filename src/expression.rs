@@ -0,0 +1,879 @@
+//! This module evaluates mongodb [aggregation expressions](https://docs.mongodb.com/manual/meta/aggregation-quick-reference/#expressions), such as
+//! `{"$add": ["$a", "$b"]}`. Pipeline updates use them to compute new field values from the existing fields of a document.
+//!
+//! An expression evaluates to `None` when it refers to a missing field. Just like mongodb, a missing value is different from null: setting a field to a
+//! missing value leaves the field out of the document.
+
+use chrono::prelude::*;
+use std::cmp::Ordering;
+use std::collections::HashMap;
+
+use crate::matcher;
+use crate::update;
+
+/// The variables an expression can access. `$$ROOT` and `$$CURRENT` refer to the document being updated, `$$NOW` to the time the update started.
+/// User variables are introduced by `$let`, `$map` and `$filter`.
+#[derive(Clone)]
+pub struct Variables<'a> {
+    pub root: &'a bson::Document,
+    pub now: bson::DateTime,
+    pub user: HashMap<String, bson::Bson>,
+}
+
+impl<'a> Variables<'a> {
+    pub fn new(root: &'a bson::Document, now: bson::DateTime) -> Self {
+        Variables { root, now, user: HashMap::new() }
+    }
+
+    fn with(&self, name: &str, value: bson::Bson) -> Variables<'a> {
+        let mut vars = self.clone();
+        vars.user.insert(name.to_string(), value);
+        vars
+    }
+}
+
+/// Evaluates an expression against the document in `vars`.
+pub fn evaluate(expression: &bson::Bson, vars: &Variables) -> Result<Option<bson::Bson>, String> {
+    match expression {
+        bson::Bson::String(s) if s.starts_with("$$") => variable(&s[2..], vars),
+        bson::Bson::String(s) if s.starts_with('$') => Ok(field_path(&bson::Bson::Document(vars.root.clone()), &s[1..])),
+        bson::Bson::Array(arr) => {
+            let mut result = Vec::new();
+            for element in arr {
+                result.push(evaluate(element, vars)?.unwrap_or(bson::Bson::Null));
+            }
+            Ok(Some(bson::Bson::Array(result)))
+        }
+        bson::Bson::Document(doc) => match doc.iter().next() {
+            Some((key, operand)) if key.starts_with('$') => {
+                if doc.len() != 1 {
+                    return Err(format!("An expression specification must contain exactly one field, found {}", doc));
+                }
+                operator(key, operand, vars)
+            }
+            _ => {
+                let mut result = bson::Document::new();
+                for (key, value) in doc.iter() {
+                    if let Some(value) = evaluate(value, vars)? {
+                        result.insert(key, value);
+                    }
+                }
+                Ok(Some(bson::Bson::Document(result)))
+            }
+        },
+        _ => Ok(Some(expression.clone())),
+    }
+}
+
+fn variable(path: &str, vars: &Variables) -> Result<Option<bson::Bson>, String> {
+    let (name, rest) = match path.split_once('.') {
+        Some((name, rest)) => (name, Some(rest)),
+        None => (path, None),
+    };
+
+    let value = match name {
+        "ROOT" | "CURRENT" => bson::Bson::Document(vars.root.clone()),
+        "NOW" => bson::Bson::DateTime(vars.now),
+        "REMOVE" => return Ok(None),
+        _ => match vars.user.get(name) {
+            Some(value) => value.clone(),
+            None => return Err(format!("Use of undefined variable: {}", name)),
+        },
+    };
+
+    match rest {
+        Some(rest) => Ok(field_path(&value, rest)),
+        None => Ok(Some(value)),
+    }
+}
+
+/// Resolves a field path. A path that walks into an array of documents yields an array of the values found in its elements.
+fn field_path(value: &bson::Bson, path: &str) -> Option<bson::Bson> {
+    let mut current = value.clone();
+    for part in path.split('.') {
+        current = match current {
+            bson::Bson::Document(doc) => doc.get(part)?.clone(),
+            bson::Bson::Array(arr) => bson::Bson::Array(arr.iter().filter_map(|element| field_path(element, part)).collect()),
+            _ => return None,
+        };
+    }
+    Some(current)
+}
+
+/// Operator arguments can be written as an array, or as a single value when the operator takes one argument.
+fn arguments(operand: &bson::Bson, vars: &Variables) -> Result<Vec<Option<bson::Bson>>, String> {
+    match operand {
+        bson::Bson::Array(arr) => arr.iter().map(|argument| evaluate(argument, vars)).collect(),
+        _ => Ok(vec![evaluate(operand, vars)?]),
+    }
+}
+
+fn expect_arguments(name: &str, args: &[Option<bson::Bson>], count: usize) -> Result<(), String> {
+    if args.len() != count {
+        return Err(format!("Expression {} takes exactly {} arguments. {} were passed in.", name, count, args.len()));
+    }
+    Ok(())
+}
+
+fn is_nullish(value: &Option<bson::Bson>) -> bool {
+    matches!(value, None | Some(bson::Bson::Null) | Some(bson::Bson::Undefined))
+}
+
+/// Mongodb treats false, null, missing values and zero as false. Everything else, including empty strings and arrays, is true.
+pub(crate) fn is_true(value: &Option<bson::Bson>) -> bool {
+    match value {
+        None | Some(bson::Bson::Null) | Some(bson::Bson::Undefined) | Some(bson::Bson::Boolean(false)) => false,
+        Some(bson::Bson::Int32(i)) => *i != 0,
+        Some(bson::Bson::Int64(i)) => *i != 0,
+        Some(bson::Bson::Double(d)) => *d != 0.0,
+        _ => true,
+    }
+}
+
+fn as_f64(value: &bson::Bson) -> Option<f64> {
+    match value {
+        bson::Bson::Int32(i) => Some(*i as f64),
+        bson::Bson::Int64(i) => Some(*i as f64),
+        bson::Bson::Double(d) => Some(*d),
+        _ => None,
+    }
+}
+
+fn as_i64(value: &bson::Bson) -> Option<i64> {
+    match value {
+        bson::Bson::Int32(i) => Some(*i as i64),
+        bson::Bson::Int64(i) => Some(*i),
+        bson::Bson::Double(d) if d.fract() == 0.0 => Some(*d as i64),
+        _ => None,
+    }
+}
+
+fn as_string(name: &str, value: &Option<bson::Bson>) -> Result<String, String> {
+    match value {
+        None | Some(bson::Bson::Null) => Ok(String::new()),
+        Some(bson::Bson::String(s)) => Ok(s.clone()),
+        Some(other) => Err(format!("{} requires a string argument, found: {}", name, other)),
+    }
+}
+
+fn as_date(name: &str, value: &bson::Bson) -> Result<DateTime<Utc>, String> {
+    match value {
+        bson::Bson::DateTime(dt) => Ok(dt.to_chrono()),
+        bson::Bson::Timestamp(ts) => Ok(bson::DateTime::from_millis(ts.time as i64 * 1000).to_chrono()),
+        _ => Err(format!("{} requires a date argument, found: {}", name, value)),
+    }
+}
+
+fn number_result(name: &str, value: f64) -> Result<Option<bson::Bson>, String> {
+    if value.is_nan() {
+        return Err(format!("{} produced an invalid number", name));
+    }
+    Ok(Some(bson::Bson::Double(value)))
+}
+
+/// Applies a unary math function. Integers stay integers for the rounding functions, everything else produces a Double.
+fn unary_math(name: &str, args: &[Option<bson::Bson>], f: fn(f64) -> f64, keeps_integers: bool) -> Result<Option<bson::Bson>, String> {
+    expect_arguments(name, args, 1)?;
+    match &args[0] {
+        value if is_nullish(value) => Ok(Some(bson::Bson::Null)),
+        Some(value @ (bson::Bson::Int32(_) | bson::Bson::Int64(_))) if keeps_integers => Ok(Some(value.clone())),
+        Some(value) => match as_f64(value) {
+            Some(number) => number_result(name, f(number)),
+            None => Err(format!("{} only supports numeric types, not {}", name, value)),
+        },
+        None => unreachable!(),
+    }
+}
+
+/// Applies a binary arithmetic operator. A null or missing argument makes the result null.
+fn binary_arithmetic(name: &str, args: &[Option<bson::Bson>], f: fn(&bson::Bson, &bson::Bson) -> Result<bson::Bson, String>) -> Result<Option<bson::Bson>, String> {
+    expect_arguments(name, args, 2)?;
+    match (&args[0], &args[1]) {
+        (Some(a), Some(b)) if !is_nullish(&args[0]) && !is_nullish(&args[1]) => f(a, b).map(Some).map_err(|e| format!("{}: {}", name, e)),
+        _ => Ok(Some(bson::Bson::Null)),
+    }
+}
+
+fn compare(name: &str, args: &[Option<bson::Bson>]) -> Result<Ordering, String> {
+    expect_arguments(name, args, 2)?;
+    let a = args[0].clone().unwrap_or(bson::Bson::Null);
+    let b = args[1].clone().unwrap_or(bson::Bson::Null);
+    Ok(matcher::compare_bson(&a, &b))
+}
+
+fn operator(name: &str, operand: &bson::Bson, vars: &Variables) -> Result<Option<bson::Bson>, String> {
+    match name {
+        "$literal" => Ok(Some(operand.clone())),
+
+        // Arithmetic expressions
+        "$add" => {
+            let mut total = bson::Bson::Int32(0);
+            let mut date: Option<i64> = None;
+            for argument in arguments(operand, vars)? {
+                match argument {
+                    None | Some(bson::Bson::Null) => return Ok(Some(bson::Bson::Null)),
+                    Some(bson::Bson::DateTime(dt)) => {
+                        if date.is_some() {
+                            return Err("only one date allowed in an $add expression".to_string());
+                        }
+                        date = Some(dt.timestamp_millis());
+                    }
+                    Some(value) => total = update::add(&total, &value).map_err(|e| format!("$add: {}", e))?,
+                }
+            }
+            match date {
+                Some(millis) => {
+                    let millis = millis.checked_add(as_f64(&total).unwrap_or(0.0).round() as i64).ok_or_else(|| "$add: integer overflow".to_string())?;
+                    Ok(Some(bson::Bson::DateTime(bson::DateTime::from_millis(millis))))
+                }
+                None => Ok(Some(total)),
+            }
+        }
+        "$subtract" => {
+            let args = arguments(operand, vars)?;
+            expect_arguments(name, &args, 2)?;
+            match (&args[0], &args[1]) {
+                (Some(bson::Bson::DateTime(a)), Some(bson::Bson::DateTime(b))) => {
+                    let millis = a.timestamp_millis().checked_sub(b.timestamp_millis()).ok_or_else(|| "$subtract: integer overflow".to_string())?;
+                    Ok(Some(bson::Bson::Int64(millis)))
+                }
+                (Some(bson::Bson::DateTime(a)), Some(b)) if as_f64(b).is_some() => {
+                    let millis = a.timestamp_millis().checked_sub(as_f64(b).unwrap().round() as i64).ok_or_else(|| "$subtract: integer overflow".to_string())?;
+                    Ok(Some(bson::Bson::DateTime(bson::DateTime::from_millis(millis))))
+                }
+                _ => binary_arithmetic(name, &args, |a, b| update::arithmetic(a, b, i64::checked_sub, |x, y| x - y)),
+            }
+        }
+        "$multiply" => {
+            let mut total = bson::Bson::Int32(1);
+            for argument in arguments(operand, vars)? {
+                match argument {
+                    None | Some(bson::Bson::Null) => return Ok(Some(bson::Bson::Null)),
+                    Some(value) => total = update::multiply(&total, &value).map_err(|e| format!("$multiply: {}", e))?,
+                }
+            }
+            Ok(Some(total))
+        }
+        "$divide" => binary_arithmetic(name, &arguments(operand, vars)?, |a, b| match (as_f64(a), as_f64(b)) {
+            (Some(_), Some(0.0)) => Err("can't divide by zero".to_string()),
+            (Some(x), Some(y)) => Ok(bson::Bson::Double(x / y)),
+            _ => Err(format!("only supports numeric types, not {} and {}", a, b)),
+        }),
+        "$mod" => binary_arithmetic(name, &arguments(operand, vars)?, |a, b| {
+            if as_f64(b) == Some(0.0) {
+                return Err("can't mod by zero".to_string());
+            }
+            update::arithmetic(a, b, i64::checked_rem, |x, y| x % y)
+        }),
+        "$abs" => {
+            let args = arguments(operand, vars)?;
+            match args.first() {
+                Some(Some(bson::Bson::Int32(i))) if args.len() == 1 => Ok(Some(i.checked_abs().map(bson::Bson::Int32).unwrap_or(bson::Bson::Int64((*i as i64).abs())))),
+                Some(Some(bson::Bson::Int64(i))) if args.len() == 1 => i.checked_abs().map(|i| Some(bson::Bson::Int64(i))).ok_or_else(|| "can't take $abs of long long min".to_string()),
+                _ => unary_math(name, &args, f64::abs, false),
+            }
+        }
+        "$ceil" => unary_math(name, &arguments(operand, vars)?, f64::ceil, true),
+        "$floor" => unary_math(name, &arguments(operand, vars)?, f64::floor, true),
+        "$trunc" => unary_math(name, &arguments(operand, vars)?, f64::trunc, true),
+        "$sqrt" => unary_math(name, &arguments(operand, vars)?, f64::sqrt, false),
+        "$exp" => unary_math(name, &arguments(operand, vars)?, f64::exp, false),
+        "$ln" => unary_math(name, &arguments(operand, vars)?, f64::ln, false),
+        "$log10" => unary_math(name, &arguments(operand, vars)?, f64::log10, false),
+        "$round" => {
+            let args = arguments(operand, vars)?;
+            let places = match args.get(1) {
+                Some(Some(places)) => as_i64(places).ok_or_else(|| format!("$round requires an integral place, found {}", places))?,
+                _ => 0,
+            };
+            match args.first() {
+                Some(value) if is_nullish(value) => Ok(Some(bson::Bson::Null)),
+                Some(Some(value @ (bson::Bson::Int32(_) | bson::Bson::Int64(_)))) if places >= 0 => Ok(Some(value.clone())),
+                Some(Some(value)) => {
+                    let number = as_f64(value).ok_or_else(|| format!("$round only supports numeric types, not {}", value))?;
+                    let factor = 10f64.powi(places as i32);
+                    number_result(name, (number * factor).round() / factor)
+                }
+                _ => Err("$round requires at least one argument".to_string()),
+            }
+        }
+        "$pow" => binary_arithmetic(name, &arguments(operand, vars)?, |a, b| match (a, b) {
+            (bson::Bson::Int32(_) | bson::Bson::Int64(_), bson::Bson::Int32(_) | bson::Bson::Int64(_)) if as_i64(b).unwrap() >= 0 => {
+                let exponent = u32::try_from(as_i64(b).unwrap()).map_err(|_| "integer overflow".to_string())?;
+                let result = as_i64(a).unwrap().checked_pow(exponent).ok_or_else(|| "integer overflow".to_string())?;
+                match (a, b) {
+                    (bson::Bson::Int32(_), bson::Bson::Int32(_)) => Ok(i32::try_from(result).map(bson::Bson::Int32).unwrap_or(bson::Bson::Int64(result))),
+                    _ => Ok(bson::Bson::Int64(result)),
+                }
+            }
+            _ => match (as_f64(a), as_f64(b)) {
+                (Some(x), Some(y)) => Ok(bson::Bson::Double(x.powf(y))),
+                _ => Err(format!("only supports numeric types, not {} and {}", a, b)),
+            },
+        }),
+
+        // String expressions
+        "$concat" => {
+            let mut result = String::new();
+            for argument in arguments(operand, vars)? {
+                match argument {
+                    None | Some(bson::Bson::Null) => return Ok(Some(bson::Bson::Null)),
+                    Some(bson::Bson::String(s)) => result.push_str(&s),
+                    Some(other) => return Err(format!("$concat only supports strings, not {}", other)),
+                }
+            }
+            Ok(Some(bson::Bson::String(result)))
+        }
+        "$toLower" | "$toUpper" => {
+            let args = arguments(operand, vars)?;
+            expect_arguments(name, &args, 1)?;
+            let value = match &args[0] {
+                Some(bson::Bson::String(s)) => s.clone(),
+                value if is_nullish(value) => String::new(),
+                Some(other) => to_string(other)?,
+                None => unreachable!(),
+            };
+            Ok(Some(bson::Bson::String(if name == "$toLower" { value.to_lowercase() } else { value.to_uppercase() })))
+        }
+        "$strLenCP" => {
+            let args = arguments(operand, vars)?;
+            expect_arguments(name, &args, 1)?;
+            match &args[0] {
+                Some(bson::Bson::String(s)) => Ok(Some(bson::Bson::Int32(s.chars().count() as i32))),
+                other => Err(format!("$strLenCP requires a string argument, found: {:?}", other)),
+            }
+        }
+        "$substr" | "$substrCP" => {
+            let args = arguments(operand, vars)?;
+            expect_arguments(name, &args, 3)?;
+            let string = as_string(name, &args[0])?;
+            let start = args[1].as_ref().and_then(as_i64).ok_or_else(|| format!("{} requires a numeric starting index", name))?;
+            let count = args[2].as_ref().and_then(as_i64).ok_or_else(|| format!("{} requires a numeric length", name))?;
+            let start = start.max(0) as usize;
+            let substring: String = if count < 0 { string.chars().skip(start).collect() } else { string.chars().skip(start).take(count as usize).collect() };
+            Ok(Some(bson::Bson::String(substring)))
+        }
+        "$trim" | "$ltrim" | "$rtrim" => {
+            let spec = match operand {
+                bson::Bson::Document(spec) => spec,
+                _ => return Err(format!("{} requires an object as an argument, found: {}", name, operand)),
+            };
+            let input = evaluate(spec.get("input").ok_or_else(|| format!("{} requires an 'input' field", name))?, vars)?;
+            if is_nullish(&input) {
+                return Ok(Some(bson::Bson::Null));
+            }
+            let input = as_string(name, &input)?;
+            let chars: Option<Vec<char>> = match spec.get("chars") {
+                Some(chars) => Some(as_string(name, &evaluate(chars, vars)?)?.chars().collect()),
+                None => None,
+            };
+            let is_trimmed = |c: char| match &chars {
+                Some(chars) => chars.contains(&c),
+                None => c.is_whitespace() || c == '\0',
+            };
+            let result = match name {
+                "$ltrim" => input.trim_start_matches(is_trimmed),
+                "$rtrim" => input.trim_end_matches(is_trimmed),
+                _ => input.trim_matches(is_trimmed),
+            };
+            Ok(Some(bson::Bson::String(result.to_string())))
+        }
+        "$split" => {
+            let args = arguments(operand, vars)?;
+            expect_arguments(name, &args, 2)?;
+            if is_nullish(&args[0]) {
+                return Ok(Some(bson::Bson::Null));
+            }
+            let string = as_string(name, &args[0])?;
+            let delimiter = as_string(name, &args[1])?;
+            if delimiter.is_empty() {
+                return Err("$split requires a non-empty separator".to_string());
+            }
+            Ok(Some(bson::Bson::Array(string.split(delimiter.as_str()).map(|s| bson::Bson::String(s.to_string())).collect())))
+        }
+        "$toString" => {
+            let args = arguments(operand, vars)?;
+            expect_arguments(name, &args, 1)?;
+            match &args[0] {
+                value if is_nullish(value) => Ok(Some(bson::Bson::Null)),
+                Some(value) => Ok(Some(bson::Bson::String(to_string(value)?))),
+                None => unreachable!(),
+            }
+        }
+
+        // Date expressions
+        "$year" | "$month" | "$dayOfMonth" | "$hour" | "$minute" | "$second" | "$millisecond" | "$dayOfWeek" | "$dayOfYear" => {
+            let date = match operand {
+                bson::Bson::Document(spec) if spec.contains_key("date") => evaluate(spec.get("date").unwrap(), vars)?,
+                _ => {
+                    let args = arguments(operand, vars)?;
+                    expect_arguments(name, &args, 1)?;
+                    args[0].clone()
+                }
+            };
+            let date = match &date {
+                value if is_nullish(value) => return Ok(Some(bson::Bson::Null)),
+                Some(date) => as_date(name, date)?,
+                None => unreachable!(),
+            };
+            let value = match name {
+                "$year" => date.year(),
+                "$month" => date.month() as i32,
+                "$dayOfMonth" => date.day() as i32,
+                "$hour" => date.hour() as i32,
+                "$minute" => date.minute() as i32,
+                "$second" => date.second() as i32,
+                "$millisecond" => (date.nanosecond() / 1_000_000) as i32,
+                "$dayOfWeek" => date.weekday().number_from_sunday() as i32,
+                _ => date.ordinal() as i32,
+            };
+            Ok(Some(bson::Bson::Int32(value)))
+        }
+        "$dateToString" => {
+            let spec = match operand {
+                bson::Bson::Document(spec) => spec,
+                _ => return Err(format!("$dateToString only supports an object as its argument, found: {}", operand)),
+            };
+            let date = evaluate(spec.get("date").ok_or("$dateToString requires a 'date' field")?, vars)?;
+            let date = match &date {
+                value if is_nullish(value) => return Ok(spec.get("onNull").map(|on_null| evaluate(on_null, vars)).transpose()?.flatten().or(Some(bson::Bson::Null))),
+                Some(date) => as_date(name, date)?,
+                None => unreachable!(),
+            };
+            let format = match spec.get("format") {
+                Some(format) => as_string(name, &evaluate(format, vars)?)?,
+                None => String::from("%Y-%m-%dT%H:%M:%S.%LZ"),
+            };
+            Ok(Some(bson::Bson::String(date_to_string(&date, &format)?)))
+        }
+        "$dateTrunc" => {
+            let spec = match operand {
+                bson::Bson::Document(spec) => spec,
+                _ => return Err(format!("$dateTrunc only supports an object as its argument, found: {}", operand)),
+            };
+            let date = evaluate(spec.get("date").ok_or("$dateTrunc requires a 'date' field")?, vars)?;
+            let unit = evaluate(spec.get("unit").ok_or("$dateTrunc requires a 'unit' field")?, vars)?;
+            let bin_size = match spec.get("binSize") {
+                Some(bin_size) => evaluate(bin_size, vars)?,
+                None => Some(bson::Bson::Int32(1)),
+            };
+            if is_nullish(&date) || is_nullish(&unit) || is_nullish(&bin_size) {
+                return Ok(Some(bson::Bson::Null));
+            }
+            let date = as_date(name, date.as_ref().unwrap())?;
+            let bin_size = match bin_size.as_ref().and_then(as_i64) {
+                Some(bin_size) if bin_size > 0 => bin_size,
+                _ => return Err("$dateTrunc requires 'binSize' to be a positive integer".to_string()),
+            };
+            if let Some(timezone) = spec.get("timezone") {
+                if !matches!(evaluate(timezone, vars)?, Some(bson::Bson::String(ref tz)) if tz == "UTC" || tz == "Z" || tz == "GMT") {
+                    return Err("$dateTrunc only supports the UTC timezone".to_string());
+                }
+            }
+            let start_of_week = match spec.get("startOfWeek") {
+                Some(day) => as_string(name, &evaluate(day, vars)?)?,
+                None => String::from("sunday"),
+            };
+            Ok(Some(bson::Bson::DateTime(bson::DateTime::from_chrono(date_trunc(&date, &as_string(name, &unit)?, bin_size, &start_of_week)?))))
+        }
+        "$toDate" => {
+            let args = arguments(operand, vars)?;
+            expect_arguments(name, &args, 1)?;
+            match &args[0] {
+                value if is_nullish(value) => Ok(Some(bson::Bson::Null)),
+                Some(bson::Bson::DateTime(dt)) => Ok(Some(bson::Bson::DateTime(*dt))),
+                Some(bson::Bson::String(s)) => DateTime::parse_from_rfc3339(s)
+                    .map(|dt| Some(bson::Bson::DateTime(bson::DateTime::from(dt.with_timezone(&Utc)))))
+                    .map_err(|e| format!("Error parsing date string '{}': {}", s, e)),
+                Some(value) => match as_f64(value) {
+                    Some(millis) => Ok(Some(bson::Bson::DateTime(bson::DateTime::from_millis(millis as i64)))),
+                    None => Err(format!("Unsupported conversion from {} to date", value)),
+                },
+                None => unreachable!(),
+            }
+        }
+
+        // Conversion expressions
+        "$toInt" | "$toLong" | "$toDouble" => {
+            let args = arguments(operand, vars)?;
+            expect_arguments(name, &args, 1)?;
+            let value = match &args[0] {
+                value if is_nullish(value) => return Ok(Some(bson::Bson::Null)),
+                Some(bson::Bson::Boolean(b)) => *b as i64 as f64,
+                Some(bson::Bson::String(s)) => s.trim().parse::<f64>().map_err(|_| format!("Failed to parse number '{}' in {}", s, name))?,
+                Some(value) => as_f64(value).ok_or_else(|| format!("Unsupported conversion from {} in {}", value, name))?,
+                None => unreachable!(),
+            };
+            match name {
+                "$toInt" if value.trunc() >= i32::MIN as f64 && value.trunc() <= i32::MAX as f64 => Ok(Some(bson::Bson::Int32(value.trunc() as i32))),
+                "$toLong" if value.trunc() >= i64::MIN as f64 && value.trunc() <= i64::MAX as f64 => Ok(Some(bson::Bson::Int64(value.trunc() as i64))),
+                "$toDouble" => Ok(Some(bson::Bson::Double(value))),
+                _ => Err(format!("Conversion would overflow target type in {}", name)),
+            }
+        }
+
+        // Conditional expressions
+        "$cond" => {
+            let (condition, then, otherwise) = match operand {
+                bson::Bson::Array(arr) if arr.len() == 3 => (&arr[0], &arr[1], &arr[2]),
+                bson::Bson::Document(spec) => match (spec.get("if"), spec.get("then"), spec.get("else")) {
+                    (Some(condition), Some(then), Some(otherwise)) => (condition, then, otherwise),
+                    _ => return Err("Missing 'if', 'then' or 'else' parameter to $cond".to_string()),
+                },
+                _ => return Err(format!("$cond requires an array of three expressions or an object, found: {}", operand)),
+            };
+            if is_true(&evaluate(condition, vars)?) {
+                evaluate(then, vars)
+            } else {
+                evaluate(otherwise, vars)
+            }
+        }
+        "$ifNull" => {
+            let expressions = match operand {
+                bson::Bson::Array(arr) if arr.len() >= 2 => arr,
+                _ => return Err("$ifNull needs at least two arguments".to_string()),
+            };
+            for expression in &expressions[..expressions.len() - 1] {
+                let value = evaluate(expression, vars)?;
+                if !is_nullish(&value) {
+                    return Ok(value);
+                }
+            }
+            evaluate(&expressions[expressions.len() - 1], vars)
+        }
+        "$switch" => {
+            let spec = match operand {
+                bson::Bson::Document(spec) => spec,
+                _ => return Err(format!("$switch requires an object as an argument, found: {}", operand)),
+            };
+            if let Some(bson::Bson::Array(branches)) = spec.get("branches") {
+                for branch in branches {
+                    let branch = branch.as_document().ok_or("$switch expected each branch to be an object")?;
+                    match (branch.get("case"), branch.get("then")) {
+                        (Some(case), Some(then)) => {
+                            if is_true(&evaluate(case, vars)?) {
+                                return evaluate(then, vars);
+                            }
+                        }
+                        _ => return Err("$switch requires each branch have a 'case' and a 'then' expression".to_string()),
+                    }
+                }
+            } else {
+                return Err("$switch requires an array of 'branches'".to_string());
+            }
+            match spec.get("default") {
+                Some(default) => evaluate(default, vars),
+                None => Err("$switch could not find a matching branch for an input, and no default was specified.".to_string()),
+            }
+        }
+
+        // Comparison and boolean expressions
+        "$eq" => Ok(Some(bson::Bson::Boolean(compare(name, &arguments(operand, vars)?)? == Ordering::Equal))),
+        "$ne" => Ok(Some(bson::Bson::Boolean(compare(name, &arguments(operand, vars)?)? != Ordering::Equal))),
+        "$gt" => Ok(Some(bson::Bson::Boolean(compare(name, &arguments(operand, vars)?)? == Ordering::Greater))),
+        "$gte" => Ok(Some(bson::Bson::Boolean(compare(name, &arguments(operand, vars)?)? != Ordering::Less))),
+        "$lt" => Ok(Some(bson::Bson::Boolean(compare(name, &arguments(operand, vars)?)? == Ordering::Less))),
+        "$lte" => Ok(Some(bson::Bson::Boolean(compare(name, &arguments(operand, vars)?)? != Ordering::Greater))),
+        "$cmp" => Ok(Some(bson::Bson::Int32(match compare(name, &arguments(operand, vars)?)? {
+            Ordering::Less => -1,
+            Ordering::Equal => 0,
+            Ordering::Greater => 1,
+        }))),
+        "$and" => {
+            let expressions = match operand {
+                bson::Bson::Array(arr) => arr.clone(),
+                _ => vec![operand.clone()],
+            };
+            for expression in &expressions {
+                if !is_true(&evaluate(expression, vars)?) {
+                    return Ok(Some(bson::Bson::Boolean(false)));
+                }
+            }
+            Ok(Some(bson::Bson::Boolean(true)))
+        }
+        "$or" => {
+            let expressions = match operand {
+                bson::Bson::Array(arr) => arr.clone(),
+                _ => vec![operand.clone()],
+            };
+            for expression in &expressions {
+                if is_true(&evaluate(expression, vars)?) {
+                    return Ok(Some(bson::Bson::Boolean(true)));
+                }
+            }
+            Ok(Some(bson::Bson::Boolean(false)))
+        }
+        "$not" => {
+            let args = arguments(operand, vars)?;
+            expect_arguments(name, &args, 1)?;
+            Ok(Some(bson::Bson::Boolean(!is_true(&args[0]))))
+        }
+
+        // Array expressions
+        "$in" => {
+            let args = arguments(operand, vars)?;
+            expect_arguments(name, &args, 2)?;
+            let needle = args[0].clone().unwrap_or(bson::Bson::Null);
+            match &args[1] {
+                Some(bson::Bson::Array(arr)) => Ok(Some(bson::Bson::Boolean(arr.iter().any(|element| matcher::bson_equal(element, &needle))))),
+                other => Err(format!("$in requires an array as a second argument, found: {:?}", other)),
+            }
+        }
+        "$size" => {
+            let args = arguments(operand, vars)?;
+            expect_arguments(name, &args, 1)?;
+            match &args[0] {
+                Some(bson::Bson::Array(arr)) => Ok(Some(bson::Bson::Int32(arr.len() as i32))),
+                other => Err(format!("The argument to $size must be an array, but was of type: {:?}", other)),
+            }
+        }
+        "$arrayElemAt" => {
+            let args = arguments(operand, vars)?;
+            expect_arguments(name, &args, 2)?;
+            match (&args[0], args[1].as_ref().and_then(as_i64)) {
+                (value, _) if is_nullish(value) => Ok(Some(bson::Bson::Null)),
+                (Some(bson::Bson::Array(arr)), Some(index)) => {
+                    let index = if index < 0 { arr.len() as i64 + index } else { index };
+                    Ok(if index >= 0 { arr.get(index as usize).cloned() } else { None })
+                }
+                _ => Err("$arrayElemAt requires an array and an integral index".to_string()),
+            }
+        }
+        "$concatArrays" => {
+            let mut result = Vec::new();
+            for argument in arguments(operand, vars)? {
+                match argument {
+                    None | Some(bson::Bson::Null) => return Ok(Some(bson::Bson::Null)),
+                    Some(bson::Bson::Array(arr)) => result.extend(arr),
+                    Some(other) => return Err(format!("$concatArrays only supports arrays, not {}", other)),
+                }
+            }
+            Ok(Some(bson::Bson::Array(result)))
+        }
+        "$map" | "$filter" => {
+            let spec = match operand {
+                bson::Bson::Document(spec) => spec,
+                _ => return Err(format!("{} only supports an object as its argument", name)),
+            };
+            let input = evaluate(spec.get("input").ok_or_else(|| format!("Missing 'input' parameter to {}", name))?, vars)?;
+            let alias = spec.get_str("as").unwrap_or("this");
+            let body = if name == "$map" { spec.get("in") } else { spec.get("cond") }.ok_or_else(|| format!("Missing 'in' or 'cond' parameter to {}", name))?;
+            let arr = match input {
+                None | Some(bson::Bson::Null) => return Ok(Some(bson::Bson::Null)),
+                Some(bson::Bson::Array(arr)) => arr,
+                Some(other) => return Err(format!("input to {} must be an array not {}", name, other)),
+            };
+            let mut result = Vec::new();
+            for element in arr {
+                let value = evaluate(body, &vars.with(alias, element.clone()))?;
+                if name == "$map" {
+                    result.push(value.unwrap_or(bson::Bson::Null));
+                } else if is_true(&value) {
+                    result.push(element);
+                }
+            }
+            Ok(Some(bson::Bson::Array(result)))
+        }
+        "$let" => {
+            let spec = match operand {
+                bson::Bson::Document(spec) => spec,
+                _ => return Err("$let only supports an object as its argument".to_string()),
+            };
+            let mut scoped = vars.clone();
+            if let Some(bson::Bson::Document(definitions)) = spec.get("vars") {
+                for (variable, expression) in definitions.iter() {
+                    let value = evaluate(expression, vars)?.unwrap_or(bson::Bson::Null);
+                    scoped.user.insert(variable.clone(), value);
+                }
+            }
+            evaluate(spec.get("in").ok_or("Missing 'in' parameter to $let")?, &scoped)
+        }
+
+        _ => Err(format!("Unrecognized expression '{}'", name)),
+    }
+}
+
+fn to_string(value: &bson::Bson) -> Result<String, String> {
+    match value {
+        bson::Bson::String(s) => Ok(s.clone()),
+        bson::Bson::Int32(i) => Ok(i.to_string()),
+        bson::Bson::Int64(i) => Ok(i.to_string()),
+        bson::Bson::Double(d) => Ok(d.to_string()),
+        bson::Bson::Boolean(b) => Ok(b.to_string()),
+        bson::Bson::ObjectId(id) => Ok(id.to_hex()),
+        bson::Bson::DateTime(dt) => date_to_string(&dt.to_chrono(), "%Y-%m-%dT%H:%M:%S.%LZ"),
+        _ => Err(format!("Unsupported conversion from {} to string", value)),
+    }
+}
+
+/// Truncates a date for `$dateTrunc`. Like mongodb, bins of more than one unit are counted from the reference date 2000-01-01, or from the week
+/// starting on or before it.
+pub(crate) fn date_trunc(date: &DateTime<Utc>, unit: &str, bin_size: i64, start_of_week: &str) -> Result<DateTime<Utc>, String> {
+    // 2000-01-01T00:00:00Z, a saturday.
+    const REFERENCE: i64 = 946_684_800_000;
+    const DAY: i64 = 86_400_000;
+    let millis = match unit {
+        "millisecond" => 1,
+        "second" => 1000,
+        "minute" => 60_000,
+        "hour" => 3_600_000,
+        "day" => DAY,
+        "week" => 7 * DAY,
+        "month" | "quarter" | "year" => {
+            let months = bin_size * if unit == "month" { 1 } else if unit == "quarter" { 3 } else { 12 };
+            let elapsed = (date.year() as i64 - 2000) * 12 + date.month0() as i64;
+            let start = elapsed.div_euclid(months) * months;
+            let first_day = NaiveDate::from_ymd_opt((2000 + start.div_euclid(12)) as i32, start.rem_euclid(12) as u32 + 1, 1).ok_or("$dateTrunc produced an invalid date")?;
+            let days = first_day.signed_duration_since(NaiveDate::from_ymd_opt(2000, 1, 1).unwrap()).num_days();
+            return Ok(bson::DateTime::from_millis(REFERENCE + days * DAY).to_chrono());
+        }
+        _ => return Err(format!("$dateTrunc doesn't support the unit '{}'", unit)),
+    };
+    let reference = if unit == "week" {
+        let weekday = match start_of_week.to_ascii_lowercase().as_str() {
+            "sunday" | "sun" => Weekday::Sun,
+            "monday" | "mon" => Weekday::Mon,
+            "tuesday" | "tue" => Weekday::Tue,
+            "wednesday" | "wed" => Weekday::Wed,
+            "thursday" | "thu" => Weekday::Thu,
+            "friday" | "fri" => Weekday::Fri,
+            "saturday" | "sat" => Weekday::Sat,
+            other => return Err(format!("$dateTrunc doesn't recognize the day '{}'", other)),
+        };
+        let days_back = (Weekday::Sat.num_days_from_sunday() + 7 - weekday.num_days_from_sunday()) % 7;
+        REFERENCE - days_back as i64 * DAY
+    } else {
+        REFERENCE
+    };
+    let span = millis * bin_size;
+    let elapsed = date.timestamp_millis() - reference;
+    Ok(bson::DateTime::from_millis(reference + elapsed.div_euclid(span) * span).to_chrono())
+}
+
+/// Formats a date with the [format specifiers](https://docs.mongodb.com/manual/reference/operator/aggregation/dateToString/#format-specifiers)
+/// of `$dateToString`.
+fn date_to_string(date: &DateTime<Utc>, format: &str) -> Result<String, String> {
+    let mut result = String::new();
+    let mut chars = format.chars();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            result.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('Y') => result.push_str(&format!("{:04}", date.year())),
+            Some('m') => result.push_str(&format!("{:02}", date.month())),
+            Some('d') => result.push_str(&format!("{:02}", date.day())),
+            Some('H') => result.push_str(&format!("{:02}", date.hour())),
+            Some('M') => result.push_str(&format!("{:02}", date.minute())),
+            Some('S') => result.push_str(&format!("{:02}", date.second())),
+            Some('L') => result.push_str(&format!("{:03}", date.nanosecond() / 1_000_000)),
+            Some('j') => result.push_str(&format!("{:03}", date.ordinal())),
+            Some('w') => result.push_str(&date.weekday().number_from_sunday().to_string()),
+            Some('u') => result.push_str(&date.weekday().number_from_monday().to_string()),
+            Some('U') => result.push_str(&format!("{:02}", date.format("%U"))),
+            Some('V') => result.push_str(&format!("{:02}", date.iso_week().week())),
+            Some('G') => result.push_str(&format!("{:04}", date.iso_week().year())),
+            Some('z') => result.push_str("+0000"),
+            Some('Z') => result.push('0'),
+            Some('%') => result.push('%'),
+            Some(other) => return Err(format!("Invalid format character '%{}' in format string", other)),
+            None => return Err("Unmatched '%' at end of format string".to_string()),
+        }
+    }
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bson::doc;
+
+    fn eval(expression: bson::Bson, doc: &bson::Document) -> Result<Option<bson::Bson>, String> {
+        evaluate(&expression, &Variables::new(doc, bson::DateTime::from_millis(0)))
+    }
+
+    #[test]
+    fn test_arithmetic() {
+        let doc = doc! {"a": 2_i32, "b": 3_i64, "c": 1.5, "n": bson::Bson::Null};
+        assert_eq!(eval(bson::bson!({"$add": ["$a", "$b"]}), &doc).unwrap(), Some(bson::Bson::Int64(5)));
+        assert_eq!(eval(bson::bson!({"$add": ["$a", "$c", 1]}), &doc).unwrap(), Some(bson::Bson::Double(4.5)));
+        assert_eq!(eval(bson::bson!({"$add": ["$a", "$missing"]}), &doc).unwrap(), Some(bson::Bson::Null));
+        assert_eq!(eval(bson::bson!({"$subtract": ["$b", "$a"]}), &doc).unwrap(), Some(bson::Bson::Int64(1)));
+        assert_eq!(eval(bson::bson!({"$multiply": ["$a", "$a", "$a"]}), &doc).unwrap(), Some(bson::Bson::Int32(8)));
+        assert_eq!(eval(bson::bson!({"$divide": ["$b", "$a"]}), &doc).unwrap(), Some(bson::Bson::Double(1.5)));
+        assert!(eval(bson::bson!({"$divide": ["$b", 0]}), &doc).is_err());
+        assert_eq!(eval(bson::bson!({"$mod": ["$b", "$a"]}), &doc).unwrap(), Some(bson::Bson::Int64(1)));
+        assert_eq!(eval(bson::bson!({"$pow": ["$a", 10]}), &doc).unwrap(), Some(bson::Bson::Int32(1024)));
+        assert_eq!(eval(bson::bson!({"$round": [2.345, 2]}), &doc).unwrap(), Some(bson::Bson::Double(2.35)));
+        assert_eq!(eval(bson::bson!({"$floor": "$c"}), &doc).unwrap(), Some(bson::Bson::Double(1.0)));
+        assert_eq!(eval(bson::bson!({"$abs": -4}), &doc).unwrap(), Some(bson::Bson::Int32(4)));
+        assert_eq!(eval(bson::bson!({"$sqrt": "$n"}), &doc).unwrap(), Some(bson::Bson::Null));
+        assert!(eval(bson::bson!({"$add": ["$a", "text"]}), &doc).is_err());
+    }
+
+    #[test]
+    fn test_strings() {
+        let doc = doc! {"first": "Ada", "last": "Lovelace", "padded": "  x  "};
+        assert_eq!(eval(bson::bson!({"$concat": ["$first", " ", "$last"]}), &doc).unwrap(), Some(bson::Bson::String("Ada Lovelace".to_string())));
+        assert_eq!(eval(bson::bson!({"$concat": ["$first", "$missing"]}), &doc).unwrap(), Some(bson::Bson::Null));
+        assert_eq!(eval(bson::bson!({"$toUpper": "$first"}), &doc).unwrap(), Some(bson::Bson::String("ADA".to_string())));
+        assert_eq!(eval(bson::bson!({"$substrCP": ["$last", 0, 4]}), &doc).unwrap(), Some(bson::Bson::String("Love".to_string())));
+        assert_eq!(eval(bson::bson!({"$strLenCP": "$last"}), &doc).unwrap(), Some(bson::Bson::Int32(8)));
+        assert_eq!(eval(bson::bson!({"$trim": {"input": "$padded"}}), &doc).unwrap(), Some(bson::Bson::String("x".to_string())));
+        assert_eq!(eval(bson::bson!({"$split": ["a-b", "-"]}), &doc).unwrap(), Some(bson::bson!(["a", "b"])));
+        assert_eq!(eval(bson::bson!({"$toString": 5}), &doc).unwrap(), Some(bson::Bson::String("5".to_string())));
+    }
+
+    #[test]
+    fn test_dates() {
+        let date = bson::DateTime::from_millis(1_000_000_000_123);
+        let doc = doc! {"d": date};
+        assert_eq!(eval(bson::bson!({"$year": "$d"}), &doc).unwrap(), Some(bson::Bson::Int32(2001)));
+        assert_eq!(eval(bson::bson!({"$month": "$d"}), &doc).unwrap(), Some(bson::Bson::Int32(9)));
+        assert_eq!(eval(bson::bson!({"$dayOfMonth": {"date": "$d"}}), &doc).unwrap(), Some(bson::Bson::Int32(9)));
+        assert_eq!(eval(bson::bson!({"$millisecond": "$d"}), &doc).unwrap(), Some(bson::Bson::Int32(123)));
+        assert_eq!(
+            eval(bson::bson!({"$dateToString": {"format": "%Y-%m-%d %H:%M:%S.%L", "date": "$d"}}), &doc).unwrap(),
+            Some(bson::Bson::String("2001-09-09 01:46:40.123".to_string()))
+        );
+        assert_eq!(eval(bson::bson!({"$add": ["$d", 1000]}), &doc).unwrap(), Some(bson::Bson::DateTime(bson::DateTime::from_millis(1_000_000_001_123))));
+        assert_eq!(eval(bson::bson!({"$subtract": ["$d", "$$NOW"]}), &doc).unwrap(), Some(bson::Bson::Int64(1_000_000_000_123)));
+        assert_eq!(eval(bson::bson!({"$add": ["$d", 1e19]}), &doc).unwrap_err(), "$add: integer overflow");
+        let extremes = doc! {"min": bson::DateTime::MIN, "max": bson::DateTime::MAX};
+        assert_eq!(eval(bson::bson!({"$subtract": ["$min", "$max"]}), &extremes).unwrap_err(), "$subtract: integer overflow");
+        assert_eq!(eval(bson::bson!({"$subtract": ["$min", 1]}), &extremes).unwrap_err(), "$subtract: integer overflow");
+
+        let trunc = |unit: &str, bin_size: i32| eval(bson::bson!({"$dateTrunc": {"date": "$d", "unit": unit, "binSize": bin_size}}), &doc).unwrap().unwrap();
+        let date = |s: &str| bson::Bson::DateTime(bson::DateTime::from_chrono(DateTime::parse_from_rfc3339(s).unwrap().with_timezone(&Utc)));
+        assert_eq!(trunc("hour", 1), date("2001-09-09T01:00:00Z"));
+        assert_eq!(trunc("minute", 15), date("2001-09-09T01:45:00Z"));
+        assert_eq!(trunc("day", 1), date("2001-09-09T00:00:00Z"));
+        assert_eq!(trunc("week", 1), date("2001-09-09T00:00:00Z"));
+        assert_eq!(trunc("month", 1), date("2001-09-01T00:00:00Z"));
+        assert_eq!(trunc("quarter", 1), date("2001-07-01T00:00:00Z"));
+        assert_eq!(trunc("year", 2), date("2000-01-01T00:00:00Z"));
+        assert_eq!(eval(bson::bson!({"$dateTrunc": {"date": "$missing", "unit": "hour"}}), &doc).unwrap(), Some(bson::Bson::Null));
+        assert!(eval(bson::bson!({"$dateTrunc": {"date": "$d", "unit": "fortnight"}}), &doc).is_err());
+    }
+
+    #[test]
+    fn test_conditionals_and_variables() {
+        let doc = doc! {"qty": 250, "name": bson::Bson::Null, "items": [1, 2, 3]};
+        assert_eq!(eval(bson::bson!({"$cond": {"if": {"$gte": ["$qty", 250]}, "then": 30, "else": 20}}), &doc).unwrap(), Some(bson::Bson::Int32(30)));
+        assert_eq!(eval(bson::bson!({"$cond": [{"$lt": ["$qty", 250]}, 30, 20]}), &doc).unwrap(), Some(bson::Bson::Int32(20)));
+        assert_eq!(eval(bson::bson!({"$ifNull": ["$name", "$missing", "unknown"]}), &doc).unwrap(), Some(bson::Bson::String("unknown".to_string())));
+        assert_eq!(
+            eval(bson::bson!({"$switch": {"branches": [{"case": {"$eq": ["$qty", 1]}, "then": "one"}], "default": "many"}}), &doc).unwrap(),
+            Some(bson::Bson::String("many".to_string()))
+        );
+        assert_eq!(eval(bson::bson!({"$map": {"input": "$items", "as": "i", "in": {"$multiply": ["$$i", 2]}}}), &doc).unwrap(), Some(bson::bson!([2, 4, 6])));
+        assert_eq!(eval(bson::bson!({"$filter": {"input": "$items", "cond": {"$gt": ["$$this", 1]}}}), &doc).unwrap(), Some(bson::bson!([2, 3])));
+        assert_eq!(eval(bson::bson!({"$let": {"vars": {"x": 2}, "in": {"$add": ["$$x", "$qty"]}}}), &doc).unwrap(), Some(bson::Bson::Int32(252)));
+        assert_eq!(eval(bson::bson!("$$ROOT.qty"), &doc).unwrap(), Some(bson::Bson::Int32(250)));
+        assert_eq!(eval(bson::bson!("$$REMOVE"), &doc).unwrap(), None);
+        assert_eq!(eval(bson::bson!({"$literal": "$qty"}), &doc).unwrap(), Some(bson::Bson::String("$qty".to_string())));
+        assert!(eval(bson::bson!("$$undefined"), &doc).is_err());
+        assert!(eval(bson::bson!({"$bogus": 1}), &doc).is_err());
+    }
+}
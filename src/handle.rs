@@ -0,0 +1,373 @@
+//! A thread-safe database handle.
+//!
+//! A [`Database`] owns a single sqlite connection, and a [`crate::collection::Collection`] borrows it, so only one collection can be used at a
+//! time and neither can be sent to another thread. [`DatabaseHandle`] is the shareable alternative. It switches the database to WAL mode and keeps
+//! one writer connection behind a mutex, plus a [`ConnectionPool`] of read-only connections. Reads from any number of threads run concurrently on
+//! the pooled connections, while writes are serialized on the writer. The handle and the [`CollectionHandle`]s obtained from it are cheap to clone,
+//! `Send` and `Sync`, and don't borrow anything.
+//!
+//! ```rust
+//! use hoardbase::base::CollectionConfig;
+//! use hoardbase::database::DatabaseConfig;
+//! use hoardbase::handle::DatabaseHandle;
+//!
+//! let db = DatabaseHandle::open(&DatabaseConfig::temporary()).unwrap();
+//! let fruits = db.create_collection("fruits", &CollectionConfig::default("fruits")).unwrap();
+//!
+//! let writer = {
+//!     let fruits = fruits.clone();
+//!     std::thread::spawn(move || fruits.insert_one(&bson::doc! {"kind": "apples", "qty": 5}).unwrap())
+//! };
+//! writer.join().unwrap();
+//! assert_eq!(fruits.count_documents(&bson::doc! {}, &None).unwrap(), 1);
+//! ```
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, MutexGuard, RwLock};
+
+use crate::base::*;
+use crate::capped::{Tail, TailableCursor};
+use crate::collection::Collection;
+use crate::database::{collection_stats, copy_database, vacuum_into, BackupProgress, Database, DatabaseConfig, JournalMode, LockingMode, Storage, Transaction};
+use crate::csv::CsvOption;
+use crate::dump::{DumpFormat, ImportReport};
+use crate::pool::ConnectionPool;
+use crate::view::View;
+
+struct Shared {
+    path: String,
+    /// Read-only connections for queries. They are declared before the writer so that they are closed before the writer deletes a temporary
+    /// database.
+    readers: ConnectionPool,
+    /// The only connection that writes. Holding its lock serializes writes.
+    writer: Mutex<Database>,
+    /// The collections' configurations, kept in sync with the writer's so that readers don't need the writer lock.
+    collections: RwLock<HashMap<String, CollectionConfig>>,
+}
+
+/// A cloneable, thread-safe handle to a database. All clones share the same connections.
+#[derive(Clone)]
+pub struct DatabaseHandle {
+    shared: Arc<Shared>,
+}
+
+impl DatabaseHandle {
+    /// Opens a database and switches it to WAL mode, so that readers don't block the writer and vice versa, whatever the journal mode of `config`
+    /// is. The number of reader connections is limited by [`DatabaseConfig::reader_pool_size`]. In-memory databases can't be in WAL mode, so they
+    /// aren't supported; a [`Storage::Temporary`] database can be used instead.
+    pub fn open(config: &DatabaseConfig) -> Result<DatabaseHandle, String> {
+        if let Storage::Memory | Storage::SharedMemory(_) = config.storage {
+            return Err("A database handle needs a database file, in-memory databases aren't supported".to_string());
+        }
+        if config.locking_mode == Some(LockingMode::Exclusive) {
+            return Err("The exclusive locking mode would lock the readers out".to_string());
+        }
+        let mut config = config.clone();
+        config.journal_mode(JournalMode::Wal);
+        let config = &config;
+        let writer = Database::open(config)?;
+
+        let collections = writer.list_collections().into_iter().collect();
+        Ok(DatabaseHandle {
+            shared: Arc::new(Shared {
+                path: config.path.clone(),
+                readers: ConnectionPool::new(config, config.reader_pool_size),
+                writer: Mutex::new(writer),
+                collections: RwLock::new(collections),
+            }),
+        })
+    }
+
+    /// Obtain the filepath of this database.
+    pub fn path(&self) -> String {
+        self.shared.path.clone()
+    }
+
+    /// Create and return a collection given its config. If the collection exists already, the existing collection is returned.
+    pub fn create_collection(&self, collection_name: &str, config: &CollectionConfig) -> Result<CollectionHandle, String> {
+        let mut writer = self.writer();
+        let config = writer.create_collection(collection_name, config).map(|collection| collection.config.clone()).map_err(|e| e.to_string())?;
+        self.shared.collections.write().unwrap_or_else(|e| e.into_inner()).insert(collection_name.to_string(), config);
+        Ok(CollectionHandle { db: self.clone(), name: collection_name.to_string() })
+    }
+
+    /// Obtain an existing collection given a name.
+    pub fn collection(&self, collection_name: &str) -> Result<CollectionHandle, String> {
+        if self.collection_config(collection_name)?.time_series.is_some() {
+            return Err("The collection is a time-series collection".to_string());
+        }
+        if self.collection_config(collection_name)?.view.is_some() {
+            return Err("The collection is a view, which is read-only".to_string());
+        }
+        Ok(CollectionHandle { db: self.clone(), name: collection_name.to_string() })
+    }
+
+    /// Create and return a view, see [`Database::create_view()`].
+    pub fn create_view(&self, view_name: &str, source: &str, pipeline: &Vec<bson::Document>) -> Result<ViewHandle, String> {
+        let mut writer = self.writer();
+        let config = writer.create_view(view_name, source, pipeline)?.config.clone();
+        self.shared.collections.write().unwrap_or_else(|e| e.into_inner()).insert(view_name.to_string(), config);
+        Ok(ViewHandle { db: self.clone(), name: view_name.to_string() })
+    }
+
+    /// Obtain an existing view given a name.
+    pub fn view(&self, view_name: &str) -> Result<ViewHandle, String> {
+        if self.collection_config(view_name)?.view.is_none() {
+            return Err("The collection isn't a view".to_string());
+        }
+        Ok(ViewHandle { db: self.clone(), name: view_name.to_string() })
+    }
+
+    /// List existing collections
+    pub fn list_collections(&self) -> Vec<(String, CollectionConfig)> {
+        self.shared.collections.read().unwrap_or_else(|e| e.into_inner()).iter().map(|(name, config)| (name.clone(), config.clone())).collect()
+    }
+
+    /// Drop a collection. Handles to the dropped collection return errors afterwards.
+    pub fn drop_collection(&self, collection_name: &str) -> Result<(), String> {
+        let mut writer = self.writer();
+        writer.drop_collection(collection_name).map_err(|e| e.to_string())?;
+        self.shared.collections.write().unwrap_or_else(|e| e.into_inner()).remove(collection_name);
+        Ok(())
+    }
+
+    /// Rename collection. Handles obtained with the old name return errors afterwards.
+    pub fn rename_collection(&self, collection_old_name: &str, collection_new_name: &str) -> Result<(), String> {
+        let mut writer = self.writer();
+        writer.rename_collection(collection_old_name, collection_new_name).map_err(|e| e.to_string())?;
+        // the table name changes with the name.
+        let (_, config) = writer.list_collections().into_iter().find(|(name, _)| name == collection_new_name).ok_or("No collection found")?;
+        let mut collections = self.shared.collections.write().unwrap_or_else(|e| e.into_inner());
+        collections.remove(collection_old_name);
+        collections.insert(collection_new_name.to_string(), config);
+        Ok(())
+    }
+
+    /// Returns the number of documents of a collection and the space it takes up, see [`Database::collection_stats()`]. The pages are read
+    /// through a pooled reader connection.
+    pub fn collection_stats(&self, collection_name: &str) -> Result<CollectionStats, String> {
+        let config = self.collection_config(collection_name)?;
+        collection_stats(&*self.shared.readers.get()?, &config)
+    }
+
+    /// Run `f` in a transaction on the writer connection. Other writes wait until the transaction finishes; reads keep seeing the last committed
+    /// state.
+    pub fn transaction<F>(&self, f: F) -> Result<(), String>
+    where
+        F: FnOnce(&Transaction) -> Result<(), &'static str>,
+    {
+        self.writer().transaction(f).map_err(|e| e.to_string())
+    }
+
+    /// Copies the database to the file at `path`, see [`Database::backup_to()`]. The copy is read through a pooled reader connection, so writes
+    /// carry on while it runs, e.g. on a background thread.
+    pub fn backup_to(&self, path: &str, mut progress: impl FnMut(BackupProgress)) -> Result<(), String> {
+        let reader = self.shared.readers.get()?;
+        let mut destination = rusqlite::Connection::open(path).map_err(|e| e.to_string())?;
+        copy_database(&reader, &mut destination, &mut progress)
+    }
+
+    /// Replaces the content of the database with that of the database file at `path`, see [`Database::restore_from()`]. Writes wait until the
+    /// restore finishes.
+    pub fn restore_from(&self, path: &str, progress: impl FnMut(BackupProgress)) -> Result<(), String> {
+        let mut writer = self.writer();
+        let result = writer.restore_from(path, progress);
+        *self.shared.collections.write().unwrap_or_else(|e| e.into_inner()) = writer.list_collections().into_iter().collect();
+        result
+    }
+
+    /// Writes a compacted copy of the database to a new file at `path`, see [`Database::snapshot_to()`].
+    pub fn snapshot_to(&self, path: &str) -> Result<(), String> {
+        vacuum_into(&*self.shared.readers.get()?, path)
+    }
+
+    fn writer(&self) -> MutexGuard<'_, Database> {
+        // sqlite rolls back whatever a panicking writer left unfinished, so the database is still consistent when the lock is poisoned.
+        self.shared.writer.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn collection_config(&self, collection_name: &str) -> Result<CollectionConfig, String> {
+        self.shared.collections.read().unwrap_or_else(|e| e.into_inner()).get(collection_name).cloned().ok_or_else(|| "No collection found".to_string())
+    }
+}
+
+/// An owned handle to a collection of a [`DatabaseHandle`]. It offers the operations of [`CollectionTrait`]; queries run on a pooled reader
+/// connection, everything else on the writer.
+#[derive(Clone)]
+pub struct CollectionHandle {
+    db: DatabaseHandle,
+    name: String,
+}
+
+impl CollectionHandle {
+    /// Runs `f` on a collection backed by a reader connection.
+    fn read<T>(&self, f: impl FnOnce(&mut Collection) -> Result<T, String>) -> Result<T, String> {
+        let config = self.db.collection_config(&self.name)?;
+        let connection = self.db.shared.readers.get()?;
+        let mut collection = Collection { table_name: config.table_name.clone(), config, name: self.name.clone(), db: &connection };
+        f(&mut collection)
+    }
+
+    /// Runs `f` on a collection backed by the writer connection.
+    fn write<T>(&self, f: impl FnOnce(&mut Collection) -> Result<T, String>) -> Result<T, String> {
+        let mut writer = self.db.writer();
+        let mut collection = writer.collection(&self.name).map_err(|e| e.to_string())?;
+        f(&mut collection)
+    }
+
+    /// The collection name
+    pub fn get_name(&self) -> &str {
+        &self.name
+    }
+
+    /// The database this collection belongs to.
+    pub fn database(&self) -> &DatabaseHandle {
+        &self.db
+    }
+
+    pub fn find(&self, query: &bson::Document, options: &Option<SearchOption>, f: &mut dyn FnMut(&Record) -> Result<(), &'static str>) -> Result<(), String> {
+        self.read(|collection| collection.find(query, options, f).map_err(|e| e.to_string()))
+    }
+
+    pub fn explain(&self, query: &bson::Document, options: &Option<SearchOption>, verbosity: ExplainVerbosity) -> Result<QueryPlan, String> {
+        self.read(|collection| collection.explain(query, options, verbosity))
+    }
+
+    pub fn count_documents(&self, query: &bson::Document, options: &Option<SearchOption>) -> Result<i64, String> {
+        self.read(|collection| collection.count_documents(query, options).map_err(|e| e.to_string()))
+    }
+
+    pub fn aggregate(&self, pipeline: &[bson::Document]) -> Result<Vec<bson::Document>, String> {
+        self.read(|collection| collection.aggregate(pipeline))
+    }
+
+    pub fn distinct(&self, field: &str, query: &Option<bson::Document>, options: &Option<SearchOption>) -> Result<i64, String> {
+        self.read(|collection| collection.distinct(field, query, options).map_err(|e| e.to_string()))
+    }
+
+    pub fn find_one(&self, query: &bson::Document, skip: i64) -> Result<Record, String> {
+        self.read(|collection| collection.find_one(query, skip).map_err(|e| e.to_string()))
+    }
+
+    pub fn get_indexes(&self) -> Result<Vec<Index>, String> {
+        self.read(|collection| collection.get_indexes())
+    }
+
+    pub fn create_index(&self, config: &bson::Document, is_unique: bool) -> Result<(), String> {
+        self.write(|collection| collection.create_index(config, is_unique))
+    }
+
+    pub fn drop_index(&self, index_name: &str) -> Result<(), String> {
+        self.write(|collection| collection.drop_index(index_name))
+    }
+
+    pub fn reindex(&self) -> Result<(), String> {
+        self.write(|collection| collection.reindex())
+    }
+
+    pub fn delete_one(&self, query: &bson::Document) -> Result<usize, String> {
+        self.write(|collection| collection.delete_one(query))
+    }
+
+    pub fn delete_many(&self, query: &bson::Document) -> Result<usize, String> {
+        self.write(|collection| collection.delete_many(query))
+    }
+
+    pub fn find_one_and_delete(&self, query: &bson::Document) -> Result<Option<Record>, String> {
+        self.write(|collection| collection.find_one_and_delete(query))
+    }
+
+    pub fn insert_one(&self, document: &bson::Document) -> Result<Option<Record>, String> {
+        self.write(|collection| collection.insert_one(document))
+    }
+
+    pub fn insert_many(&self, documents: &Vec<bson::Document>) -> Result<(), String> {
+        self.write(|collection| collection.insert_many(documents))
+    }
+
+    pub fn replace_one(&self, query: &bson::Document, replacement: &bson::Document, skip: i64) -> Result<Option<Record>, String> {
+        self.write(|collection| collection.replace_one(query, replacement, skip))
+    }
+
+    pub fn update_one(&self, query: &bson::Document, update: &bson::Document, skip: i64, upsert: bool, options: &Option<UpdateOption>) -> Result<Option<Record>, String> {
+        self.write(|collection| collection.update_one(query, update, skip, upsert, options))
+    }
+
+    pub fn update_many(&self, query: &bson::Document, update: &bson::Document, limit: i64, skip: i64, upsert: bool, options: &Option<UpdateOption>) -> Result<i64, String> {
+        self.write(|collection| collection.update_many(query, update, limit, skip, upsert, options))
+    }
+
+    pub fn update_one_pipeline(&self, query: &bson::Document, pipeline: &Vec<bson::Document>, skip: i64, upsert: bool) -> Result<Option<Record>, String> {
+        self.write(|collection| collection.update_one_pipeline(query, pipeline, skip, upsert))
+    }
+
+    pub fn update_many_pipeline(&self, query: &bson::Document, pipeline: &Vec<bson::Document>, limit: i64, skip: i64, upsert: bool) -> Result<i64, String> {
+        self.write(|collection| collection.update_many_pipeline(query, pipeline, limit, skip, upsert))
+    }
+
+    pub fn export(&self, writer: &mut dyn std::io::Write, format: DumpFormat) -> Result<usize, String> {
+        self.read(|collection| collection.export(writer, format))
+    }
+
+    pub fn import(&self, reader: &mut dyn std::io::BufRead, format: DumpFormat) -> Result<ImportReport, String> {
+        self.write(|collection| collection.import(reader, format))
+    }
+
+    pub fn export_csv(&self, writer: &mut dyn std::io::Write, query: &bson::Document, options: &Option<SearchOption>, csv: &CsvOption) -> Result<usize, String> {
+        self.read(|collection| collection.export_csv(writer, query, options, csv))
+    }
+
+    pub fn import_csv(&self, reader: &mut dyn std::io::BufRead, csv: &CsvOption) -> Result<ImportReport, String> {
+        self.write(|collection| collection.import_csv(reader, csv))
+    }
+
+    /// Returns the next documents of a tailable cursor, see [`Collection::tail()`].
+    pub fn tail(&self, cursor: &mut TailableCursor) -> Result<Vec<Record>, String> {
+        self.read(|collection| collection.tail(cursor))
+    }
+
+    /// Follows a capped collection, returning an iterator that blocks until new documents arrive. The collection is polled every
+    /// `poll_interval` while there is nothing to return.
+    pub fn follow(&self, cursor: TailableCursor, poll_interval: std::time::Duration) -> Tail {
+        Tail::new(self.clone(), cursor, poll_interval)
+    }
+}
+
+/// An owned handle to a view of a [`DatabaseHandle`]. Views are read-only, so everything runs on a pooled reader connection.
+#[derive(Clone)]
+pub struct ViewHandle {
+    db: DatabaseHandle,
+    name: String,
+}
+
+impl ViewHandle {
+    /// Runs `f` on the view, backed by a reader connection.
+    fn read<T>(&self, f: impl FnOnce(&mut View) -> Result<T, String>) -> Result<T, String> {
+        let config = self.db.collection_config(&self.name)?;
+        let collections = self.db.shared.collections.read().unwrap_or_else(|e| e.into_inner()).clone();
+        let connection = self.db.shared.readers.get()?;
+        let mut view = View::new(&self.name, &config, &connection, &|name| collections.get(name).cloned())?;
+        f(&mut view)
+    }
+
+    /// The view name
+    pub fn get_name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn find(&self, query: &bson::Document, options: &Option<SearchOption>, f: &mut dyn FnMut(&bson::Document) -> Result<(), &'static str>) -> Result<(), String> {
+        self.read(|view| view.find(query, options, f))
+    }
+
+    pub fn find_one(&self, query: &bson::Document) -> Result<Option<bson::Document>, String> {
+        self.read(|view| view.find_one(query))
+    }
+
+    pub fn count_documents(&self, query: &bson::Document, options: &Option<SearchOption>) -> Result<i64, String> {
+        self.read(|view| view.count_documents(query, options))
+    }
+
+    pub fn aggregate(&self, pipeline: &[bson::Document]) -> Result<Vec<bson::Document>, String> {
+        self.read(|view| view.aggregate(pipeline))
+    }
+}
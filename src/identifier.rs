@@ -0,0 +1,209 @@
+//! Names and field paths in SQL.
+//!
+//! Collection names, index names and field paths end up in the SQL hoardbase generates, so they are checked and quoted here before they are
+//! spliced in. Values are always bound as parameters. Field paths can't be: sqlite only uses an index on `json_field('age', raw)` for a query
+//! whose expression is the very same text, so paths are written as escaped string literals instead. Table names are chosen by hoardbase, see
+//! [`crate::database::Database::create_collection()`], and written in brackets.
+//!
+//! Names follow MongoDB's rules, see [`validate_collection_name()`] and [`validate_field_path()`].
+
+/// The maximum length of a collection name in bytes. MongoDB limits the namespace, the database and collection names together, to 255 bytes.
+pub const MAX_COLLECTION_NAME_LENGTH: usize = 255;
+
+/// Checks a collection name. It must not be empty or longer than [`MAX_COLLECTION_NAME_LENGTH`], must not contain `$` or the null character,
+/// and must not start with the `system.` prefix MongoDB reserves.
+pub fn validate_collection_name(name: &str) -> Result<(), &'static str> {
+    if name.is_empty() {
+        return Err("A collection name can't be empty");
+    }
+    if name.len() > MAX_COLLECTION_NAME_LENGTH {
+        return Err("A collection name can't be longer than 255 bytes");
+    }
+    if name.contains('$') {
+        return Err("A collection name can't contain '$'");
+    }
+    if name.contains('\0') {
+        return Err("A collection name can't contain the null character");
+    }
+    if name.starts_with("system.") {
+        return Err("Collection names starting with 'system.' are reserved");
+    }
+    Ok(())
+}
+
+/// Checks a dotted field path as used by queries and indexes. It must not be empty or contain the null character, and none of its fields may be
+/// empty or start with `$`.
+pub fn validate_field_path(path: &str) -> Result<(), &'static str> {
+    if path.is_empty() {
+        return Err("A field path can't be empty");
+    }
+    if path.contains('\0') {
+        return Err("A field path can't contain the null character");
+    }
+    for field in path.split('.') {
+        if field.is_empty() {
+            return Err("A field path can't contain an empty field name");
+        }
+        if field.starts_with('$') {
+            return Err("A field name in a field path can't start with '$'");
+        }
+    }
+    Ok(())
+}
+
+/// Quotes a string as a SQL string literal.
+pub(crate) fn quote_literal(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "''"))
+}
+
+/// Quotes a name as a SQL identifier.
+pub(crate) fn quote_identifier(name: &str) -> String {
+    format!("\"{}\"", name.replace('"', "\"\""))
+}
+
+/// Validates a field path and quotes it as the first argument of `json_field()` and its siblings.
+pub(crate) fn field_literal(path: &str) -> Result<String, String> {
+    validate_field_path(path).map_err(|e| format!("{}: {:?}", e, path))?;
+    Ok(quote_literal(path))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::base::{CollectionConfig, CollectionTrait};
+    use crate::database::{Database, DatabaseConfig};
+    use crate::query_translator::QueryTranslator;
+
+    /// A xorshift generator, so that the generated queries are the same on every run.
+    struct Generator(u64);
+
+    impl Generator {
+        fn next(&mut self) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0
+        }
+
+        fn below(&mut self, n: usize) -> usize {
+            (self.next() % n as u64) as usize
+        }
+
+        fn pick<'a>(&mut self, items: &[&'a str]) -> &'a str {
+            items[self.below(items.len())]
+        }
+
+        fn key(&mut self) -> String {
+            const PARTS: &[&str] = &["a", "b", "_id", "x'y", "]", "[", "\"", "'); DROP TABLE fuzz; --", ".", "..", "$", "$gt", "", "é", "\0", " ", "a.b", "raw"];
+            (0..1 + self.below(3)).map(|_| self.pick(PARTS)).collect()
+        }
+
+        fn value(&mut self, depth: usize) -> bson::Bson {
+            const OPERATORS: &[&str] = &[
+                "$lt", "$gt", "$gte", "$lte", "$eq", "$ne", "$in", "$nin", "$exists", "$type", "$size", "$all", "$elemMatch", "$mod", "$regex", "$options", "$bitsAllSet", "$bitsAllClear", "$bitsAnySet", "$bitsAnyClear", "$or", "$and", "$not", "$nor", "$bogus",
+            ];
+            match self.below(if depth > 2 { 6 } else { 9 }) {
+                0 => bson::Bson::Int32(self.next() as i32 % 5),
+                1 => bson::Bson::Int64(self.next() as i64 % 5),
+                2 => bson::Bson::Double((self.next() % 100) as f64 / 7.0),
+                3 => bson::Bson::String(self.key()),
+                4 => bson::Bson::Boolean(self.next().is_multiple_of(2)),
+                5 => bson::Bson::Null,
+                6 => bson::Bson::Array((0..self.below(3)).map(|_| self.value(depth + 1)).collect()),
+                7 => {
+                    let mut doc = bson::Document::new();
+                    for _ in 0..1 + self.below(2) {
+                        doc.insert(self.pick(OPERATORS), self.value(depth + 1));
+                    }
+                    bson::Bson::Document(doc)
+                }
+                _ => bson::Bson::Document(self.query(depth + 1)),
+            }
+        }
+
+        fn query(&mut self, depth: usize) -> bson::Document {
+            let mut doc = bson::Document::new();
+            for _ in 0..self.below(3) {
+                let key = if self.below(4) == 0 { self.pick(&["$or", "$and", "$not", "$nor"]).to_string() } else { self.key() };
+                doc.insert(key, self.value(depth));
+            }
+            doc
+        }
+    }
+
+    #[test]
+    fn test_validation() {
+        assert!(validate_collection_name("fruits").is_ok());
+        assert!(validate_collection_name("team members").is_ok());
+        assert!(validate_collection_name("").is_err());
+        assert!(validate_collection_name("a$b").is_err());
+        assert!(validate_collection_name("a\0b").is_err());
+        assert!(validate_collection_name("system.views").is_err());
+        assert!(validate_collection_name(&"x".repeat(256)).is_err());
+
+        assert!(validate_field_path("address.city").is_ok());
+        assert!(validate_field_path("it's").is_ok());
+        assert!(validate_field_path("").is_err());
+        assert!(validate_field_path("a..b").is_err());
+        assert!(validate_field_path("a.").is_err());
+        assert!(validate_field_path("a.$gt").is_err());
+
+        assert_eq!(quote_literal("it's"), "'it''s'");
+        assert_eq!(quote_identifier("a\"b"), "\"a\"\"b\"");
+    }
+
+    #[test]
+    fn test_quoted_paths() {
+        let mut db = Database::open(&DatabaseConfig::in_memory()).unwrap();
+        assert!(db.create_collection("a$b", &CollectionConfig::default("a$b")).is_err());
+        assert!(db.create_collection("system.users", &CollectionConfig::default("system.users")).is_err());
+
+        let mut collection = db.create_collection("quotes", &CollectionConfig::default("quotes")).unwrap();
+        collection.insert_one(&bson::doc! { "it's": "x', raw) OR 1 = 1 --", "[a]": 1 }).unwrap();
+        collection.insert_one(&bson::doc! { "it's": "other", "[a]": 2 }).unwrap();
+        collection.create_index(&bson::doc! { "it's": 1 }, false).unwrap();
+
+        let query = bson::doc! { "it's": "x', raw) OR 1 = 1 --" };
+        assert_eq!(collection.count_documents(&query, &None).unwrap(), 1);
+        assert_eq!(collection.count_documents(&bson::doc! { "[a]": { "$gt": 1 } }, &None).unwrap(), 1);
+        let plan = collection.explain(&query, &None, crate::base::ExplainVerbosity::QueryPlanner).unwrap();
+        assert_eq!(plan.indexes, vec!["quotes_it_s".to_string()]);
+
+        assert!(collection.count_documents(&bson::doc! { "a..b": 1 }, &None).is_err());
+        assert!(collection.create_index(&bson::doc! { "a.$b": 1 }, false).is_err());
+    }
+
+    /// Translates random queries full of quotes, brackets and operators. The translator must never panic, and whatever SQL it produces must be a
+    /// single valid statement that leaves the database alone.
+    #[test]
+    fn test_translator_fuzz() {
+        let mut db = Database::open(&DatabaseConfig::in_memory()).unwrap();
+        let mut collection = db.create_collection("fuzz", &CollectionConfig::default("fuzz")).unwrap();
+        collection.insert_one(&bson::doc! { "a": 1, "x'y": "it's", "b": [1, 2] }).unwrap();
+
+        let mut generator = Generator(0x9e37_79b9_7f4a_7c15);
+        let mut translated = 0;
+        for _ in 0..5000 {
+            let query = generator.query(0);
+            let mut params = Vec::new();
+            let translation = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| QueryTranslator {}.query_document(&query, &mut params)));
+            let where_str = match translation {
+                Ok(Ok(where_str)) => where_str,
+                Ok(Err(_)) => continue,
+                Err(_) => panic!("the translator panicked on {}", query),
+            };
+            translated += 1;
+            if where_str.is_empty() {
+                continue;
+            }
+            assert_eq!(where_str.matches('?').count(), params.len(), "{} has unbound values in {}", query, where_str);
+
+            let sql = format!("SELECT COUNT(1) FROM [fuzz] WHERE {}", where_str);
+            // rusqlite refuses to prepare more than one statement, so an injected statement fails here.
+            let mut stmt = collection.db.prepare(&sql).unwrap_or_else(|e| panic!("{} translated to invalid SQL {}: {}", query, sql, e));
+            let _ = stmt.query_row(rusqlite::params_from_iter(params.iter()), |row| row.get::<_, i64>(0));
+        }
+        assert!(translated > 500, "only {} queries were translated", translated);
+        assert_eq!(collection.count_documents(&bson::doc! {}, &None).unwrap(), 1);
+    }
+}
@@ -35,10 +35,10 @@
 //! 
 //! Nodejs:
 //! ```javascript
-//! const Database = require('hoardbase')
-//! let db = new Database(path)
-//! let col = db.createCollection("test")
-//! let r = col.insertOne({ data: "test", age: 23, test_arr: [1, 2, 3], test_obj: { a: 1, b: 2 } })
+//! const { Database } = require('hoardbase')
+//! let db = await Database.open(path)
+//! let col = await db.createCollection("test")
+//! let r = await col.insertOne({ data: "test", age: 23, test_arr: [1, 2, 3], test_obj: { a: 1, b: 2 } })
 //! ```
 //! 
 //! ## Unsupported Mongodb Features
@@ -48,7 +48,9 @@
 //! * find_one_and_update
 //! * find_and_modify
 //! 
-//! Aggregation is also not implemented, it is not a feature I use very much. I will look into it later.
+//! Aggregation pipelines run on collections, time-series collections and views, see [`aggregation`]. The leading `$match` stages are translated
+//! to SQL, the rest of the pipeline runs in memory. `$lookup` and `$graphLookup` read the other collections and views of the database.
+//! `$out`, `$merge` and `$facet` are not implemented.
 //! 
 //! Transaction implementation is also different from mongodb. Hoardbase's transaction can't return records. It is mainly used for creating related documents.
 //! 
@@ -73,15 +75,36 @@ use std::fs::File;
 use std::io::Write;
 #[macro_use]
 extern crate slugify;
+// lets the code generated by hoardbase_derive, which refers to ::hoardbase, compile inside this crate.
+extern crate self as hoardbase;
 use base::SearchOption;
 use crate::base::CollectionTrait;
 
 
+pub mod aggregation;
+#[cfg(feature = "async")]
+pub mod async_database;
 pub mod base;
+pub mod builder;
+pub mod capped;
 pub mod collection;
+pub mod csv;
 pub mod database;
+pub mod dump;
+pub mod expression;
+pub mod handle;
+pub mod identifier;
+pub mod lookup;
+pub mod matcher;
+pub mod migration;
+pub mod pool;
 pub mod query_translator;
+pub mod schema;
+pub mod time_series;
 pub mod transaction;
+pub mod typed;
+pub mod update;
+pub mod view;
 
 
 #[cfg(test)]
@@ -92,11 +115,10 @@ mod tests {
     #[test]
     fn test_find() {
 
-        std::fs::remove_file("test_find.db").unwrap_or(());
     
         {
             println!("{:?}", env!("CARGO_PKG_VERSION"));
-            let mut config = database::DatabaseConfig::new("test_find.db");
+            let mut config = database::DatabaseConfig::in_memory();
             config.trace(true);
             config.profile(true);
         
@@ -118,14 +140,629 @@ mod tests {
 
             assert_eq!(row.data.get("kind").unwrap().as_str().unwrap(), "apples");
             assert_eq!(row.data.get("qty").unwrap().as_i64().unwrap(), 5);
+            assert_eq!(collection.find_one(&bson::doc! { "kind": "pears" }, 0).unwrap_err(), "Query returned no rows");
+
+            assert_eq!(collection.count_documents(&bson::doc! { "qty.ordered": { "$exists": true } }, &None).unwrap(), 1);
+            assert_eq!(collection.count_documents(&bson::doc! { "qty": { "$type": "string" } }, &None).unwrap(), 1);
+            assert_eq!(collection.count_documents(&bson::doc! { "kind": { "$regex": "^A", "$options": "i" } }, &None).unwrap(), 2);
+            assert_eq!(collection.count_documents(&bson::doc! { "qty": { "$bitsAllSet": 5 } }, &None).unwrap(), 2);
+            assert_eq!(collection.count_documents(&bson::doc! { "qty": { "$size": 2 } }, &None).unwrap(), 0);
+            assert!(collection.count_documents(&bson::doc! { "qty": { "$regex": "[" } }, &None).is_err());
+            let row = collection.update_one(&bson::doc! { "kind": { "$regex": "^ban" } }, &bson::doc! { "$set": { "ripe": true } }, 0, false, &None).unwrap().unwrap();
+            assert_eq!(row.data.get_str("kind").unwrap(), "bananas");
+            assert_eq!(collection.distinct("kind", &Some(bson::doc! { "qty": { "$exists": true } }), &None).unwrap(), 4);
+
 
-           
         }
     
-        std::fs::remove_file("test_find.db").unwrap();
 
 
     }
 
+    #[test]
+    fn test_update() {
+
+        {
+            let config = database::DatabaseConfig::in_memory();
+            let mut db = database::Database::open(&config).unwrap();
+
+            let ccol: base::CollectionConfig = base::CollectionConfig::default("test_update");
+            let mut collection = db.create_collection("test_update", &ccol).unwrap();
+
+            collection.insert_one(&bson::doc! { "kind": "apples", "qty": 5, "grades": [80, 85, 90] }).unwrap();
+            collection.insert_one(&bson::doc! { "kind": "bananas", "qty": 7, "grades": [95, 100, 102] }).unwrap();
+            collection.insert_one(&bson::doc! { "kind": "oranges", "qty": 9, "grades": [70] }).unwrap();
+
+            let changed = collection.update_many(&bson::doc! { "qty": { "$gt": 6 } }, &bson::doc! { "$inc": { "qty": 1 } }, 0, 0, false, &None).unwrap();
+            assert_eq!(changed, 2);
+            let changed = collection.update_many(&bson::doc! {}, &bson::doc! { "$set": { "checked": true } }, 2, 1, false, &None).unwrap();
+            assert_eq!(changed, 2);
+
+            let row = collection.update_one(&bson::doc! { "kind": "apples" }, &bson::doc! { "$inc": { "grades.$[]": 1 } }, 0, false, &None).unwrap().unwrap();
+            assert_eq!(row.data.get_array("grades").unwrap(), &vec![bson::Bson::Int32(81), bson::Bson::Int32(86), bson::Bson::Int32(91)]);
+
+            let options = base::UpdateOption::default().array_filters(vec![bson::doc! { "elem": { "$gte": 100 } }]).clone();
+            let row = collection.update_one(&bson::doc! { "kind": "bananas" }, &bson::doc! { "$set": { "grades.$[elem]": 100 } }, 0, false, &Some(options)).unwrap().unwrap();
+            assert_eq!(row.data.get_array("grades").unwrap(), &vec![bson::Bson::Int32(95), bson::Bson::Int32(100), bson::Bson::Int32(100)]);
+            assert_eq!(row.data.get_i32("qty").unwrap(), 8);
+
+            assert!(collection.update_one(&bson::doc! { "kind": "oranges" }, &bson::doc! { "$push": { "kind": 1 } }, 0, false, &None).is_err());
+
+            let row = collection.update_one(&bson::doc! { "kind": "pears" }, &bson::doc! { "$setOnInsert": { "qty": 1 } }, 0, true, &None).unwrap().unwrap();
+            assert_eq!(row.data, bson::doc! { "kind": "pears", "qty": 1 });
+
+            let pipeline = vec![bson::doc! { "$set": { "total": { "$multiply": ["$qty", 2] }, "label": { "$concat": ["$kind", "!"] } } }];
+            let row = collection.update_one_pipeline(&bson::doc! { "kind": "pears" }, &pipeline, 0, false).unwrap().unwrap();
+            assert_eq!(row.data, bson::doc! { "kind": "pears", "qty": 1, "total": 2, "label": "pears!" });
+
+            let changed = collection.update_many_pipeline(&bson::doc! {}, &vec![bson::doc! { "$unset": "grades" }], 0, 0, false).unwrap();
+            assert_eq!(changed, 4);
+            let row = collection.find_one(&bson::doc! { "kind": "bananas" }, 0).unwrap();
+            assert!(row.data.get("grades").is_none());
+        }
+
+    }
+
+    #[test]
+    fn test_explain() {
+
+        {
+            let config = database::DatabaseConfig::in_memory();
+            let mut db = database::Database::open(&config).unwrap();
+
+            let ccol: base::CollectionConfig = base::CollectionConfig::default("test_explain");
+            let mut collection = db.create_collection("test_explain", &ccol).unwrap();
+            collection.create_index(&bson::doc! { "age": 1 }, false).unwrap();
+
+            for age in 0..20 {
+                collection.insert_one(&bson::doc! { "name": format!("person {}", age), "age": age }).unwrap();
+            }
+
+            let plan = collection.explain(&bson::doc! { "age": { "$gt": 15 } }, &None, base::ExplainVerbosity::QueryPlanner).unwrap();
+            assert!(plan.sql.starts_with("SELECT * FROM [test_explain]"));
+            assert_eq!(plan.params.len(), 1);
+            assert_eq!(plan.indexes, vec!["test_explain_age".to_string()]);
+            assert!(plan.plan[0].detail.contains("USING INDEX test_explain_age"));
+            assert!(plan.stats.is_none());
+
+            let plan = collection.explain(&bson::doc! { "name": "person 3" }, &None, base::ExplainVerbosity::ExecutionStats).unwrap();
+            assert!(plan.indexes.is_empty());
+            assert!(plan.plan[0].detail.starts_with("SCAN"));
+            let stats = plan.stats.unwrap();
+            assert_eq!(stats.rows_returned, 1);
+            assert!(stats.rows_scanned > 0);
+        }
+
+    }
+
+    #[test]
+    fn test_storage() {
+        let mut db = database::Database::open(&database::DatabaseConfig::in_memory()).unwrap();
+        assert_eq!(db.path(), None);
+        db.create_collection("test_storage", &base::CollectionConfig::default("test_storage")).unwrap();
+        // every private in-memory database is a new one.
+        assert!(database::Database::open(&database::DatabaseConfig::in_memory()).unwrap().list_collections().is_empty());
+
+        let config = database::DatabaseConfig::shared_memory("test_storage");
+        let mut first = database::Database::open(&config).unwrap();
+        first.create_collection("test_storage", &base::CollectionConfig::default("test_storage")).unwrap().insert_one(&bson::doc! { "a": 1 }).unwrap();
+        let mut second = database::Database::open(&config).unwrap();
+        assert_eq!(second.collection("test_storage").unwrap().count_documents(&bson::doc! {}, &None).unwrap(), 1);
+
+        let temporary = database::DatabaseConfig::temporary();
+        assert_ne!(temporary.path, database::DatabaseConfig::temporary().path);
+        let db = database::Database::open(&temporary).unwrap();
+        let path = db.path().unwrap();
+        assert!(std::path::Path::new(&path).exists());
+        drop(db);
+        assert!(!std::path::Path::new(&path).exists());
+    }
+
+    #[test]
+    fn test_backup() {
+        let mut db = database::Database::open(&database::DatabaseConfig::in_memory()).unwrap();
+        let documents: Vec<bson::Document> = (0..2000).map(|i| bson::doc! { "i": i, "text": "x".repeat(1000) }).collect();
+        db.create_collection("test_backup", &base::CollectionConfig::default("test_backup")).unwrap().insert_many(&documents).unwrap();
+
+        let backup = database::DatabaseConfig::temporary();
+        let mut steps = Vec::new();
+        db.backup_to(&backup.path, |progress| steps.push(progress)).unwrap();
+        assert!(steps.len() > 1);
+        assert_eq!(steps.last().unwrap().fraction(), 1.0);
+
+        let mut copy = database::Database::open(&backup).unwrap();
+        assert_eq!(copy.collection("test_backup").unwrap().count_documents(&bson::doc! {}, &None).unwrap(), 2000);
+
+        let snapshot = database::DatabaseConfig::temporary();
+        db.collection("test_backup").unwrap().delete_many(&bson::doc! { "i": { "$gte": 1000 } }).unwrap();
+        db.snapshot_to(&snapshot.path).unwrap();
+        assert!(db.snapshot_to(&snapshot.path).is_err());
+        assert!(std::fs::metadata(&snapshot.path).unwrap().len() < std::fs::metadata(&backup.path).unwrap().len());
+
+        // restoring replaces both the documents and the collection list.
+        let mut restored = database::Database::open(&database::DatabaseConfig::in_memory()).unwrap();
+        restored.create_collection("other", &base::CollectionConfig::default("other")).unwrap();
+        restored.restore_from(&snapshot.path, |_| {}).unwrap();
+        assert!(restored.collection("other").is_err());
+        assert_eq!(restored.collection("test_backup").unwrap().count_documents(&bson::doc! {}, &None).unwrap(), 1000);
+
+        let handle = handle::DatabaseHandle::open(&database::DatabaseConfig::temporary()).unwrap();
+        handle.restore_from(&backup.path, |_| {}).unwrap();
+        assert_eq!(handle.collection("test_backup").unwrap().count_documents(&bson::doc! {}, &None).unwrap(), 2000);
+        let handle_backup = database::DatabaseConfig::temporary();
+        handle.backup_to(&handle_backup.path, |_| {}).unwrap();
+        assert_eq!(database::Database::open(&handle_backup).unwrap().list_collections().len(), 1);
+        std::fs::remove_file(&snapshot.path).unwrap();
+    }
+
+    #[test]
+    fn test_dump() {
+        use dump::{DumpFormat, ExtendedJsonMode};
+
+        let mut db = database::Database::open(&database::DatabaseConfig::in_memory()).unwrap();
+        let mut source = db.create_collection("source", &base::CollectionConfig::default("source")).unwrap();
+        source.create_index(&bson::doc! { "kind": -1 }, true).unwrap();
+        let id = bson::oid::ObjectId::new();
+        source.insert_one(&bson::doc! { "_id": id, "kind": "apples", "qty": 5, "big": 1i64 << 40, "price": 1.5 }).unwrap();
+        source.insert_one(&bson::doc! { "kind": "pears", "when": bson::DateTime::from_millis(0) }).unwrap();
+
+        let mut plain = Vec::new();
+        assert_eq!(source.export(&mut plain, DumpFormat::JsonLines).unwrap(), 2);
+        let plain = String::from_utf8(plain).unwrap();
+        assert!(plain.contains(&format!("\"_id\":\"{}\"", id.to_hex())));
+        assert!(plain.contains("\"when\":\"1970-01-01T00:00:00Z\""));
+
+        let mut canonical = Vec::new();
+        source.export(&mut canonical, DumpFormat::ExtendedJson(ExtendedJsonMode::Canonical)).unwrap();
+        assert!(String::from_utf8(canonical.clone()).unwrap().contains("{\"$numberInt\":\"5\"}"));
+        let mut relaxed = Vec::new();
+        source.export(&mut relaxed, DumpFormat::ExtendedJson(ExtendedJsonMode::Relaxed)).unwrap();
+        let mut dump = Vec::new();
+        source.export(&mut dump, DumpFormat::Bson).unwrap();
+        let mut metadata = Vec::new();
+        source.export_metadata(&mut metadata).unwrap();
+
+        let originals: Vec<bson::Document> = {
+            let mut documents = Vec::new();
+            source.find(&bson::doc! {}, &None, &mut |record| {
+                documents.push(record.data.clone());
+                Ok(())
+            }).unwrap();
+            documents
+        };
+
+        for (name, data, format) in [("canonical", &canonical, DumpFormat::ExtendedJson(ExtendedJsonMode::Canonical)), ("relaxed", &relaxed, DumpFormat::JsonLines), ("dump", &dump, DumpFormat::Bson)] {
+            let mut target = db.create_collection(name, &base::CollectionConfig::default(name)).unwrap();
+            let report = target.import(&mut data.as_slice(), format).unwrap();
+            assert_eq!(report.inserted, 2);
+            assert!(report.errors.is_empty());
+            let mut imported = Vec::new();
+            target.find(&bson::doc! {}, &None, &mut |record| {
+                imported.push(record.data.clone());
+                Ok(())
+            }).unwrap();
+            assert_eq!(imported, originals);
+        }
+
+        // bad lines are reported and skipped.
+        let mut other = database::Database::open(&database::DatabaseConfig::in_memory()).unwrap();
+        let mut target = other.create_collection("bad", &base::CollectionConfig::default("bad")).unwrap();
+        let report = target.import(&mut "{\"a\": 1}\nnot json\n\n[1, 2]\n{\"a\": {\"$numberInt\": \"2\"}}\n".as_bytes(), DumpFormat::JsonLines).unwrap();
+        assert_eq!(report.inserted, 2);
+        assert_eq!(report.errors.iter().map(|e| e.line).collect::<Vec<_>>(), vec![2, 4]);
+        assert_eq!(target.count_documents(&bson::doc! { "a": 2 }, &None).unwrap(), 1);
+
+        let mut truncated = dump.clone();
+        truncated.truncate(dump.len() - 3);
+        let report = target.import(&mut truncated.as_slice(), DumpFormat::Bson).unwrap();
+        assert_eq!(report.inserted, 1);
+        assert_eq!(report.errors[0].line, 2);
+
+        // a corrupt length prefix stops the import before anything is allocated for it.
+        let mut corrupt = i32::MAX.to_le_bytes().to_vec();
+        corrupt.extend_from_slice(&[0; 16]);
+        let report = target.import(&mut corrupt.as_slice(), DumpFormat::Bson).unwrap();
+        assert_eq!(report.inserted, 0);
+        assert_eq!(report.errors[0].message, format!("invalid document length {}", i32::MAX));
+
+        target.import_metadata(&mut metadata.as_slice()).unwrap();
+        let indexes = target.get_indexes().unwrap();
+        assert!(indexes.iter().any(|index| index.is_unique && index.name == "bad_kind"));
+    }
+
+    #[test]
+    fn test_csv() {
+        use crate::csv::{ColumnType, CsvOption};
+
+        let mut db = database::Database::open(&database::DatabaseConfig::in_memory()).unwrap();
+        let mut people = db.create_collection("people", &base::CollectionConfig::default("people")).unwrap();
+        let input = "name.first,name.last,age,joined,note,zip\r\n\
+                     Ada,Lovelace,36,2021-12-01T10:00:00Z,\"says \"\"hi\"\", twice\",01234\r\n\
+                     Alan,Turing,forty-one,2021-12-02T10:00:00Z,\"two\nlines\",\n\
+                     Grace,Hopper,85,,,\n";
+        let mut csv = CsvOption::default();
+        csv.typed_column("age", "age", ColumnType::Int32).typed_column("joined", "joined", ColumnType::DateTime).typed_column("zip", "address.zip", ColumnType::String);
+        let report = people.import_csv(&mut input.as_bytes(), &csv).unwrap();
+        assert_eq!(report.inserted, 2);
+        assert_eq!(report.errors.len(), 1);
+        // the rejected row starts on line 3 and spans two lines.
+        assert_eq!(report.errors[0].line, 3);
+        assert!(report.errors[0].message.starts_with("age:"));
+
+        let ada = people.find_one(&bson::doc! { "name.first": "Ada" }, 0).unwrap().data;
+        assert_eq!(ada.get_document("name").unwrap().get_str("last").unwrap(), "Lovelace");
+        assert_eq!(ada.get_i32("age").unwrap(), 36);
+        assert_eq!(ada.get_datetime("joined").unwrap().timestamp_millis(), 1638352800000);
+        assert_eq!(ada.get_str("note").unwrap(), "says \"hi\", twice");
+        assert_eq!(ada.get_document("address").unwrap().get_str("zip").unwrap(), "01234");
+        let grace = people.find_one(&bson::doc! { "name.first": "Grace" }, 0).unwrap().data;
+        assert!(!grace.contains_key("joined"));
+        assert_eq!(grace.get_document("address").unwrap().get_str("zip").unwrap(), "");
+
+        let mut columns = CsvOption::tsv();
+        columns.column("name.last").column("age").column("note").column("address");
+        let mut output = Vec::new();
+        assert_eq!(people.export_csv(&mut output, &bson::doc! { "age": { "$gt": 30 } }, &None, &columns).unwrap(), 2);
+        let output = String::from_utf8(output).unwrap();
+        let lines: Vec<&str> = output.lines().collect();
+        assert_eq!(lines[0], "name.last\tage\tnote\taddress");
+        assert_eq!(lines[1], "Lovelace\t36\t\"says \"\"hi\"\", twice\"\t\"{\"\"zip\"\":\"\"01234\"\"}\"");
+
+        // without a header row, the columns are positional and inferred.
+        let mut positional = CsvOption::default();
+        positional.has_header(false).column("a").column("b.c");
+        let report = people.import_csv(&mut "1,true\n2.5,x,extra\n".as_bytes(), &positional).unwrap();
+        assert_eq!(report.inserted, 1);
+        assert_eq!(report.errors[0].line, 2);
+        let row = people.find_one(&bson::doc! { "a": 1 }, 0).unwrap().data;
+        assert_eq!(row.get_document("b").unwrap().get_bool("c").unwrap(), true);
+    }
+
+    #[test]
+    fn test_database_config() {
+        {
+            let mut config = database::DatabaseConfig::temporary();
+            config.create_if_missing(false);
+            assert!(database::Database::open(&config).is_err());
+
+            config
+                .create_if_missing(true)
+                .journal_mode(database::JournalMode::Wal)
+                .synchronous(database::Synchronous::Normal)
+                .page_size(8192)
+                .cache_size(-4096)
+                .mmap_size(1 << 20)
+                .busy_timeout(std::time::Duration::from_millis(250));
+            let mut db = database::Database::open(&config).unwrap();
+            db.create_collection("test_config", &base::CollectionConfig::default("test_config")).unwrap().insert_one(&bson::doc! { "a": 1 }).unwrap();
+
+            let check = rusqlite::Connection::open(db.path().unwrap()).unwrap();
+            assert_eq!(check.pragma_query_value(None, "journal_mode", |row| row.get::<_, String>(0)).unwrap(), "wal");
+            assert_eq!(check.pragma_query_value(None, "page_size", |row| row.get::<_, i64>(0)).unwrap(), 8192);
+
+            let mut read_only = database::DatabaseConfig::new(&db.path().unwrap());
+            read_only.read_only(true);
+            let mut reader = database::Database::open(&read_only).unwrap();
+            let mut collection = reader.collection("test_config").unwrap();
+            assert_eq!(collection.count_documents(&bson::doc! {}, &None).unwrap(), 1);
+            assert!(collection.insert_one(&bson::doc! { "a": 2 }).is_err());
+            assert_eq!(reader.create_collection("test_new", &base::CollectionConfig::default("test_new")).err(), Some("The database is opened read-only"));
+            assert!(reader.create_view("test_view", "test_config", &vec![]).is_err());
+            assert!(reader.collection("test_new").is_err());
+        }
+    }
+
+    #[test]
+    fn test_catalog() {
+        // a plain file, so that it survives closing the database.
+        let path = database::DatabaseConfig::temporary().path;
+        let mut db = database::Database::open(&database::DatabaseConfig::new(&path)).unwrap();
+
+        let mut ccol = base::CollectionConfig::default("ignored");
+        ccol.hash_unique(true).log_last_modified(false);
+        let mut people = db.create_collection("team members", &ccol).unwrap();
+        assert_eq!(people.config.name, "team members");
+        assert_eq!(people.config.table_name, "team_members");
+        people.create_index(&bson::doc! { "age": 1 }, false).unwrap();
+        people.insert_many(&vec![bson::doc! { "name": "Ada", "age": 36 }, bson::doc! { "name": "Alan", "age": 41 }]).unwrap();
+
+        // table names are unique, and hoardbase's own names are reserved.
+        assert_eq!(db.create_collection("team_members", &base::CollectionConfig::default("team_members")).unwrap().config.table_name, "team_members_2");
+        assert_eq!(db.create_collection("_hoardbase", &base::CollectionConfig::default("_hoardbase")).unwrap().config.table_name, "hoardbase");
+        assert_eq!(db.create_collection("2021", &base::CollectionConfig::default("2021")).unwrap().config.table_name, "c_2021");
+
+        let stats = db.collection_stats("team members").unwrap();
+        assert_eq!(stats.count, 2);
+        assert!(stats.size > 0 && stats.storage_size > 0);
+        assert_eq!(stats.index_sizes.iter().map(|(name, _)| name.as_str()).collect::<Vec<_>>(), vec!["team_members__hash", "team_members_age"]);
+        assert_eq!(stats.total_index_size, stats.index_sizes.iter().map(|(_, size)| size).sum::<i64>());
+
+        assert!(db.rename_collection("team members", "team_members").is_err());
+        db.rename_collection("team members", "staff").unwrap();
+        assert!(db.collection("team members").is_err());
+        let mut staff = db.collection("staff").unwrap();
+        assert_eq!(staff.config.table_name, "staff");
+        let mut names: Vec<String> = staff.get_indexes().unwrap().into_iter().map(|index| index.name).collect();
+        names.sort();
+        assert_eq!(names, vec!["staff__hash", "staff_age"]);
+        assert!(staff.insert_one(&bson::doc! { "name": "Ada", "age": 36 }).is_err());
+        assert!(staff.drop_index("team_members_2__hash").is_err());
+        staff.drop_index("staff_age").unwrap();
+
+        db.drop_collection("team_members").unwrap();
+        drop(db);
+
+        let mut db = database::Database::open(&database::DatabaseConfig::new(&path)).unwrap();
+        let mut names: Vec<String> = db.list_collections().into_iter().map(|(name, _)| name).collect();
+        names.sort();
+        assert_eq!(names, vec!["2021", "_hoardbase", "staff"]);
+        let mut staff = db.collection("staff").unwrap();
+        assert!(staff.config.should_hash_unique);
+        assert!(!staff.config.should_log_last_modified);
+        assert_eq!(staff.count_documents(&bson::doc! {}, &None).unwrap(), 2);
+        drop(db);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_capped() {
+        let path = database::DatabaseConfig::temporary().path;
+        let mut db = database::Database::open(&database::DatabaseConfig::new(&path)).unwrap();
+
+        let mut ccol = base::CollectionConfig::default("log");
+        ccol.capped(3, 0);
+        let mut log = db.create_collection("log", &ccol).unwrap();
+        log.create_index(&bson::doc! { "i": -1 }, false).unwrap();
+        for i in 0..5 {
+            log.insert_one(&bson::doc! { "i": i }).unwrap();
+        }
+        assert_eq!(log.count_documents(&bson::doc! {}, &None).unwrap(), 3);
+        // natural order, even where the index would return another one.
+        let mut order = Vec::new();
+        log.find(&bson::doc! { "i": { "$gte": 0 } }, &None, &mut |r| {
+            order.push(r.data.get_i32("i").unwrap());
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(order, vec![2, 3, 4]);
+
+        let mut cursor = capped::TailableCursor::new(&bson::doc! { "i": { "$ne": 3 } });
+        let batch = log.tail(&mut cursor).unwrap();
+        assert_eq!(batch.iter().map(|r| r.data.get_i32("i").unwrap()).collect::<Vec<_>>(), vec![2, 4]);
+        assert!(log.tail(&mut cursor).unwrap().is_empty());
+        log.insert_one(&bson::doc! { "i": 5 }).unwrap();
+        assert_eq!(log.tail(&mut cursor).unwrap()[0].data.get_i32("i").unwrap(), 5);
+
+        let mut ccol = base::CollectionConfig::default("small");
+        ccol.capped(0, 200);
+        let mut small = db.create_collection("small", &ccol).unwrap();
+        for i in 0..20 {
+            small.insert_one(&bson::doc! { "i": i, "text": "0123456789" }).unwrap();
+        }
+        let count = small.count_documents(&bson::doc! {}, &None).unwrap();
+        assert!(count > 0 && count < 20);
+        assert!(db.collection_stats("small").unwrap().size <= 200);
+        let mut small = db.collection("small").unwrap();
+        assert!(small.insert_one(&bson::doc! { "text": "x".repeat(300) }).is_err());
+        assert_eq!(small.count_documents(&bson::doc! {}, &None).unwrap(), count);
+
+        let mut plain = db.create_collection("plain", &base::CollectionConfig::default("plain")).unwrap();
+        assert!(plain.tail(&mut capped::TailableCursor::new(&bson::doc! {})).is_err());
+
+        // the limits survive a rename and reopening the database.
+        db.rename_collection("log", "events").unwrap();
+        drop(db);
+        let mut db = database::Database::open(&database::DatabaseConfig::new(&path)).unwrap();
+        let mut events = db.collection("events").unwrap();
+        assert_eq!((events.config.capped_max_documents, events.config.capped_max_bytes), (3, 0));
+        for i in 10..15 {
+            events.insert_one(&bson::doc! { "i": i }).unwrap();
+        }
+        assert_eq!(events.count_documents(&bson::doc! {}, &None).unwrap(), 3);
+        drop(db);
+
+        let db = handle::DatabaseHandle::open(&database::DatabaseConfig::new(&path)).unwrap();
+        let events = db.collection("events").unwrap();
+        let mut tail = events.follow(capped::TailableCursor::new(&bson::doc! { "i": { "$gte": 100 } }), std::time::Duration::from_millis(5));
+        assert!(tail.next_timeout(std::time::Duration::from_millis(20)).unwrap().is_none());
+        let writer = {
+            let events = events.clone();
+            std::thread::spawn(move || {
+                for i in 99..102 {
+                    events.insert_one(&bson::doc! { "i": i }).unwrap();
+                }
+            })
+        };
+        let received: Vec<i32> = tail.by_ref().take(2).map(|r| r.unwrap().data.get_i32("i").unwrap()).collect();
+        assert_eq!(received, vec![100, 101]);
+        writer.join().unwrap();
+        drop((tail, events, db));
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_view() {
+        let path = database::DatabaseConfig::temporary().path;
+        let mut db = database::Database::open(&database::DatabaseConfig::new(&path)).unwrap();
+        let mut fruits = db.create_collection("fruits", &base::CollectionConfig::default("fruits")).unwrap();
+        fruits.create_index(&bson::doc! { "kind": 1 }, false).unwrap();
+        for (kind, qty, price) in [("apples", 5, 2), ("bananas", 0, 1), ("cherries", 12, 3), ("apples", 3, 2)] {
+            fruits.insert_one(&bson::doc! { "kind": kind, "qty": qty, "price": price }).unwrap();
+        }
+
+        let pipeline = vec![bson::doc! { "$match": { "qty": { "$gt": 0 } } }, bson::doc! { "$set": { "total": { "$multiply": ["$qty", "$price"] } } }, bson::doc! { "$unset": "_id" }];
+        assert!(db.create_view("stock", "missing", &pipeline).is_err());
+        assert!(db.create_view("stock", "fruits", &vec![bson::doc! { "$frobnicate": {} }]).is_err());
+        let mut stock = db.create_view("stock", "fruits", &pipeline).unwrap();
+        assert_eq!(stock.count_documents(&bson::doc! {}, &None).unwrap(), 3);
+        // the filter on kind goes before $set, next to the view's $match, and both are translated to SQL.
+        assert_eq!(stock.explain(&bson::doc! { "kind": "apples" })[1], bson::doc! { "$match": { "kind": "apples" } });
+        assert_eq!(stock.explain(&bson::doc! { "total": 6 })[2], bson::doc! { "$match": { "total": 6 } });
+        let mut totals = Vec::new();
+        stock
+            .find(&bson::doc! { "kind": "apples" }, &None, &mut |doc| {
+                totals.push(doc.get_i32("total").unwrap());
+                Ok(())
+            })
+            .unwrap();
+        assert_eq!(totals, vec![10, 6]);
+        assert_eq!(stock.count_documents(&bson::doc! { "total": { "$gte": 10 } }, &None).unwrap(), 2);
+        assert_eq!(stock.count_documents(&bson::doc! {}, &Some(*base::SearchOption::default().skip(1).limit(1))).unwrap(), 1);
+        // $type is matched by the matcher inside the query on the source.
+        assert_eq!(stock.count_documents(&bson::doc! { "qty": { "$type": "int" } }, &None).unwrap(), 3);
+        assert_eq!(stock.find_one(&bson::doc! { "kind": "cherries" }).unwrap().unwrap().get_i32("total").unwrap(), 36);
+
+        // views of views, and views that group.
+        db.create_view("apples", "stock", &vec![bson::doc! { "$match": { "kind": "apples" } }]).unwrap();
+        db.create_view("by_kind", "stock", &vec![bson::doc! { "$group": { "_id": "$kind", "qty": { "$sum": "$qty" } } }, bson::doc! { "$sort": { "_id": 1 } }]).unwrap();
+        assert_eq!(db.view("apples").unwrap().count_documents(&bson::doc! { "qty": 3 }, &None).unwrap(), 1);
+        let by_kind = db.view("by_kind").unwrap().aggregate(&[bson::doc! { "$match": { "_id": { "$ne": "cherries" } } }]).unwrap();
+        assert_eq!(by_kind, vec![bson::doc! { "_id": "apples", "qty": 8 }]);
+
+        // views are read-only.
+        assert!(db.collection("stock").is_err());
+        assert!(db.create_collection("stock", &base::CollectionConfig::default("stock")).is_err());
+        assert!(db.collection_stats("stock").is_err());
+        assert!(db.transaction(|tx| tx.collection("stock").map(|_| ()).map_err(|_| "read-only")).is_err());
+        assert!(db.view("fruits").is_err());
+        let mut names: Vec<String> = db.list_collections().into_iter().map(|(name, _)| name).collect();
+        names.sort();
+        assert_eq!(names, vec!["apples", "by_kind", "fruits", "stock"]);
+
+        // a view over a time-series collection.
+        let mut readings = db.create_time_series("readings", &time_series::TimeSeriesConfig::default("time")).unwrap();
+        readings.insert_many(&(0..10).map(|i| bson::doc! { "time": bson::DateTime::from_millis(i * 1000), "value": i }).collect()).unwrap();
+        db.create_view("high", "readings", &vec![bson::doc! { "$match": { "value": { "$gte": 5 } } }, bson::doc! { "$project": { "_id": 0, "value": 1 } }]).unwrap();
+        assert_eq!(db.view("high").unwrap().count_documents(&bson::doc! { "value": { "$lt": 8 } }, &None).unwrap(), 3);
+
+        db.rename_collection("stock", "in_stock").unwrap();
+        db.drop_collection("high").unwrap();
+        drop(db);
+
+        let mut db = database::Database::open(&database::DatabaseConfig::new(&path)).unwrap();
+        let mut stock = db.view("in_stock").unwrap();
+        assert_eq!(stock.definition().view_on, "fruits");
+        assert_eq!(stock.count_documents(&bson::doc! { "kind": "apples" }, &None).unwrap(), 2);
+        assert!(db.view("high").is_err());
+        // the view refers to its source by name, so it is empty without it.
+        db.drop_collection("fruits").unwrap();
+        assert_eq!(db.view("in_stock").unwrap().count_documents(&bson::doc! {}, &None).unwrap(), 0);
+        drop(db);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_handle() {
+        fn assert_send_sync<T: Send + Sync>() {}
+        assert_send_sync::<handle::DatabaseHandle>();
+        assert_send_sync::<handle::CollectionHandle>();
+
+        {
+            let mut config = database::DatabaseConfig::temporary();
+            config.reader_pool_size(2);
+            let db = handle::DatabaseHandle::open(&config).unwrap();
+            let collection = db.create_collection("test_handle", &base::CollectionConfig::default("test_handle")).unwrap();
+
+            let writers: Vec<_> = (0..4)
+                .map(|t| {
+                    let collection = collection.clone();
+                    std::thread::spawn(move || {
+                        for i in 0..25 {
+                            collection.insert_one(&bson::doc! { "t": t, "i": i }).unwrap();
+                        }
+                    })
+                })
+                .collect();
+            // More readers than pooled connections, so some of them have to wait for a connection.
+            let readers: Vec<_> = (0..6)
+                .map(|_| {
+                    let collection = collection.clone();
+                    std::thread::spawn(move || {
+                        for _ in 0..10 {
+                            let count = collection.count_documents(&bson::doc! {}, &None).unwrap();
+                            assert!((0..=100).contains(&count));
+                        }
+                    })
+                })
+                .collect();
+            for thread in writers.into_iter().chain(readers) {
+                thread.join().unwrap();
+            }
+
+            assert_eq!(collection.count_documents(&bson::doc! {}, &None).unwrap(), 100);
+            assert_eq!(collection.count_documents(&bson::doc! { "t": 2 }, &None).unwrap(), 25);
+
+            // A failing transaction is rolled back.
+            let result = db.transaction(|tx| {
+                let mut collection = tx.collection("test_handle").unwrap();
+                collection.delete_many(&bson::doc! {}).unwrap();
+                Err("abort")
+            });
+            assert_eq!(result, Err("abort".to_string()));
+            assert_eq!(collection.count_documents(&bson::doc! {}, &None).unwrap(), 100);
+
+            db.rename_collection("test_handle", "renamed").unwrap();
+            assert!(collection.find_one(&bson::doc! {}, 0).is_err());
+            assert!(db.collection("renamed").is_ok());
+        }
+    }
+
+    #[cfg(feature = "async")]
+    #[test]
+    fn test_async() {
+        use futures::StreamExt;
+
+
+        futures::executor::block_on(async {
+            let config = database::DatabaseConfig::in_memory();
+            let db = async_database::AsyncDatabase::open(&config).await.unwrap();
+
+            let collection = db.create_collection("test_async", &base::CollectionConfig::default("test_async")).await.unwrap();
+            let documents: Vec<bson::Document> = (0..100).map(|i| bson::doc! { "i": i }).collect();
+            collection.insert_many(&documents).await.unwrap();
+
+            let handles: Vec<_> = (0..4)
+                .map(|t| {
+                    let collection = collection.clone();
+                    std::thread::spawn(move || futures::executor::block_on(collection.update_many(&bson::doc! { "i": { "$lt": (t + 1) * 10 } }, &bson::doc! { "$inc": { "hits": 1 } }, 0, 0, false, &None)).unwrap())
+                })
+                .collect();
+            for handle in handles {
+                handle.join().unwrap();
+            }
+
+            assert_eq!(collection.count_documents(&bson::doc! { "hits": { "$gte": 1 } }, &None).await.unwrap(), 40);
+            assert_eq!(collection.find_one(&bson::doc! { "i": 5 }, 0).await.unwrap().data.get_i32("hits").unwrap(), 4);
+
+            let records: Vec<_> = collection.find(&bson::doc! { "i": { "$gte": 90 } }, &None).collect().await;
+            assert_eq!(records.len(), 10);
+            assert!(records.iter().all(|r| r.is_ok()));
+
+            // Dropping a stream early stops the database thread from producing more records.
+            let first = collection.find(&bson::doc! {}, &None).next().await.unwrap().unwrap();
+            assert_eq!(first.data.get_i32("i").unwrap(), 0);
+
+            // Other operations can be awaited while a stream is consumed, across more than one batch.
+            let mut records = collection.find(&bson::doc! {}, &None);
+            let mut seen = 0;
+            while let Some(record) = records.next().await {
+                let record = record.unwrap();
+                collection.update_one(&bson::doc! { "i": record.data.get_i32("i").unwrap() }, &bson::doc! { "$set": { "seen": true } }, 0, false, &None).await.unwrap();
+                seen += 1;
+            }
+            assert_eq!(seen, 100);
+            assert_eq!(collection.count_documents(&bson::doc! { "seen": true }, &None).await.unwrap(), 100);
+
+            let limited: Vec<_> = collection.find(&bson::doc! {}, &Some(*base::SearchOption::default().limit(70).skip(20))).collect().await;
+            assert_eq!(limited.len(), 70);
+            assert_eq!(limited[0].as_ref().unwrap().data.get_i32("i").unwrap(), 20);
+
+            assert!(db.collection("missing").await.is_err());
+            assert!(collection.update_one(&bson::doc! { "i": 1 }, &bson::doc! { "$bogus": { "i": 1 } }, 0, false, &None).await.is_err());
+            assert_eq!(db.list_collections().await.unwrap().len(), 1);
+        });
+
+    }
+
 }
 
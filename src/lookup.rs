@@ -0,0 +1,300 @@
+//! Joins across collections.
+//!
+//! The `$lookup` and `$graphLookup` stages of an aggregation pipeline read other collections of the database, which can be collections,
+//! time-series collections or views. They find the collections in the catalog through the connection, so pipelines with lookups run the same
+//! on a [`crate::database::Database`], in a transaction, or on a [`crate::handle::DatabaseHandle`].
+//!
+//! Each lookup of a document is a query on the other collection, which uses its indexes. An equality `$lookup` between two collections that
+//! comes right after the leading `$match` stages of [`Collection::aggregate()`] is compiled to a single SQL join on `json_field` expressions
+//! instead, so that sqlite can use the index on the foreign field:
+//!
+//! ```rust
+//! use hoardbase::base::{CollectionConfig, CollectionTrait};
+//! use hoardbase::database::{Database, DatabaseConfig};
+//!
+//! let mut db = Database::open(&DatabaseConfig::in_memory()).unwrap();
+//! let mut authors = db.create_collection("authors", &CollectionConfig::default("authors")).unwrap();
+//! authors.insert_one(&bson::doc! { "name": "Ada", "country": "uk" }).unwrap();
+//! let mut books = db.create_collection("books", &CollectionConfig::default("books")).unwrap();
+//! books.create_index(&bson::doc! { "author": 1 }, false).unwrap();
+//! books.insert_one(&bson::doc! { "title": "Notes", "author": "Ada" }).unwrap();
+//!
+//! let mut authors = db.collection("authors").unwrap();
+//! let joined = authors.aggregate(&[bson::doc! { "$lookup": { "from": "books", "localField": "name", "foreignField": "author", "as": "books" } }]).unwrap();
+//! assert_eq!(joined[0].get_array("books").unwrap().len(), 1);
+//! ```
+
+use rusqlite::params_from_iter;
+
+use crate::aggregation::{self, Lookup, Resolver};
+use crate::base::*;
+use crate::collection::Collection;
+use crate::database;
+use crate::identifier::field_literal;
+use crate::query_translator::QueryTranslator;
+use crate::time_series::TimeSeries;
+use crate::transaction::TransactionCollection;
+use crate::view::View;
+
+/// The maximum number of lookups that can be nested, e.g. by a view that looks up a view that looks up another one.
+pub const MAX_LOOKUP_DEPTH: usize = 20;
+
+/// A [`Resolver`] that finds the collections in the catalog of a connection.
+pub(crate) struct CatalogResolver<'c> {
+    db: &'c rusqlite::Connection,
+    depth: usize,
+}
+
+impl<'c> CatalogResolver<'c> {
+    pub(crate) fn new(db: &'c rusqlite::Connection) -> Self {
+        CatalogResolver { db, depth: 0 }
+    }
+}
+
+impl<'c> Resolver for CatalogResolver<'c> {
+    fn aggregate(&self, collection: &str, pipeline: &[bson::Document]) -> Result<Vec<bson::Document>, String> {
+        if self.depth == MAX_LOOKUP_DEPTH {
+            return Err(format!("Lookups nested too deep, or a lookup cycle. The maximum depth is {}", MAX_LOOKUP_DEPTH));
+        }
+        let config = match database::collection_config(self.db, collection)? {
+            Some(config) => config,
+            None => return Ok(Vec::new()),
+        };
+        let nested = CatalogResolver { db: self.db, depth: self.depth + 1 };
+        if config.view.is_some() {
+            let db = self.db;
+            let mut view = View::new(collection, &config, db, &|name| database::collection_config(db, name).ok().flatten())?;
+            view.aggregate_with(pipeline, &nested)
+        } else if config.time_series.is_some() {
+            TimeSeries { name: config.name.clone(), config, db: self.db }.aggregate_with(pipeline, &nested)
+        } else {
+            let (query, rest) = split_match(pipeline);
+            aggregate_collection(self.db, &config, &query, rest, &nested)
+        }
+    }
+}
+
+/// Splits the leading `$match` stages off a pipeline, and combines their queries.
+pub(crate) fn split_match(pipeline: &[bson::Document]) -> (bson::Document, &[bson::Document]) {
+    let leading = pipeline.iter().take_while(|stage| stage.len() == 1 && matches!(stage.get("$match"), Some(bson::Bson::Document(_)))).count();
+    let mut queries: Vec<bson::Bson> = pipeline[..leading].iter().map(|stage| stage.get("$match").unwrap().clone()).collect();
+    let query = match queries.len() {
+        0 => bson::Document::new(),
+        1 => queries.pop().unwrap().as_document().unwrap().clone(),
+        _ => bson::doc! { "$and": queries },
+    };
+    (query, &pipeline[leading..])
+}
+
+/// Runs `pipeline` on the documents of a collection that match `query`.
+pub(crate) fn aggregate_collection(db: &rusqlite::Connection, config: &CollectionConfig, query: &bson::Document, pipeline: &[bson::Document], resolver: &dyn Resolver) -> Result<Vec<bson::Document>, String> {
+    if let Some((bson::Bson::Document(spec), rest)) = pipeline.split_first().map(|(first, rest)| (first.get("$lookup").unwrap_or(&bson::Bson::Null), rest)) {
+        if let Some(joined) = join(db, config, query, &Lookup::parse(spec)?, resolver)? {
+            return aggregation::aggregate_with(joined, rest, resolver);
+        }
+    }
+    let mut collection = Collection { config: config.clone(), name: config.name.clone(), db, table_name: config.table_name.clone() };
+    aggregation::aggregate_with(collection.find_documents(query, &None)?, pipeline, resolver)
+}
+
+/// The SQL of a join. The local rows are selected in a subquery, so that the columns of the translated query aren't ambiguous.
+fn join_sql(table_name: &str, foreign_table_name: &str, local_field: &str, foreign_field: &str, where_str: &str) -> String {
+    format!(
+        "SELECT l._id, l.raw, json_field({local}, l.raw) IS NULL, f.raw FROM (SELECT _id, raw FROM [{}] {}) AS l LEFT JOIN [{}] AS f ON json_field({foreign}, f.raw) = json_field({local}, l.raw) ORDER BY l._id, f._id",
+        table_name,
+        if where_str.is_empty() { String::new() } else { format!("WHERE {}", where_str) },
+        foreign_table_name,
+        local = local_field,
+        foreign = foreign_field,
+    )
+}
+
+/// Runs an equality `$lookup` on the documents of a collection that match `query` with a SQL join. Returns `None` if the lookup can't be
+/// done in SQL: the foreign collection isn't a plain collection, or the query has no SQL translation.
+///
+/// `json_field` is NULL for arrays, documents, null and missing values, which the join can't compare the way mongodb does, so the documents
+/// with such a local value are looked up one by one instead.
+fn join(db: &rusqlite::Connection, config: &CollectionConfig, query: &bson::Document, lookup: &Lookup, resolver: &dyn Resolver) -> Result<Option<Vec<bson::Document>>, String> {
+    if !lookup.is_equality() {
+        return Ok(None);
+    }
+    let foreign = match database::collection_config(db, lookup.from)? {
+        Some(foreign) if foreign.view.is_none() && foreign.time_series.is_none() => foreign,
+        _ => return Ok(None),
+    };
+    let (local_field, foreign_field) = match (field_literal(lookup.local_field.unwrap()), field_literal(lookup.foreign_field.unwrap())) {
+        (Ok(local_field), Ok(foreign_field)) => (local_field, foreign_field),
+        _ => return Ok(None),
+    };
+    let mut params = Vec::<rusqlite::types::Value>::new();
+    let translated = QueryTranslator {}.query_document(query, &mut params);
+    let where_str = match translated {
+        Ok(where_str) => where_str,
+        Err(_) => return Ok(None),
+    };
+
+    let sql = join_sql(&config.table_name, &foreign.table_name, &local_field, &foreign_field, &where_str);
+    let mut stmt = match db.prepare_cached(&sql) {
+        Ok(stmt) => stmt,
+        Err(_) => return Ok(None),
+    };
+    let mut rows = stmt.query(params_from_iter(params.iter())).map_err(|e| e.to_string())?;
+
+    let decode = |raw: Vec<u8>| bson::Document::from_reader(raw.as_slice()).map_err(|e| e.to_string());
+    let now = bson::DateTime::now();
+    let mut joined: Vec<(i64, bson::Document, Vec<bson::Bson>)> = Vec::new();
+    while let Some(row) = rows.next().map_err(|e| e.to_string())? {
+        let id: i64 = row.get(0).map_err(|e| e.to_string())?;
+        if joined.last().is_none_or(|(last, _, _)| *last != id) {
+            let doc = decode(row.get(1).map_err(|e| e.to_string())?)?;
+            let matches = if row.get(2).map_err(|e| e.to_string())? { lookup.run(&doc, resolver, now)?.into_iter().map(bson::Bson::Document).collect() } else { Vec::new() };
+            joined.push((id, doc, matches));
+        }
+        if let Some(raw) = row.get::<_, Option<Vec<u8>>>(3).map_err(|e| e.to_string())? {
+            joined.last_mut().unwrap().2.push(bson::Bson::Document(decode(raw)?));
+        }
+    }
+
+    let mut result = Vec::with_capacity(joined.len());
+    for (_, mut doc, matches) in joined {
+        crate::update::set_path(&mut doc, lookup.as_field, Some(bson::Bson::Array(matches)))?;
+        result.push(doc);
+    }
+    Ok(Some(result))
+}
+
+impl<'a> Collection<'a> {
+    /// Runs an aggregation pipeline on the documents of the collection, see [`crate::aggregation`]. The leading `$match` stages are translated
+    /// to SQL, and so is an equality `$lookup` right after them.
+    pub fn aggregate(&mut self, pipeline: &[bson::Document]) -> Result<Vec<bson::Document>, String> {
+        let (query, rest) = split_match(pipeline);
+        aggregate_collection(self.db, &self.config, &query, rest, &CatalogResolver::new(self.db))
+    }
+}
+
+impl<'conn> TransactionCollection<'conn> {
+    /// Runs an aggregation pipeline on the documents of the collection, see [`Collection::aggregate()`]. Lookups see the changes made in the
+    /// transaction.
+    pub fn aggregate(&mut self, pipeline: &[bson::Document]) -> Result<Vec<bson::Document>, String> {
+        let (query, rest) = split_match(pipeline);
+        aggregate_collection(self.db, &self.config, &query, rest, &CatalogResolver::new(self.db))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::{Database, DatabaseConfig};
+    use bson::doc;
+
+    #[test]
+    fn test_lookup() {
+        let mut db = Database::open(&DatabaseConfig::in_memory()).unwrap();
+        let mut authors = db.create_collection("authors", &CollectionConfig::default("authors")).unwrap();
+        authors.insert_many(&vec![doc! { "name": "Ada", "born": 1815 }, doc! { "name": "Alan", "born": 1912 }, doc! { "name": ["Ada", "Alan"] }, doc! { "born": 0 }]).unwrap();
+        let mut books = db.create_collection("books", &CollectionConfig::default("books")).unwrap();
+        books.create_index(&doc! { "author": 1 }, false).unwrap();
+        books.insert_many(&vec![doc! { "title": "Notes", "author": "Ada", "year": 1843 }, doc! { "title": "Computing", "author": "Alan", "year": 1950 }, doc! { "title": "Anonymous" }]).unwrap();
+
+        // the join searches the books by the index on author.
+        let sql = join_sql("authors", "books", &field_literal("name").unwrap(), &field_literal("author").unwrap(), "");
+        let plan: Vec<String> = db.collection("books").unwrap().db.prepare(&format!("EXPLAIN QUERY PLAN {}", sql)).unwrap().query_map([], |row| row.get(3)).unwrap().map(|r| r.unwrap()).collect();
+        assert!(plan.iter().any(|step| step.contains("USING INDEX books_author")), "{:?}", plan);
+
+        let titles = |doc: &bson::Document, field: &str| doc.get_array(field).unwrap().iter().map(|book| book.as_document().unwrap().get_str("title").unwrap().to_string()).collect::<Vec<_>>();
+        let mut authors = db.collection("authors").unwrap();
+        let lookup = doc! { "$lookup": { "from": "books", "localField": "name", "foreignField": "author", "as": "books" } };
+        let joined = authors.aggregate(&[lookup.clone()]).unwrap();
+        assert_eq!(joined.len(), 4);
+        assert_eq!(titles(&joined[0], "books"), vec!["Notes"]);
+        assert_eq!(titles(&joined[1], "books"), vec!["Computing"]);
+        // an array joins each of its elements, and a missing field joins the documents without the foreign field.
+        assert_eq!(titles(&joined[2], "books"), vec!["Notes", "Computing"]);
+        assert_eq!(titles(&joined[3], "books"), vec!["Anonymous"]);
+
+        let joined = authors.aggregate(&[doc! { "$match": { "born": { "$gt": 1900 } } }, lookup.clone(), doc! { "$unwind": "$books" }, doc! { "$project": { "_id": 0, "title": "$books.title" } }]).unwrap();
+        assert_eq!(joined, vec![doc! { "title": "Computing" }]);
+        // $expr is evaluated inside the join, and behind another stage the documents are looked up one by one, with the same result.
+        assert_eq!(authors.aggregate(&[doc! { "$match": { "$expr": { "$gt": ["$born", 1900] } } }, lookup.clone()]).unwrap().len(), 1);
+        assert_eq!(authors.aggregate(&[doc! { "$match": { "$or": [{ "$expr": { "$lt": ["$born", 1900] } }, { "name": "Alan" }] } }, lookup.clone()]).unwrap().len(), 4);
+        // a query the translator refuses is an error, rather than a search of every document.
+        assert!(authors.aggregate(&[doc! { "$match": { "born..year": 1815 } }, lookup.clone()]).is_err());
+        // values that SQL can't compare are matched in the query.
+        assert_eq!(authors.count_documents(&doc! { "born": { "$in": [null, 1815] } }, &None).unwrap(), 2);
+        assert_eq!(authors.aggregate(&[doc! { "$sort": { "born": -1 } }, lookup.clone()]).unwrap()[0].get_array("books").unwrap().len(), 1);
+        assert_eq!(authors.aggregate(&[doc! { "$lookup": { "from": "missing", "localField": "name", "foreignField": "author", "as": "books" } }]).unwrap()[0].get_array("books").unwrap().len(), 0);
+
+        // the pipeline form, with variables.
+        let recent = doc! { "$lookup": {
+            "from": "books",
+            "let": { "name": "$name", "born": "$born" },
+            "pipeline": [
+                { "$match": { "$expr": { "$and": [{ "$eq": ["$author", "$$name"] }, { "$gt": ["$year", { "$add": ["$$born", 30] }] }] } } },
+                { "$project": { "_id": 0, "title": 1, "age": { "$subtract": ["$year", "$$born"] } } },
+            ],
+            "as": "late_books",
+        } };
+        let joined = authors.aggregate(&[doc! { "$match": { "born": { "$gt": 1000 } } }, recent]).unwrap();
+        assert!(joined[0].get_array("late_books").unwrap().is_empty());
+        assert_eq!(joined[1].get_array("late_books").unwrap().to_vec(), vec![bson::Bson::Document(doc! { "title": "Computing", "age": 38 })]);
+        assert!(authors.aggregate(&[doc! { "$lookup": { "from": "books", "localField": "name", "as": "books" } }]).is_err());
+        assert!(aggregation::aggregate(vec![doc! {}], &[lookup.clone()]).is_err());
+
+        // lookups from views, and views with lookups.
+        db.create_view("classics", "books", &vec![doc! { "$match": { "year": { "$lt": 1900 } } }]).unwrap();
+        db.create_view("bibliography", "authors", &vec![doc! { "$match": { "born": { "$gt": 0 } } }, doc! { "$lookup": { "from": "classics", "localField": "name", "foreignField": "author", "as": "classics" } }]).unwrap();
+        let mut bibliography = db.view("bibliography").unwrap();
+        let ada = bibliography.find_one(&doc! { "name": "Ada" }).unwrap().unwrap();
+        assert_eq!(titles(&ada, "classics"), vec!["Notes"]);
+        assert_eq!(bibliography.count_documents(&doc! { "classics": { "$size": 0 } }, &None).unwrap(), 1);
+
+        db.transaction(|tx| {
+            let mut books = tx.collection("books").unwrap();
+            books.insert_one(&doc! { "title": "Sketch", "author": "Ada", "year": 1842 }).unwrap();
+            let mut authors = tx.collection("authors").unwrap();
+            let joined = authors.aggregate(&[doc! { "$match": { "name": "Ada" } }, lookup.clone()]).unwrap();
+            assert_eq!(joined[0].get_array("books").unwrap().len(), 2);
+            Err("rolled back")
+        })
+        .unwrap_err();
+    }
+
+    #[test]
+    fn test_graph_lookup() {
+        let mut db = Database::open(&DatabaseConfig::in_memory()).unwrap();
+        let mut staff = db.create_collection("staff", &CollectionConfig::default("staff")).unwrap();
+        staff.create_index(&doc! { "name": 1 }, false).unwrap();
+        staff
+            .insert_many(&vec![
+                doc! { "name": "Dev", "reportsTo": "Eliot" },
+                doc! { "name": "Eliot", "reportsTo": "Ron" },
+                doc! { "name": "Ron", "reportsTo": "Andrew" },
+                doc! { "name": "Andrew", "reportsTo": "Dev" },
+                doc! { "name": "Asya", "reportsTo": "Ron", "remote": true },
+            ])
+            .unwrap();
+
+        let mut chain = |spec: bson::Document| {
+            let mut staff = db.collection("staff").unwrap();
+            let result = staff.aggregate(&[doc! { "$match": { "name": "Dev" } }, doc! { "$graphLookup": spec }]).unwrap();
+            result[0].get_array("chain").unwrap().iter().map(|doc| doc.as_document().unwrap().clone()).collect::<Vec<_>>()
+        };
+        let names = |chain: &[bson::Document]| chain.iter().map(|doc| doc.get_str("name").unwrap().to_string()).collect::<Vec<_>>();
+
+        // the cycle back to Dev stops the search.
+        let spec = doc! { "from": "staff", "startWith": "$reportsTo", "connectFromField": "reportsTo", "connectToField": "name", "as": "chain", "depthField": "level" };
+        let found = chain(spec.clone());
+        assert_eq!(names(&found), vec!["Eliot", "Ron", "Andrew", "Dev"]);
+        assert_eq!(found.iter().map(|doc| doc.get_i64("level").unwrap()).collect::<Vec<_>>(), vec![0, 1, 2, 3]);
+
+        let mut limited = spec.clone();
+        limited.insert("maxDepth", 1);
+        assert_eq!(names(&chain(limited)), vec!["Eliot", "Ron"]);
+
+        // the managers of the people reporting to Ron.
+        let reports = doc! { "from": "staff", "startWith": "Ron", "connectFromField": "name", "connectToField": "reportsTo", "as": "chain", "restrictSearchWithMatch": { "remote": { "$ne": true } } };
+        assert_eq!(names(&chain(reports)), vec!["Eliot", "Dev", "Andrew", "Ron"]);
+
+        assert!(db.collection("staff").unwrap().aggregate(&[doc! { "$graphLookup": { "from": "staff", "startWith": "$x", "as": "chain" } }]).is_err());
+    }
+}
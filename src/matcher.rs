@@ -0,0 +1,490 @@
+//! The [`crate::query_translator::QueryTranslator`] turns a query into SQL, so that sqlite can do the searching. Some features, however, need to evaluate a
+//! query against a document that is already loaded in memory, for example `$pull` with a condition, the positional update operator `$`, or `arrayFilters`.
+//! This module implements the mongodb query semantics directly on bson values for those cases.
+
+use std::cmp::Ordering;
+
+/// Returns the position of a bson type in mongodb's [comparison order](https://docs.mongodb.com/manual/reference/bson-type-comparison-order/).
+/// Values of different types are ordered by this number.
+fn type_order(value: &bson::Bson) -> u8 {
+    match value {
+        bson::Bson::MinKey => 1,
+        bson::Bson::Null | bson::Bson::Undefined => 2,
+        bson::Bson::Int32(_) | bson::Bson::Int64(_) | bson::Bson::Double(_) | bson::Bson::Decimal128(_) => 3,
+        bson::Bson::String(_) | bson::Bson::Symbol(_) => 4,
+        bson::Bson::Document(_) => 5,
+        bson::Bson::Array(_) => 6,
+        bson::Bson::Binary(_) => 7,
+        bson::Bson::ObjectId(_) => 8,
+        bson::Bson::Boolean(_) => 9,
+        bson::Bson::DateTime(_) => 10,
+        bson::Bson::Timestamp(_) => 11,
+        bson::Bson::RegularExpression(_) => 12,
+        bson::Bson::JavaScriptCode(_) | bson::Bson::JavaScriptCodeWithScope(_) | bson::Bson::DbPointer(_) => 13,
+        bson::Bson::MaxKey => 14,
+    }
+}
+
+/// Compares two bson values using mongodb's comparison order. Numbers of different types (Int32, Int64 and Double) are compared by their values.
+pub fn compare_bson(a: &bson::Bson, b: &bson::Bson) -> Ordering {
+    let (order_a, order_b) = (type_order(a), type_order(b));
+    if order_a != order_b {
+        return order_a.cmp(&order_b);
+    }
+
+    match (a, b) {
+        (bson::Bson::Int32(x), bson::Bson::Int32(y)) => x.cmp(y),
+        (bson::Bson::Int64(x), bson::Bson::Int64(y)) => x.cmp(y),
+        (bson::Bson::Int32(x), bson::Bson::Int64(y)) => (*x as i64).cmp(y),
+        (bson::Bson::Int64(x), bson::Bson::Int32(y)) => x.cmp(&(*y as i64)),
+        (bson::Bson::String(x), bson::Bson::String(y)) => x.cmp(y),
+        (bson::Bson::Symbol(x), bson::Bson::Symbol(y)) => x.cmp(y),
+        (bson::Bson::String(x), bson::Bson::Symbol(y)) => x.cmp(y),
+        (bson::Bson::Symbol(x), bson::Bson::String(y)) => x.cmp(y),
+        (bson::Bson::Document(x), bson::Bson::Document(y)) => {
+            for ((key_x, value_x), (key_y, value_y)) in x.iter().zip(y.iter()) {
+                let ordering = key_x.cmp(key_y).then_with(|| compare_bson(value_x, value_y));
+                if ordering != Ordering::Equal {
+                    return ordering;
+                }
+            }
+            x.len().cmp(&y.len())
+        }
+        (bson::Bson::Array(x), bson::Bson::Array(y)) => {
+            for (value_x, value_y) in x.iter().zip(y.iter()) {
+                let ordering = compare_bson(value_x, value_y);
+                if ordering != Ordering::Equal {
+                    return ordering;
+                }
+            }
+            x.len().cmp(&y.len())
+        }
+        (bson::Bson::Binary(x), bson::Bson::Binary(y)) => x.bytes.len().cmp(&y.bytes.len()).then_with(|| u8::from(x.subtype).cmp(&u8::from(y.subtype))).then_with(|| x.bytes.cmp(&y.bytes)),
+        (bson::Bson::ObjectId(x), bson::Bson::ObjectId(y)) => x.bytes().cmp(&y.bytes()),
+        (bson::Bson::Boolean(x), bson::Bson::Boolean(y)) => x.cmp(y),
+        (bson::Bson::DateTime(x), bson::Bson::DateTime(y)) => x.timestamp_millis().cmp(&y.timestamp_millis()),
+        (bson::Bson::Timestamp(x), bson::Bson::Timestamp(y)) => (x.time, x.increment).cmp(&(y.time, y.increment)),
+        (bson::Bson::RegularExpression(x), bson::Bson::RegularExpression(y)) => x.pattern.cmp(&y.pattern).then_with(|| x.options.cmp(&y.options)),
+        (bson::Bson::Decimal128(x), bson::Bson::Decimal128(y)) => x.bytes().cmp(&y.bytes()),
+        _ => match (as_f64(a), as_f64(b)) {
+            (Some(x), Some(y)) => x.partial_cmp(&y).unwrap_or(Ordering::Equal),
+            _ => Ordering::Equal,
+        },
+    }
+}
+
+/// Two values are considered equal by mongodb if they compare equal, which means `1` (Int32), `1` (Int64) and `1.0` (Double) are the same value.
+pub fn bson_equal(a: &bson::Bson, b: &bson::Bson) -> bool {
+    compare_bson(a, b) == Ordering::Equal
+}
+
+fn as_f64(value: &bson::Bson) -> Option<f64> {
+    match value {
+        bson::Bson::Int32(i) => Some(*i as f64),
+        bson::Bson::Int64(i) => Some(*i as f64),
+        bson::Bson::Double(d) => Some(*d),
+        _ => None,
+    }
+}
+
+/// Looks up a field by a dotted path, the same path syntax used by the `json_field` sqlite function. Numeric path parts can be used to index into arrays.
+pub fn get_path<'a>(doc: &'a bson::Document, path: &str) -> Option<&'a bson::Bson> {
+    let mut parts = path.split('.');
+    let mut current = doc.get(parts.next()?)?;
+    for part in parts {
+        current = match current {
+            bson::Bson::Document(doc) => doc.get(part)?,
+            bson::Bson::Array(arr) => arr.get(part.parse::<usize>().ok()?)?,
+            _ => return None,
+        };
+    }
+    Some(current)
+}
+
+/// Collects all values reachable by a path. Just like mongodb, when a path walks into an array of documents, the remaining path is applied to each
+/// element of the array. For example, `items.qty` against `{items: [{qty: 1}, {qty: 2}]}` yields both `1` and `2`.
+fn collect_values<'a>(value: &'a bson::Bson, parts: &[&str], result: &mut Vec<&'a bson::Bson>) {
+    if parts.is_empty() {
+        result.push(value);
+        return;
+    }
+
+    match value {
+        bson::Bson::Document(doc) => {
+            if let Some(inner) = doc.get(parts[0]) {
+                collect_values(inner, &parts[1..], result);
+            }
+        }
+        bson::Bson::Array(arr) => {
+            if let Ok(index) = parts[0].parse::<usize>() {
+                if let Some(inner) = arr.get(index) {
+                    collect_values(inner, &parts[1..], result);
+                }
+            }
+            for element in arr {
+                if let bson::Bson::Document(_) = element {
+                    collect_values(element, parts, result);
+                }
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Returns true if `doc` satisfies `query`. The supported query syntax is the same as that of the [`crate::query_translator::QueryTranslator`].
+pub fn matches(doc: &bson::Document, query: &bson::Document) -> Result<bool, String> {
+    for (key, condition) in query.iter() {
+        let matched = match key.as_str() {
+            "$and" => {
+                let mut result = true;
+                for sub_query in query_array(key, condition)? {
+                    if !matches(doc, sub_query)? {
+                        result = false;
+                        break;
+                    }
+                }
+                result
+            }
+            "$or" => {
+                let mut result = false;
+                for sub_query in query_array(key, condition)? {
+                    if matches(doc, sub_query)? {
+                        result = true;
+                        break;
+                    }
+                }
+                result
+            }
+            "$nor" => {
+                let mut result = true;
+                for sub_query in query_array(key, condition)? {
+                    if matches(doc, sub_query)? {
+                        result = false;
+                        break;
+                    }
+                }
+                result
+            }
+            "$not" => {
+                if let bson::Bson::Document(sub_query) = condition {
+                    !matches(doc, sub_query)?
+                } else {
+                    return Err(format!("Error in $not: {}", condition));
+                }
+            }
+            // sqlite can't evaluate expressions, so a query with `$expr` can only be matched here.
+            "$expr" => crate::expression::is_true(&crate::expression::evaluate(condition, &crate::expression::Variables::new(doc, bson::DateTime::now()))?),
+            _ => {
+                if key.starts_with('$') {
+                    return Err(format!("Unsupported operator: {}", key));
+                }
+                let parts: Vec<&str> = key.split('.').collect();
+                let mut values = Vec::new();
+                if let Some(value) = doc.get(parts[0]) {
+                    collect_values(value, &parts[1..], &mut values);
+                }
+                matches_condition(&values, condition)?
+            }
+        };
+
+        if !matched {
+            return Ok(false);
+        }
+    }
+    Ok(true)
+}
+
+fn query_array<'a>(operator: &str, value: &'a bson::Bson) -> Result<Vec<&'a bson::Document>, String> {
+    if let bson::Bson::Array(arr) = value {
+        let mut result = Vec::new();
+        for element in arr {
+            if let bson::Bson::Document(doc) = element {
+                result.push(doc);
+            } else {
+                return Err(format!("Error in {}: {}", operator, value));
+            }
+        }
+        Ok(result)
+    } else {
+        Err(format!("Error in {}: {}", operator, value))
+    }
+}
+
+/// Returns true if a single value satisfies a condition. The condition is either an operator document like `{$gte: 5}`, a query document that is
+/// applied to the value if the value is a document, or a plain value that is compared for equality. This is the matching rule used by `$pull`
+/// and `arrayFilters`.
+pub fn matches_value(value: &bson::Bson, condition: &bson::Bson) -> Result<bool, String> {
+    match (value, condition) {
+        (_, bson::Bson::Document(cond_doc)) if is_operator_document(cond_doc) => matches_condition(&[value], condition),
+        (bson::Bson::Document(doc), bson::Bson::Document(cond_doc)) => matches(doc, cond_doc),
+        _ => Ok(bson_equal(value, condition)),
+    }
+}
+
+fn is_operator_document(doc: &bson::Document) -> bool {
+    doc.keys().next().map(|key| key.starts_with('$')).unwrap_or(false)
+}
+
+/// Evaluates a condition against all values found at a path. An empty `values` means the field is missing.
+fn matches_condition(values: &[&bson::Bson], condition: &bson::Bson) -> Result<bool, String> {
+    if let bson::Bson::Document(cond_doc) = condition {
+        if is_operator_document(cond_doc) {
+            for (operator, operand) in cond_doc.iter() {
+                if !matches_operator(values, operator, operand, cond_doc)? {
+                    return Ok(false);
+                }
+            }
+            return Ok(true);
+        }
+    }
+
+    if let bson::Bson::Null = condition {
+        return Ok(values.is_empty() || any_value(values, |v| matches!(v, bson::Bson::Null)));
+    }
+
+    Ok(any_value(values, |v| bson_equal(v, condition)))
+}
+
+/// Applies a predicate to each value, and to each element of array values, the same way mongodb matches a scalar condition against an array field.
+fn any_value<F: Fn(&bson::Bson) -> bool>(values: &[&bson::Bson], predicate: F) -> bool {
+    values.iter().any(|value| {
+        if predicate(value) {
+            return true;
+        }
+        if let bson::Bson::Array(arr) = value {
+            return arr.iter().any(&predicate);
+        }
+        false
+    })
+}
+
+fn comparable(a: &bson::Bson, b: &bson::Bson) -> bool {
+    type_order(a) == type_order(b)
+}
+
+fn matches_operator(values: &[&bson::Bson], operator: &str, operand: &bson::Bson, cond_doc: &bson::Document) -> Result<bool, String> {
+    match operator {
+        "$eq" => {
+            if let bson::Bson::Null = operand {
+                Ok(values.is_empty() || any_value(values, |v| matches!(v, bson::Bson::Null)))
+            } else {
+                Ok(any_value(values, |v| bson_equal(v, operand)))
+            }
+        }
+        "$ne" => Ok(!matches_operator(values, "$eq", operand, cond_doc)?),
+        "$gt" => Ok(any_value(values, |v| comparable(v, operand) && compare_bson(v, operand) == Ordering::Greater)),
+        "$gte" => Ok(any_value(values, |v| comparable(v, operand) && compare_bson(v, operand) != Ordering::Less)),
+        "$lt" => Ok(any_value(values, |v| comparable(v, operand) && compare_bson(v, operand) == Ordering::Less)),
+        "$lte" => Ok(any_value(values, |v| comparable(v, operand) && compare_bson(v, operand) != Ordering::Greater)),
+        "$in" => {
+            if let bson::Bson::Array(arr) = operand {
+                for candidate in arr {
+                    if matches_operator(values, "$eq", candidate, cond_doc)? {
+                        return Ok(true);
+                    }
+                }
+                Ok(false)
+            } else {
+                Err(format!("Error in $in: {}", operand))
+            }
+        }
+        "$nin" => Ok(!matches_operator(values, "$in", operand, cond_doc)?),
+        "$exists" => {
+            let should_exist = match operand {
+                bson::Bson::Boolean(b) => *b,
+                bson::Bson::Int32(i) => *i != 0,
+                bson::Bson::Int64(i) => *i != 0,
+                _ => return Err(format!("Error in $exists: {}", operand)),
+            };
+            Ok(values.is_empty() != should_exist)
+        }
+        "$type" => {
+            let types: Vec<&bson::Bson> = match operand {
+                bson::Bson::Array(arr) => arr.iter().collect(),
+                _ => vec![operand],
+            };
+            for type_value in types {
+                for value in values {
+                    if type_matches(value, type_value)? {
+                        return Ok(true);
+                    }
+                }
+            }
+            Ok(false)
+        }
+        "$size" => {
+            let size = match operand {
+                bson::Bson::Int32(i) => *i as i64,
+                bson::Bson::Int64(i) => *i,
+                bson::Bson::Double(d) if d.fract() == 0.0 => *d as i64,
+                _ => return Err(format!("Error in $size: {}", operand)),
+            };
+            Ok(values.iter().any(|v| matches!(v, bson::Bson::Array(arr) if arr.len() as i64 == size)))
+        }
+        "$all" => {
+            if let bson::Bson::Array(arr) = operand {
+                if arr.is_empty() {
+                    return Ok(false);
+                }
+                for required in arr {
+                    if !matches_condition(values, required)? {
+                        return Ok(false);
+                    }
+                }
+                Ok(true)
+            } else {
+                Err(format!("Error in $all: {}", operand))
+            }
+        }
+        "$elemMatch" => {
+            if let bson::Bson::Document(_) = operand {
+                for value in values {
+                    if let bson::Bson::Array(arr) = value {
+                        for element in arr {
+                            if matches_value(element, operand)? {
+                                return Ok(true);
+                            }
+                        }
+                    }
+                }
+                Ok(false)
+            } else {
+                Err(format!("Error in $elemMatch: {}", operand))
+            }
+        }
+        "$mod" => {
+            if let bson::Bson::Array(arr) = operand {
+                if arr.len() != 2 {
+                    return Err(format!("Error in $mod: {}", operand));
+                }
+                let divisor = as_f64(&arr[0]).ok_or_else(|| format!("Error in $mod: {}", operand))? as i64;
+                let remainder = as_f64(&arr[1]).ok_or_else(|| format!("Error in $mod: {}", operand))? as i64;
+                if divisor == 0 {
+                    return Err(format!("Error in $mod: {}, Divisor can't be zero.", operand));
+                }
+                Ok(any_value(values, |v| as_f64(v).map(|n| (n as i64) % divisor == remainder).unwrap_or(false)))
+            } else {
+                Err(format!("Error in $mod: {}", operand))
+            }
+        }
+        "$regex" => {
+            let options = match cond_doc.get("$options") {
+                Some(bson::Bson::String(options)) => options.as_str(),
+                Some(other) => return Err(format!("Error in $regex: {}", other)),
+                None => "",
+            };
+            let regex = match operand {
+                bson::Bson::String(pattern) => build_regex(pattern, options)?,
+                bson::Bson::RegularExpression(regex) => build_regex(&regex.pattern, &regex.options)?,
+                _ => return Err(format!("Error in $regex: {}", operand)),
+            };
+            Ok(any_value(values, |v| matches!(v, bson::Bson::String(s) if regex.is_match(s))))
+        }
+        "$options" => {
+            if cond_doc.contains_key("$regex") {
+                Ok(true)
+            } else {
+                Err("$options needs a $regex".to_string())
+            }
+        }
+        "$not" => match operand {
+            bson::Bson::Document(_) => Ok(!matches_condition(values, operand)?),
+            bson::Bson::RegularExpression(regex) => {
+                let regex = build_regex(&regex.pattern, &regex.options)?;
+                Ok(!any_value(values, |v| matches!(v, bson::Bson::String(s) if regex.is_match(s))))
+            }
+            _ => Err(format!("Error in $not: {}", operand)),
+        },
+        "$bitsAllSet" | "$bitsAllClear" | "$bitsAnySet" | "$bitsAnyClear" => {
+            let mask = match operand {
+                bson::Bson::Int32(i) => *i as i64,
+                bson::Bson::Int64(i) => *i,
+                _ => return Err(format!("Error in {}: {}", operator, operand)),
+            };
+            Ok(values.iter().any(|v| {
+                let number = match v {
+                    bson::Bson::Int32(i) => *i as i64,
+                    bson::Bson::Int64(i) => *i,
+                    _ => return false,
+                };
+                match operator {
+                    "$bitsAllSet" => number & mask == mask,
+                    "$bitsAllClear" => number & mask == 0,
+                    "$bitsAnySet" => number & mask != 0,
+                    _ => number & mask != mask,
+                }
+            }))
+        }
+        _ => Err(format!("Unsupported operator: {}", operator)),
+    }
+}
+
+fn build_regex(pattern: &str, options: &str) -> Result<regex::Regex, String> {
+    let mut builder = regex::RegexBuilder::new(pattern);
+    for option in options.chars() {
+        match option {
+            'i' => builder.case_insensitive(true),
+            'm' => builder.multi_line(true),
+            's' => builder.dot_matches_new_line(true),
+            'x' => builder.ignore_whitespace(true),
+            _ => return Err(format!("Unsupported regex option: {}", option)),
+        };
+    }
+    builder.build().map_err(|e| e.to_string())
+}
+
+/// Matches a value against a `$type` operand, which is either a bson type alias such as `"string"`, or its numeric code.
+fn type_matches(value: &bson::Bson, type_value: &bson::Bson) -> Result<bool, String> {
+    let alias = match type_value {
+        bson::Bson::String(alias) => alias.clone(),
+        bson::Bson::Int32(_) | bson::Bson::Int64(_) | bson::Bson::Double(_) => {
+            let code = as_f64(type_value).unwrap() as i64;
+            match code {
+                1 => "double",
+                2 => "string",
+                3 => "object",
+                4 => "array",
+                5 => "binData",
+                6 => "undefined",
+                7 => "objectId",
+                8 => "bool",
+                9 => "date",
+                10 => "null",
+                11 => "regex",
+                13 => "javascript",
+                16 => "int",
+                17 => "timestamp",
+                18 => "long",
+                19 => "decimal",
+                -1 => "minKey",
+                127 => "maxKey",
+                _ => return Err(format!("Unsupported type code: {}", code)),
+            }
+            .to_string()
+        }
+        _ => return Err(format!("Error in $type: {}", type_value)),
+    };
+
+    Ok(match alias.as_str() {
+        "number" => matches!(value, bson::Bson::Int32(_) | bson::Bson::Int64(_) | bson::Bson::Double(_) | bson::Bson::Decimal128(_)),
+        "double" => matches!(value, bson::Bson::Double(_)),
+        "string" => matches!(value, bson::Bson::String(_)),
+        "object" => matches!(value, bson::Bson::Document(_)),
+        "array" => matches!(value, bson::Bson::Array(_)),
+        "binData" => matches!(value, bson::Bson::Binary(_)),
+        "undefined" => matches!(value, bson::Bson::Undefined),
+        "objectId" => matches!(value, bson::Bson::ObjectId(_)),
+        "bool" => matches!(value, bson::Bson::Boolean(_)),
+        "date" => matches!(value, bson::Bson::DateTime(_)),
+        "null" => matches!(value, bson::Bson::Null),
+        "regex" => matches!(value, bson::Bson::RegularExpression(_)),
+        "javascript" => matches!(value, bson::Bson::JavaScriptCode(_)),
+        "int" => matches!(value, bson::Bson::Int32(_)),
+        "timestamp" => matches!(value, bson::Bson::Timestamp(_)),
+        "long" => matches!(value, bson::Bson::Int64(_)),
+        "decimal" => matches!(value, bson::Bson::Decimal128(_)),
+        "minKey" => matches!(value, bson::Bson::MinKey),
+        "maxKey" => matches!(value, bson::Bson::MaxKey),
+        _ => return Err(format!("Unsupported type alias: {}", alias)),
+    })
+}
@@ -0,0 +1,323 @@
+//! Versioning of the on-disk format, and migrations of documents.
+//!
+//! The single row of the `_hoardbase_meta` table records the `format_version` of a database file, the layout of hoardbase's own tables. A file
+//! written by a newer hoardbase, whose format version is higher than [`FORMAT_VERSION`], is refused. An older file is upgraded when it is opened
+//! by running the missing steps of [`FORMAT_MIGRATIONS`] in order, each in its own transaction.
+//!
+//! Applications can migrate their documents the same way. A [`DocumentMigration`] registered with [`crate::database::DatabaseConfig::migration()`]
+//! rewrites every document of a collection once; the version of the last migration that ran is stored with the collection.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use crate::base::CollectionConfig;
+use crate::database::rename_schema_object;
+
+/// A step that upgrades the format by one version.
+type FormatMigration = fn(&rusqlite::Transaction) -> rusqlite::Result<()>;
+
+/// The upgrade steps. The step at index `i` upgrades a file from format version `i` to `i + 1`.
+const FORMAT_MIGRATIONS: &[FormatMigration] = &[
+    // 1: the meta table has a single row, and collections remember the version of their document migrations.
+    |tx| {
+        tx.execute("DELETE FROM _hoardbase_meta WHERE id <> (SELECT MIN(id) FROM _hoardbase_meta)", [])?;
+        tx.execute("UPDATE _hoardbase_meta SET id = 1", [])?;
+        tx.execute("ALTER TABLE _hoardbase ADD COLUMN user_version INTEGER NOT NULL DEFAULT 0", [])?;
+        Ok(())
+    },
+    // 2: `hash_unique` is stored, and index names are prefixed with their table name, since sqlite's index names are global.
+    |tx| {
+        tx.execute("ALTER TABLE _hoardbase ADD COLUMN hash_unique BOOLEAN NOT NULL DEFAULT 0", [])?;
+        let mut stmt = tx.prepare("SELECT table_name, hash_document FROM _hoardbase WHERE type = 0")?;
+        let tables: Vec<(String, bool)> = stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?.collect::<rusqlite::Result<_>>()?;
+        for (table_name, hash_document) in tables {
+            let prefix = format!("{}_", table_name);
+            let mut stmt = tx.prepare("SELECT name, sql FROM sqlite_master WHERE type = 'index' AND tbl_name = ?1 AND sql IS NOT NULL")?;
+            let indexes: Vec<(String, String)> = stmt.query_map([&table_name], |row| Ok((row.get(0)?, row.get(1)?)))?.collect::<rusqlite::Result<_>>()?;
+            for (name, sql) in &indexes {
+                if name == "_hash" {
+                    tx.execute("UPDATE _hoardbase SET hash_unique = ?1 WHERE table_name = ?2", rusqlite::params![sql.starts_with("CREATE UNIQUE"), table_name])?;
+                }
+                if !name.starts_with(&prefix) {
+                    rename_schema_object(tx, "index", name, sql, &format!("{}_{}", table_name, name))?;
+                }
+            }
+            // the hash index used to be created with `IF NOT EXISTS` under the same name for every collection, so only the first one got it.
+            if hash_document && !indexes.iter().any(|(name, _)| name == "_hash") {
+                tx.execute(&format!("CREATE INDEX [{}__hash] ON [{}](_hash)", table_name, table_name), [])?;
+            }
+        }
+        Ok(())
+    },
+    // 3: capped collections.
+    |tx| {
+        tx.execute("ALTER TABLE _hoardbase ADD COLUMN capped_max_documents INTEGER NOT NULL DEFAULT 0", [])?;
+        tx.execute("ALTER TABLE _hoardbase ADD COLUMN capped_max_bytes INTEGER NOT NULL DEFAULT 0", [])?;
+        Ok(())
+    },
+    // 4: time-series collections, whose `type` is 1.
+    |tx| {
+        tx.execute_batch(
+            "ALTER TABLE _hoardbase ADD COLUMN time_field TEXT;
+             ALTER TABLE _hoardbase ADD COLUMN meta_field TEXT;
+             ALTER TABLE _hoardbase ADD COLUMN granularity TEXT;
+             ALTER TABLE _hoardbase ADD COLUMN expire_after_seconds INTEGER NOT NULL DEFAULT 0;",
+        )
+    },
+    // 5: views, whose `type` is 2. The pipeline is stored as the bson of `{"pipeline": [...]}`.
+    |tx| tx.execute_batch("ALTER TABLE _hoardbase ADD COLUMN view_on TEXT; ALTER TABLE _hoardbase ADD COLUMN pipeline BLOB;"),
+];
+
+/// The format version this version of hoardbase writes.
+pub const FORMAT_VERSION: i64 = FORMAT_MIGRATIONS.len() as i64;
+
+/// A versioned rewrite of the documents of a collection, see [`crate::database::DatabaseConfig::migration()`].
+#[derive(Clone)]
+pub struct DocumentMigration {
+    /// The name of the collection.
+    pub collection: String,
+    /// Migrations run in the order of their versions, and only once.
+    pub version: i64,
+    pub(crate) migrate: Arc<dyn Fn(&mut bson::Document) -> Result<(), String> + Send + Sync>,
+}
+
+impl std::fmt::Debug for DocumentMigration {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "DocumentMigration {{ collection: {}, version: {} }}", self.collection, self.version)
+    }
+}
+
+/// The version of the newest migration registered for a collection, 0 if there is none.
+pub(crate) fn latest_version(migrations: &[DocumentMigration], collection: &str) -> i64 {
+    migrations.iter().filter(|m| m.collection == collection).map(|m| m.version).max().unwrap_or(0)
+}
+
+/// Creates hoardbase's tables in a new file, and brings the format of an existing file up to [`FORMAT_VERSION`].
+pub(crate) fn upgrade_format(connection: &mut rusqlite::Connection, read_only: bool) -> Result<(), String> {
+    if !read_only {
+        let tx = connection.transaction().map_err(|e| e.to_string())?;
+        // this is the layout of format version 0, which the migrations start from.
+        tx.execute_batch(
+            "CREATE TABLE IF NOT EXISTS _hoardbase (
+                  id              INTEGER PRIMARY KEY,
+                  collection      TEXT NOT NULL,
+                  type            INTEGER NOT NULL,
+                  table_name      TEXT UNIQUE NOT NULL,
+                  hash_document   BOOLEAN NOT NULL,
+                  log_last_modified BOOLEAN NOT NULL,
+                  encrypt          BOOLEAN NOT NULL,
+                  compress         BOOLEAN NOT NULL,
+                  serialization_method         TEXT NOT NULL
+                  );
+            CREATE UNIQUE INDEX IF NOT EXISTS collection ON _hoardbase(collection);
+            CREATE TABLE IF NOT EXISTS _hoardbase_meta (
+                  id              INTEGER PRIMARY KEY,
+                  version      TEXT NOT NULL,
+                  git_hash             TEXT NOT NULL,
+                  format_version      INTEGER NOT NULL,
+                  build_time   DATETIME NOT NULL
+                  );",
+        )
+        .map_err(|e| e.to_string())?;
+        tx.execute(
+            "INSERT INTO _hoardbase_meta (id, version, git_hash, format_version, build_time)
+             SELECT 1, ?1, ?2, 0, datetime('now') WHERE NOT EXISTS (SELECT 1 FROM _hoardbase_meta)",
+            [env!("CARGO_PKG_VERSION"), env!("GIT_HASH")],
+        )
+        .map_err(|e| e.to_string())?;
+        tx.commit().map_err(|e| e.to_string())?;
+    }
+
+    // a read-only database that was never initialized has no meta table.
+    let version: i64 = connection
+        .query_row("SELECT MAX(format_version) FROM _hoardbase_meta", [], |row| row.get(0))
+        .map_err(|_| "The database is not a hoardbase database".to_string())?;
+    if version > FORMAT_VERSION {
+        return Err(format!("The database has format version {}, but this version of hoardbase only supports format versions up to {}", version, FORMAT_VERSION));
+    }
+    if version < FORMAT_VERSION && read_only {
+        return Err(format!("The database has format version {} and needs to be upgraded to {}, which can't be done in read-only mode", version, FORMAT_VERSION));
+    }
+
+    for (from, migration) in FORMAT_MIGRATIONS.iter().enumerate().skip(version as usize) {
+        let tx = connection.transaction().map_err(|e| e.to_string())?;
+        migration(&tx).map_err(|e| format!("Failed to upgrade the database to format version {}: {}", from + 1, e))?;
+        tx.execute(
+            "UPDATE _hoardbase_meta SET format_version = ?1, version = ?2, git_hash = ?3, build_time = datetime('now')",
+            rusqlite::params![from as i64 + 1, env!("CARGO_PKG_VERSION"), env!("GIT_HASH")],
+        )
+        .map_err(|e| e.to_string())?;
+        tx.commit().map_err(|e| e.to_string())?;
+    }
+    Ok(())
+}
+
+/// Runs the document migrations that haven't run on the existing collections yet. All pending migrations of a collection run in one
+/// transaction, so a failing migration leaves the collection untouched.
+pub(crate) fn migrate_documents(connection: &mut rusqlite::Connection, migrations: &[DocumentMigration], collections: &HashMap<String, (String, CollectionConfig)>, read_only: bool) -> Result<(), String> {
+    for (name, (_, config)) in collections {
+        let current: i64 = connection.query_row("SELECT user_version FROM _hoardbase WHERE collection = ?1", [name], |row| row.get(0)).map_err(|e| e.to_string())?;
+        let mut pending: Vec<&DocumentMigration> = migrations.iter().filter(|m| &m.collection == name && m.version > current).collect();
+        if pending.is_empty() {
+            continue;
+        }
+        if config.time_series.is_some() {
+            return Err(format!("The collection {} is a time-series collection, whose measurements can't be migrated", name));
+        }
+        if config.view.is_some() {
+            return Err(format!("The collection {} is a view, which has no documents to migrate", name));
+        }
+        if read_only {
+            return Err(format!("The collection {} has pending migrations, which can't run in read-only mode", name));
+        }
+        pending.sort_by_key(|m| m.version);
+
+        let tx = connection.transaction().map_err(|e| e.to_string())?;
+        {
+            let mut select = tx.prepare(&format!("SELECT _id, raw FROM [{}]", config.table_name)).map_err(|e| e.to_string())?;
+            let mut update = tx.prepare(&format!("UPDATE [{}] SET raw = ?2 WHERE _id = ?1", config.table_name)).map_err(|e| e.to_string())?;
+            let mut rows = select.query([]).map_err(|e| e.to_string())?;
+            while let Some(row) = rows.next().map_err(|e| e.to_string())? {
+                let id: i64 = row.get(0).map_err(|e| e.to_string())?;
+                let raw: Vec<u8> = row.get(1).map_err(|e| e.to_string())?;
+                let original = bson::Document::from_reader(raw.as_slice()).map_err(|e| e.to_string())?;
+                let mut doc = original.clone();
+                for migration in &pending {
+                    (migration.migrate)(&mut doc).map_err(|e| format!("Migration {} of collection {} failed on document {}: {}", migration.version, name, id, e))?;
+                }
+                if doc != original {
+                    let mut bytes: Vec<u8> = Vec::new();
+                    doc.to_writer(&mut bytes).map_err(|e| e.to_string())?;
+                    update.execute(rusqlite::params![id, bytes]).map_err(|e| e.to_string())?;
+                }
+            }
+        }
+        tx.execute("UPDATE _hoardbase SET user_version = ?1 WHERE collection = ?2", rusqlite::params![pending.last().unwrap().version, name]).map_err(|e| e.to_string())?;
+        tx.commit().map_err(|e| e.to_string())?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::base::CollectionTrait;
+    use crate::database::{Database, DatabaseConfig};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    fn meta_rows(path: &str) -> Vec<i64> {
+        let connection = rusqlite::Connection::open(path).unwrap();
+        let mut stmt = connection.prepare("SELECT format_version FROM _hoardbase_meta").unwrap();
+        let rows = stmt.query_map([], |row| row.get(0)).unwrap().map(|r| r.unwrap()).collect();
+        rows
+    }
+
+    #[test]
+    fn test_format_version() {
+        let path = DatabaseConfig::temporary().path;
+        let config = DatabaseConfig::new(&path);
+        Database::open(&config).unwrap();
+        Database::open(&config).unwrap();
+        assert_eq!(meta_rows(&path), vec![FORMAT_VERSION]);
+
+        rusqlite::Connection::open(&path).unwrap().execute("UPDATE _hoardbase_meta SET format_version = format_version + 1", []).unwrap();
+        let error = Database::open(&config).err().unwrap();
+        assert!(error.contains("only supports format versions up to"), "{}", error);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_upgrade_from_version_0() {
+        let path = DatabaseConfig::temporary().path;
+        {
+            // a file written before format versioning: one meta row per open.
+            let mut connection = rusqlite::Connection::open(&path).unwrap();
+            crate::database::register_functions(&mut connection, &DatabaseConfig::new(&path));
+            connection
+                .execute_batch(
+                    "CREATE TABLE _hoardbase (id INTEGER PRIMARY KEY, collection TEXT NOT NULL, type INTEGER NOT NULL, table_name TEXT UNIQUE NOT NULL,
+                        hash_document BOOLEAN NOT NULL, log_last_modified BOOLEAN NOT NULL, encrypt BOOLEAN NOT NULL, compress BOOLEAN NOT NULL,
+                        serialization_method TEXT NOT NULL);
+                    CREATE UNIQUE INDEX collection ON _hoardbase(collection);
+                    CREATE TABLE _hoardbase_meta (id INTEGER PRIMARY KEY, version TEXT NOT NULL, git_hash TEXT NOT NULL, format_version INTEGER NOT NULL,
+                        build_time DATETIME NOT NULL);
+                    INSERT INTO _hoardbase_meta (version, git_hash, format_version, build_time) VALUES ('0.1.0', '', 0, datetime('now'));
+                    INSERT INTO _hoardbase_meta (version, git_hash, format_version, build_time) VALUES ('0.1.0', '', 0, datetime('now'));
+                    INSERT INTO _hoardbase (collection, type, table_name, hash_document, log_last_modified, encrypt, compress, serialization_method)
+                        VALUES ('old', 0, 'old', 1, 0, 0, 0, 'bson');
+                    INSERT INTO _hoardbase (collection, type, table_name, hash_document, log_last_modified, encrypt, compress, serialization_method)
+                        VALUES ('older', 0, 'older', 1, 0, 0, 0, 'bson');
+                    CREATE TABLE [old] (_id INTEGER PRIMARY KEY, raw BLOB NOT NULL, _hash NCHAR(40) GENERATED ALWAYS AS (blake3(raw)) STORED);
+                    CREATE TABLE [older] (_id INTEGER PRIMARY KEY, raw BLOB NOT NULL, _hash NCHAR(40) GENERATED ALWAYS AS (blake3(raw)) STORED);
+                    CREATE UNIQUE INDEX _hash ON [old](_hash);
+                    CREATE INDEX name ON [old](json_field('name', raw) ASC);",
+                )
+                .unwrap();
+        }
+
+        let mut read_only = DatabaseConfig::new(&path);
+        read_only.read_only(true);
+        assert!(Database::open(&read_only).is_err());
+
+        let mut db = Database::open(&DatabaseConfig::new(&path)).unwrap();
+        db.collection("old").unwrap().insert_one(&bson::doc! { "a": 1 }).unwrap();
+        // the unique hash index still rejects duplicates under its new name.
+        assert!(db.collection("old").unwrap().insert_one(&bson::doc! { "a": 1 }).is_err());
+        assert!(db.collection("old").unwrap().config.should_hash_unique);
+        let mut names: Vec<String> = db.collection("old").unwrap().get_indexes().unwrap().into_iter().map(|index| index.name).collect();
+        names.sort();
+        assert_eq!(names, vec!["old__hash", "old_name"]);
+        assert!(!db.collection("older").unwrap().config.should_hash_unique);
+        assert_eq!(db.collection("older").unwrap().get_indexes().unwrap()[0].name, "older__hash");
+        drop(db);
+        assert_eq!(meta_rows(&path), vec![FORMAT_VERSION]);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_document_migrations() {
+        let path = DatabaseConfig::temporary().path;
+        {
+            let mut db = Database::open(&DatabaseConfig::new(&path)).unwrap();
+            let mut people = db.create_collection("people", &CollectionConfig::default("people")).unwrap();
+            people.insert_many(&vec![bson::doc! { "name": "Ada Lovelace" }, bson::doc! { "name": "Alan Turing" }]).unwrap();
+        }
+
+        let calls = Arc::new(AtomicUsize::new(0));
+        let mut config = DatabaseConfig::new(&path);
+        let counter = calls.clone();
+        config
+            .migration("people", 2, |doc| {
+                doc.insert("version", 2);
+                Ok(())
+            })
+            .migration("people", 1, move |doc| {
+                counter.fetch_add(1, Ordering::SeqCst);
+                let name = doc.get_str("name").map_err(|e| e.to_string())?.to_string();
+                let (first, last) = name.split_once(' ').ok_or("no last name")?;
+                doc.insert("name", bson::doc! { "first": first, "last": last });
+                Ok(())
+            })
+            .migration("pets", 3, |_| Err("pets have no old documents".to_string()));
+
+        let mut db = Database::open(&config).unwrap();
+        let ada = db.collection("people").unwrap().find_one(&bson::doc! { "version": 2 }, 0).unwrap().data;
+        assert_eq!(ada.get_document("name").unwrap().get_str("last").unwrap(), "Lovelace");
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+        db.create_collection("pets", &CollectionConfig::default("pets")).unwrap().insert_one(&bson::doc! { "name": "Rex" }).unwrap();
+        drop(db);
+
+        // migrations run once.
+        Database::open(&config).unwrap();
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+
+        // a failing migration leaves the documents untouched and the database closed.
+        let mut failing = DatabaseConfig::new(&path);
+        failing.migration("people", 4, |doc| if doc.get_document("name").unwrap().get_str("first") == Ok("Alan") { Err("no".to_string()) } else { Ok(()) });
+        assert!(Database::open(&failing).err().unwrap().contains("Migration 4 of collection people failed"));
+        let mut db = Database::open(&DatabaseConfig::new(&path)).unwrap();
+        assert_eq!(db.collection("people").unwrap().count_documents(&bson::doc! { "version": 2 }, &None).unwrap(), 2);
+        drop(db);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,96 @@
+//! A small pool of read-only sqlite connections.
+//!
+//! sqlite connections can't be used by two threads at the same time, but a database in WAL mode lets any number of readers run alongside a
+//! single writer. [`ConnectionPool`] hands out reader connections to the threads of a [`crate::handle::DatabaseHandle`], opening new ones lazily
+//! up to a maximum and blocking when all of them are in use.
+
+use std::ops::Deref;
+use std::sync::{Condvar, Mutex, MutexGuard};
+
+use crate::database::{configure_connection, register_functions, DatabaseConfig};
+
+struct PoolState {
+    /// Connections that are open and not checked out.
+    idle: Vec<rusqlite::Connection>,
+    /// The number of open connections, including the checked out ones.
+    open: usize,
+}
+
+/// A pool of read-only connections to one database file.
+pub struct ConnectionPool {
+    config: DatabaseConfig,
+    max_size: usize,
+    state: Mutex<PoolState>,
+    available: Condvar,
+}
+
+/// A connection checked out of a [`ConnectionPool`]. It goes back to the pool when dropped.
+pub struct PooledConnection<'a> {
+    pool: &'a ConnectionPool,
+    connection: Option<rusqlite::Connection>,
+}
+
+impl ConnectionPool {
+    /// Creates a pool that opens at most `max_size` connections to the database described by `config`. No connection is opened until one is needed.
+    pub fn new(config: &DatabaseConfig, max_size: usize) -> Self {
+        ConnectionPool { config: config.clone(), max_size: max_size.max(1), state: Mutex::new(PoolState { idle: Vec::new(), open: 0 }), available: Condvar::new() }
+    }
+
+    /// The maximum number of connections this pool opens.
+    pub fn max_size(&self) -> usize {
+        self.max_size
+    }
+
+    /// Checks out a connection, opening a new one if none is idle and the pool isn't full. Otherwise this blocks until another thread returns one.
+    pub fn get(&self) -> Result<PooledConnection<'_>, String> {
+        let mut state = self.lock();
+        loop {
+            if let Some(connection) = state.idle.pop() {
+                return Ok(PooledConnection { pool: self, connection: Some(connection) });
+            }
+            if state.open < self.max_size {
+                state.open += 1;
+                drop(state);
+                return match self.connect() {
+                    Ok(connection) => Ok(PooledConnection { pool: self, connection: Some(connection) }),
+                    Err(e) => {
+                        self.lock().open -= 1;
+                        self.available.notify_one();
+                        Err(e)
+                    }
+                };
+            }
+            state = self.available.wait(state).unwrap_or_else(|e| e.into_inner());
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, PoolState> {
+        // a panic while holding the lock can't leave the state half updated, so a poisoned lock is still usable.
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn connect(&self) -> Result<rusqlite::Connection, String> {
+        let flags = rusqlite::OpenFlags::SQLITE_OPEN_READ_ONLY | rusqlite::OpenFlags::SQLITE_OPEN_NO_MUTEX | rusqlite::OpenFlags::SQLITE_OPEN_URI;
+        let mut connection = rusqlite::Connection::open_with_flags(&self.config.path, flags).map_err(|e| e.to_string())?;
+        configure_connection(&connection, &self.config)?;
+        register_functions(&mut connection, &self.config);
+        Ok(connection)
+    }
+}
+
+impl<'a> Deref for PooledConnection<'a> {
+    type Target = rusqlite::Connection;
+
+    fn deref(&self) -> &rusqlite::Connection {
+        self.connection.as_ref().unwrap()
+    }
+}
+
+impl<'a> Drop for PooledConnection<'a> {
+    fn drop(&mut self) {
+        if let Some(connection) = self.connection.take() {
+            self.pool.lock().idle.push(connection);
+            self.pool.available.notify_one();
+        }
+    }
+}
@@ -1,6 +1,8 @@
 use bson::Bson;
 use bson::Document;
 
+use crate::identifier::field_literal;
+
 pub struct QueryTranslator {}
 
 impl QueryTranslator {
@@ -10,7 +12,7 @@ impl QueryTranslator {
         let mut result = String::new();
 
         for (key, value) in query.iter() {
-            if key.chars().nth(0).unwrap() == '$' {
+            if key.starts_with('$') {
                 match key.as_str() {
                     "$or" => {
                         if let bson::Bson::Array(arr) = value {
@@ -71,21 +73,34 @@ impl QueryTranslator {
                                         in_values.push_str(" OR ");
                                     }
 
-                                    in_values.push_str(self.nested("", val_doc, params).unwrap().as_str());
+                                    in_values.push_str(&self.clause(val_doc, params)?);
+                                } else {
+                                    return Err(format!("Error in $nor: {}", value));
                                 }
                             }
 
+                            if in_values.is_empty() {
+                                return Err(String::from("$nor needs a non-empty array"));
+                            }
                             result.push_str(&format!("NOT ({}) ", &in_values));
                             term_count += 1;
                         } else {
                             return Err(format!("Error in $nor: {}", value));
                         }
                     }
+                    "$expr" => {
+                        if term_count > 0 {
+                            result.push_str(" AND ");
+                        }
+                        result.push_str(&self.expr(value, params)?);
+                        term_count += 1;
+                    }
                     _ => {
                         return Err(format!("Unsupported operator: {}", key));
                     }
                 }
             } else {
+                field_literal(key)?;
                 match value {
                     bson::Bson::Document(val_doc) => {
                         if key == "_id" {
@@ -111,7 +126,7 @@ impl QueryTranslator {
                                 return Err(format!("_id cannot be null"));
                             }
                             _ => {
-                                result.push_str(&format!("json_field('{}', raw) IS NULL", key));
+                                result.push_str(&format!("json_field({}, raw) IS NULL", field_literal(key)?));
                             }
                         }
                         term_count += 1;
@@ -130,7 +145,7 @@ impl QueryTranslator {
                                 return Err(format!("_id cannot be string"));
                             }
                             _ => {
-                                result.push_str(&format!("json_field('{}', raw) = {}", key, self.value(value, params).unwrap()));
+                                result.push_str(&format!("json_field({}, raw) = {}", field_literal(key)?, self.value(value, params)?));
                             }
                         }
                         term_count += 1;
@@ -144,7 +159,7 @@ impl QueryTranslator {
                                 result.push_str(&format!("{} = '{}'", key, val));
                             }
                             _ => {
-                                result.push_str(&format!("json_field('{}', raw) = {}", key, self.value(value, params).unwrap()));
+                                result.push_str(&format!("json_field({}, raw) = {}", field_literal(key)?, self.value(value, params)?));
                             }
                         }
                         term_count += 1;
@@ -158,7 +173,7 @@ impl QueryTranslator {
                                 result.push_str(&format!("{} = '{}'", key, val));
                             }
                             _ => {
-                                result.push_str(&format!("json_field('{}', raw) = {}", key, self.value(value, params).unwrap()));
+                                result.push_str(&format!("json_field({}, raw) = {}", field_literal(key)?, self.value(value, params)?));
                             }
                         }
                         term_count += 1;
@@ -172,7 +187,7 @@ impl QueryTranslator {
                                 return Err(format!("_id cannot be double"));
                             }
                             _ => {
-                                result.push_str(&format!("json_field('{}', raw) = {}", key, self.value(value, params).unwrap()));
+                                result.push_str(&format!("json_field({}, raw) = {}", field_literal(key)?, self.value(value, params)?));
                             }
                         }
                         term_count += 1;
@@ -186,7 +201,7 @@ impl QueryTranslator {
                                 return Err(format!("_id cannot be boolean"));
                             }
                             _ => {
-                                result.push_str(&format!("json_field('{}', raw) = {}", key, self.value(value, params).unwrap()));
+                                result.push_str(&format!("json_field({}, raw) = {}", field_literal(key)?, self.value(value, params)?));
                             }
                         }
                         term_count += 1;
@@ -201,6 +216,12 @@ impl QueryTranslator {
         Ok(result)
     }
 
+    /// Translates a query document nested in a logical operator, in parentheses. An empty document matches everything.
+    fn clause(&self, query: &bson::Document, params: &mut Vec<rusqlite::types::Value>) -> Result<String, String> {
+        let where_str = self.query_document(query, params)?;
+        Ok(format!("({})", if where_str.is_empty() { "1" } else { &where_str }))
+    }
+
     fn value(&self, value: &bson::Bson, params: &mut Vec<rusqlite::types::Value>) -> Result<String, String> {
         match value {
             bson::Bson::String(val) => {
@@ -229,488 +250,38 @@ impl QueryTranslator {
         Ok(format!("?{}", params.len()))
     }
 
-    fn nested(&self, scope: &str, value_doc: &bson::Document, params: &mut Vec<rusqlite::types::Value>) -> Result<String, String> {
-        let mut result = String::new();
-        let mut term_count = 0;
-        for (key, value) in value_doc.iter() {
-            if key.chars().nth(0).unwrap() == '$' {
-                match key.as_str() {
-                    "$lt" => match value {
-                        bson::Bson::Int32(val) => {
-                            if term_count > 0 {
-                                return Err(format!("Error in $lt: {}", value));
-                            }
-
-                            return Ok(format!("json_field('{}', raw) < {}", scope, self.value(value, params).unwrap()));
-                        }
-                        bson::Bson::Int64(val) => {
-                            if term_count > 0 {
-                                return Err(format!("Error in $lt: {}", value));
-                            }
-
-                            return Ok(format!("json_field('{}', raw) < {}", scope, self.value(value, params).unwrap()));
-                        }
-                        bson::Bson::Double(val) => {
-                            if term_count > 0 {
-                                return Err(format!("Error in $lt: {}", value));
-                            }
-
-                            return Ok(format!("json_field('{}', raw) < {}", scope, self.value(value, params).unwrap()));
-                        }
-                        bson::Bson::String(val) => {
-                            if term_count > 0 {
-                                return Err(format!("Error in $lt: {}", value));
-                            }
-
-                            return Ok(format!("json_field('{}', raw) < {}", scope, self.value(value, params).unwrap()));
-                        }
-                        _ => {
-                            return Err(format!("Error in $lt: {}", value));
-                        }
-                    },
-                    "$gt" => match value {
-                        bson::Bson::Int32(val) => {
-                            if term_count > 0 {
-                                return Err(format!("Error in $gt: {}", value));
-                            }
-
-                            return Ok(format!("json_field('{}', raw) > {}", scope, self.value(value, params).unwrap()));
-                        }
-                        bson::Bson::Int64(val) => {
-                            if term_count > 0 {
-                                return Err(format!("Error in $gt: {}", value));
-                            }
-
-                            return Ok(format!("json_field('{}', raw) > {}", scope, self.value(value, params).unwrap()));
-                        }
-                        bson::Bson::Double(val) => {
-                            if term_count > 0 {
-                                return Err(format!("Error in $gt: {}", value));
-                            }
-
-                            return Ok(format!("json_field('{}', raw) > {}", scope, self.value(value, params).unwrap()));
-                        }
-                        bson::Bson::String(val) => {
-                            if term_count > 0 {
-                                return Err(format!("Error in $gt: {}", value));
-                            }
-
-                            return Ok(format!("json_field('{}', raw) > {}", scope, self.value(value, params).unwrap()));
-                        }
-                        _ => {
-                            return Err(format!("Error in $gt: {}", value));
-                        }
-                    },
-                    "$gte" => match value {
-                        bson::Bson::Int32(val) => {
-                            if term_count > 0 {
-                                return Err(format!("Error in $gt: {}", value));
-                            }
-
-                            return Ok(format!("json_field('{}', raw) >= {}", scope, self.value(value, params).unwrap()));
-                        }
-                        bson::Bson::Int64(val) => {
-                            if term_count > 0 {
-                                return Err(format!("Error in $gt: {}", value));
-                            }
-
-                            return Ok(format!("json_field('{}', raw) >= {}", scope, self.value(value, params).unwrap()));
-                        }
-                        bson::Bson::Double(val) => {
-                            if term_count > 0 {
-                                return Err(format!("Error in $gt: {}", value));
-                            }
-
-                            return Ok(format!("json_field('{}', raw) >= {}", scope, self.value(value, params).unwrap()));
-                        }
-                        bson::Bson::String(val) => {
-                            if term_count > 0 {
-                                return Err(format!("Error in $gt: {}", value));
-                            }
-
-                            return Ok(format!("json_field('{}', raw) >= {}", scope, self.value(value, params).unwrap()));
-                        }
-                        _ => {
-                            return Err(format!("Error in $gt: {}", value));
-                        }
-                    },
-                    "$eq" => match value {
-                        bson::Bson::Int32(val) => {
-                            if term_count > 0 {
-                                return Err(format!("Error in $gt: {}", value));
-                            }
-
-                            return Ok(format!("json_field('{}', raw) = {}", scope, self.value(value, params).unwrap()));
-                        }
-                        bson::Bson::Int64(val) => {
-                            if term_count > 0 {
-                                return Err(format!("Error in $gt: {}", value));
-                            }
-
-                            return Ok(format!("json_field('{}', raw) = {}", scope, self.value(value, params).unwrap()));
-                        }
-                        bson::Bson::Double(val) => {
-                            if term_count > 0 {
-                                return Err(format!("Error in $gt: {}", value));
-                            }
-
-                            return Ok(format!("json_field('{}', raw) = {}", scope, self.value(value, params).unwrap()));
-                        }
-                        bson::Bson::String(val) => {
-                            if term_count > 0 {
-                                return Err(format!("Error in $gt: {}", value));
-                            }
-
-                            return Ok(format!("json_field('{}', raw) = {}", scope, self.value(value, params).unwrap()));
-                        }
-                        _ => {
-                            return Err(format!("Error in $gt: {}", value));
-                        }
-                    },
-                    "$in" => {
-                        if let bson::Bson::Array(arr) = value {
-                            if term_count > 0 {
-                                return Err(format!("Error in $in: {}", value));
-                            }
-
-                            let mut in_values = String::new();
-
-                            for val in arr {
-                                if in_values.len() > 0 {
-                                    in_values.push_str(", ");
-                                }
-
-                                in_values.push_str(self.value(val, params).unwrap().as_str());
-                            }
-                            return Ok(format!("json_field('{}', raw) IN ({})", scope, in_values));
-                        } else {
-                            return Err(format!("Error in $in: {}", value));
-                        }
-                    }
-                    "$lte" => match value {
-                        bson::Bson::Int32(val) => {
-                            if term_count > 0 {
-                                return Err(format!("Error in $gt: {}", value));
-                            }
-
-                            return Ok(format!("json_field('{}', raw) <= {}", scope, self.value(value, params).unwrap()));
-                        }
-                        bson::Bson::Int64(val) => {
-                            if term_count > 0 {
-                                return Err(format!("Error in $gt: {}", value));
-                            }
-
-                            return Ok(format!("json_field('{}', raw) <= {}", scope, self.value(value, params).unwrap()));
-                        }
-                        bson::Bson::Double(val) => {
-                            if term_count > 0 {
-                                return Err(format!("Error in $gt: {}", value));
-                            }
-
-                            return Ok(format!("json_field('{}', raw) <= {}", scope, self.value(value, params).unwrap()));
-                        }
-                        bson::Bson::String(val) => {
-                            if term_count > 0 {
-                                return Err(format!("Error in $gt: {}", value));
-                            }
-
-                            return Ok(format!("json_field('{}', raw) <= {}", scope, self.value(value, params).unwrap()));
-                        }
-                        _ => {
-                            return Err(format!("Error in $gt: {}", value));
-                        }
-                    },
-                    "$ne" => match value {
-                        bson::Bson::Int32(val) => {
-                            if term_count > 0 {
-                                return Err(format!("Error in $gt: {}", value));
-                            }
-
-                            return Ok(format!("json_field('{}', raw) != {}", scope, self.value(value, params).unwrap()));
-                        }
-                        bson::Bson::Int64(val) => {
-                            if term_count > 0 {
-                                return Err(format!("Error in $gt: {}", value));
-                            }
-
-                            return Ok(format!("json_field('{}', raw) != {}", scope, self.value(value, params).unwrap()));
-                        }
-                        bson::Bson::Double(val) => {
-                            if term_count > 0 {
-                                return Err(format!("Error in $gt: {}", value));
-                            }
-
-                            return Ok(format!("json_field('{}', raw) != {}", scope, self.value(value, params).unwrap()));
-                        }
-                        bson::Bson::String(val) => {
-                            if term_count > 0 {
-                                return Err(format!("Error in $gt: {}", value));
-                            }
-
-                            return Ok(format!("json_field('{}', raw) != {}", scope, self.value(value, params).unwrap()));
-                        }
-                        _ => {
-                            return Err(format!("Error in $gt: {}", value));
-                        }
-                    },
-                    "$nin" => {
-                        if let bson::Bson::Array(arr) = value {
-                            if term_count > 0 {
-                                return Err(format!("Error in $nin: {}", value));
-                            }
-
-                            let mut in_values = String::new();
-
-                            for val in arr {
-                                if in_values.len() > 0 {
-                                    in_values.push_str(", ");
-                                }
-
-                                in_values.push_str(self.value(val, params).unwrap().as_str());
-                            }
-                            return Ok(format!("json_field('{}', raw) NOT IN ({})", scope, in_values));
-                        } else {
-                            return Err(format!("Error in $nin: {}", value));
-                        }
-                    }
-                    "$exists" => {
-                        if let bson::Bson::Boolean(val) = value {
-                            if term_count > 0 {
-                                return Err(format!("Error in $exists: {}", value));
-                            }
-
-                            return Ok(format!("json_field_exists('{}', raw) = {}", scope, self.value(value, params).unwrap()));
-                        } else {
-                            return Err(format!("Error in $exists: {}", value));
-                        }
-                    }
-                    "$type" => match value {
-                        bson::Bson::String(val) => {
-                            if term_count > 0 {
-                                return Err(format!("Error in $exists: {}", value));
-                            }
-
-                            return Ok(format!("json_field_type('{}', raw) = {}", scope, self.value(value, params).unwrap()));
-                        }
-                        bson::Bson::Array(arr) => {
-                            if term_count > 0 {
-                                return Err(format!("Error in $exists: {}", value));
-                            }
-
-                            let mut in_values = String::new();
-
-                            for val in arr {
-                                if let bson::Bson::String(val_str) = val {
-                                    if in_values.len() > 0 {
-                                        in_values.push_str(", ");
-                                    }
-
-                                    in_values.push_str(self.value(val, params).unwrap().as_str());
-                                } else {
-                                    return Err(format!("Error in $exists: {}", value));
-                                }
-                            }
-
-                            return Ok(format!("json_field_type('{}', raw) IN ({})", scope, in_values));
-                        }
-                        _ => {
-                            return Err(format!("Error in $exists: {}", value));
-                        }
-                    },
-                    "$size" => match value {
-                        bson::Bson::Int32(val) => {
-                            if term_count > 0 {
-                                return Err(format!("Error in $size: {}", value));
-                            }
-
-                            return Ok(format!("json_field_size('{}', raw) = {}", scope, self.value(value, params).unwrap()));
-                        }
-                        bson::Bson::Int64(val) => {
-                            if term_count > 0 {
-                                return Err(format!("Error in $size: {}", value));
-                            }
-
-                            return Ok(format!("json_field_size('{}', raw) = {}", scope, self.value(value, params).unwrap()));
-                        }
-                        bson::Bson::Double(val) => {
-                            if term_count > 0 {
-                                return Err(format!("Error in $size: {}", value));
-                            }
-
-                            return Ok(format!("json_field_size('{}', raw) = {}", scope, self.value(value, params).unwrap()));
-                        }
-                        _ => {
-                            return Err(format!("Error in $size: {}", value));
-                        }
-                    },
-                    "$all" => {
-                        if let bson::Bson::Array(arr) = value {
-                            if term_count > 0 {
-                                return Err(format!("Error in $all: {}", value));
-                            }
-
-                            let mut in_values = String::new();
-
-                            for val in arr {
-                                if in_values.len() > 0 {
-                                    in_values.push_str(" AND ");
-                                }
-
-                                in_values.push_str(&format!("json_field('{}', raw) = {}", scope, self.value(val, params).unwrap()));
-                            }
-
-                            return Ok(format!("({})", in_values.as_str()));
-                        } else {
-                            return Err(format!("Error in $all: {}", value));
-                        }
-                    }
-                    "$elemMatch" => {
-                        if let bson::Bson::Array(arr) = value {
-                            if term_count > 0 {
-                                return Err(format!("Error in $elemMatch: {}", value));
-                            }
-
-                            let mut in_values = String::new();
-
-                            for val in arr {
-                                if in_values.len() > 0 {
-                                    in_values.push_str(", ");
-                                }
-
-                                in_values.push_str(&format!("{}", self.value(val, params).unwrap()));
-                            }
-
-                            return Ok(format!("json_field('{}', raw) IN ({})", scope, in_values.as_str()));
-                        } else {
-                            return Err(format!("Error in $elemMatch: {}", value));
-                        }
-                    }
-                    "$bitsAllClear" => match value {
-                        bson::Bson::Int64(val) => {
-                            if term_count > 0 {
-                                return Err(format!("Error in $bitsAllClear: {}", value));
-                            }
-
-                            return Ok(format!("json_field_bits_all_clear('{}', raw, {})", scope, self.value(value, params).unwrap()));
-                        }
-                        bson::Bson::Int32(val) => {
-                            if term_count > 0 {
-                                return Err(format!("Error in $bitsAllClear: {}", value));
-                            }
-
-                            return Ok(format!("json_field_bits_all_clear('{}', raw, {})", scope, self.value(value, params).unwrap()));
-                        }
-                        _ => {
-                            return Err(format!("Error in $bitsAllClear: {}", value));
-                        }
-                    },
-                    "$bitsAllSet" => match value {
-                        bson::Bson::Int64(val) => {
-                            if term_count > 0 {
-                                return Err(format!("Error in $bitsAllSet: {}", value));
-                            }
-
-                            return Ok(format!("json_field_bits_all_set('{}', raw, {})", scope, self.value(value, params).unwrap()));
-                        }
-                        bson::Bson::Int32(val) => {
-                            if term_count > 0 {
-                                return Err(format!("Error in $bitsAllSet: {}", value));
-                            }
-
-                            return Ok(format!("json_field_bits_all_set('{}', raw, {})", scope, self.value(value, params).unwrap()));
-                        }
-                        _ => {
-                            return Err(format!("Error in $bitsAllSet: {}", value));
-                        }
-                    },
-                    "$bitsAnyClear" => match value {
-                        bson::Bson::Int64(val) => {
-                            if term_count > 0 {
-                                return Err(format!("Error in $bitsAnyClear: {}", value));
-                            }
-
-                            return Ok(format!("json_field_bits_any_clear('{}', raw, {})", scope, self.value(value, params).unwrap()));
-                        }
-                        bson::Bson::Int32(val) => {
-                            if term_count > 0 {
-                                return Err(format!("Error in $bitsAnyClear: {}", value));
-                            }
-
-                            return Ok(format!("json_field_bits_any_clear('{}', raw, {})", scope, self.value(value, params).unwrap()));
-                        }
-                        _ => {
-                            return Err(format!("Error in $bitsAnyClear: {}", value));
-                        }
-                    },
-                    "$bitsAnySet" => match value {
-                        bson::Bson::Int64(val) => {
-                            if term_count > 0 {
-                                return Err(format!("Error in $bitsAnySet: {}", value));
-                            }
-
-                            return Ok(format!("json_field_bits_any_set('{}', raw, {})", scope, self.value(value, params).unwrap()));
-                        }
-                        bson::Bson::Int32(val) => {
-                            if term_count > 0 {
-                                return Err(format!("Error in $bitsAnySet: {}", value));
-                            }
-
-                            return Ok(format!("json_field_bits_any_set('{}', raw, {})", scope, self.value(value, params).unwrap()));
-                        }
-                        _ => {
-                            return Err(format!("Error in $bitsAnySet: {}", value));
-                        }
-                    },
-                    "$mod" => {
-                        if let bson::Bson::Array(arr) = value {
-                            if arr.len() != 2 {
-                                return Err(format!("Error in $mod: {}", value));
-                            }
-
-                            let divisor = arr[0].clone();
-                            let remainder = arr[1].clone();
-
-                            if divisor.as_i64().unwrap() == 0 {
-                                return Err(format!("Error in $mod: {}, Divisor can't be zero.", value));
-                            }
+    /// Translates a condition with no SQL counterpart into a call of `bson_matches`, which runs the matcher on the document.
+    fn matches(&self, scope: &str, condition: bson::Document, params: &mut Vec<rusqlite::types::Value>) -> Result<String, String> {
+        field_literal(scope)?;
+        self.matcher(bson::doc! { scope: condition }, params)
+    }
 
-                            return Ok(format!("json_field('{}', raw) % {} = {}", scope, self.value(&divisor, params).unwrap(), self.value(&remainder, params).unwrap()));
-                        } else {
-                            return Err(format!("Error in $mod: {}", value));
-                        }
-                    }
-                    //todo $jsonSchema and $text not implemented
-                    "$regex" => {
-                        if let bson::Bson::String(_) = value {
-                            let mut options = String::new();
-                            if value_doc.keys().count() > 1 {
-                                if let Some(option_obj) = value_doc.get("$options") {
-                                    if let bson::Bson::String(str_val) = option_obj {
-                                        options = str_val.to_string();
-                                    } else {
-                                        return Err(format!("Error in $regex: {}", value));
-                                    }
-                                }
-                            }
+    /// Translates an `$expr` query, which only the matcher can evaluate.
+    fn expr(&self, value: &bson::Bson, params: &mut Vec<rusqlite::types::Value>) -> Result<String, String> {
+        self.matcher(bson::doc! { "$expr": value }, params)
+    }
 
-                            let regex = value;
+    fn matcher(&self, query: bson::Document, params: &mut Vec<rusqlite::types::Value>) -> Result<String, String> {
+        let mut bytes = Vec::new();
+        query.to_writer(&mut bytes).map_err(|e| e.to_string())?;
+        params.push(rusqlite::types::Value::Blob(bytes));
+        Ok(format!("bson_matches(raw, ?{})", params.len()))
+    }
 
-                            return Ok(format!("json_field_regex('{}', raw, {}, {})", scope, self.value(regex, params).unwrap(), self.value(&bson::Bson::String(options), params).unwrap()));
-                        } else {
-                            return Err(format!("Error in $regex: {}", value));
-                        }
-                    }
-                    _ => {}
+    /// Translates the conditions on the field `scope`, e.g. `{"$gte": 18, "$lt": 65}`. Every operator and subfield is a term, and the terms are
+    /// joined with AND.
+    fn nested(&self, scope: &str, value_doc: &bson::Document, params: &mut Vec<rusqlite::types::Value>) -> Result<String, String> {
+        let mut terms = Vec::new();
+        for (key, value) in value_doc.iter() {
+            if key.starts_with('$') {
+                if let Some(term) = self.operator(scope, key, value, value_doc, params)? {
+                    terms.push(term);
                 }
             } else {
                 match value {
                     bson::Bson::Document(value_doc) => {
                         if let Ok(res) = self.nested(key, &value_doc, params) {
-                            if term_count > 0 {
-                                result.push_str(" AND ");
-                            }
-                            result.push_str(&res);
-                            term_count += 1;
+                            terms.push(res);
                         } else {
                             return Err(format!("Error in nested query: {}", value_doc));
                         }
@@ -721,51 +292,27 @@ impl QueryTranslator {
                     }
 
                     bson::Bson::String(value_str) => {
-                        if term_count > 0 {
-                            result.push_str(" AND ");
-                        }
-                        result.push_str(&format!("json_field('{}.{}', raw) = {}", scope, key, self.value(value, params).unwrap()));
-                        term_count += 1;
+                        terms.push(format!("json_field({}, raw) = {}", field_literal(&format!("{}.{}", scope, key))?, self.value(value, params)?));
                     }
 
                     bson::Bson::Int32(value_i32) => {
-                        if term_count > 0 {
-                            result.push_str(" AND ");
-                        }
-                        result.push_str(&format!("json_field('{}.{}', raw) = {}", scope, key, self.value(value, params).unwrap()));
-                        term_count += 1;
+                        terms.push(format!("json_field({}, raw) = {}", field_literal(&format!("{}.{}", scope, key))?, self.value(value, params)?));
                     }
 
                     bson::Bson::Int64(value_i64) => {
-                        if term_count > 0 {
-                            result.push_str(" AND ");
-                        }
-                        result.push_str(&format!("json_field('{}.{}', raw) = {}", scope, key, self.value(value, params).unwrap()));
-                        term_count += 1;
+                        terms.push(format!("json_field({}, raw) = {}", field_literal(&format!("{}.{}", scope, key))?, self.value(value, params)?));
                     }
 
                     bson::Bson::Double(value_f64) => {
-                        if term_count > 0 {
-                            result.push_str(" AND ");
-                        }
-                        result.push_str(&format!("json_field('{}.{}', raw) = {}", scope, key, self.value(value, params).unwrap()));
-                        term_count += 1;
+                        terms.push(format!("json_field({}, raw) = {}", field_literal(&format!("{}.{}", scope, key))?, self.value(value, params)?));
                     }
 
                     bson::Bson::Boolean(value_bool) => {
-                        if term_count > 0 {
-                            result.push_str(" AND ");
-                        }
-                        result.push_str(&format!("json_field('{}.{}', raw) = {}", scope, key, self.value(value, params).unwrap()));
-                        term_count += 1;
+                        terms.push(format!("json_field({}, raw) = {}", field_literal(&format!("{}.{}", scope, key))?, self.value(value, params)?));
                     }
 
                     bson::Bson::Null => {
-                        if term_count > 0 {
-                            result.push_str(" AND ");
-                        }
-                        result.push_str(&format!("json_field('{}.{}', raw) IS NULL", scope, key));
-                        term_count += 1;
+                        terms.push(format!("json_field({}, raw) IS NULL", field_literal(&format!("{}.{}", scope, key))?));
                     }
 
                     _ => {
@@ -775,7 +322,139 @@ impl QueryTranslator {
             }
         }
 
-        Ok(result)
+        if terms.is_empty() {
+            return Err(format!("Empty query on {}", scope));
+        }
+        Ok(terms.join(" AND "))
+    }
+
+    /// Translates the operator `key` of the conditions `value_doc` on the field `scope`. Returns `None` for `$options`, which is part of
+    /// `$regex`.
+    fn operator(&self, scope: &str, key: &str, value: &bson::Bson, value_doc: &bson::Document, params: &mut Vec<rusqlite::types::Value>) -> Result<Option<String>, String> {
+        match key {
+            "$lt" | "$gt" | "$gte" | "$lte" | "$eq" | "$ne" => {
+                let operator = match key {
+                    "$lt" => "<",
+                    "$gt" => ">",
+                    "$gte" => ">=",
+                    "$lte" => "<=",
+                    "$eq" => "=",
+                    _ => "!=",
+                };
+                match value {
+                    bson::Bson::Int32(_) | bson::Bson::Int64(_) | bson::Bson::Double(_) | bson::Bson::String(_) => Ok(Some(format!("json_field({}, raw) {} {}", field_literal(scope)?, operator, self.value(value, params)?))),
+                    // other values are compared by the matcher.
+                    _ => self.matches(scope, bson::doc! { key: value }, params).map(Some),
+                }
+            }
+            "$in" | "$nin" => {
+                if let bson::Bson::Array(arr) = value {
+                    if !arr.iter().all(|val| matches!(val, bson::Bson::String(_) | bson::Bson::Int32(_) | bson::Bson::Int64(_) | bson::Bson::Double(_))) {
+                        return self.matches(scope, bson::doc! { key: value }, params).map(Some);
+                    }
+
+                    let mut in_values = String::new();
+                    for val in arr {
+                        if !in_values.is_empty() {
+                            in_values.push_str(", ");
+                        }
+
+                        in_values.push_str(self.value(val, params)?.as_str());
+                    }
+                    Ok(Some(format!("json_field({}, raw) {} ({})", field_literal(scope)?, if key == "$in" { "IN" } else { "NOT IN" }, in_values)))
+                } else {
+                    Err(format!("Error in {}: {}", key, value))
+                }
+            }
+            "$exists" => match value {
+                bson::Bson::Boolean(_) => self.matches(scope, bson::doc! { key: value }, params).map(Some),
+                _ => Err(format!("Error in $exists: {}", value)),
+            },
+            "$type" => match value {
+                bson::Bson::String(_) | bson::Bson::Int32(_) | bson::Bson::Int64(_) | bson::Bson::Double(_) | bson::Bson::Array(_) => self.matches(scope, bson::doc! { key: value }, params).map(Some),
+                _ => Err(format!("Error in $type: {}", value)),
+            },
+            "$size" => match value {
+                bson::Bson::Int32(_) | bson::Bson::Int64(_) | bson::Bson::Double(_) => self.matches(scope, bson::doc! { key: value }, params).map(Some),
+                _ => Err(format!("Error in $size: {}", value)),
+            },
+            "$all" => {
+                if let bson::Bson::Array(arr) = value {
+                    let mut in_values = String::new();
+
+                    for val in arr {
+                        if !in_values.is_empty() {
+                            in_values.push_str(" AND ");
+                        }
+
+                        in_values.push_str(&format!("json_field({}, raw) = {}", field_literal(scope)?, self.value(val, params)?));
+                    }
+
+                    Ok(Some(format!("({})", in_values.as_str())))
+                } else {
+                    Err(format!("Error in $all: {}", value))
+                }
+            }
+            "$elemMatch" => {
+                if let bson::Bson::Array(arr) = value {
+                    let mut in_values = String::new();
+
+                    for val in arr {
+                        if !in_values.is_empty() {
+                            in_values.push_str(", ");
+                        }
+
+                        in_values.push_str(&self.value(val, params)?);
+                    }
+
+                    Ok(Some(format!("json_field({}, raw) IN ({})", field_literal(scope)?, in_values.as_str())))
+                } else {
+                    Err(format!("Error in $elemMatch: {}", value))
+                }
+            }
+            "$bitsAllClear" | "$bitsAllSet" | "$bitsAnyClear" | "$bitsAnySet" => match value {
+                bson::Bson::Int32(_) | bson::Bson::Int64(_) => self.matches(scope, bson::doc! { key: value }, params).map(Some),
+                _ => Err(format!("Error in {}: {}", key, value)),
+            },
+            "$mod" => {
+                if let bson::Bson::Array(arr) = value {
+                    if arr.len() != 2 {
+                        return Err(format!("Error in $mod: {}", value));
+                    }
+
+                    let divisor = arr[0].clone();
+                    let remainder = arr[1].clone();
+
+                    if divisor.as_i64().or_else(|| divisor.as_i32().map(i64::from)) == Some(0) || divisor.as_f64() == Some(0.0) {
+                        return Err(format!("Error in $mod: {}, Divisor can't be zero.", value));
+                    }
+
+                    Ok(Some(format!("json_field({}, raw) % {} = {}", field_literal(scope)?, self.value(&divisor, params)?, self.value(&remainder, params)?)))
+                } else {
+                    Err(format!("Error in $mod: {}", value))
+                }
+            }
+            //todo $jsonSchema and $text not implemented
+            "$regex" => {
+                if let bson::Bson::String(_) | bson::Bson::RegularExpression(_) = value {
+                    let mut condition = bson::doc! { key: value };
+                    if let Some(options) = value_doc.get("$options") {
+                        if let bson::Bson::String(_) = options {
+                            condition.insert("$options", options);
+                        } else {
+                            return Err(format!("Error in $regex: {}", value));
+                        }
+                    }
+
+                    self.matches(scope, condition, params).map(Some)
+                } else {
+                    Err(format!("Error in $regex: {}", value))
+                }
+            }
+            // read by $regex.
+            "$options" if value_doc.contains_key("$regex") => Ok(None),
+            _ => Err(format!("Unsupported operator: {}", key)),
+        }
     }
 
     fn or(&self, arr: &bson::Array, params: &mut Vec<rusqlite::types::Value>) -> Result<String, String> {
@@ -784,7 +463,7 @@ impl QueryTranslator {
         for doc in arr {
             if let bson::Bson::Document(value_doc) = doc {
                 for (key, value) in value_doc.iter() {
-                    if key.chars().nth(0).unwrap() == '$' {
+                    if key.starts_with('$') {
                         match key.as_str() {
                             "$or" => {
                                 if let bson::Bson::Array(arr) = value {
@@ -845,18 +524,28 @@ impl QueryTranslator {
                                                 in_values.push_str(" OR ");
                                             }
 
-                                            in_values.push_str(self.nested("", value_doc, params).unwrap().as_str());
+                                            in_values.push_str(&self.clause(value_doc, params)?);
                                         } else {
                                             return Err(format!("Error in $nor: {}", value));
                                         }
                                     }
 
+                                    if in_values.is_empty() {
+                                        return Err(String::from("$nor needs a non-empty array"));
+                                    }
                                     result.push_str(&format!("NOT ({}) ", &in_values));
                                     term_count += 1;
                                 } else {
                                     return Err(format!("Error in $nor: {}", value));
                                 }
                             }
+                            "$expr" => {
+                                if term_count > 0 {
+                                    result.push_str(" OR ");
+                                }
+                                result.push_str(&self.expr(value, params)?);
+                                term_count += 1;
+                            }
                             _ => {
                                 return Err(format!("Unsupported operator: {}", key));
                             }
@@ -867,35 +556,35 @@ impl QueryTranslator {
                                 if term_count > 0 {
                                     result.push_str(" OR ");
                                 }
-                                result.push_str(&format!("json_field('{}', raw) = {}", key, self.value(value, params).unwrap()));
+                                result.push_str(&format!("json_field({}, raw) = {}", field_literal(key)?, self.value(value, params)?));
                                 term_count += 1;
                             }
                             bson::Bson::Int64(val) => {
                                 if term_count > 0 {
                                     result.push_str(" OR ");
                                 }
-                                result.push_str(&format!("json_field('{}', raw) = {}", key, self.value(value, params).unwrap()));
+                                result.push_str(&format!("json_field({}, raw) = {}", field_literal(key)?, self.value(value, params)?));
                                 term_count += 1;
                             }
                             bson::Bson::Int32(val) => {
                                 if term_count > 0 {
                                     result.push_str(" OR ");
                                 }
-                                result.push_str(&format!("json_field('{}', raw) = {}", key, self.value(value, params).unwrap()));
+                                result.push_str(&format!("json_field({}, raw) = {}", field_literal(key)?, self.value(value, params)?));
                                 term_count += 1;
                             }
                             bson::Bson::Double(val) => {
                                 if term_count > 0 {
                                     result.push_str(" OR ");
                                 }
-                                result.push_str(&format!("json_field('{}', raw) = {}", key, self.value(value, params).unwrap()));
+                                result.push_str(&format!("json_field({}, raw) = {}", field_literal(key)?, self.value(value, params)?));
                                 term_count += 1;
                             }
                             bson::Bson::Boolean(val) => {
                                 if term_count > 0 {
                                     result.push_str(" OR ");
                                 }
-                                result.push_str(&format!("json_field('{}', raw) = {}", key, self.value(value, params).unwrap()));
+                                result.push_str(&format!("json_field({}, raw) = {}", field_literal(key)?, self.value(value, params)?));
                                 term_count += 1;
                             }
                             bson::Bson::Document(value_doc) => {
@@ -916,7 +605,7 @@ impl QueryTranslator {
                                 if term_count > 0 {
                                     result.push_str(" OR ");
                                 }
-                                result.push_str(&format!("json_field('{}', raw) IS NULL", key));
+                                result.push_str(&format!("json_field({}, raw) IS NULL", field_literal(key)?));
                                 term_count += 1;
                             }
                             _ => {
@@ -929,6 +618,9 @@ impl QueryTranslator {
                 return Err(format!("Unsupported type"));
             }
         }
+        if result.is_empty() {
+            return Err(String::from("$or needs a non-empty query"));
+        }
         Ok(result)
     }
 
@@ -938,7 +630,7 @@ impl QueryTranslator {
         for doc in arr {
             if let bson::Bson::Document(value_doc) = doc {
                 for (key, value) in value_doc.iter() {
-                    if key.chars().nth(0).unwrap() == '$' {
+                    if key.starts_with('$') {
                         match key.as_str() {
                             "$or" => {
                                 if let bson::Bson::Array(arr) = value {
@@ -999,16 +691,26 @@ impl QueryTranslator {
                                                 in_values.push_str(" OR ");
                                             }
 
-                                            in_values.push_str(self.nested("", &doc, params).unwrap().as_str());
+                                            in_values.push_str(&self.clause(&doc, params)?);
                                         }
                                     }
 
+                                    if in_values.is_empty() {
+                                        return Err(String::from("$nor needs a non-empty array"));
+                                    }
                                     result.push_str(&format!("NOT ({}) ", &in_values));
                                     term_count += 1;
                                 } else {
                                     return Err(format!("Error in $nor: {}", value));
                                 }
                             }
+                            "$expr" => {
+                                if term_count > 0 {
+                                    result.push_str(" AND ");
+                                }
+                                result.push_str(&self.expr(value, params)?);
+                                term_count += 1;
+                            }
                             _ => {
                                 return Err(format!("Unsupported operator: {}", key));
                             }
@@ -1033,42 +735,42 @@ impl QueryTranslator {
                                 if term_count > 0 {
                                     result.push_str(" AND ");
                                 }
-                                result.push_str(&format!("json_field('{}', raw) = {}", key, self.value(value, params).unwrap()));
+                                result.push_str(&format!("json_field({}, raw) = {}", field_literal(key)?, self.value(value, params)?));
                                 term_count += 1;
                             }
                             bson::Bson::Int32(val) => {
                                 if term_count > 0 {
                                     result.push_str(" AND ");
                                 }
-                                result.push_str(&format!("json_field('{}', raw) = {}", key, self.value(value, params).unwrap()));
+                                result.push_str(&format!("json_field({}, raw) = {}", field_literal(key)?, self.value(value, params)?));
                                 term_count += 1;
                             }
                             bson::Bson::Int64(val) => {
                                 if term_count > 0 {
                                     result.push_str(" AND ");
                                 }
-                                result.push_str(&format!("json_field('{}', raw) = {}", key, self.value(value, params).unwrap()));
+                                result.push_str(&format!("json_field({}, raw) = {}", field_literal(key)?, self.value(value, params)?));
                                 term_count += 1;
                             }
                             bson::Bson::Boolean(val) => {
                                 if term_count > 0 {
                                     result.push_str(" AND ");
                                 }
-                                result.push_str(&format!("json_field('{}', raw) = {}", key, self.value(value, params).unwrap()));
+                                result.push_str(&format!("json_field({}, raw) = {}", field_literal(key)?, self.value(value, params)?));
                                 term_count += 1;
                             }
                             bson::Bson::Double(val) => {
                                 if term_count > 0 {
                                     result.push_str(" AND ");
                                 }
-                                result.push_str(&format!("json_field('{}', raw) = {}", key, self.value(value, params).unwrap()));
+                                result.push_str(&format!("json_field({}, raw) = {}", field_literal(key)?, self.value(value, params)?));
                                 term_count += 1;
                             }
                             bson::Bson::Null => {
                                 if term_count > 0 {
                                     result.push_str(" AND ");
                                 }
-                                result.push_str(&format!("json_field('{}', raw) IS NULL", key));
+                                result.push_str(&format!("json_field({}, raw) IS NULL", field_literal(key)?));
                                 term_count += 1;
                             }
                             _ => {
@@ -1081,18 +783,21 @@ impl QueryTranslator {
                 return Err(format!("Unsupported type"));
             }
         }
+        if result.is_empty() {
+            return Err(String::from("$and needs a non-empty query"));
+        }
         Ok(result)
     }
 
     fn not(&self, value_doc: &bson::Document, params: &mut Vec<rusqlite::types::Value>) -> Result<String, String> {
         let mut result = String::new();
         for (key, value) in value_doc.iter() {
-            if key.chars().nth(0).unwrap() == '$' {
+            if key.starts_with('$') {
                 match key.as_str() {
                     "$or" => {
                         if let bson::Bson::Array(arr) = value {
                             if let Ok(res) = self.or(arr, params) {
-                                result.push_str(&format!("json_field('{}', raw) IS NOT ({})", key, &res));
+                                result.push_str(&format!("json_field({}, raw) IS NOT ({})", field_literal(key)?, &res));
                             } else {
                                 return Err(format!("Error in $or: {}", value));
                             }
@@ -1103,7 +808,7 @@ impl QueryTranslator {
                     "$and" => {
                         if let bson::Bson::Array(arr) = value {
                             if let Ok(res) = self.and(arr, params) {
-                                result.push_str(&format!("json_field('{}', raw) IS NOT ({})", key, &res));
+                                result.push_str(&format!("json_field({}, raw) IS NOT ({})", field_literal(key)?, &res));
                             } else {
                                 return Err(format!("Error in $and: {}", value));
                             }
@@ -1114,7 +819,7 @@ impl QueryTranslator {
                     "$not" => {
                         if let bson::Bson::Document(val_doc) = value {
                             if let Ok(res) = self.not(val_doc, params) {
-                                result.push_str(&format!("json_field('{}', raw) IS NOT ({})", key, &res));
+                                result.push_str(&format!("json_field({}, raw) IS NOT ({})", field_literal(key)?, &res));
                             } else {
                                 return Err(format!("Error in $not: {}", value));
                             }
@@ -1132,17 +837,23 @@ impl QueryTranslator {
                                         in_values.push_str(" OR ");
                                     }
 
-                                    in_values.push_str(self.nested("", &doc, params).unwrap().as_str());
+                                    in_values.push_str(&self.clause(&doc, params)?);
                                 } else {
                                     return Err(format!("Error in $nor: {}", value));
                                 }
                             }
 
+                            if in_values.is_empty() {
+                                return Err(String::from("$nor needs a non-empty array"));
+                            }
                             result.push_str(&format!("NOT ({}) ", &in_values));
                         } else {
                             return Err(format!("Error in $nor: {}", value));
                         }
                     }
+                    "$expr" => {
+                        result.push_str(&format!("NOT ({})", self.expr(value, params)?));
+                    }
                     _ => {
                         return Err(format!("Unsupported operator: {}", key));
                     }
@@ -1151,7 +862,7 @@ impl QueryTranslator {
                 match value {
                     bson::Bson::Document(doc) => {
                         if let Ok(res) = self.nested(key, &doc, params) {
-                            result.push_str(&format!("json_field('{}', raw) IS NOT ({})", key, &res));
+                            result.push_str(&format!("json_field({}, raw) IS NOT ({})", field_literal(key)?, &res));
                         } else {
                             return Err(format!("Error in nested query: {}", value));
                         }
@@ -1160,22 +871,22 @@ impl QueryTranslator {
                         return Err(format!("Unsupported type: {}", value));
                     }
                     bson::Bson::String(val) => {
-                        result.push_str(&format!("json_field('{}', raw) IS NOT {}", key, self.value(value, params).unwrap()));
+                        result.push_str(&format!("json_field({}, raw) IS NOT {}", field_literal(key)?, self.value(value, params)?));
                     }
                     bson::Bson::Boolean(val) => {
-                        result.push_str(&format!("json_field('{}', raw) IS NOT {}", key, self.value(value, params).unwrap()));
+                        result.push_str(&format!("json_field({}, raw) IS NOT {}", field_literal(key)?, self.value(value, params)?));
                     }
                     bson::Bson::Int64(val) => {
-                        result.push_str(&format!("json_field('{}', raw) IS NOT {}", key, self.value(value, params).unwrap()));
+                        result.push_str(&format!("json_field({}, raw) IS NOT {}", field_literal(key)?, self.value(value, params)?));
                     }
                     bson::Bson::Int32(val) => {
-                        result.push_str(&format!("json_field('{}', raw) IS NOT {}", key, self.value(value, params).unwrap()));
+                        result.push_str(&format!("json_field({}, raw) IS NOT {}", field_literal(key)?, self.value(value, params)?));
                     }
                     bson::Bson::Double(val) => {
-                        result.push_str(&format!("json_field('{}', raw) IS NOT {}", key, self.value(value, params).unwrap()));
+                        result.push_str(&format!("json_field({}, raw) IS NOT {}", field_literal(key)?, self.value(value, params)?));
                     }
                     bson::Bson::Null => {
-                        result.push_str(&format!("json_field('{}', raw) IS NOT NULL", key));
+                        result.push_str(&format!("json_field({}, raw) IS NOT NULL", field_literal(key)?));
                     }
                     _ => {
                         return Err(format!("Unsupported type: {}", value));
@@ -1184,6 +895,9 @@ impl QueryTranslator {
             }
             break;
         }
+        if result.is_empty() {
+            return Err(String::from("$not needs a non-empty query"));
+        }
         Ok(result)
     }
 }
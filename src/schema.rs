@@ -0,0 +1,255 @@
+//! Schemas of typed documents.
+//!
+//! A type implementing [`HoardDocument`], usually with `#[derive(HoardDocument)]`, declares the collection it is stored in, its indexes, a TTL
+//! field and a validator. [`crate::database::Database::ensure_schema()`] applies the declaration at startup: it creates the collection if it
+//! doesn't exist, creates the missing indexes and installs the validator and the TTL, replacing the previous ones. Running it again with the
+//! same schema changes nothing, so it is safe to call every time the application starts. Indexes that were removed from the schema are left
+//! in place, use [`CollectionTrait::drop_index()`] to remove them.
+//!
+//! Like capped collections, the validator and the TTL are enforced by triggers on the collection's table, so they apply to every write, typed
+//! or not:
+//! * the validator is a query document, and a document that doesn't match it can't be inserted, nor can an update produce one. Documents that
+//!   existed before the validator are not checked.
+//! * a document expires once the date in its TTL field is older than the given number of seconds. Expired documents are deleted by inserts.
+//!   The field holds a bson date or a number of milliseconds since the epoch; documents without it never expire.
+//!
+//! ```rust
+//! use hoardbase::database::{Database, DatabaseConfig};
+//! use hoardbase::schema::HoardDocument;
+//! use serde::{Deserialize, Serialize};
+//!
+//! #[derive(Serialize, Deserialize, HoardDocument)]
+//! #[hoard(collection = "users", validator = r#"{"age": {"$gte": 0}}"#)]
+//! struct User {
+//!     #[index(unique)]
+//!     email: String,
+//!     age: i32,
+//!     #[ttl(expire_after = 86400)]
+//!     last_seen: bson::DateTime,
+//! }
+//!
+//! let mut db = Database::open(&DatabaseConfig::in_memory()).unwrap();
+//! let mut users = db.ensure_schema::<User>().unwrap();
+//! users.insert_one(&User { email: "ada@example.com".to_string(), age: 36, last_seen: bson::DateTime::now() }).unwrap();
+//! assert!(users.insert_one(&User { email: "bob@example.com".to_string(), age: -1, last_seen: bson::DateTime::now() }).is_err());
+//! ```
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use crate::base::*;
+
+#[cfg(feature = "derive")]
+pub use hoardbase_derive::HoardDocument;
+
+/// A type stored in a collection whose indexes, TTL and validator are declared by [`HoardDocument::schema()`].
+pub trait HoardDocument: Serialize + DeserializeOwned {
+    fn schema() -> Schema;
+}
+
+/// An index declared by a [`Schema`].
+#[derive(Clone, Debug, PartialEq)]
+pub struct IndexSpec {
+    /// The indexed fields and their order, 1 or -1, as passed to [`CollectionTrait::create_index()`].
+    pub keys: bson::Document,
+    pub unique: bool,
+}
+
+/// The TTL declared by a [`Schema`].
+#[derive(Clone, Debug, PartialEq)]
+pub struct Ttl {
+    pub field: String,
+    pub expire_after_seconds: u64,
+}
+
+/// The declaration of a collection of typed documents. This struct uses the builder pattern.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Schema {
+    pub collection: String,
+    pub indexes: Vec<IndexSpec>,
+    pub ttl: Option<Ttl>,
+    /// A query document every document written has to match.
+    pub validator: Option<bson::Document>,
+}
+
+impl Schema {
+    pub fn new(collection: &str) -> Self {
+        Schema { collection: collection.to_string(), indexes: Vec::new(), ttl: None, validator: None }
+    }
+
+    /// Declares an index on `fields`, given with their order, 1 or -1.
+    pub fn index<'a>(&'a mut self, fields: &[(&str, i32)], unique: bool) -> &'a mut Schema {
+        let mut keys = bson::Document::new();
+        for (field, order) in fields {
+            keys.insert(*field, *order);
+        }
+        self.indexes.push(IndexSpec { keys, unique });
+        self
+    }
+
+    /// Declares the TTL field. The field is indexed, so that finding the expired documents doesn't scan the collection.
+    pub fn ttl<'a>(&'a mut self, field: &str, expire_after_seconds: u64) -> &'a mut Schema {
+        self.ttl = Some(Ttl { field: field.to_string(), expire_after_seconds });
+        self
+    }
+
+    pub fn validator<'a>(&'a mut self, validator: bson::Document) -> &'a mut Schema {
+        self.validator = Some(validator);
+        self
+    }
+
+    /// Sets the validator from its JSON text. This is what `#[hoard(validator = "...")]` expands to; the derive macro checks the JSON at compile
+    /// time.
+    ///
+    /// Panics if `json` isn't a JSON object.
+    pub fn validator_json<'a>(&'a mut self, json: &str) -> &'a mut Schema {
+        let value: serde_json::Value = serde_json::from_str(json).expect("The validator isn't valid JSON");
+        match bson::to_bson(&value) {
+            Ok(bson::Bson::Document(validator)) => self.validator(validator),
+            _ => panic!("The validator isn't a JSON object"),
+        }
+    }
+
+    /// The indexes to create: the declared ones, and the one on the TTL field.
+    pub(crate) fn all_indexes(&self) -> Vec<IndexSpec> {
+        let mut indexes = self.indexes.clone();
+        if let Some(ttl) = &self.ttl {
+            let keys = bson::doc! { ttl.field.as_str(): 1 };
+            if !indexes.iter().any(|index| index.keys == keys) {
+                indexes.push(IndexSpec { keys, unique: false });
+            }
+        }
+        indexes
+    }
+}
+
+/// Replaces the validation and TTL triggers of the collection with those of `schema`. The validator is embedded in the trigger as a blob and
+/// checked with the `bson_matches` sqlite function.
+pub(crate) fn create_triggers(tx: &rusqlite::Transaction, config: &CollectionConfig, schema: &Schema) -> Result<(), String> {
+    let table = &config.table_name;
+    for suffix in ["schema_insert", "schema_update", "schema_ttl"] {
+        tx.execute(&format!("DROP TRIGGER IF EXISTS [{}_{}]", table, suffix), []).map_err(|e| e.to_string())?;
+    }
+
+    if let Some(validator) = &schema.validator {
+        crate::matcher::matches(&bson::Document::new(), validator)?;
+        let mut bytes = Vec::new();
+        validator.to_writer(&mut bytes).map_err(|e| e.to_string())?;
+        for (suffix, event) in [("schema_insert", "INSERT"), ("schema_update", "UPDATE OF raw")] {
+            tx.execute(
+                &format!(
+                    "CREATE TRIGGER [{}_{}] BEFORE {} ON [{}] WHEN NOT bson_matches(NEW.raw, X'{}') BEGIN SELECT RAISE(ABORT, 'Document failed validation'); END",
+                    table,
+                    suffix,
+                    event,
+                    table,
+                    hex::encode(&bytes)
+                ),
+                [],
+            )
+            .map_err(|e| e.to_string())?;
+        }
+    }
+
+    if let Some(ttl) = &schema.ttl {
+        let expire_after_millis = crate::capped::limit(ttl.expire_after_seconds).saturating_mul(1000);
+        tx.execute(
+            &format!(
+                "CREATE TRIGGER [{}_schema_ttl] AFTER INSERT ON [{}] BEGIN DELETE FROM [{}] WHERE json_field({}, raw) < CAST((julianday('now') - 2440587.5) * 86400000 AS INTEGER) - {}; END",
+                table,
+                table,
+                table,
+                crate::identifier::field_literal(&ttl.field)?,
+                expire_after_millis
+            ),
+            [],
+        )
+        .map_err(|e| e.to_string())?;
+    }
+    Ok(())
+}
+
+#[cfg(all(test, feature = "derive"))]
+mod tests {
+    use super::*;
+    use crate::database::{Database, DatabaseConfig};
+    use serde::Deserialize;
+
+    #[derive(Serialize, Deserialize, HoardDocument, Debug, PartialEq)]
+    #[hoard(collection = "accounts", validator = r#"{"balance": {"$gte": 0}}"#)]
+    #[serde(rename_all = "camelCase")]
+    #[index("ownerName", "-openedAt")]
+    struct Account {
+        #[index(unique)]
+        number: String,
+        owner_name: String,
+        #[index(desc)]
+        balance: i64,
+        opened_at: i64,
+        #[serde(rename = "seen")]
+        #[ttl(expire_after = 60)]
+        last_seen: bson::DateTime,
+    }
+
+    #[derive(Serialize, Deserialize, HoardDocument)]
+    struct AuditEvent {
+        message: String,
+    }
+
+    fn account(number: &str, balance: i64, last_seen: bson::DateTime) -> Account {
+        Account { number: number.to_string(), owner_name: "Ada".to_string(), balance, opened_at: 0, last_seen }
+    }
+
+    #[test]
+    fn test_derive() {
+        let mut expected = Schema::new("accounts");
+        expected.index(&[("ownerName", 1), ("openedAt", -1)], false).index(&[("number", 1)], true).index(&[("balance", -1)], false).ttl("seen", 60);
+        expected.validator(bson::doc! { "balance": { "$gte": 0_i64 } });
+        assert_eq!(Account::schema(), expected);
+        assert_eq!(AuditEvent::schema(), Schema::new("audit_event"));
+    }
+
+    #[test]
+    fn test_ensure_schema() {
+        let mut db = Database::open(&DatabaseConfig::in_memory()).unwrap();
+        let now = bson::DateTime::now();
+        {
+            let mut accounts = db.ensure_schema::<Account>().unwrap();
+            accounts.insert_one(&account("1", 100, now)).unwrap();
+            assert!(accounts.insert_one(&account("1", 5, now)).is_err());
+            let error = accounts.insert_one(&account("2", -5, now)).unwrap_err();
+            assert!(error.contains("Document failed validation"), "{}", error);
+            let error = accounts.insert_many(&[account("5", 1, now), account("6", -1, now)]).unwrap_err();
+            assert!(error.contains("Document failed validation"), "{}", error);
+            assert_eq!(accounts.count_documents(&bson::doc! {}, &None).unwrap(), 1);
+            assert!(accounts.update_one(&bson::doc! { "number": "1" }, &bson::doc! { "$inc": { "balance": -200 } }, 0, false, &None).is_err());
+            // an insert deletes the expired documents.
+            accounts.insert_one(&account("3", 0, bson::DateTime::from_millis(now.timestamp_millis() - 61_000))).unwrap();
+            accounts.insert_one(&account("4", 0, now)).unwrap();
+            let numbers: Vec<String> = accounts.find(&bson::doc! {}, &None).unwrap().into_iter().map(|record| record.unwrap().data.number).collect();
+            assert_eq!(numbers, vec!["1", "4"]);
+        }
+        let names = |db: &mut Database| -> Vec<String> { db.collection("accounts").unwrap().get_indexes().unwrap().into_iter().map(|index| index.name).collect() };
+        let indexes = names(&mut db);
+        // the three declared indexes, the one on the TTL field and the hash index.
+        assert_eq!(indexes.len(), 5);
+        assert!(indexes.contains(&"accounts_ownername_openedat".to_string()), "{:?}", indexes);
+
+        // applying the schema again changes nothing, and an updated schema replaces the validator.
+        db.ensure_schema::<Account>().unwrap();
+        assert_eq!(names(&mut db), indexes);
+        let mut relaxed = Account::schema();
+        relaxed.validator = None;
+        relaxed.ttl = None;
+        db.apply_schema(&relaxed).unwrap();
+        let mut accounts = db.typed_collection::<Account>("accounts").unwrap();
+        accounts.insert_one(&account("5", -5, bson::DateTime::from_millis(0))).unwrap();
+        assert_eq!(accounts.count_documents(&bson::doc! {}, &None).unwrap(), 3);
+
+        let mut invalid = Schema::new("accounts");
+        invalid.validator(bson::doc! { "balance": { "$gtee": 0 } });
+        assert!(db.apply_schema(&invalid).is_err());
+        db.create_time_series("metrics", &crate::time_series::TimeSeriesConfig::default("time")).unwrap();
+        assert!(db.apply_schema(&Schema::new("metrics")).is_err());
+    }
+}
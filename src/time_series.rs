@@ -0,0 +1,494 @@
+//! Time-series collections.
+//!
+//! A time-series collection, created with [`crate::database::Database::create_time_series()`], stores measurements: documents with a date in
+//! their time field, and optionally a meta field naming their source, like a sensor id. Like mongodb, hoardbase doesn't store measurements one
+//! per row. Measurements with the same meta value whose times fall into the same range, an hour, a day or 30 days depending on the
+//! [`Granularity`], are grouped into buckets of at most [`MAX_BUCKET_SIZE`] measurements. A bucket is a row holding its meta value, its time range
+//! and the deflate-compressed bson of its measurements in time order.
+//!
+//! Conditions on the time field and the meta field are answered from the indexed bucket columns, so a range query only decompresses the buckets
+//! that can hold matches; the measurements in them are matched in memory. [`TimeSeries::aggregate()`] does the same for a leading `$match` stage
+//! and runs the rest of the pipeline with [`crate::aggregation`]. Measurements are grouped by time window with `$dateTrunc`:
+//!
+//! ```rust
+//! use hoardbase::database::{Database, DatabaseConfig};
+//! use hoardbase::time_series::TimeSeriesConfig;
+//!
+//! let mut db = Database::open(&DatabaseConfig::in_memory()).unwrap();
+//! let mut config = TimeSeriesConfig::default("time");
+//! config.meta_field("sensor");
+//! let mut readings = db.create_time_series("readings", &config).unwrap();
+//! for minute in 0..90 {
+//!     let time = bson::DateTime::from_millis(minute * 60_000);
+//!     readings.insert_one(&bson::doc! { "time": time, "sensor": "a", "temperature": 20 + minute % 3 }).unwrap();
+//! }
+//! let hourly = readings
+//!     .aggregate(&[
+//!         bson::doc! { "$match": { "sensor": "a" } },
+//!         bson::doc! { "$group": { "_id": { "$dateTrunc": { "date": "$time", "unit": "hour" } }, "max": { "$max": "$temperature" } } },
+//!     ])
+//!     .unwrap();
+//! assert_eq!(hourly.len(), 2);
+//! ```
+//!
+//! With [`TimeSeriesConfig::expire_after()`], buckets whose newest measurement is older than the given number of seconds are deleted by
+//! inserts and by [`TimeSeries::expire()`].
+
+use std::collections::BTreeMap;
+use std::io::{Read, Write};
+
+use flate2::read::DeflateDecoder;
+use flate2::write::DeflateEncoder;
+use flate2::Compression;
+use rusqlite::params_from_iter;
+
+use crate::aggregation::{self, Resolver};
+use crate::base::CollectionConfig;
+use crate::lookup::CatalogResolver;
+use crate::matcher;
+
+/// The maximum number of measurements in a bucket.
+pub const MAX_BUCKET_SIZE: i64 = 1000;
+
+/// How far apart the measurements of a series are, which decides the time range a bucket covers.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Granularity {
+    /// Buckets cover an hour.
+    Seconds,
+    /// Buckets cover a day.
+    Minutes,
+    /// Buckets cover 30 days.
+    Hours,
+}
+
+impl Granularity {
+    /// The time range a bucket covers in milliseconds.
+    pub fn bucket_span(&self) -> i64 {
+        match self {
+            Granularity::Seconds => 3_600_000,
+            Granularity::Minutes => 86_400_000,
+            Granularity::Hours => 30 * 86_400_000,
+        }
+    }
+
+    pub(crate) fn as_str(&self) -> &'static str {
+        match self {
+            Granularity::Seconds => "seconds",
+            Granularity::Minutes => "minutes",
+            Granularity::Hours => "hours",
+        }
+    }
+
+    pub(crate) fn from_str(name: &str) -> Option<Granularity> {
+        match name {
+            "seconds" => Some(Granularity::Seconds),
+            "minutes" => Some(Granularity::Minutes),
+            "hours" => Some(Granularity::Hours),
+            _ => None,
+        }
+    }
+}
+
+/// The options of a time-series collection. They can't be changed once the collection is created.
+#[derive(Clone, Debug)]
+pub struct TimeSeriesConfig {
+    /// The field holding the date of a measurement. Every measurement must have one.
+    pub time_field: String,
+    /// The field identifying the source of a measurement, if any.
+    pub meta_field: Option<String>,
+    pub granularity: Granularity,
+    /// The age in seconds after which measurements are deleted, 0 to keep them.
+    pub expire_after_seconds: u64,
+}
+
+impl TimeSeriesConfig {
+    pub fn default(time_field: &str) -> TimeSeriesConfig {
+        TimeSeriesConfig { time_field: time_field.to_string(), meta_field: None, granularity: Granularity::Seconds, expire_after_seconds: 0 }
+    }
+
+    pub fn meta_field<'a>(&'a mut self, field: &str) -> &'a mut TimeSeriesConfig {
+        self.meta_field = Some(field.to_string());
+        self
+    }
+
+    pub fn granularity<'a>(&'a mut self, arg: Granularity) -> &'a mut TimeSeriesConfig {
+        self.granularity = arg;
+        self
+    }
+
+    /// Deletes measurements once they are older than `seconds`. Measurements are deleted a bucket at a time, when the newest measurement of
+    /// the bucket expires.
+    pub fn expire_after<'a>(&'a mut self, seconds: u64) -> &'a mut TimeSeriesConfig {
+        self.expire_after_seconds = seconds;
+        self
+    }
+}
+
+/// Creates the bucket table of a time-series collection and its indexes.
+pub(crate) fn create_table(tx: &rusqlite::Transaction, table_name: &str) -> rusqlite::Result<()> {
+    tx.execute_batch(&format!(
+        "CREATE TABLE [{t}] (
+              _id              INTEGER PRIMARY KEY,
+              meta             BLOB,
+              start_time       INTEGER NOT NULL,
+              end_time         INTEGER NOT NULL,
+              count            INTEGER NOT NULL,
+              raw              BLOB NOT NULL
+              );
+        CREATE INDEX [{t}__bucket] ON [{t}](meta, start_time);
+        CREATE INDEX [{t}__start_time] ON [{t}](start_time);
+        CREATE INDEX [{t}__end_time] ON [{t}](end_time);",
+        t = table_name
+    ))
+}
+
+fn compress(measurements: &[(i64, bson::Document)]) -> Result<Vec<u8>, String> {
+    let mut encoder = DeflateEncoder::new(Vec::new(), Compression::default());
+    for (_, measurement) in measurements {
+        let mut bytes = Vec::new();
+        measurement.to_writer(&mut bytes).map_err(|e| e.to_string())?;
+        encoder.write_all(&bytes).map_err(|e| e.to_string())?;
+    }
+    encoder.finish().map_err(|e| e.to_string())
+}
+
+fn decompress(raw: &[u8]) -> Result<Vec<bson::Document>, String> {
+    let mut bytes = Vec::new();
+    DeflateDecoder::new(raw).read_to_end(&mut bytes).map_err(|e| format!("Failed to decompress a bucket: {}", e))?;
+    let mut reader = bytes.as_slice();
+    let mut measurements = Vec::new();
+    while !reader.is_empty() {
+        measurements.push(bson::Document::from_reader(&mut reader).map_err(|e| e.to_string())?);
+    }
+    Ok(measurements)
+}
+
+/// The bytes a meta value is stored as, so that buckets can be looked up by it.
+fn meta_key(value: Option<&bson::Bson>) -> Result<Option<Vec<u8>>, String> {
+    match value {
+        None | Some(bson::Bson::Null) | Some(bson::Bson::Undefined) => Ok(None),
+        Some(value) => {
+            let mut bytes = Vec::new();
+            bson::doc! { "": value.clone() }.to_writer(&mut bytes).map_err(|e| e.to_string())?;
+            Ok(Some(bytes))
+        }
+    }
+}
+
+fn date_millis(value: &bson::Bson) -> Option<i64> {
+    match value {
+        bson::Bson::DateTime(date) => Some(date.timestamp_millis()),
+        _ => None,
+    }
+}
+
+/// Measurements with their times, by meta value and bucket start time.
+type Series = BTreeMap<(Option<Vec<u8>>, i64), Vec<(i64, bson::Document)>>;
+
+/// A time-series collection. Obtained from [`crate::database::Database::create_time_series()`] or
+/// [`crate::database::Database::time_series()`].
+pub struct TimeSeries<'a> {
+    pub name: String,
+    pub config: CollectionConfig,
+    pub db: &'a rusqlite::Connection,
+}
+
+impl<'a> TimeSeries<'a> {
+    /// The time-series options.
+    pub fn options(&self) -> &TimeSeriesConfig {
+        self.config.time_series.as_ref().expect("a time-series collection has time-series options")
+    }
+
+    fn time_of(&self, measurement: &bson::Document) -> Result<i64, String> {
+        let field = &self.options().time_field;
+        match matcher::get_path(measurement, field) {
+            Some(value) => date_millis(value).ok_or_else(|| format!("The time field '{}' must be a date, found: {}", field, value)),
+            None => Err(format!("A measurement must have the time field '{}'", field)),
+        }
+    }
+
+    /// Inserts a measurement.
+    pub fn insert_one(&mut self, document: &bson::Document) -> Result<(), String> {
+        self.insert_many(&vec![document.clone()])
+    }
+
+    /// Inserts measurements, adding them to the open bucket of their series and time range, or to new buckets. Either all of them are inserted,
+    /// or none.
+    pub fn insert_many(&mut self, documents: &Vec<bson::Document>) -> Result<(), String> {
+        let span = self.options().granularity.bucket_span();
+        let mut series: Series = BTreeMap::new();
+        for document in documents {
+            let time = self.time_of(document)?;
+            let meta = match &self.options().meta_field {
+                Some(field) => meta_key(matcher::get_path(document, field))?,
+                None => None,
+            };
+            let start_time = time.div_euclid(span).checked_mul(span).ok_or_else(|| format!("The time {} is too early to be placed in a bucket", bson::DateTime::from_millis(time)))?;
+            series.entry((meta, start_time)).or_default().push((time, document.clone()));
+        }
+
+        // a savepoint rather than a transaction, so that this also works inside a transaction.
+        self.db.execute_batch("SAVEPOINT time_series_insert").map_err(|e| e.to_string())?;
+        let result = series.into_iter().try_for_each(|((meta, start_time), measurements)| self.add_to_bucket(meta, start_time, measurements)).and_then(|_| self.expire_buckets().map(|_| ()));
+        let end = if result.is_ok() { "RELEASE time_series_insert" } else { "ROLLBACK TO time_series_insert; RELEASE time_series_insert" };
+        self.db.execute_batch(end).map_err(|e| e.to_string())?;
+        result
+    }
+
+    fn add_to_bucket(&self, meta: Option<Vec<u8>>, start_time: i64, measurements: Vec<(i64, bson::Document)>) -> Result<(), String> {
+        let mut stmt = self
+            .db
+            .prepare_cached(&format!("SELECT _id, raw FROM [{}] WHERE meta IS ?1 AND start_time = ?2 AND count < ?3 ORDER BY _id DESC LIMIT 1", self.config.table_name))
+            .map_err(|e| e.to_string())?;
+        let open: Option<(i64, Vec<u8>)> = match stmt.query_row(rusqlite::params![meta, start_time, MAX_BUCKET_SIZE], |row| Ok((row.get(0)?, row.get(1)?))) {
+            Ok(bucket) => Some(bucket),
+            Err(rusqlite::Error::QueryReturnedNoRows) => None,
+            Err(e) => return Err(e.to_string()),
+        };
+
+        let mut all = Vec::new();
+        if let Some((_, raw)) = &open {
+            for measurement in decompress(raw)? {
+                all.push((self.time_of(&measurement)?, measurement));
+            }
+        }
+        all.extend(measurements);
+        // stable, so measurements with the same time keep their insertion order.
+        all.sort_by_key(|(time, _)| *time);
+
+        let mut open_id = open.map(|(id, _)| id);
+        for chunk in all.chunks(MAX_BUCKET_SIZE as usize) {
+            let end_time = chunk.last().map(|(time, _)| *time).unwrap_or(start_time);
+            let raw = compress(chunk)?;
+            match open_id.take() {
+                Some(id) => self.db.execute(
+                    &format!("UPDATE [{}] SET end_time = ?2, count = ?3, raw = ?4 WHERE _id = ?1", self.config.table_name),
+                    rusqlite::params![id, end_time, chunk.len() as i64, raw],
+                ),
+                None => self.db.execute(
+                    &format!("INSERT INTO [{}] (meta, start_time, end_time, count, raw) VALUES (?1, ?2, ?3, ?4, ?5)", self.config.table_name),
+                    rusqlite::params![meta, start_time, end_time, chunk.len() as i64, raw],
+                ),
+            }
+            .map_err(|e| e.to_string())?;
+        }
+        Ok(())
+    }
+
+    /// Translates the conditions of `query` on the time field and on the meta field into conditions on the bucket columns. The buckets that
+    /// are left out can't hold a matching measurement; the ones that are selected still have to be matched measurement by measurement.
+    fn bucket_filter(&self, query: &bson::Document) -> (String, Vec<rusqlite::types::Value>) {
+        let options = self.options();
+        let mut conditions = Vec::new();
+        let mut params = Vec::new();
+        for (key, value) in query.iter() {
+            if key == &options.time_field {
+                let (mut low, mut high) = (None::<i64>, None::<i64>);
+                match value {
+                    bson::Bson::DateTime(date) => {
+                        low = Some(date.timestamp_millis());
+                        high = low;
+                    }
+                    bson::Bson::Document(operators) => {
+                        for (operator, operand) in operators.iter() {
+                            match (operator.as_str(), date_millis(operand)) {
+                                ("$gt" | "$gte", Some(time)) => low = Some(low.map_or(time, |low| low.max(time))),
+                                ("$lt" | "$lte", Some(time)) => high = Some(high.map_or(time, |high| high.min(time))),
+                                ("$eq", Some(time)) => {
+                                    low = Some(time);
+                                    high = Some(time);
+                                }
+                                _ => {}
+                            }
+                        }
+                    }
+                    _ => {}
+                }
+                if let Some(low) = low {
+                    // the start time condition is implied, but unlike the end time it can use the index.
+                    params.push(rusqlite::types::Value::Integer(low));
+                    conditions.push(format!("end_time >= ?{}", params.len()));
+                    params.push(rusqlite::types::Value::Integer(low.saturating_sub(options.granularity.bucket_span())));
+                    conditions.push(format!("start_time > ?{}", params.len()));
+                }
+                if let Some(high) = high {
+                    params.push(rusqlite::types::Value::Integer(high));
+                    conditions.push(format!("start_time <= ?{}", params.len()));
+                }
+            } else if Some(key) == options.meta_field.as_ref() {
+                // numbers are left out: 1 and 1.0 are equal, but are stored differently.
+                if matches!(value, bson::Bson::String(_) | bson::Bson::ObjectId(_) | bson::Bson::Boolean(_) | bson::Bson::DateTime(_)) {
+                    if let Ok(Some(meta)) = meta_key(Some(value)) {
+                        params.push(rusqlite::types::Value::Blob(meta));
+                        conditions.push(format!("meta = ?{}", params.len()));
+                    }
+                }
+            }
+        }
+        let where_str = if conditions.is_empty() { String::new() } else { format!("WHERE {}", conditions.join(" AND ")) };
+        (where_str, params)
+    }
+
+    /// Calls `f` with every measurement in the buckets that can match `query`, bucket by bucket in the order of their time ranges.
+    fn scan(&self, query: &bson::Document, f: &mut dyn FnMut(bson::Document) -> Result<(), String>) -> Result<(), String> {
+        let (where_str, params) = self.bucket_filter(query);
+        let mut stmt = self.db.prepare_cached(&format!("SELECT raw FROM [{}] {} ORDER BY start_time, _id", self.config.table_name, where_str)).map_err(|e| e.to_string())?;
+        let mut rows = stmt.query(params_from_iter(params.iter())).map_err(|e| e.to_string())?;
+        while let Some(row) = rows.next().map_err(|e| e.to_string())? {
+            let raw: Vec<u8> = row.get(0).map_err(|e| e.to_string())?;
+            for measurement in decompress(&raw)? {
+                if matcher::matches(&measurement, query)? {
+                    f(measurement)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Finds the measurements matching `query`. The measurements of a series are returned in time order.
+    pub fn find(&mut self, query: &bson::Document, f: &mut dyn FnMut(&bson::Document) -> Result<(), &'static str>) -> Result<(), String> {
+        self.scan(query, &mut |measurement| f(&measurement).map_err(|e| e.to_string()))
+    }
+
+    /// Counts the measurements matching `query`.
+    pub fn count_documents(&mut self, query: &bson::Document) -> Result<i64, String> {
+        if query.is_empty() {
+            return self.db.query_row(&format!("SELECT IFNULL(SUM(count), 0) FROM [{}]", self.config.table_name), [], |row| row.get(0)).map_err(|e| e.to_string());
+        }
+        let mut count = 0;
+        self.scan(query, &mut |_| {
+            count += 1;
+            Ok(())
+        })?;
+        Ok(count)
+    }
+
+    /// Runs an aggregation pipeline over the measurements, see [`crate::aggregation`]. A leading `$match` stage selects the buckets to read.
+    pub fn aggregate(&mut self, pipeline: &[bson::Document]) -> Result<Vec<bson::Document>, String> {
+        self.aggregate_with(pipeline, &CatalogResolver::new(self.db))
+    }
+
+    pub(crate) fn aggregate_with(&mut self, pipeline: &[bson::Document], resolver: &dyn Resolver) -> Result<Vec<bson::Document>, String> {
+        let (query, rest) = match pipeline.split_first() {
+            Some((first, rest)) if first.len() == 1 && matches!(first.get("$match"), Some(bson::Bson::Document(_))) => (first.get_document("$match").unwrap().clone(), rest),
+            _ => (bson::Document::new(), pipeline),
+        };
+        let mut measurements = Vec::new();
+        self.scan(&query, &mut |measurement| {
+            measurements.push(measurement);
+            Ok(())
+        })?;
+        aggregation::aggregate_with(measurements, rest, resolver)
+    }
+
+    /// Deletes the buckets whose newest measurement has expired, see [`TimeSeriesConfig::expire_after()`], and returns how many measurements
+    /// were deleted.
+    pub fn expire(&mut self) -> Result<i64, String> {
+        self.expire_buckets()
+    }
+
+    fn expire_buckets(&self) -> Result<i64, String> {
+        let expire_after_seconds = self.options().expire_after_seconds;
+        if expire_after_seconds == 0 {
+            return Ok(0);
+        }
+        let cutoff = bson::DateTime::now().timestamp_millis().saturating_sub(i64::try_from(expire_after_seconds).unwrap_or(i64::MAX).saturating_mul(1000));
+        let expired: i64 = self
+            .db
+            .query_row(&format!("SELECT IFNULL(SUM(count), 0) FROM [{}] WHERE end_time < ?1", self.config.table_name), [cutoff], |row| row.get(0))
+            .map_err(|e| e.to_string())?;
+        self.db.execute(&format!("DELETE FROM [{}] WHERE end_time < ?1", self.config.table_name), [cutoff]).map_err(|e| e.to_string())?;
+        Ok(expired)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::{Database, DatabaseConfig};
+
+    fn minutes(n: i64) -> bson::DateTime {
+        bson::DateTime::from_millis(n * 60_000)
+    }
+
+    #[test]
+    fn test_time_series() {
+        let path = DatabaseConfig::temporary().path;
+        let mut db = Database::open(&DatabaseConfig::new(&path)).unwrap();
+        let mut config = TimeSeriesConfig::default("time");
+        config.meta_field("sensor");
+        let mut readings = db.create_time_series("readings", &config).unwrap();
+
+        // three hours of readings from sensor a, one every 10 seconds, inserted newest first.
+        let documents: Vec<bson::Document> = (0..1080).rev().map(|i| bson::doc! { "time": bson::DateTime::from_millis(i * 10_000), "sensor": "a", "value": i % 7 }).collect();
+        readings.insert_many(&documents).unwrap();
+        readings.insert_one(&bson::doc! { "time": minutes(30), "sensor": "b", "value": 100 }).unwrap();
+        readings.insert_one(&bson::doc! { "time": minutes(31), "value": 200 }).unwrap();
+        let buckets = |readings: &TimeSeries| readings.db.query_row(&format!("SELECT COUNT(1) FROM [{}]", readings.config.table_name), [], |row| row.get::<_, i64>(0)).unwrap();
+        assert_eq!(buckets(&readings), 5);
+        assert_eq!(readings.count_documents(&bson::doc! {}).unwrap(), 1082);
+
+        // a full bucket is continued by a new one.
+        readings.insert_many(&(0..700).map(|i| bson::doc! { "time": bson::DateTime::from_millis(i), "sensor": "a", "value": -1 }).collect()).unwrap();
+        assert_eq!(buckets(&readings), 6);
+        assert_eq!(readings.count_documents(&bson::doc! { "value": -1 }).unwrap(), 700);
+
+        // the range only selects the buckets of the second hour, and the measurements come out in time order.
+        let query = bson::doc! { "sensor": "a", "time": { "$gte": minutes(60), "$lt": minutes(70) } };
+        let (where_str, params) = readings.bucket_filter(&query);
+        let selected: i64 = readings.db.query_row(&format!("SELECT COUNT(1) FROM [{}] {}", readings.config.table_name, where_str), params_from_iter(params.iter()), |row| row.get(0)).unwrap();
+        assert_eq!(selected, 1);
+        let mut times = Vec::new();
+        readings
+            .find(&query, &mut |measurement| {
+                times.push(measurement.get_datetime("time").unwrap().timestamp_millis());
+                Ok(())
+            })
+            .unwrap();
+        assert_eq!(times.len(), 60);
+        assert!(times.windows(2).all(|pair| pair[0] < pair[1]));
+        assert_eq!(readings.count_documents(&bson::doc! { "sensor": "b" }).unwrap(), 1);
+
+        let windows = readings
+            .aggregate(&[
+                bson::doc! { "$match": { "sensor": "a", "value": { "$gte": 0 } } },
+                bson::doc! { "$group": { "_id": { "$dateTrunc": { "date": "$time", "unit": "minute", "binSize": 30 } }, "n": { "$count": {} }, "max": { "$max": "$value" } } },
+                bson::doc! { "$sort": { "_id": 1 } },
+            ])
+            .unwrap();
+        assert_eq!(windows.len(), 6);
+        assert_eq!(windows[1], bson::doc! { "_id": minutes(30), "n": 180, "max": 6_i64 });
+
+        // a bad measurement fails the whole batch.
+        assert!(readings.insert_one(&bson::doc! { "sensor": "a" }).is_err());
+        assert!(readings.insert_many(&vec![bson::doc! { "time": minutes(1) }, bson::doc! { "time": "yesterday" }]).is_err());
+        assert!(readings.insert_one(&bson::doc! { "time": bson::DateTime::MIN }).is_err());
+        assert_eq!(readings.count_documents(&bson::doc! { "time": { "$gte": bson::DateTime::MIN } }).unwrap(), 1782);
+        assert_eq!(readings.count_documents(&bson::doc! {}).unwrap(), 1782);
+
+        let mut config = TimeSeriesConfig::default("t");
+        config.granularity(Granularity::Hours).expire_after(3600);
+        let mut recent = db.create_time_series("recent", &config).unwrap();
+        recent.insert_one(&bson::doc! { "t": bson::DateTime::from_millis(0) }).unwrap();
+        assert_eq!(recent.count_documents(&bson::doc! {}).unwrap(), 0);
+        recent.insert_one(&bson::doc! { "t": bson::DateTime::now() }).unwrap();
+        assert_eq!(recent.count_documents(&bson::doc! {}).unwrap(), 1);
+
+        assert!(db.collection("readings").is_err());
+        assert!(db.create_collection("readings", &crate::base::CollectionConfig::default("readings")).is_err());
+        assert!(db.create_time_series("bad", &TimeSeriesConfig::default("a..b")).is_err());
+        db.create_collection("plain", &crate::base::CollectionConfig::default("plain")).unwrap();
+        assert!(db.time_series("plain").is_err());
+        db.rename_collection("readings", "sensor readings").unwrap();
+        drop(db);
+
+        let mut db = Database::open(&DatabaseConfig::new(&path)).unwrap();
+        assert_eq!(db.collection_stats("sensor readings").unwrap().count, 1782);
+        let readings = db.time_series("sensor readings").unwrap();
+        assert_eq!(readings.options().meta_field.as_deref(), Some("sensor"));
+        let recent = db.time_series("recent").unwrap();
+        assert_eq!((recent.options().granularity, recent.options().expire_after_seconds), (Granularity::Hours, 3600));
+        drop(db);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
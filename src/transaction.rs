@@ -32,6 +32,10 @@ impl<'conn> CollectionTrait for TransactionCollection<'conn> {
         }
     }
 
+    fn explain(&mut self, query: &bson::Document, options: &Option<SearchOption>, verbosity: ExplainVerbosity) -> std::result::Result<QueryPlan, String> {
+        explain_internal(self.db, &self.config, query, options, verbosity)
+    }
+
     fn get_name(&self) -> &str {
         self.name.as_str()
     }
@@ -125,21 +129,39 @@ impl<'conn> CollectionTrait for TransactionCollection<'conn> {
         }
     }
 
-    fn update_one(&mut self, query: &bson::Document, update: &bson::Document, skip: i64, upsert: bool) -> std::result::Result<Option<Record>, String> {
+    fn update_one(&mut self, query: &bson::Document, update: &bson::Document, skip: i64, upsert: bool, options: &Option<UpdateOption>) -> std::result::Result<Option<Record>, String> {
+        match (self.config.should_hash_document, self.config.should_log_last_modified) {
+            (true, true) => update_one_internal::<_, _, true, true>(self.db, &self.config, query, update, skip, upsert, options),
+            (true, false) => update_one_internal::<_, _, true, false>(self.db, &self.config, query, update, skip, upsert, options),
+            (false, false) => update_one_internal::<_, _, false, false>(self.db, &self.config, query, update, skip, upsert, options),
+            (false, true) => update_one_internal::<_, _, false, true>(self.db, &self.config, query, update, skip, upsert, options),
+        }
+    }
+
+    fn update_many(&mut self, query: &bson::Document, update: &bson::Document, limit: i64, skip: i64, upsert: bool, options: &Option<UpdateOption>) -> Result<i64, String> {
+        match (self.config.should_hash_document, self.config.should_log_last_modified) {
+            (true, true) => update_many_internal::<_, _, true, true>(self.db, &self.config, query, update, limit, skip, upsert, options),
+            (true, false) => update_many_internal::<_, _, true, false>(self.db, &self.config, query, update, limit, skip, upsert, options),
+            (false, false) => update_many_internal::<_, _, false, false>(self.db, &self.config, query, update, limit, skip, upsert, options),
+            (false, true) => update_many_internal::<_, _, false, true>(self.db, &self.config, query, update, limit, skip, upsert, options),
+        }
+    }
+
+    fn update_one_pipeline(&mut self, query: &bson::Document, pipeline: &Vec<bson::Document>, skip: i64, upsert: bool) -> std::result::Result<Option<Record>, String> {
         match (self.config.should_hash_document, self.config.should_log_last_modified) {
-            (true, true) => update_one_internal::<_, _, true, true>(self.db, &self.config, query, update, skip, upsert),
-            (true, false) => update_one_internal::<_, _, true, false>(self.db, &self.config, query, update, skip, upsert),
-            (false, false) => update_one_internal::<_, _, false, false>(self.db, &self.config, query, update, skip, upsert),
-            (false, true) => update_one_internal::<_, _, false, true>(self.db, &self.config, query, update, skip, upsert),
+            (true, true) => update_one_pipeline_internal::<_, _, true, true>(self.db, &self.config, query, pipeline, skip, upsert),
+            (true, false) => update_one_pipeline_internal::<_, _, true, false>(self.db, &self.config, query, pipeline, skip, upsert),
+            (false, false) => update_one_pipeline_internal::<_, _, false, false>(self.db, &self.config, query, pipeline, skip, upsert),
+            (false, true) => update_one_pipeline_internal::<_, _, false, true>(self.db, &self.config, query, pipeline, skip, upsert),
         }
     }
 
-    fn update_many(&mut self, query: &bson::Document, update: &bson::Document, limit: i64, skip: i64, upsert: bool) -> Result<i64, String> {
+    fn update_many_pipeline(&mut self, query: &bson::Document, pipeline: &Vec<bson::Document>, limit: i64, skip: i64, upsert: bool) -> Result<i64, String> {
         match (self.config.should_hash_document, self.config.should_log_last_modified) {
-            (true, true) => update_many_internal::<_, _, true, true>(self.db, &self.config, query, update, limit, skip, upsert),
-            (true, false) => update_many_internal::<_, _, true, false>(self.db, &self.config, query, update, limit, skip, upsert),
-            (false, false) => update_many_internal::<_, _, false, false>(self.db, &self.config, query, update, limit, skip, upsert),
-            (false, true) => update_many_internal::<_, _, false, true>(self.db, &self.config, query, update, limit, skip, upsert),
+            (true, true) => update_many_pipeline_internal::<_, _, true, true>(self.db, &self.config, query, pipeline, limit, skip, upsert),
+            (true, false) => update_many_pipeline_internal::<_, _, true, false>(self.db, &self.config, query, pipeline, limit, skip, upsert),
+            (false, false) => update_many_pipeline_internal::<_, _, false, false>(self.db, &self.config, query, pipeline, limit, skip, upsert),
+            (false, true) => update_many_pipeline_internal::<_, _, false, true>(self.db, &self.config, query, pipeline, limit, skip, upsert),
         }
     }
 }
@@ -0,0 +1,222 @@
+//! Typed collections.
+//!
+//! A [`TypedCollection`] stores values of a Rust type instead of bson documents, converting them with serde, so the application doesn't need
+//! to build and pick apart documents. It is obtained with [`crate::database::Database::typed_collection()`], or by wrapping any collection with
+//! [`TypedCollection::new()`]. Queries and updates are still bson documents.
+//!
+//! A document that doesn't deserialize into the type, e.g. one that was written by an older version of the application, doesn't fail the
+//! whole query; [`TypedCollection::find()`] returns a [`DecodeError`] in its place.
+//!
+//! ```rust
+//! use hoardbase::base::CollectionConfig;
+//! use hoardbase::database::{Database, DatabaseConfig};
+//! use serde::{Deserialize, Serialize};
+//!
+//! #[derive(Serialize, Deserialize, Debug, PartialEq)]
+//! struct User {
+//!     name: String,
+//!     age: i32,
+//! }
+//!
+//! let mut db = Database::open(&DatabaseConfig::in_memory()).unwrap();
+//! db.create_collection("users", &CollectionConfig::default("users")).unwrap();
+//! let mut users = db.typed_collection::<User>("users").unwrap();
+//! users.insert_one(&User { name: "Ada".to_string(), age: 36 }).unwrap();
+//! let ada = users.find_one(&bson::doc! { "name": "Ada" }, 0).unwrap().unwrap();
+//! assert_eq!(ada.data.age, 36);
+//! ```
+
+use std::marker::PhantomData;
+
+use chrono::prelude::*;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use crate::base::*;
+
+/// A document read from a [`TypedCollection`], like a [`Record`] whose data is a `T`.
+#[derive(Clone, Debug)]
+pub struct TypedRecord<T> {
+    pub id: i64,
+    pub data: T,
+    /// Empty if the collection doesn't hash its documents.
+    pub hash: String,
+    /// The epoch if the collection doesn't log when its documents were last modified.
+    pub last_modified: DateTime<Utc>,
+}
+
+impl<T: DeserializeOwned> TypedRecord<T> {
+    /// Deserializes the data of a record.
+    pub fn from_record(record: &Record) -> Result<TypedRecord<T>, DecodeError> {
+        let data = bson::from_document(record.data.clone()).map_err(|e| DecodeError { id: record.id, message: e.to_string() })?;
+        Ok(TypedRecord { id: record.id, data, hash: record.hash.clone(), last_modified: record.last_modified })
+    }
+}
+
+/// A document that doesn't deserialize into the type of a [`TypedCollection`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DecodeError {
+    /// The id of the document.
+    pub id: i64,
+    pub message: String,
+}
+
+impl std::fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Failed to deserialize document {}: {}", self.id, self.message)
+    }
+}
+
+impl std::error::Error for DecodeError {}
+
+/// Serializes a value into the document that is stored.
+fn to_document<T: Serialize>(value: &T) -> Result<bson::Document, String> {
+    bson::to_document(value).map_err(|e| format!("Failed to serialize the document: {}", e))
+}
+
+/// A collection of values of type `T`. It wraps a [`crate::collection::Collection`] or a [`crate::transaction::TransactionCollection`],
+/// which stays available as [`TypedCollection::collection`] for the operations that don't involve a `T`.
+pub struct TypedCollection<C, T> {
+    pub collection: C,
+    _type: PhantomData<T>,
+}
+
+impl<C: CollectionTrait, T: Serialize + DeserializeOwned> TypedCollection<C, T> {
+    pub fn new(collection: C) -> Self {
+        TypedCollection { collection, _type: PhantomData }
+    }
+
+    /// Inserts a value and returns the record written, if the collection returns it.
+    pub fn insert_one(&mut self, value: &T) -> Result<Option<TypedRecord<T>>, String> {
+        match self.collection.insert_one(&to_document(value)?)? {
+            Some(record) => Ok(Some(TypedRecord::from_record(&record).map_err(|e| e.to_string())?)),
+            None => Ok(None),
+        }
+    }
+
+    pub fn insert_many(&mut self, values: &[T]) -> Result<(), String> {
+        let documents = values.iter().map(to_document).collect::<Result<Vec<_>, _>>()?;
+        self.collection.insert_many(&documents)
+    }
+
+    /// Finds the documents matching `query`. Each document is deserialized on its own, so a document that doesn't fit `T` is returned as a
+    /// [`DecodeError`] without failing the others.
+    pub fn find(&mut self, query: &bson::Document, options: &Option<SearchOption>) -> Result<Vec<Result<TypedRecord<T>, DecodeError>>, String> {
+        let mut records = Vec::new();
+        self.collection
+            .find(query, options, &mut |record| {
+                records.push(TypedRecord::from_record(record));
+                Ok(())
+            })
+            .map_err(|e| e.to_string())?;
+        Ok(records)
+    }
+
+    /// Finds the first document matching `query`, after skipping `skip` of them. A document that doesn't fit `T` is an error.
+    pub fn find_one(&mut self, query: &bson::Document, skip: i64) -> Result<Option<TypedRecord<T>>, String> {
+        let mut options = SearchOption::default();
+        options.skip(skip).limit(1);
+        match self.find(query, &Some(options))?.pop() {
+            Some(record) => Ok(Some(record.map_err(|e| e.to_string())?)),
+            None => Ok(None),
+        }
+    }
+
+    pub fn count_documents(&mut self, query: &bson::Document, options: &Option<SearchOption>) -> Result<i64, String> {
+        self.collection.count_documents(query, options).map_err(|e| e.to_string())
+    }
+
+    /// Replaces the first document matching `query` with `value`.
+    pub fn replace_one(&mut self, query: &bson::Document, value: &T, skip: i64) -> Result<Option<TypedRecord<T>>, String> {
+        match self.collection.replace_one(query, &to_document(value)?, skip)? {
+            Some(record) => Ok(Some(TypedRecord::from_record(&record).map_err(|e| e.to_string())?)),
+            None => Ok(None),
+        }
+    }
+
+    pub fn update_one(&mut self, query: &bson::Document, update: &bson::Document, skip: i64, upsert: bool, options: &Option<UpdateOption>) -> Result<Option<TypedRecord<T>>, String> {
+        match self.collection.update_one(query, update, skip, upsert, options)? {
+            Some(record) => Ok(Some(TypedRecord::from_record(&record).map_err(|e| e.to_string())?)),
+            None => Ok(None),
+        }
+    }
+
+    pub fn update_many(&mut self, query: &bson::Document, update: &bson::Document, limit: i64, skip: i64, upsert: bool, options: &Option<UpdateOption>) -> Result<i64, String> {
+        self.collection.update_many(query, update, limit, skip, upsert, options)
+    }
+
+    pub fn delete_one(&mut self, query: &bson::Document) -> Result<usize, String> {
+        self.collection.delete_one(query)
+    }
+
+    pub fn delete_many(&mut self, query: &bson::Document) -> Result<usize, String> {
+        self.collection.delete_many(query)
+    }
+
+    /// Deletes the first document matching `query` and returns it.
+    pub fn find_one_and_delete(&mut self, query: &bson::Document) -> Result<Option<TypedRecord<T>>, String> {
+        match self.collection.find_one_and_delete(query)? {
+            Some(record) => Ok(Some(TypedRecord::from_record(&record).map_err(|e| e.to_string())?)),
+            None => Ok(None),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::{Database, DatabaseConfig};
+    use serde::Deserialize;
+
+    #[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+    struct User {
+        name: String,
+        age: i32,
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        tags: Vec<String>,
+    }
+
+    fn user(name: &str, age: i32) -> User {
+        User { name: name.to_string(), age, tags: Vec::new() }
+    }
+
+    #[test]
+    fn test_typed_collection() {
+        let mut db = Database::open(&DatabaseConfig::in_memory()).unwrap();
+        db.create_collection("users", &CollectionConfig::default("users")).unwrap();
+        assert!(db.typed_collection::<User>("missing").is_err());
+
+        let mut users = db.typed_collection::<User>("users").unwrap();
+        let ada = users.insert_one(&user("Ada", 36)).unwrap().unwrap();
+        assert_eq!(ada.data, user("Ada", 36));
+        assert_eq!(ada.hash.len(), 64);
+        users.insert_many(&[user("Alan", 41), User { name: "Grace".to_string(), age: 85, tags: vec!["navy".to_string()] }]).unwrap();
+        // a document written by someone else, which isn't a User.
+        users.collection.insert_one(&bson::doc! { "name": "Bob", "age": "unknown" }).unwrap();
+
+        let found = users.find(&bson::doc! {}, &None).unwrap();
+        assert_eq!(found.len(), 4);
+        assert_eq!(found[2].as_ref().unwrap().data.tags, vec!["navy"]);
+        let error = found[3].as_ref().unwrap_err();
+        assert_eq!(error.id, 4);
+        assert!(error.to_string().starts_with("Failed to deserialize document 4"), "{}", error);
+
+        assert_eq!(users.find_one(&bson::doc! { "age": { "$gt": 40 } }, 1).unwrap().unwrap().data.name, "Grace");
+        assert!(users.find_one(&bson::doc! { "name": "Bob" }, 0).is_err());
+        assert!(users.find_one(&bson::doc! { "name": "Eve" }, 0).unwrap().is_none());
+
+        let alan = users.replace_one(&bson::doc! { "name": "Alan" }, &user("Alan", 42), 0).unwrap().unwrap();
+        assert_eq!(alan.data.age, 42);
+        assert_eq!(users.update_one(&bson::doc! { "name": "Ada" }, &bson::doc! { "$inc": { "age": 1 } }, 0, false, &None).unwrap().unwrap().data.age, 37);
+        assert_eq!(users.find_one_and_delete(&bson::doc! { "name": "Grace" }).unwrap().unwrap().data.tags.len(), 1);
+        assert_eq!(users.count_documents(&bson::doc! {}, &None).unwrap(), 3);
+
+        db.transaction(|tx| {
+            let mut users = tx.typed_collection::<User>("users").map_err(|_| "no collection")?;
+            users.insert_one(&user("Edsger", 72)).map_err(|_| "insert failed")?;
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(db.typed_collection::<User>("users").unwrap().count_documents(&bson::doc! {}, &None).unwrap(), 4);
+    }
+}
@@ -0,0 +1,985 @@
+//! This module implements the mongodb [update operators](https://docs.mongodb.com/manual/reference/operator/update/). They are applied to a stored
+//! document by the `json_patch` sqlite function, which is registered in [`crate::database::Database`].
+
+use chrono::prelude::*;
+use std::cmp::Ordering;
+
+use crate::expression;
+use crate::matcher;
+
+/// This is the operations that can be performed on a bson document. These operations are corresponding to the mongodb operations found on this page.
+enum UpdateOperator {
+    Set,
+    Unset,
+    Inc,
+    Min,
+    Max,
+    CurrentDate,
+    Mul,
+    Rename,
+    AddToSet,
+    Pop,
+    Pull,
+    Push,
+    PullAll,
+    Bit,
+}
+
+/// Besides the update document itself, some operators need to know more about the update being performed.
+pub struct UpdateContext<'a> {
+    /// True if the update creates a new document as part of an upsert. `$setOnInsert` is only applied in this case.
+    pub is_insert: bool,
+    /// The query that selected the document. The positional operator `$` uses it to find the matched array element.
+    pub query: Option<&'a bson::Document>,
+    /// The filters used by the filtered positional operator `$[<identifier>]`.
+    pub array_filters: &'a [bson::Document],
+}
+
+/// Applies an update document, for example `{"$set": {"a": 1}, "$inc": {"b": 2}}`, to `doc`.
+pub fn apply_update(doc: &mut bson::Document, update: &bson::Document, context: &UpdateContext) -> Result<(), String> {
+    if context.is_insert {
+        if let Some(query) = context.query {
+            seed_from_query(doc, query)?;
+        }
+    }
+
+    for (key, value) in update.iter() {
+        let operator = match key.as_str() {
+            "$currentDate" => UpdateOperator::CurrentDate,
+            "$inc" => UpdateOperator::Inc,
+            "$min" => UpdateOperator::Min,
+            "$max" => UpdateOperator::Max,
+            "$mul" => UpdateOperator::Mul,
+            "$rename" => UpdateOperator::Rename,
+            "$set" => UpdateOperator::Set,
+            "$setOnInsert" => {
+                if context.is_insert {
+                    UpdateOperator::Set
+                } else {
+                    continue;
+                }
+            }
+            "$unset" => UpdateOperator::Unset,
+            "$addToSet" => UpdateOperator::AddToSet,
+            "$pop" => UpdateOperator::Pop,
+            "$pull" => UpdateOperator::Pull,
+            "$push" => UpdateOperator::Push,
+            "$pullAll" => UpdateOperator::PullAll,
+            "$bit" => UpdateOperator::Bit,
+            _ => {
+                return Err(format!("unknown update operator: {}", key));
+            }
+        };
+
+        if let bson::Bson::Document(fields) = value {
+            for (path, operand) in fields.iter() {
+                apply_operator(doc, path, &operator, operand, context)?;
+            }
+        } else {
+            return Err(format!("Modifiers operate on fields but we found {} instead for {}", value, key));
+        }
+    }
+    Ok(())
+}
+
+/// Applies an [aggregation pipeline update](https://docs.mongodb.com/manual/tutorial/update-documents-with-aggregation-pipeline/), for example
+/// `[{"$set": {"total": {"$add": ["$a", "$b"]}}}]`, to `doc`. The supported stages are `$set` (or its alias `$addFields`), `$unset`, `$project`,
+/// `$replaceRoot` and `$replaceWith`. Each stage sees the document as it was left by the previous stage.
+pub fn apply_pipeline(doc: &mut bson::Document, pipeline: &[bson::Document], context: &UpdateContext) -> Result<(), String> {
+    if context.is_insert {
+        if let Some(query) = context.query {
+            seed_from_query(doc, query)?;
+        }
+    }
+
+    let now = bson::DateTime::now();
+    for stage in pipeline {
+        let (name, spec) = match stage.iter().next() {
+            Some(first) if stage.len() == 1 => first,
+            _ => return Err(format!("A pipeline stage specification object must contain exactly one field: {}", stage)),
+        };
+
+        let current = doc.clone();
+        let vars = expression::Variables::new(&current, now);
+        match (name.as_str(), spec) {
+            ("$set" | "$addFields", bson::Bson::Document(fields)) => {
+                for (path, expr) in fields.iter() {
+                    let value = expression::evaluate(expr, &vars)?;
+                    set_path(doc, path, value)?;
+                }
+            }
+            ("$unset", bson::Bson::String(path)) => set_path(doc, path, None)?,
+            ("$unset", bson::Bson::Array(paths)) => {
+                for path in paths {
+                    match path {
+                        bson::Bson::String(path) => set_path(doc, path, None)?,
+                        _ => return Err(format!("$unset specification must be a string or an array of strings: {}", spec)),
+                    }
+                }
+            }
+            ("$project", bson::Bson::Document(fields)) => {
+                let is_excluded = |value: &bson::Bson| matches!(value, bson::Bson::Boolean(false)) || matches!(value, bson::Bson::Int32(0) | bson::Bson::Int64(0)) || matches!(value, bson::Bson::Double(d) if *d == 0.0);
+                let is_included = |value: &bson::Bson| matches!(value, bson::Bson::Boolean(true)) || matches!(value, bson::Bson::Int32(1) | bson::Bson::Int64(1)) || matches!(value, bson::Bson::Double(d) if *d == 1.0);
+
+                if fields.iter().all(|(_, value)| is_excluded(value)) {
+                    for (path, _) in fields.iter() {
+                        set_path(doc, path, None)?;
+                    }
+                } else {
+                    let mut projected = bson::Document::new();
+                    for (path, value) in fields.iter() {
+                        if is_excluded(value) {
+                            if path != "_id" {
+                                return Err(format!("Invalid $project: cannot exclude '{}' in inclusion projection", path));
+                            }
+                        } else if is_included(value) {
+                            set_path(&mut projected, path, matcher::get_path(&current, path).cloned())?;
+                        } else {
+                            set_path(&mut projected, path, expression::evaluate(value, &vars)?)?;
+                        }
+                    }
+                    *doc = projected;
+                }
+            }
+            ("$replaceRoot", bson::Bson::Document(options)) => {
+                let new_root = options.get("newRoot").ok_or("no newRoot specified for the $replaceRoot stage")?;
+                *doc = replacement_root(expression::evaluate(new_root, &vars)?)?;
+            }
+            ("$replaceWith", new_root) => *doc = replacement_root(expression::evaluate(new_root, &vars)?)?,
+            ("$set" | "$addFields" | "$unset" | "$project" | "$replaceRoot", _) => return Err(format!("Invalid specification for {}: {}", name, spec)),
+            _ => return Err(format!("{} is not allowed to be used within an update", name)),
+        }
+    }
+    Ok(())
+}
+
+fn replacement_root(value: Option<bson::Bson>) -> Result<bson::Document, String> {
+    match value {
+        Some(bson::Bson::Document(doc)) => Ok(doc),
+        other => Err(format!("'newRoot' expression must evaluate to an object, but resulting value was: {:?}", other)),
+    }
+}
+
+/// Sets a field by a dotted path, creating embedded documents as needed. A `None` value removes the field.
+pub(crate) fn set_path(doc: &mut bson::Document, path: &str, value: Option<bson::Bson>) -> Result<(), String> {
+    let segments: Vec<&str> = path.split('.').collect();
+    let mut root = bson::Bson::Document(std::mem::take(doc));
+    let create = value.is_some();
+    let mut value = value;
+    let result = walk(&mut root, &segments, create, &[], &mut |container, key| match value.take() {
+        Some(value) => set_child(container, key, value),
+        None => {
+            remove_child(container, key);
+            Ok(())
+        }
+    });
+    if let bson::Bson::Document(d) = root {
+        *doc = d;
+    }
+    result
+}
+
+/// When an upsert inserts a new document, mongodb copies the equality conditions of the query into the new document first.
+fn seed_from_query(doc: &mut bson::Document, query: &bson::Document) -> Result<(), String> {
+    for (key, value) in query.iter() {
+        if key == "$and" {
+            if let bson::Bson::Array(arr) = value {
+                for sub_query in arr {
+                    if let bson::Bson::Document(sub_query) = sub_query {
+                        seed_from_query(doc, sub_query)?;
+                    }
+                }
+            }
+            continue;
+        }
+
+        if key.starts_with('$') || key == "_id" {
+            continue;
+        }
+
+        let value = match value {
+            bson::Bson::Document(cond) if cond.keys().next().map(|k| k.starts_with('$')).unwrap_or(false) => match cond.get("$eq") {
+                Some(eq) if cond.len() == 1 => eq,
+                _ => continue,
+            },
+            _ => value,
+        };
+
+        set_path(doc, key, Some(value.clone()))?;
+    }
+    Ok(())
+}
+
+fn apply_operator(doc: &mut bson::Document, path: &str, operator: &UpdateOperator, operand: &bson::Bson, context: &UpdateContext) -> Result<(), String> {
+    if let UpdateOperator::Rename = operator {
+        return rename(doc, path, operand);
+    }
+
+    let path = match resolve_positional(doc, path, context)? {
+        Some(path) => path,
+        None => return Ok(()),
+    };
+    let segments: Vec<&str> = path.split('.').collect();
+    // Operators that only remove data never create the missing parts of a path.
+    let create = !matches!(operator, UpdateOperator::Unset | UpdateOperator::Pop | UpdateOperator::Pull | UpdateOperator::PullAll);
+
+    let mut root = bson::Bson::Document(std::mem::take(doc));
+    let result = walk(&mut root, &segments, create, context.array_filters, &mut |container, key| apply_leaf(container, key, operator, operand));
+    if let bson::Bson::Document(d) = root {
+        *doc = d;
+    }
+    result
+}
+
+/// Replaces the positional operator `$` in a path with the index of the first array element matched by the query. `None` is returned if the
+/// document is being inserted, because a new document has no array element to update.
+fn resolve_positional(doc: &bson::Document, path: &str, context: &UpdateContext) -> Result<Option<String>, String> {
+    let mut segments: Vec<String> = path.split('.').map(|s| s.to_string()).collect();
+
+    if let Some(position) = segments.iter().position(|s| s == "$") {
+        if context.is_insert {
+            return Ok(None);
+        }
+
+        let not_found = || "The positional operator did not find the match needed from the query.".to_string();
+        let array_path = segments[..position].join(".");
+        let query = context.query.ok_or_else(not_found)?;
+        let array = match matcher::get_path(doc, &array_path) {
+            Some(bson::Bson::Array(arr)) => arr,
+            _ => return Err(not_found()),
+        };
+        let index = positional_index(array, &array_path, query)?.ok_or_else(not_found)?;
+        segments[position] = index.to_string();
+    }
+
+    Ok(Some(segments.join(".")))
+}
+
+fn positional_index(array: &[bson::Bson], array_path: &str, query: &bson::Document) -> Result<Option<usize>, String> {
+    for (key, condition) in query.iter() {
+        if key == "$and" {
+            if let bson::Bson::Array(sub_queries) = condition {
+                for sub_query in sub_queries {
+                    if let bson::Bson::Document(sub_query) = sub_query {
+                        if let Some(index) = positional_index(array, array_path, sub_query)? {
+                            return Ok(Some(index));
+                        }
+                    }
+                }
+            }
+        } else if key == array_path {
+            let condition = match condition {
+                bson::Bson::Document(cond) if cond.len() == 1 && cond.contains_key("$elemMatch") => cond.get("$elemMatch").unwrap(),
+                _ => condition,
+            };
+            for (index, element) in array.iter().enumerate() {
+                if matcher::matches_value(element, condition)? {
+                    return Ok(Some(index));
+                }
+            }
+        } else if let Some(rest) = key.strip_prefix(array_path).and_then(|r| r.strip_prefix('.')) {
+            let sub_query = bson::doc! { rest: condition.clone() };
+            for (index, element) in array.iter().enumerate() {
+                if let bson::Bson::Document(element_doc) = element {
+                    if matcher::matches(element_doc, &sub_query)? {
+                        return Ok(Some(index));
+                    }
+                }
+            }
+        }
+    }
+    Ok(None)
+}
+
+/// Returns the identifier of a filtered positional operator, for example `elem` for `$[elem]`.
+fn array_filter_identifier(segment: &str) -> Option<&str> {
+    segment.strip_prefix("$[").and_then(|s| s.strip_suffix(']')).filter(|s| !s.is_empty())
+}
+
+/// Checks an array element against all `arrayFilters` that belong to an identifier.
+fn element_matches_filters(element: &bson::Bson, identifier: &str, array_filters: &[bson::Document]) -> Result<bool, String> {
+    let mut found = false;
+    for filter in array_filters {
+        for (key, condition) in filter.iter() {
+            let (head, rest) = match key.split_once('.') {
+                Some((head, rest)) => (head, Some(rest)),
+                None => (key.as_str(), None),
+            };
+
+            if head != identifier {
+                continue;
+            }
+            found = true;
+
+            let matched = match (rest, element) {
+                (None, _) => matcher::matches_value(element, condition)?,
+                (Some(rest), bson::Bson::Document(element_doc)) => matcher::matches(element_doc, &bson::doc! { rest: condition.clone() })?,
+                _ => false,
+            };
+
+            if !matched {
+                return Ok(false);
+            }
+        }
+    }
+
+    if found {
+        Ok(true)
+    } else {
+        Err(format!("No array filter found for identifier '{}'", identifier))
+    }
+}
+
+/// Returns the indices of the array elements addressed by `$[]` or `$[<identifier>]`. `None` means the segment is not an all positional operator.
+fn selected_indices(array: &[bson::Bson], segment: &str, array_filters: &[bson::Document]) -> Result<Option<Vec<usize>>, String> {
+    if segment == "$[]" {
+        return Ok(Some((0..array.len()).collect()));
+    }
+
+    if let Some(identifier) = array_filter_identifier(segment) {
+        let mut indices = Vec::new();
+        for (index, element) in array.iter().enumerate() {
+            if element_matches_filters(element, identifier, array_filters)? {
+                indices.push(index);
+            }
+        }
+        return Ok(Some(indices));
+    }
+
+    Ok(None)
+}
+
+/// Walks a path and calls `f` with the container (a document or an array) and the key of every field the path resolves to. A path resolves to
+/// more than one field when it contains `$[]` or `$[<identifier>]`. If `create` is true, missing embedded documents along the path are created.
+fn walk(target: &mut bson::Bson, segments: &[&str], create: bool, array_filters: &[bson::Document], f: &mut dyn FnMut(&mut bson::Bson, &str) -> Result<(), String>) -> Result<(), String> {
+    walk_from(target, segments, 0, create, array_filters, f)
+}
+
+/// Walks the path from `segments[depth]` on. `target` is the field the segments before it resolve to.
+fn walk_from(target: &mut bson::Bson, segments: &[&str], depth: usize, create: bool, array_filters: &[bson::Document], f: &mut dyn FnMut(&mut bson::Bson, &str) -> Result<(), String>) -> Result<(), String> {
+    let segment = segments[depth];
+
+    // A positional operator never creates the array it updates.
+    if create && !matches!(target, bson::Bson::Array(_)) && (segment == "$[]" || array_filter_identifier(segment).is_some()) {
+        return Err(format!("The path '{}' must exist in the document in order to apply array updates", segments[..depth].join(".")));
+    }
+
+    if depth + 1 == segments.len() {
+        if let bson::Bson::Array(arr) = target {
+            if let Some(indices) = selected_indices(arr, segment, array_filters)? {
+                for index in indices {
+                    f(target, &index.to_string())?;
+                }
+                return Ok(());
+            }
+        }
+        return f(target, segment);
+    }
+
+    match target {
+        bson::Bson::Document(doc) => {
+            if !doc.contains_key(segment) {
+                if !create {
+                    return Ok(());
+                }
+                doc.insert(segment, bson::Document::new());
+            }
+            walk_from(doc.get_mut(segment).unwrap(), segments, depth + 1, create, array_filters, f)
+        }
+        bson::Bson::Array(arr) => {
+            if let Some(indices) = selected_indices(arr, segment, array_filters)? {
+                for index in indices {
+                    walk_from(&mut arr[index], segments, depth + 1, create, array_filters, f)?;
+                }
+                Ok(())
+            } else if let Ok(index) = segment.parse::<usize>() {
+                if index >= arr.len() {
+                    if !create {
+                        return Ok(());
+                    }
+                    while arr.len() < index {
+                        arr.push(bson::Bson::Null);
+                    }
+                    arr.push(bson::Bson::Document(bson::Document::new()));
+                }
+                walk_from(&mut arr[index], segments, depth + 1, create, array_filters, f)
+            } else if create {
+                Err(format!("Cannot create field '{}' in element {}", segment, target))
+            } else {
+                Ok(())
+            }
+        }
+        _ => {
+            if create {
+                Err(format!("Cannot create field '{}' in element {}", segment, target))
+            } else {
+                Ok(())
+            }
+        }
+    }
+}
+
+fn get_child<'a>(container: &'a bson::Bson, key: &str) -> Option<&'a bson::Bson> {
+    match container {
+        bson::Bson::Document(doc) => doc.get(key),
+        bson::Bson::Array(arr) => arr.get(key.parse::<usize>().ok()?),
+        _ => None,
+    }
+}
+
+/// Sets a field. An existing field keeps its position in the document. Setting an array element past the end of the array pads the array with nulls.
+fn set_child(container: &mut bson::Bson, key: &str, value: bson::Bson) -> Result<(), String> {
+    match container {
+        bson::Bson::Document(doc) => {
+            if let Some(existing) = doc.get_mut(key) {
+                *existing = value;
+            } else {
+                doc.insert(key, value);
+            }
+            Ok(())
+        }
+        bson::Bson::Array(arr) => {
+            let index = key.parse::<usize>().map_err(|_| format!("Cannot create field '{}' in element {{{}}}", key, bson::Bson::Array(arr.clone())))?;
+            while arr.len() <= index {
+                arr.push(bson::Bson::Null);
+            }
+            arr[index] = value;
+            Ok(())
+        }
+        _ => Err(format!("Cannot create field '{}' in element {}", key, container)),
+    }
+}
+
+/// Removes a field. Just like mongodb, unsetting an array element sets it to null instead of shrinking the array.
+fn remove_child(container: &mut bson::Bson, key: &str) {
+    match container {
+        bson::Bson::Document(doc) => {
+            doc.remove(key);
+        }
+        bson::Bson::Array(arr) => {
+            if let Ok(index) = key.parse::<usize>() {
+                if index < arr.len() {
+                    arr[index] = bson::Bson::Null;
+                }
+            }
+        }
+        _ => {}
+    }
+}
+
+fn apply_leaf(container: &mut bson::Bson, key: &str, operator: &UpdateOperator, operand: &bson::Bson) -> Result<(), String> {
+    match operator {
+        UpdateOperator::Set => set_child(container, key, operand.clone()),
+
+        UpdateOperator::Unset => {
+            remove_child(container, key);
+            Ok(())
+        }
+
+        UpdateOperator::Inc => {
+            let result = match get_child(container, key) {
+                Some(current) => add(current, operand).map_err(|e| format!("Cannot apply $inc to field '{}': {}", key, e))?,
+                None => {
+                    check_numeric(operand, "$inc")?;
+                    operand.clone()
+                }
+            };
+            set_child(container, key, result)
+        }
+
+        UpdateOperator::Mul => {
+            let result = match get_child(container, key) {
+                Some(current) => multiply(current, operand).map_err(|e| format!("Cannot apply $mul to field '{}': {}", key, e))?,
+                None => {
+                    check_numeric(operand, "$mul")?;
+                    multiply(operand, &bson::Bson::Int32(0))?
+                }
+            };
+            set_child(container, key, result)
+        }
+
+        UpdateOperator::Min | UpdateOperator::Max => {
+            let wanted = if let UpdateOperator::Min = operator { Ordering::Less } else { Ordering::Greater };
+            let should_replace = match get_child(container, key) {
+                Some(current) => matcher::compare_bson(operand, current) == wanted,
+                None => true,
+            };
+            if should_replace {
+                set_child(container, key, operand.clone())
+            } else {
+                Ok(())
+            }
+        }
+
+        UpdateOperator::CurrentDate => set_child(container, key, current_date(operand)?),
+
+        UpdateOperator::Rename => Err("$rename can't be applied to a single field".to_string()),
+
+        UpdateOperator::AddToSet => {
+            let values = match operand {
+                bson::Bson::Document(modifiers) if modifiers.contains_key("$each") => match modifiers.get("$each") {
+                    Some(bson::Bson::Array(each)) if modifiers.len() == 1 => each.clone(),
+                    _ => return Err(format!("The argument to $each in $addToSet must be an array: {}", operand)),
+                },
+                _ => vec![operand.clone()],
+            };
+            let mut arr = existing_array(container, key, "$addToSet")?.unwrap_or_default();
+            for value in values {
+                if !arr.iter().any(|element| matcher::bson_equal(element, &value)) {
+                    arr.push(value);
+                }
+            }
+            set_child(container, key, bson::Bson::Array(arr))
+        }
+
+        UpdateOperator::Pop => {
+            let from_front = match as_integer(operand) {
+                Some(1) => false,
+                Some(-1) => true,
+                _ => return Err(format!("$pop expects 1 or -1, found: {}", operand)),
+            };
+            if let Some(mut arr) = existing_array(container, key, "$pop")? {
+                if !arr.is_empty() {
+                    if from_front {
+                        arr.remove(0);
+                    } else {
+                        arr.pop();
+                    }
+                }
+                set_child(container, key, bson::Bson::Array(arr))?;
+            }
+            Ok(())
+        }
+
+        UpdateOperator::Pull => {
+            if let Some(arr) = existing_array(container, key, "$pull")? {
+                let mut kept = Vec::new();
+                for element in arr {
+                    if !matcher::matches_value(&element, operand)? {
+                        kept.push(element);
+                    }
+                }
+                set_child(container, key, bson::Bson::Array(kept))?;
+            }
+            Ok(())
+        }
+
+        UpdateOperator::PullAll => {
+            let values = match operand {
+                bson::Bson::Array(values) => values,
+                _ => return Err(format!("$pullAll requires an array argument but was given {}", operand)),
+            };
+            if let Some(mut arr) = existing_array(container, key, "$pullAll")? {
+                arr.retain(|element| !values.iter().any(|value| matcher::bson_equal(element, value)));
+                set_child(container, key, bson::Bson::Array(arr))?;
+            }
+            Ok(())
+        }
+
+        UpdateOperator::Push => {
+            let arr = existing_array(container, key, "$push")?.unwrap_or_default();
+            set_child(container, key, bson::Bson::Array(push(arr, operand)?))
+        }
+
+        UpdateOperator::Bit => {
+            let current = get_child(container, key).cloned();
+            set_child(container, key, bit(current, operand)?)
+        }
+    }
+}
+
+/// Returns the array stored in a field, or `None` if the field doesn't exist. It is an error if the field holds something other than an array.
+fn existing_array(container: &bson::Bson, key: &str, operator: &str) -> Result<Option<bson::Array>, String> {
+    match get_child(container, key) {
+        None => Ok(None),
+        Some(bson::Bson::Array(arr)) => Ok(Some(arr.clone())),
+        Some(other) => Err(format!("Cannot apply {} to a non-array field. Field '{}' has value {}", operator, key, other)),
+    }
+}
+
+fn push(mut arr: bson::Array, operand: &bson::Bson) -> Result<bson::Array, String> {
+    let modifiers = match operand {
+        bson::Bson::Document(modifiers) if modifiers.contains_key("$each") => modifiers,
+        _ => {
+            arr.push(operand.clone());
+            return Ok(arr);
+        }
+    };
+
+    let mut each = Vec::new();
+    let mut position = None;
+    let mut slice = None;
+    let mut sort = None;
+    for (modifier, value) in modifiers.iter() {
+        match modifier.as_str() {
+            "$each" => match value {
+                bson::Bson::Array(values) => each = values.clone(),
+                _ => return Err(format!("The argument to $each in $push must be an array but it was of type {:?}", value.element_type())),
+            },
+            "$position" => position = Some(as_integer(value).ok_or_else(|| format!("The value for $position must be an integer value, not {}", value))?),
+            "$slice" => slice = Some(as_integer(value).ok_or_else(|| format!("The value for $slice must be an integer value but was given {}", value))?),
+            "$sort" => sort = Some(value),
+            _ => return Err(format!("Unrecognized clause in $push: {}", modifier)),
+        }
+    }
+
+    let length = arr.len() as i64;
+    let index = match position {
+        None => length,
+        Some(p) if p >= 0 => p.min(length),
+        Some(p) => (length + p).max(0),
+    } as usize;
+    arr.splice(index..index, each);
+
+    if let Some(sort) = sort {
+        sort_array(&mut arr, sort)?;
+    }
+
+    if let Some(slice) = slice {
+        if slice >= 0 {
+            arr.truncate(slice as usize);
+        } else {
+            let keep = slice.unsigned_abs() as usize;
+            if arr.len() > keep {
+                arr.drain(0..arr.len() - keep);
+            }
+        }
+    }
+
+    Ok(arr)
+}
+
+/// Sorts an array for `$push` with `$sort`. The sort specification is either `1`/`-1` to sort the elements themselves, or a document such as
+/// `{score: -1}` to sort embedded documents by their fields.
+fn sort_array(arr: &mut bson::Array, sort: &bson::Bson) -> Result<(), String> {
+    match sort {
+        bson::Bson::Document(spec) => {
+            let mut fields = Vec::new();
+            for (field, direction) in spec.iter() {
+                fields.push((field.clone(), sort_direction(direction)?));
+            }
+            arr.sort_by(|a, b| {
+                for (field, direction) in &fields {
+                    let value_a = a.as_document().and_then(|d| matcher::get_path(d, field)).unwrap_or(&bson::Bson::Null);
+                    let value_b = b.as_document().and_then(|d| matcher::get_path(d, field)).unwrap_or(&bson::Bson::Null);
+                    let ordering = matcher::compare_bson(value_a, value_b);
+                    if ordering != Ordering::Equal {
+                        return if *direction > 0 { ordering } else { ordering.reverse() };
+                    }
+                }
+                Ordering::Equal
+            });
+            Ok(())
+        }
+        _ => {
+            let direction = sort_direction(sort)?;
+            arr.sort_by(|a, b| if direction > 0 { matcher::compare_bson(a, b) } else { matcher::compare_bson(b, a) });
+            Ok(())
+        }
+    }
+}
+
+fn sort_direction(value: &bson::Bson) -> Result<i64, String> {
+    match as_integer(value) {
+        Some(1) => Ok(1),
+        Some(-1) => Ok(-1),
+        _ => Err(format!("The $sort element value must be either 1 or -1, found: {}", value)),
+    }
+}
+
+fn current_date(operand: &bson::Bson) -> Result<bson::Bson, String> {
+    let date_type = match operand {
+        bson::Bson::Boolean(true) => "date",
+        bson::Bson::String(date_type) => date_type.as_str(),
+        bson::Bson::Document(spec) => match spec.get("$type") {
+            Some(bson::Bson::String(date_type)) if spec.len() == 1 => date_type.as_str(),
+            _ => return Err(format!("incorrect date type for operator CurrentDate: {}", operand)),
+        },
+        _ => return Err(format!("incorrect data type for operator CurrentDate: {}", operand)),
+    };
+
+    let utc: DateTime<Utc> = Utc::now();
+    match date_type {
+        "date" => Ok(bson::Bson::DateTime(bson::DateTime::from(utc))),
+        "timestamp" => Ok(bson::Bson::Timestamp(bson::Timestamp { time: utc.timestamp() as u32, increment: 1 })),
+        _ => Err(format!("incorrect date type for operator CurrentDate: {}", date_type)),
+    }
+}
+
+fn bit(current: Option<bson::Bson>, operand: &bson::Bson) -> Result<bson::Bson, String> {
+    let operations = match operand {
+        bson::Bson::Document(operations) if !operations.is_empty() => operations,
+        _ => return Err(format!("The $bit modifier is not compatible with {}", operand)),
+    };
+
+    let mut result = match current {
+        Some(bson::Bson::Int32(i)) => bson::Bson::Int32(i),
+        Some(bson::Bson::Int64(i)) => bson::Bson::Int64(i),
+        Some(other) => return Err(format!("Cannot apply $bit to a value of non-integral type: {}", other)),
+        None => bson::Bson::Int32(0),
+    };
+
+    for (operation, value) in operations.iter() {
+        let apply = |a: i64, b: i64| -> Result<i64, String> {
+            match operation.as_str() {
+                "and" => Ok(a & b),
+                "or" => Ok(a | b),
+                "xor" => Ok(a ^ b),
+                _ => Err(format!("The $bit modifier only supports 'and', 'or', and 'xor', not '{}'", operation)),
+            }
+        };
+        result = match (&result, value) {
+            (bson::Bson::Int32(a), bson::Bson::Int32(b)) => bson::Bson::Int32(apply(*a as i64, *b as i64)? as i32),
+            (bson::Bson::Int32(a), bson::Bson::Int64(b)) => bson::Bson::Int64(apply(*a as i64, *b)?),
+            (bson::Bson::Int64(a), bson::Bson::Int32(b)) => bson::Bson::Int64(apply(*a, *b as i64)?),
+            (bson::Bson::Int64(a), bson::Bson::Int64(b)) => bson::Bson::Int64(apply(*a, *b)?),
+            _ => return Err(format!("The $bit modifier field must be an Integer(32/64 bit): {}", value)),
+        };
+    }
+    Ok(result)
+}
+
+fn as_f64(value: &bson::Bson) -> Option<f64> {
+    match value {
+        bson::Bson::Int32(i) => Some(*i as f64),
+        bson::Bson::Int64(i) => Some(*i as f64),
+        bson::Bson::Double(d) => Some(*d),
+        _ => None,
+    }
+}
+
+fn as_integer(value: &bson::Bson) -> Option<i64> {
+    match value {
+        bson::Bson::Int32(i) => Some(*i as i64),
+        bson::Bson::Int64(i) => Some(*i),
+        bson::Bson::Double(d) if d.fract() == 0.0 => Some(*d as i64),
+        _ => None,
+    }
+}
+
+fn check_numeric(value: &bson::Bson, operator: &str) -> Result<(), String> {
+    match value {
+        bson::Bson::Int32(_) | bson::Bson::Int64(_) | bson::Bson::Double(_) => Ok(()),
+        _ => Err(format!("Cannot {} with non-numeric argument: {}", operator, value)),
+    }
+}
+
+/// Applies an arithmetic operation with mongodb's numeric type promotion: Int32 with Int32 stays Int32 unless the result overflows, in which case
+/// it becomes Int64. Mixing Int32 and Int64 produces Int64, and anything combined with a Double produces a Double.
+pub(crate) fn arithmetic(a: &bson::Bson, b: &bson::Bson, int_op: fn(i64, i64) -> Option<i64>, float_op: fn(f64, f64) -> f64) -> Result<bson::Bson, String> {
+    match (a, b) {
+        (bson::Bson::Int32(x), bson::Bson::Int32(y)) => {
+            let result = int_op(*x as i64, *y as i64).ok_or_else(|| "integer overflow".to_string())?;
+            Ok(i32::try_from(result).map(bson::Bson::Int32).unwrap_or(bson::Bson::Int64(result)))
+        }
+        (bson::Bson::Int32(_), bson::Bson::Int64(_)) | (bson::Bson::Int64(_), bson::Bson::Int32(_)) | (bson::Bson::Int64(_), bson::Bson::Int64(_)) => {
+            let result = int_op(as_integer(a).unwrap(), as_integer(b).unwrap()).ok_or_else(|| "integer overflow".to_string())?;
+            Ok(bson::Bson::Int64(result))
+        }
+        _ => match (as_f64(a), as_f64(b)) {
+            (Some(x), Some(y)) => Ok(bson::Bson::Double(float_op(x, y))),
+            (None, _) => Err(format!("{} is not a number", a)),
+            (_, None) => Err(format!("{} is not a number", b)),
+        },
+    }
+}
+
+pub(crate) fn add(a: &bson::Bson, b: &bson::Bson) -> Result<bson::Bson, String> {
+    arithmetic(a, b, i64::checked_add, |x, y| x + y)
+}
+
+pub(crate) fn multiply(a: &bson::Bson, b: &bson::Bson) -> Result<bson::Bson, String> {
+    arithmetic(a, b, i64::checked_mul, |x, y| x * y)
+}
+
+/// Implements `$rename`, which moves the value of a field to another field, possibly in another embedded document.
+fn rename(doc: &mut bson::Document, from: &str, to: &bson::Bson) -> Result<(), String> {
+    let to = match to {
+        bson::Bson::String(to) => to,
+        _ => return Err(format!("The 'to' field for $rename must be a string: {}", to)),
+    };
+
+    if from.contains('$') || to.contains('$') {
+        return Err("The source and destination field for $rename may not be dynamic".to_string());
+    }
+
+    if from == to || to.starts_with(&format!("{}.", from)) || from.starts_with(&format!("{}.", to)) {
+        return Err(format!("The source and destination field for $rename must differ and not overlap: {} => {}", from, to));
+    }
+
+    let from_segments: Vec<&str> = from.split('.').collect();
+    let to_segments: Vec<&str> = to.split('.').collect();
+
+    let mut root = bson::Bson::Document(std::mem::take(doc));
+    let mut taken = None;
+    let mut result = walk(&mut root, &from_segments, false, &[], &mut |container, key| {
+        if let bson::Bson::Document(d) = container {
+            taken = d.remove(key);
+        }
+        Ok(())
+    });
+
+    if let (Ok(()), Some(value)) = (&result, taken) {
+        let mut value = Some(value);
+        result = walk(&mut root, &to_segments, true, &[], &mut |container, key| set_child(container, key, value.take().unwrap_or(bson::Bson::Null)));
+    }
+
+    if let bson::Bson::Document(d) = root {
+        *doc = d;
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bson::doc;
+
+    fn update(mut doc: bson::Document, update: bson::Document) -> Result<bson::Document, String> {
+        apply_update(&mut doc, &update, &UpdateContext { is_insert: false, query: None, array_filters: &[] })?;
+        Ok(doc)
+    }
+
+    fn update_with(mut doc: bson::Document, update: bson::Document, query: Option<&bson::Document>, array_filters: &[bson::Document]) -> Result<bson::Document, String> {
+        apply_update(&mut doc, &update, &UpdateContext { is_insert: false, query, array_filters })?;
+        Ok(doc)
+    }
+
+    #[test]
+    fn test_set_and_unset() {
+        assert_eq!(update(doc! {"a": 1, "b": 2}, doc! {"$set": {"a": 5}}).unwrap(), doc! {"a": 5, "b": 2});
+        assert_eq!(update(doc! {"a": 1}, doc! {"$set": {"b.c.d": "x"}}).unwrap(), doc! {"a": 1, "b": {"c": {"d": "x"}}});
+        assert_eq!(update(doc! {"a": [1, 2]}, doc! {"$set": {"a.4": 5}}).unwrap(), doc! {"a": [1, 2, bson::Bson::Null, bson::Bson::Null, 5]});
+        assert!(update(doc! {"a": 1}, doc! {"$set": {"a.b": 2}}).is_err());
+        assert_eq!(update(doc! {"a": 1, "b": {"c": 1, "d": 2}}, doc! {"$unset": {"b.c": "", "z.y": ""}}).unwrap(), doc! {"a": 1, "b": {"d": 2}});
+        assert_eq!(update(doc! {"a": [1, 2, 3]}, doc! {"$unset": {"a.1": ""}}).unwrap(), doc! {"a": [1, bson::Bson::Null, 3]});
+    }
+
+    #[test]
+    fn test_numeric_promotion() {
+        assert_eq!(update(doc! {"a": 1_i32}, doc! {"$inc": {"a": 2_i32}}).unwrap(), doc! {"a": 3_i32});
+        assert_eq!(update(doc! {"a": 1_i32}, doc! {"$inc": {"a": 2_i64}}).unwrap(), doc! {"a": 3_i64});
+        assert_eq!(update(doc! {"a": 1_i64}, doc! {"$inc": {"a": 0.5}}).unwrap(), doc! {"a": 1.5});
+        assert_eq!(update(doc! {"a": i32::MAX}, doc! {"$inc": {"a": 1_i32}}).unwrap(), doc! {"a": i32::MAX as i64 + 1});
+        assert!(update(doc! {"a": i64::MAX}, doc! {"$inc": {"a": 1_i64}}).is_err());
+        assert!(update(doc! {"a": "x"}, doc! {"$inc": {"a": 1}}).is_err());
+        assert_eq!(update(doc! {}, doc! {"$inc": {"a": 4_i64}}).unwrap(), doc! {"a": 4_i64});
+        assert_eq!(update(doc! {"a": 3_i32}, doc! {"$mul": {"a": 2.5}}).unwrap(), doc! {"a": 7.5});
+        assert_eq!(update(doc! {}, doc! {"$mul": {"a": 5_i64}}).unwrap(), doc! {"a": 0_i64});
+        assert_eq!(update(doc! {"a": 3_i64}, doc! {"$min": {"a": 2.5}}).unwrap(), doc! {"a": 2.5});
+        assert_eq!(update(doc! {"a": 3_i64}, doc! {"$min": {"a": 5_i32}}).unwrap(), doc! {"a": 3_i64});
+        assert_eq!(update(doc! {"a": 3_i32}, doc! {"$max": {"a": 4_i64}}).unwrap(), doc! {"a": 4_i64});
+        assert_eq!(update(doc! {"a": "apple"}, doc! {"$max": {"a": "banana"}}).unwrap(), doc! {"a": "banana"});
+    }
+
+    #[test]
+    fn test_rename_current_date_and_bit() {
+        assert_eq!(update(doc! {"a": 1, "b": {"c": 2}}, doc! {"$rename": {"b.c": "d.e"}}).unwrap(), doc! {"a": 1, "b": {}, "d": {"e": 2}});
+        assert_eq!(update(doc! {"a": 1}, doc! {"$rename": {"missing": "b"}}).unwrap(), doc! {"a": 1});
+        assert!(update(doc! {"a": 1}, doc! {"$rename": {"a": "a.b"}}).is_err());
+
+        let result = update(doc! {}, doc! {"$currentDate": {"d": true, "t": {"$type": "timestamp"}}}).unwrap();
+        assert!(matches!(result.get("d"), Some(bson::Bson::DateTime(_))));
+        assert!(matches!(result.get("t"), Some(bson::Bson::Timestamp(_))));
+
+        assert_eq!(update(doc! {"a": 13_i32}, doc! {"$bit": {"a": {"and": 10_i32}}}).unwrap(), doc! {"a": 8_i32});
+        assert_eq!(update(doc! {"a": 3_i32}, doc! {"$bit": {"a": {"or": 5_i64}}}).unwrap(), doc! {"a": 7_i64});
+        assert_eq!(update(doc! {"a": 1_i64}, doc! {"$bit": {"a": {"xor": 5_i32}}}).unwrap(), doc! {"a": 4_i64});
+        assert!(update(doc! {"a": 1.5}, doc! {"$bit": {"a": {"and": 1}}}).is_err());
+    }
+
+    #[test]
+    fn test_push_modifiers() {
+        assert_eq!(update(doc! {}, doc! {"$push": {"a": 1}}).unwrap(), doc! {"a": [1]});
+        assert_eq!(update(doc! {"a": [1]}, doc! {"$push": {"a": [2, 3]}}).unwrap(), doc! {"a": [1, [2, 3]]});
+        assert_eq!(update(doc! {"a": [1]}, doc! {"$push": {"a": {"$each": [2, 3]}}}).unwrap(), doc! {"a": [1, 2, 3]});
+        assert_eq!(update(doc! {"a": [1, 4]}, doc! {"$push": {"a": {"$each": [2, 3], "$position": 1}}}).unwrap(), doc! {"a": [1, 2, 3, 4]});
+        assert_eq!(update(doc! {"a": [1, 4]}, doc! {"$push": {"a": {"$each": [9], "$position": -1}}}).unwrap(), doc! {"a": [1, 9, 4]});
+        assert_eq!(update(doc! {"a": [1, 2]}, doc! {"$push": {"a": {"$each": [3, 4], "$slice": -3}}}).unwrap(), doc! {"a": [2, 3, 4]});
+        assert_eq!(update(doc! {"a": [3, 1]}, doc! {"$push": {"a": {"$each": [2], "$sort": -1, "$slice": 2}}}).unwrap(), doc! {"a": [3, 2]});
+        assert_eq!(
+            update(doc! {"q": [{"s": 3}, {"s": 1}]}, doc! {"$push": {"q": {"$each": [{"s": 2}], "$sort": {"s": 1}}}}).unwrap(),
+            doc! {"q": [{"s": 1}, {"s": 2}, {"s": 3}]}
+        );
+        assert!(update(doc! {"a": 1}, doc! {"$push": {"a": 2}}).is_err());
+        assert!(update(doc! {"a": []}, doc! {"$push": {"a": {"$each": [1], "$bogus": 1}}}).is_err());
+    }
+
+    #[test]
+    fn test_array_removal() {
+        assert_eq!(update(doc! {"a": [1, 2, 3]}, doc! {"$pop": {"a": 1}}).unwrap(), doc! {"a": [1, 2]});
+        assert_eq!(update(doc! {"a": [1, 2, 3]}, doc! {"$pop": {"a": -1}}).unwrap(), doc! {"a": [2, 3]});
+        assert_eq!(update(doc! {"a": [1, 2, 3, 2]}, doc! {"$pull": {"a": 2}}).unwrap(), doc! {"a": [1, 3]});
+        assert_eq!(update(doc! {"a": [1, 5, 6, 9]}, doc! {"$pull": {"a": {"$gte": 6}}}).unwrap(), doc! {"a": [1, 5]});
+        assert_eq!(update(doc! {"a": [{"x": 1, "y": 1}, {"x": 2, "y": 1}]}, doc! {"$pull": {"a": {"x": 2}}}).unwrap(), doc! {"a": [{"x": 1, "y": 1}]});
+        assert_eq!(update(doc! {"a": [1, 2, 3, 1_i64]}, doc! {"$pullAll": {"a": [1.0, 3]}}).unwrap(), doc! {"a": [2]});
+        assert_eq!(update(doc! {"a": [1, 2]}, doc! {"$addToSet": {"a": 2}}).unwrap(), doc! {"a": [1, 2]});
+        assert_eq!(update(doc! {"a": [1, 2]}, doc! {"$addToSet": {"a": {"$each": [2, 3, 3]}}}).unwrap(), doc! {"a": [1, 2, 3]});
+    }
+
+    #[test]
+    fn test_positional_operators() {
+        let query = doc! {"grades": 80};
+        assert_eq!(update_with(doc! {"grades": [85, 80, 80]}, doc! {"$set": {"grades.$": 82}}, Some(&query), &[]).unwrap(), doc! {"grades": [85, 82, 80]});
+
+        let query = doc! {"items.qty": {"$gt": 5}};
+        assert_eq!(
+            update_with(doc! {"items": [{"qty": 1}, {"qty": 7}]}, doc! {"$inc": {"items.$.qty": 1}}, Some(&query), &[]).unwrap(),
+            doc! {"items": [{"qty": 1}, {"qty": 8}]}
+        );
+        assert!(update_with(doc! {"grades": [1]}, doc! {"$set": {"grades.$": 2}}, Some(&doc! {"grades": 5}), &[]).is_err());
+
+        assert_eq!(update(doc! {"a": [1, 2]}, doc! {"$inc": {"a.$[]": 10}}).unwrap(), doc! {"a": [11, 12]});
+        assert_eq!(update(doc! {"a": [{"b": 1}, {"b": 2}]}, doc! {"$set": {"a.$[].c": 0}}).unwrap(), doc! {"a": [{"b": 1, "c": 0}, {"b": 2, "c": 0}]});
+
+        let filters = vec![doc! {"elem": {"$gte": 100}}];
+        assert_eq!(update_with(doc! {"a": [95, 100, 102]}, doc! {"$set": {"a.$[elem]": 100}}, None, &filters).unwrap(), doc! {"a": [95, 100, 100]});
+
+        let filters = vec![doc! {"g.grade": {"$gte": 85}}];
+        assert_eq!(
+            update_with(doc! {"gs": [{"grade": 80, "mean": 75}, {"grade": 85, "mean": 90}]}, doc! {"$set": {"gs.$[g].mean": 100}}, None, &filters).unwrap(),
+            doc! {"gs": [{"grade": 80, "mean": 75}, {"grade": 85, "mean": 100}]}
+        );
+        assert!(update_with(doc! {"a": [1]}, doc! {"$set": {"a.$[missing]": 1}}, None, &[]).is_err());
+
+        let error = "The path 'arr' must exist in the document in order to apply array updates";
+        assert_eq!(update(doc! {"a": 1}, doc! {"$set": {"arr.$[].x": 1}}).unwrap_err(), error);
+        assert_eq!(update(doc! {"a": 1}, doc! {"$set": {"arr.$[]": 1}}).unwrap_err(), error);
+        assert_eq!(update_with(doc! {"a": 1}, doc! {"$set": {"arr.$[elem].x": 1}}, None, &[doc! {"elem.x": 0}]).unwrap_err(), error);
+        assert_eq!(update(doc! {"a": 1}, doc! {"$unset": {"arr.$[].x": ""}}).unwrap(), doc! {"a": 1});
+    }
+
+    #[test]
+    fn test_upsert_seeding() {
+        let mut doc = bson::Document::new();
+        let query = doc! {"name": "x", "age": {"$gt": 3}, "tag": {"$eq": "t"}};
+        apply_update(&mut doc, &doc! {"$set": {"v": 1}, "$setOnInsert": {"created": true}}, &UpdateContext { is_insert: true, query: Some(&query), array_filters: &[] }).unwrap();
+        assert_eq!(doc, doc! {"name": "x", "tag": "t", "v": 1, "created": true});
+    }
+
+    fn pipeline(mut doc: bson::Document, pipeline: Vec<bson::Document>) -> Result<bson::Document, String> {
+        apply_pipeline(&mut doc, &pipeline, &UpdateContext { is_insert: false, query: None, array_filters: &[] })?;
+        Ok(doc)
+    }
+
+    #[test]
+    fn test_pipeline_stages() {
+        assert_eq!(
+            pipeline(doc! {"a": 1, "b": 2}, vec![doc! {"$set": {"total": {"$add": ["$a", "$b"]}, "x.y": "$a"}}]).unwrap(),
+            doc! {"a": 1, "b": 2, "total": 3, "x": {"y": 1}}
+        );
+        assert_eq!(pipeline(doc! {"a": 1}, vec![doc! {"$set": {"a": "$$REMOVE", "b": "$missing"}}]).unwrap(), doc! {});
+        assert_eq!(
+            pipeline(doc! {"a": 1}, vec![doc! {"$addFields": {"b": {"$multiply": ["$a", 10]}}}, doc! {"$set": {"c": {"$add": ["$b", 1]}}}]).unwrap(),
+            doc! {"a": 1, "b": 10, "c": 11}
+        );
+        assert_eq!(pipeline(doc! {"a": 1, "b": 2, "c": 3}, vec![doc! {"$unset": ["a", "b"]}]).unwrap(), doc! {"c": 3});
+        assert_eq!(pipeline(doc! {"a": 1, "b": {"c": 2, "d": 3}}, vec![doc! {"$project": {"b.c": 1, "e": "$a"}}]).unwrap(), doc! {"b": {"c": 2}, "e": 1});
+        assert_eq!(pipeline(doc! {"a": 1, "b": 2}, vec![doc! {"$project": {"a": 0}}]).unwrap(), doc! {"b": 2});
+        assert_eq!(pipeline(doc! {"a": {"b": 1}}, vec![doc! {"$replaceWith": "$a"}]).unwrap(), doc! {"b": 1});
+        assert_eq!(pipeline(doc! {"a": {"b": 1}}, vec![doc! {"$replaceRoot": {"newRoot": {"n": "$a.b"}}}]).unwrap(), doc! {"n": 1});
+        assert!(pipeline(doc! {"a": 1}, vec![doc! {"$replaceWith": "$a"}]).is_err());
+        assert!(pipeline(doc! {"a": 1}, vec![doc! {"$match": {"a": 1}}]).is_err());
+    }
+
+}
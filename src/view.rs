@@ -0,0 +1,255 @@
+//! Views.
+//!
+//! A view, created with [`crate::database::Database::create_view()`], is a read-only collection whose documents are computed whenever it is
+//! read, by running an aggregation pipeline on another collection, the view's source. The source can be a collection, a time-series collection
+//! or another view. A view is stored in the catalog like a collection, but has no table. If its source is dropped, the view is empty.
+//!
+//! A query on a view is the view's pipeline followed by a `$match` stage with the query. The `$match` stage is moved ahead of the stages that
+//! don't touch the fields it looks at, and the `$match` stages the pipeline then starts with are translated to SQL, so that they can use the
+//! indexes of the source. The rest of the pipeline runs in memory, see [`crate::aggregation`].
+//!
+//! ```rust
+//! use hoardbase::base::{CollectionConfig, CollectionTrait};
+//! use hoardbase::database::{Database, DatabaseConfig};
+//!
+//! let mut db = Database::open(&DatabaseConfig::in_memory()).unwrap();
+//! let mut fruits = db.create_collection("fruits", &CollectionConfig::default("fruits")).unwrap();
+//! fruits.insert_one(&bson::doc! { "kind": "apples", "qty": 5 }).unwrap();
+//! fruits.insert_one(&bson::doc! { "kind": "bananas", "qty": 0 }).unwrap();
+//!
+//! db.create_view("in_stock", "fruits", &vec![bson::doc! { "$match": { "qty": { "$gt": 0 } } }, bson::doc! { "$project": { "kind": 1 } }]).unwrap();
+//! let mut in_stock = db.view("in_stock").unwrap();
+//! assert_eq!(in_stock.count_documents(&bson::doc! { "kind": "apples" }, &None).unwrap(), 1);
+//! ```
+
+use crate::aggregation::{self, Resolver};
+use crate::base::*;
+use crate::collection::Collection;
+use crate::lookup::{self, CatalogResolver};
+use crate::time_series::TimeSeries;
+
+/// The maximum number of views a view can be defined on in a chain, as in mongodb.
+pub const MAX_VIEW_DEPTH: usize = 20;
+
+/// The definition of a view.
+#[derive(Clone, Debug)]
+pub struct ViewConfig {
+    /// The name of the collection or view the view reads from.
+    pub view_on: String,
+    pub pipeline: Vec<bson::Document>,
+}
+
+/// Checks the stages of a view's pipeline.
+pub(crate) fn validate_pipeline(pipeline: &[bson::Document]) -> Result<(), String> {
+    // running the pipeline on nothing checks the stage names and the specifications that are parsed up front.
+    aggregation::aggregate(Vec::new(), pipeline).map(|_| ())
+}
+
+/// Follows a view to the collection it reads from. Returns the source's config, `None` if it doesn't exist, and the pipelines of the views on
+/// the way, concatenated.
+pub(crate) fn resolve(config: &CollectionConfig, lookup: &dyn Fn(&str) -> Option<CollectionConfig>) -> Result<(Option<CollectionConfig>, Vec<bson::Document>), String> {
+    let mut pipelines = Vec::new();
+    let mut current = config.clone();
+    while let Some(view) = &current.view {
+        if pipelines.len() == MAX_VIEW_DEPTH {
+            return Err(format!("View depth too deep or view cycle detected. Maximum depth is {}", MAX_VIEW_DEPTH));
+        }
+        pipelines.push(view.pipeline.clone());
+        current = match lookup(&view.view_on) {
+            Some(source) => source,
+            None => return Ok((None, Vec::new())),
+        };
+    }
+    Ok((Some(current), pipelines.into_iter().rev().flatten().collect()))
+}
+
+/// The field paths a query looks at, or `None` if it uses an operator that can look at any field.
+fn query_paths(query: &bson::Document) -> Option<Vec<String>> {
+    let mut paths = Vec::new();
+    for (key, value) in query.iter() {
+        match (key.as_str(), value) {
+            ("$and" | "$or" | "$nor", bson::Bson::Array(clauses)) => {
+                for clause in clauses {
+                    match clause {
+                        bson::Bson::Document(clause) => paths.extend(query_paths(clause)?),
+                        _ => return None,
+                    }
+                }
+            }
+            (key, _) if key.starts_with('$') => return None,
+            (key, _) => paths.push(key.to_string()),
+        }
+    }
+    Some(paths)
+}
+
+/// Whether one of the paths is the other one, or a field inside it.
+fn overlaps(a: &str, b: &str) -> bool {
+    a == b || a.starts_with(&format!("{}.", b)) || b.starts_with(&format!("{}.", a))
+}
+
+/// Whether a `$match` on `paths` gives the same result before `stage` as after it.
+fn commutes(stage: &bson::Document, paths: &[String]) -> bool {
+    let touches = |fields: Vec<&str>| fields.iter().any(|field| paths.iter().any(|path| overlaps(path, field)));
+    let is_included = |value: &bson::Bson| matches!(value, bson::Bson::Boolean(true) | bson::Bson::Int32(1) | bson::Bson::Int64(1)) || matches!(value, bson::Bson::Double(d) if *d == 1.0);
+    let is_excluded = |value: &bson::Bson| matches!(value, bson::Bson::Boolean(false) | bson::Bson::Int32(0) | bson::Bson::Int64(0)) || matches!(value, bson::Bson::Double(d) if *d == 0.0);
+    match stage.iter().next() {
+        Some((name, _)) if name == "$match" || name == "$sort" => true,
+        Some((name, bson::Bson::Document(fields))) if name == "$set" || name == "$addFields" => !touches(fields.keys().map(|key| key.as_str()).collect()),
+        Some((name, bson::Bson::String(field))) if name == "$unset" => !touches(vec![field.as_str()]),
+        Some((name, bson::Bson::Array(fields))) if name == "$unset" => !touches(fields.iter().filter_map(|field| field.as_str()).collect()),
+        Some((name, bson::Bson::Document(fields))) if name == "$project" => {
+            if fields.iter().all(|(_, value)| is_excluded(value)) {
+                !touches(fields.keys().map(|key| key.as_str()).collect())
+            } else {
+                // every path must be kept as it is, and not be changed by a computed field.
+                paths.iter().all(|path| {
+                    fields.iter().any(|(field, value)| is_included(value) && (path == field || path.starts_with(&format!("{}.", field))))
+                        && !fields.iter().any(|(field, value)| !is_included(value) && overlaps(path, field))
+                })
+            }
+        }
+        _ => false,
+    }
+}
+
+/// Appends a `$match` stage with `query` to `pipeline`, and moves it ahead as far as it goes without changing the result.
+pub(crate) fn push_down(pipeline: &[bson::Document], query: &bson::Document) -> Vec<bson::Document> {
+    let mut position = pipeline.len();
+    if let Some(paths) = query_paths(query) {
+        // there is nothing to gain by going ahead of the `$match` stages the pipeline starts with.
+        while position > 0 && commutes(&pipeline[position - 1], &paths) && !pipeline[..position].iter().all(|stage| stage.contains_key("$match")) {
+            position -= 1;
+        }
+    }
+    let mut pushed = pipeline.to_vec();
+    pushed.insert(position, bson::doc! { "$match": query.clone() });
+    pushed
+}
+
+/// A view. Obtained from [`crate::database::Database::view()`].
+pub struct View<'a> {
+    pub name: String,
+    pub config: CollectionConfig,
+    pub db: &'a rusqlite::Connection,
+    /// The collection the view reads from in the end, `None` if it doesn't exist.
+    source: Option<CollectionConfig>,
+    /// The pipelines of the views from the source to this one.
+    pipeline: Vec<bson::Document>,
+}
+
+impl<'a> View<'a> {
+    pub(crate) fn new(name: &str, config: &CollectionConfig, db: &'a rusqlite::Connection, lookup: &dyn Fn(&str) -> Option<CollectionConfig>) -> Result<View<'a>, String> {
+        let (source, pipeline) = resolve(config, lookup)?;
+        Ok(View { name: name.to_string(), config: config.clone(), db, source, pipeline })
+    }
+
+    fn source_collection(&self, source: &CollectionConfig) -> Collection<'a> {
+        Collection { config: source.clone(), name: source.name.clone(), db: self.db, table_name: source.table_name.clone() }
+    }
+
+    /// The view's definition.
+    pub fn definition(&self) -> &ViewConfig {
+        self.config.view.as_ref().expect("a view has a definition")
+    }
+
+    /// Runs the view's pipeline followed by `stages`, with `query` pushed down, and applies `options` to the result.
+    fn run(&self, query: &bson::Document, stages: &[bson::Document], options: &Option<SearchOption>, resolver: &dyn Resolver) -> Result<Vec<bson::Document>, String> {
+        let source = match &self.source {
+            Some(source) => source,
+            None => return Ok(Vec::new()),
+        };
+        let mut pipeline = self.pipeline.clone();
+        pipeline.extend_from_slice(stages);
+        if !query.is_empty() {
+            pipeline = push_down(&pipeline, query);
+        }
+
+        let (source_query, rest) = lookup::split_match(&pipeline);
+        let mut rest = rest.to_vec();
+        if let Some(options) = options {
+            // without stages left, skip and limit go into the SQL as well.
+            if rest.is_empty() && source.time_series.is_none() {
+                return self.source_collection(source).find_documents(&source_query, &Some(*options));
+            }
+            if options.skip > 0 {
+                rest.push(bson::doc! { "$skip": options.skip });
+            }
+            if options.limit >= 0 {
+                rest.push(bson::doc! { "$limit": options.limit });
+            }
+        }
+
+        if source.time_series.is_some() {
+            let mut time_series = TimeSeries { name: source.name.clone(), config: source.clone(), db: self.db };
+            rest.insert(0, bson::doc! { "$match": source_query });
+            return time_series.aggregate_with(&rest, resolver);
+        }
+        lookup::aggregate_collection(self.db, source, &source_query, &rest, resolver)
+    }
+
+    /// Finds the documents of the view that match `query`.
+    pub fn find(&mut self, query: &bson::Document, options: &Option<SearchOption>, f: &mut dyn FnMut(&bson::Document) -> Result<(), &'static str>) -> Result<(), String> {
+        for document in self.run(query, &[], options, &CatalogResolver::new(self.db))? {
+            f(&document)?;
+        }
+        Ok(())
+    }
+
+    /// Finds the first document of the view that matches `query`.
+    pub fn find_one(&mut self, query: &bson::Document) -> Result<Option<bson::Document>, String> {
+        Ok(self.run(query, &[], &Some(*SearchOption::default().limit(1)), &CatalogResolver::new(self.db))?.pop())
+    }
+
+    /// Counts the documents of the view that match `query`.
+    pub fn count_documents(&mut self, query: &bson::Document, options: &Option<SearchOption>) -> Result<i64, String> {
+        Ok(self.run(query, &[], options, &CatalogResolver::new(self.db))?.len() as i64)
+    }
+
+    /// Runs an aggregation pipeline on the documents of the view. A leading `$match` stage is pushed down like the query of
+    /// [`View::find()`].
+    pub fn aggregate(&mut self, pipeline: &[bson::Document]) -> Result<Vec<bson::Document>, String> {
+        self.aggregate_with(pipeline, &CatalogResolver::new(self.db))
+    }
+
+    pub(crate) fn aggregate_with(&mut self, pipeline: &[bson::Document], resolver: &dyn Resolver) -> Result<Vec<bson::Document>, String> {
+        match pipeline.split_first() {
+            Some((first, rest)) if first.len() == 1 && matches!(first.get("$match"), Some(bson::Bson::Document(_))) => self.run(first.get_document("$match").unwrap(), rest, &None, resolver),
+            _ => self.run(&bson::doc! {}, pipeline, &None, resolver),
+        }
+    }
+
+    /// The pipeline that runs for `query`, starting with the `$match` stage that is translated to SQL. Useful to see how far the query was
+    /// pushed down.
+    pub fn explain(&self, query: &bson::Document) -> Vec<bson::Document> {
+        if query.is_empty() {
+            self.pipeline.clone()
+        } else {
+            push_down(&self.pipeline, query)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bson::doc;
+
+    #[test]
+    fn test_push_down() {
+        let pipeline = vec![
+            doc! { "$match": { "qty": { "$gt": 0 } } },
+            doc! { "$set": { "total": { "$multiply": ["$qty", "$price"] } } },
+            doc! { "$project": { "kind": 1, "total": 1, "address.city": 1 } },
+            doc! { "$sort": { "kind": 1 } },
+        ];
+        let pushed = |query: bson::Document| push_down(&pipeline, &query).iter().position(|stage| stage.get("$match") == Some(&bson::Bson::Document(query.clone()))).unwrap();
+        assert_eq!(pushed(doc! { "kind": "apples" }), 1);
+        assert_eq!(pushed(doc! { "$or": [{ "kind": "apples" }, { "address.city.name": "x" }] }), 1);
+        assert_eq!(pushed(doc! { "total": { "$gt": 5 } }), 2);
+        assert_eq!(pushed(doc! { "address": { "city": "x" } }), 3);
+        assert_eq!(pushed(doc! { "$where": "true" }), 4);
+        assert_eq!(push_down(&[doc! { "$group": { "_id": "$kind" } }], &doc! { "_id": "apples" })[1], doc! { "$match": { "_id": "apples" } });
+        assert_eq!(push_down(&[doc! { "$project": { "secret": 0 } }], &doc! { "secret": 1 })[1], doc! { "$match": { "secret": 1 } });
+    }
+}